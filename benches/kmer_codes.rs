@@ -0,0 +1,81 @@
+//! Throughput of base encoding and of the fused rolling code pass.
+//!
+//! Run with `cargo bench --bench kmer_codes`.
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use reference::reference::kmer_codec::{
+    build_codes_per_k, build_kmer_specs, encode_base, encode_bases,
+};
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+/// Bases per run
+const SEQ_LEN: usize = 32_000_000;
+const REPEATS: usize = 5;
+
+/// A random sequence with an N run every megabase
+fn sequence() -> Vec<u8> {
+    let mut rng = StdRng::seed_from_u64(1);
+    let mut seq: Vec<u8> = (0..SEQ_LEN)
+        .map(|_| b"ACGTacgt"[rng.gen_range(0..8)])
+        .collect();
+    for start in (0..SEQ_LEN).step_by(1 << 20) {
+        seq[start..(start + 100).min(SEQ_LEN)].fill(b'N');
+    }
+    seq
+}
+
+/// Fastest of `REPEATS` runs of `f`
+fn fastest(mut f: impl FnMut()) -> Duration {
+    (0..REPEATS)
+        .map(|_| {
+            let start = Instant::now();
+            f();
+            start.elapsed()
+        })
+        .min()
+        .unwrap()
+}
+
+fn report(name: &str, time: Duration) {
+    println!(
+        "{:<40} {:>8.1} ms {:>8.0} Mb/s",
+        name,
+        time.as_secs_f64() * 1e3,
+        SEQ_LEN as f64 / time.as_secs_f64() / 1e6
+    );
+}
+
+fn main() {
+    let seq = sequence();
+    let mut out = vec![0u8; seq.len()];
+
+    report(
+        "encode_base (scalar)",
+        fastest(|| {
+            for (o, &b) in out.iter_mut().zip(&seq) {
+                *o = encode_base(black_box(b)) as u8;
+            }
+            black_box(&out);
+        }),
+    );
+    report(
+        "encode_bases (SIMD)",
+        fastest(|| {
+            encode_bases(&seq, &mut out);
+            black_box(&out);
+        }),
+    );
+
+    for ks in [&[3u8][..], &[5], &[7], &[1, 2, 3, 4, 5, 6], &[11, 13, 15]] {
+        let specs = build_kmer_specs(ks).unwrap();
+        let name = format!("build_codes_per_k k={:?}", ks);
+        report(
+            &name,
+            fastest(|| {
+                black_box(build_codes_per_k(&seq, &specs));
+            }),
+        );
+    }
+}
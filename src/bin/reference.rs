@@ -1,25 +1,98 @@
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
+use clap::error::ErrorKind;
 use clap::ArgAction;
-use clap::{value_parser, ArgGroup, Parser};
-use fxhash::FxHashMap;
+use clap::{
+    value_parser, ArgGroup, ArgMatches, Args, CommandFactory, FromArgMatches, Parser, Subcommand,
+    ValueEnum,
+};
+use clap_complete::Shell;
+use flate2::read::MultiGzDecoder;
+use fxhash::{FxHashMap, FxHashSet};
 use indicatif::{ProgressBar, ProgressStyle};
+use rand::{rngs::StdRng, SeedableRng};
 use rayon::prelude::*;
-use reference::cli::io::read_seq;
+use reference::cli::bam::BamReader;
+use reference::cli::cloud::OutputRoute;
+use reference::cli::config::{with_config_args, write_config};
+use reference::cli::fastq::FastqReader;
+use reference::cli::interrupt::{check_interrupted, install_handlers, interrupted_by};
+use reference::cli::io::{
+    open_sequence_source, InMemoryFastaSource, RefFormat, SequenceSource, StagedOutputDir,
+};
+use reference::cli::remote::is_url;
+use reference::cli::tabix::{parse_region, query_lines};
 use reference::cli::BigCount;
-use reference::reference::bed::load_windows;
+use reference::reference::aggregate::{aggregate_run, read_regions, TargetBins};
+use reference::reference::bed::{
+    bed12_blocks, clamp_windows, load_anchors, load_windows_with_columns,
+    merge_overlapping_windows, validate_windows, BedColumns, WindowIssue, WindowMap, WindowProblem,
+};
+use reference::reference::bisulfite::{bisulfite_convert, Bisulfite};
 use reference::reference::blacklist::*;
-use reference::reference::counting::{count_kmers_by_window, Enc};
+use reference::reference::checkpoint::{
+    checkpoint_path, load_checkpoint, options_fingerprint, store_checkpoint, Checkpoint,
+};
+use reference::reference::chrom_alias::{load_chrom_aliases, ChromAliases};
+use reference::reference::code_cache::load_or_build_codes_per_k;
+use reference::reference::compare::compare_runs;
+use reference::reference::counting::{
+    count_acgt, count_acgt_strided, count_kmers, count_kmers_by_offset, count_kmers_in_blocks, Enc,
+    KmerBoundary, KmerSource, RefKmerExtractionCounters, WindowLayout,
+};
+use reference::reference::cytoband::{cytoband_windows, load_cytobands, CytobandLevel};
+use reference::reference::gtf::{
+    build_feature_intervals, feature_windows, load_gtf, FeatureIntervals, GtfFeature,
+};
 use reference::reference::kmer_codec::*;
-use reference::reference::process_counts::prepare_decoded_counts;
-use reference::reference::write::write_decoded_counts_matrix;
+use reference::reference::memory::{
+    estimate_chrom_bytes, estimate_peak_chrom_bytes, estimate_window_counts_bytes, MemoryBudget,
+};
+use reference::reference::merge::merge_runs;
+use reference::reference::minimizer::keep_minimizers;
+use reference::reference::motif_groups::{
+    load_motif_groups, load_motif_list, sbs96_motifs, MotifGroups, MotifList,
+};
+use reference::reference::process_counts::{
+    code_motifs, motif_universe, n_possible_motifs, prepare_code_counts, rarefy_counts, row_scales,
+    Normalization,
+};
+use reference::reference::profile::{peak_rss_bytes, Profiler, Stage};
+use reference::reference::progress::ProgressLog;
+use reference::reference::pwm::{load_pwms, Pwm, PwmHits};
+use reference::reference::reads::{
+    family_sizes, fragment_end, fragment_lengths, ReadFilter, ReadKmerCounter,
+};
+use reference::reference::repeats::RepeatContent;
+use reference::reference::run_info::{
+    sha256_file, write_checksum_manifest, ChromCounters, RunInfo,
+};
+use reference::reference::shuffle::shuffle_segments;
+use reference::reference::spill::CountSpill;
+use reference::reference::uniqueness::{
+    add_kmer_frequencies, score_runs, uniqueness_scores, window_means, KmerFrequencies,
+};
+use reference::reference::vcf::{apply_variants, load_vcf, CoordinateMap, Variant};
+use reference::reference::windows::{
+    anchor_windows, anchors_by_chrom, equal_windows, random_windows, size_windows,
+    split_windows_at_intervals, LastWindow, WindowOrder,
+};
+use reference::reference::write::{
+    bundle_directory, chromosome_dir_names, write_decoded_counts_matrix,
+    write_decoded_counts_table, write_histograms, write_jellyfish_dump, write_kmer_diversity,
+    write_kmer_spectrum, write_lines, write_motif_lists, write_pwm_hits, write_repeat_content,
+    write_triplets_sparse, CountDtype, CountWriteOptions, MotifTrackWriter, OutputFormat,
+    SparseFormat, StreamingCountWriter, WindowIds,
+};
+use regex::{Regex, RegexSet};
 use smallvec::SmallVec;
 use std::mem::drop;
 use std::{
-    collections::HashMap,
+    collections::{BTreeMap, HashMap, HashSet},
     fs::{create_dir_all, File},
-    io::{BufWriter, Write},
-    path::PathBuf,
-    sync::Arc,
+    io::{BufRead, BufReader, BufWriter, Read, Write},
+    ops::{Range, RangeInclusive},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
     time::Instant,
 };
 
@@ -33,362 +106,4445 @@ use std::{
 
 EXAMPLES:
     // Using defaults
-    $ reference --ref-2bit <path/to/hg38.2bit> --output-dir <path/to/output_directory/> --kmer-sizes 3 --n-threads <N> --global -b <path/to/blacklist_1.bed> -b <path/to/blacklist_2.bed>
+    $ reference --ref <path/to/hg38.2bit> --output-dir <path/to/output_directory/> --kmer-sizes 3 --n-threads <N> --global -b <path/to/blacklist_1.bed> -b <path/to/blacklist_2.bed>
     ",
     author = "Ludvig Renbo Olsen",
-    version = env!("CARGO_PKG_VERSION")
+    version = env!("CARGO_PKG_VERSION"),
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
 )]
-#[clap(group = ArgGroup::new("windows").required(true).args(&["by_size", "by_bed", "global"]).multiple(false))]
-#[clap(group = ArgGroup::new("chrom_select").args(&["chromosomes", "chromosomes_file"]).multiple(false))]
 struct Cli {
-    /// 2bit reference file [path]
-    /// E.g., "hg38.2bit"
-    #[clap(
-        short = 'r',
-        long,
-        value_parser,
-        required = true,
-        help_heading = "Core"
-    )]
-    pub ref_2bit: PathBuf,
+    #[command(flatten)]
+    pub count: Option<CountArgs>,
 
-    /// Output directory for results [path]
-    #[clap(
-        short = 'o',
-        long,
-        value_parser,
-        required = true,
-        help_heading = "Core"
-    )]
-    pub output_dir: PathBuf,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
 
-    /// List of K-mer sizes [integer].
+#[derive(Subcommand)]
+enum Command {
+    /// Estimate the matrix dimensions, storage and memory of a counting run
     ///
-    /// When counting for many kmer-sizes (>8) consider splitting
-    /// into multiple runs for speed and RAM purposes.
-    #[clap(short = 'k', long, num_args = 1.., value_parser = value_parser!(u8).range(1..28), value_delimiter = ',', required=true, help_heading="Core")]
-    pub kmer_sizes: Vec<u8>,
+    /// Takes the counting options (`--output-dir` is not needed) and prints
+    /// the plan of `--dry-run`: the windows per chromosome, the rows,
+    /// columns and dense and sparse array sizes per kmer size (with
+    /// elements of `--count-dtype`) and the estimated peak memory. Nothing
+    /// is counted.
+    Estimate(Box<CountArgs>),
 
-    /// Number of threads to use (increases RAM usage) [integer]
-    #[clap(short = 't', long, default_value = "1", help_heading = "Core")]
-    pub n_threads: usize,
+    /// Count reference kmers in genomic windows
+    ///
+    /// Same as running without a subcommand; takes the same options.
+    RefKmers(Box<CountArgs>),
 
-    /// Use a fixed window size [integer]
-    #[clap(
-        long = "by-size",
-        alias = "by",
-        value_parser,
-        group = "windows",
-        help_heading = "Windows (select one)"
-    )]
-    pub by_size: Option<usize>,
+    /// Count kmers in dinucleotide-shuffled versions of the selected windows
+    ///
+    /// Every window's sequence is shuffled while preserving its dinucleotide
+    /// counts (N and blacklisted bases stay in place) and counted exactly like
+    /// the main command, giving matched background matrices for enrichment
+    /// testing. Each shuffle is written to `<output-dir>/shuffle_<i>/`.
+    ///
+    /// Overlapping BED windows are shuffled in turn and share the final
+    /// shuffled sequence.
+    ShuffleBackground(Box<ShuffleArgs>),
 
-    /// Use a BED file of windows [path]
-    #[clap(
-        long = "by-bed",
-        value_parser,
-        group = "windows",
-        help_heading = "Windows (select one)"
-    )]
-    pub by_bed: Option<PathBuf>,
+    /// Count the kmers of the reads of FASTQ files
+    ///
+    /// Sums the kmers of every read passing the length filter over all
+    /// `--input` files (optionally gzipped) into one row per file. Kmers
+    /// never span two reads.
+    FastqMers(Box<FastqMersArgs>),
 
-    /// Use a single genome-wide window [flag]
-    #[clap(
-        long = "global",
-        group = "windows",
-        help_heading = "Windows (select one)"
-    )]
-    pub global: bool,
+    /// Count the fragment end motifs of the reads of BAM files
+    ///
+    /// Counts the first kmer of the 5' end of every kept read (reverse
+    /// complemented for reverse-strand reads) into one row per `--input`
+    /// file, e.g. the end motifs of cell-free DNA fragments.
+    BamMotifs(Box<BamMotifsArgs>),
 
-    /// Names of chromosomes to process (comma-separated or repeated). E.g. 'chr1,chr2,chr3'.
+    /// Write the fragment length histogram of paired-end BAM files
     ///
-    /// When no chromosomes are specified, it defaults to chr1..chr22.
-    #[clap(long, num_args = 1.., value_parser, value_delimiter = ',', group = "chrom_select", help_heading="Chromosome Selection (select max. one)")]
-    pub chromosomes: Option<Vec<String>>,
+    /// Counts the absolute template length of every kept, mapped read pair
+    /// (by its first read) into `fragment_lengths.tsv`, with an
+    /// `input, length, count` line per length of every `--input` file.
+    Fragsize(Box<FragsizeArgs>),
 
-    /// File with chromosome names to process (one per line).
-    #[arg(
-        long,
-        value_parser,
-        group = "chrom_select",
-        help_heading = "Chromosome Selection (select max. one)"
-    )]
-    pub chromosomes_file: Option<PathBuf>,
+    /// Write the duplicate family size histogram of BAM files
+    ///
+    /// Groups kept reads by reference, start, strand and template length
+    /// and counts the families of each size, i.e. the depth of their
+    /// consensus reads, into `family_sizes.tsv` (`input, family_size, count`
+    /// lines). Duplicates are kept by default. BAM files must be
+    /// coordinate-sorted.
+    ConsensusDepth(Box<ConsensusDepthArgs>),
 
-    /// Optional BED files of blacklisted regions [path]
-    #[clap(short = 'b', long, value_parser, num_args = 1.., action = ArgAction::Append, help_heading="Filtering")]
-    pub blacklist: Option<Vec<PathBuf>>,
+    /// Print the long-format counts of windows overlapping a region
+    ///
+    /// Reads only the needed blocks of a `counts_long.tsv.gz` written with
+    /// `--output-format long --bgzip`, using its tabix index.
+    Query(QueryArgs),
 
-    /// Minimum size of blacklist intervals to load (bp) [integer]
-    #[clap(
-        long,
-        alias = "bl-min-size",
-        default_value = "1",
-        help_heading = "Filtering"
-    )]
-    pub blacklist_min_size: u64,
+    /// Sum the count matrices of several runs with the same windows
+    ///
+    /// E.g. technical replicates or runs of the same windows on separate
+    /// nodes. All output directories must have the same count matrices,
+    /// the same `bins.bed` and the same motif lists, which are checked
+    /// before anything is added. Scaled (`--normalize`) matrices cannot be
+    /// merged.
+    Merge(MergeArgs),
 
-    /// Collapse each kmer with its reverse-complement. [flag]
+    /// Sum the windows of a run into coarser bins or target regions
     ///
-    /// The lexicographically lowest kmer is used.
-    #[clap(short = 'c', long, help_heading = "Core")]
-    canonical: bool,
+    /// Reads the count matrices and `bins.bed` of an output directory and
+    /// adds every window to each `--bin-size` tile or `--target-bed` region
+    /// fully containing it, e.g. to go from 10 kb to 1 Mb without
+    /// recounting. Windows crossing a bin border are left out. Scaled
+    /// (`--normalize`) matrices cannot be aggregated.
+    Aggregate(AggregateArgs),
 
-    /// Save counts as sparse-array. [flag]
+    /// Compare the count matrices of two runs with the same windows
     ///
-    /// For large kmer-sizes, we cannot save dense arrays with all motifs
-    /// unless we have a LOT of RAM and storage space. Enable this
-    /// flag to save as a COO sparse array that can be opened in
-    /// python via `scipy.sparse.load_npz()`.
-    #[clap(long, help_heading = "Core")]
-    pub save_sparse: bool,
+    /// E.g. two genomes, or masked vs. unmasked. Writes per-window,
+    /// per-motif differences (`b - a`) and log2 ratios as dense matrices,
+    /// per-motif totals and a `compare_summary.tsv` with totals, changed
+    /// cells and the correlation per matrix. The windows of `bins.bed`
+    /// must match; motif columns are matched by name.
+    Compare(CompareArgs),
+
+    /// Check a BED file of windows for problems and optionally fix them
+    ///
+    /// Empty (start >= end), duplicate and overlapping windows and windows
+    /// extending beyond the chromosome end are printed as a TSV. Windows
+    /// on sequences missing from the reference are ignored.
+    ValidateBed(ValidateBedArgs),
+
+    /// Write a genome-wide k-mer uniqueness (mappability) track
+    ///
+    /// Counts every k-mer of the reference (merged with its reverse
+    /// complement) in a first pass and then writes the uniqueness
+    /// (1 / genome-wide frequency) of the k-mer starting at each position
+    /// as a bedGraph: per position (runs of equal scores merged) or averaged
+    /// per `--window-size` window. Positions without a k-mer (N) are left
+    /// out. Convert to bigWig with UCSC `bedGraphToBigWig`.
+    Uniqueness(UniquenessArgs),
+
+    /// Print the motif columns of the count matrices
+    ///
+    /// Prints `k, column, motif` rows in the column order of the dense
+    /// matrices written for the same options, so downstream code can be
+    /// written before the counts are done. Sparse matrices keep the
+    /// observed motifs in the same order.
+    Motifs(MotifsArgs),
+
+    /// Print the numeric codes of motifs
+    ///
+    /// Prints `k, motif, code, sentinel` rows with the codes used in
+    /// sparse outputs and code caches. Motifs with N get the N sentinel.
+    Encode(EncodeArgs),
+
+    /// Print the motifs of numeric codes
+    ///
+    /// Prints `k, motif, code, sentinel` rows; the sentinel column explains
+    /// the reserved codes for missing k-mers and k-mers with N.
+    Decode(DecodeArgs),
+
+    /// Print a shell completion script
+    ///
+    /// E.g. `reference generate-completions bash >
+    /// ~/.local/share/bash-completion/completions/reference`.
+    #[command(hide = true)]
+    GenerateCompletions(GenerateCompletionsArgs),
+
+    /// Print the man page, or write one per subcommand to a directory
+    #[command(hide = true)]
+    GenerateMan(GenerateManArgs),
 }
 
-impl Cli {
-    /// Returns the final chromosome list, in priority order:
-    /// 1) from `--chromosomes-file`
-    /// 2) from `--chromosomes`
-    /// 3) default `chr1`..`chr22`
-    pub fn resolve_chromosomes(&self) -> anyhow::Result<Vec<String>> {
-        if let Some(file) = &self.chromosomes_file {
-            let text: String = std::fs::read_to_string(file)
-                .context(format!("reading chromosome file {:?}", file))?;
-            let list: Vec<String> = text
-                .lines()
-                .map(str::trim)
-                .filter(|l| !l.is_empty() && !l.starts_with('#'))
-                .map(String::from)
-                .collect();
-            Ok(list)
-        } else if let Some(chrs) = &self.chromosomes {
-            Ok(chrs.clone())
-        } else {
-            Ok((1..=22).map(|i| format!("chr{}", i)).collect())
-        }
-    }
+#[derive(Args)]
+struct MergeArgs {
+    /// Output directories of the runs to merge [path]
+    #[clap(num_args = 2.., required = true)]
+    pub inputs: Vec<PathBuf>,
+
+    /// Directory to write the merged matrices, `bins.bed` and motif lists to [path]
+    #[clap(short = 'o', long)]
+    pub output_dir: PathBuf,
+
+    /// Replace the contents of a non-empty `--output-dir`. [flag]
+    #[clap(long)]
+    pub force: bool,
+
+    /// Element type of the merged count arrays [u16|u32|u64|f32|f64]
+    #[clap(long, value_enum, default_value = "u64")]
+    pub count_dtype: CountDtype,
 }
 
-fn main() {
-    // Catch and handle errors
-    // Ensures that tempfile has time to remove the tmp dir
-    if let Err(e) = run() {
-        eprintln!("{:?}", e);
-        std::process::exit(1);
-    }
-    std::process::exit(0);
+#[derive(Args)]
+#[clap(group = ArgGroup::new("bins").required(true).args(&["bin_size", "target_bed"]).multiple(false))]
+struct AggregateArgs {
+    /// Output directory of the run to aggregate [path]
+    pub input: PathBuf,
+
+    /// Sum windows into tiles of this size from the chromosome starts [integer]
+    #[clap(long, value_parser = value_parser!(u64).range(1..))]
+    pub bin_size: Option<u64>,
+
+    /// Sum windows into these (possibly overlapping) BED regions, in their order [path]
+    #[clap(long)]
+    pub target_bed: Option<PathBuf>,
+
+    /// Directory to write the aggregated matrices, `bins.bed` and motif lists to [path]
+    #[clap(short = 'o', long)]
+    pub output_dir: PathBuf,
+
+    /// Replace the contents of a non-empty `--output-dir`. [flag]
+    #[clap(long)]
+    pub force: bool,
+
+    /// Element type of the aggregated count arrays [u16|u32|u64|f32|f64]
+    #[clap(long, value_enum, default_value = "u64")]
+    pub count_dtype: CountDtype,
 }
 
-fn run() -> Result<()> {
-    let start_time = Instant::now();
-    let opt = Cli::parse();
-    let chromosomes = opt.resolve_chromosomes()?;
-    let pb = Arc::new(ProgressBar::new(chromosomes.len() as u64));
-    pb.set_style(
-        ProgressStyle::default_bar()
-            .template("       {bar:40} {pos}/{len} [{elapsed_precise}] {msg}")
-            .unwrap(),
-    );
+#[derive(Args)]
+struct CompareArgs {
+    /// Output directory of the first run [path]
+    pub a: PathBuf,
 
-    // Create output directory
-    create_dir_all(&opt.output_dir).context("Cannot create output_dir")?;
+    /// Output directory of the second run [path]
+    pub b: PathBuf,
 
-    // Load blacklist intervals if provided
-    let blacklist_map = if let Some(beds) = &opt.blacklist {
-        println!("Start: Loading blacklists");
-        load_blacklists(beds, opt.blacklist_min_size, &chromosomes)?
-    } else {
-        HashMap::new()
-    };
+    /// Directory to write the differences, log2 ratios and summaries to [path]
+    #[clap(short = 'o', long)]
+    pub output_dir: PathBuf,
 
-    let windows_map = if let Some(bed) = &opt.by_bed {
-        println!("Start: Loading window coordinates");
-        Some(load_windows(bed, &chromosomes)?)
-    } else {
-        None
-    };
+    /// Added to both counts of the log2 ratios [number]
+    #[clap(long, default_value = "1")]
+    pub pseudocount: f64,
 
-    let kmer_specs: HashMap<u8, KmerSpec> = build_kmer_specs(&opt.kmer_sizes)?;
+    /// Replace the contents of a non-empty `--output-dir`. [flag]
+    #[clap(long)]
+    pub force: bool,
+}
 
-    // Configure global thread‐pool size
-    rayon::ThreadPoolBuilder::new()
-        .num_threads(opt.n_threads as usize)
-        .build_global()
-        .context("building Rayon thread pool")?;
+#[derive(Args)]
+struct EncodeArgs {
+    /// K-mer size [integer]
+    #[clap(short = 'k', long, value_parser = value_parser!(u8).range(1..=MAX_K_2BIT as i64))]
+    pub k: u8,
 
-    // Prepare per-bin counts and metadata
-    let mut all_bins = Vec::new();
-    let mut bin_info = Vec::new();
+    /// Motifs to encode [string]
+    #[clap(required = true)]
+    pub motifs: Vec<String>,
+}
 
-    // Main loop: process each autosome
-    println!("Start: Counting per chromosome");
+#[derive(Args)]
+struct DecodeArgs {
+    /// K-mer size [integer]
+    #[clap(short = 'k', long, value_parser = value_parser!(u8).range(1..=MAX_K_2BIT as i64))]
+    pub k: u8,
 
-    pb.set_position(0);
+    /// Codes to decode [integer]
+    #[clap(required = true)]
+    pub codes: Vec<u64>,
+}
 
-    let results: Vec<(
-        Vec<FxHashMap<Kmer, BigCount>>,
-        Vec<(String, u64, u64, u64, f64)>,
-    )> = chromosomes
-        .par_iter()
-        .map(|chr| -> Result<(_, _)> {
-            let out = process_chrom(
-                &chr,
-                &opt,
-                &kmer_specs,
-                windows_map
-                    .as_ref()
-                    .and_then(|m| m.get(chr).map(|v| v.as_slice())),
-                //gc_bins,
-                blacklist_map.get(chr).map(|v| v.as_slice()).unwrap_or(&[]),
-            )?;
-            pb.inc(1);
-            Ok(out)
-        })
-        .collect::<Result<_>>()?; // short-circuits on the first Err
+#[derive(Args)]
+struct GenerateCompletionsArgs {
+    /// Shell to complete in [bash|zsh|fish|elvish|powershell]
+    #[clap(value_enum)]
+    pub shell: Shell,
+}
 
-    pb.finish_with_message("| Finished counting");
+#[derive(Args)]
+struct GenerateManArgs {
+    /// Write `reference.1` and a page per subcommand (`reference-merge.1`, …)
+    /// to this directory instead of printing `reference.1` [path]
+    #[clap(short = 'o', long)]
+    pub output_dir: Option<PathBuf>,
+}
 
-    println!("Start: Processing counts");
+#[derive(Args)]
+struct MotifsArgs {
+    /// K-mer sizes, e.g. `3` or `1-4,6` [integer]
+    #[clap(short = 'k', long, num_args = 1.., value_delimiter = ',', required = true, value_parser = |s: &str| parse_kmer_size_range(s, 16))]
+    pub kmer_sizes: Vec<RangeInclusive<u8>>,
 
-    // Collect results (in chromosome order) back into the global vectors
-    for (counts_by_bin, bin_vec) in results {
-        let counts_decoded: Vec<DecodedCounts> = counts_by_bin
-            .iter()
-            .map(|c| split_and_decode_counts(c, &kmer_specs))
-            .collect();
-        all_bins.extend(counts_decoded);
-        if !opt.global {
-            bin_info.extend(bin_vec);
-        }
-    }
+    /// Merge reverse complements (to lexicographically lowest) [flag]
+    #[clap(short = 'c', long)]
+    pub canonical: bool,
 
-    // Convert to single hashmap for global
-    // Keep wrapped in vector to simplify writer
-    let all_bins = if opt.global {
-        vec![merge_decoded_counts(all_bins)]
-    } else {
-        all_bins
-    };
+    /// Only the motifs of this list, in its order [path]
+    #[clap(long, conflicts_with = "sbs96")]
+    pub motif_list: Option<PathBuf>,
 
-    // Prepare to get correct motifs (collapsed, N-filtered, etc.)
-    let (mut prepared_counts, motifs_by_k) =
-        prepare_decoded_counts(&all_bins, opt.canonical, &kmer_specs);
+    /// The 96 SBS contexts of `--kmer-sizes 3` [flag]
+    #[clap(long)]
+    pub sbs96: bool,
 
-    // Sort by original index (when given a bed file)
-    if opt.by_bed.is_some() {
-        println!("Start: Reordering counts by original window index in bed file");
+    /// Leave out the motifs of this list [path]
+    #[clap(long, conflicts_with_all = ["motif_list", "sbs96"])]
+    pub exclude_motifs: Option<PathBuf>,
+}
 
-        // Zip into a single Vec
-        let mut paired: Vec<_> = bin_info
-            .into_iter()
-            .zip(prepared_counts.into_iter())
-            .collect(); // (BinInfo, DecodedCounts)
+#[derive(Args)]
+struct UniquenessArgs {
+    /// Reference genome: 2bit or FASTA [path]
+    #[clap(short = 'r', long = "ref")]
+    pub reference: PathBuf,
 
-        // Sort primarily by original window index
-        paired.sort_unstable_by_key(|(info, _)| info.3);
+    /// Format of the reference file [auto, 2bit, fasta]
+    #[clap(long, value_enum, default_value = "auto")]
+    pub ref_format: RefFormat,
 
-        // Unzip back out if you need separate Vecs again
-        (bin_info, prepared_counts) = paired.into_iter().unzip();
-    }
+    /// K-mer size (read length) [integer]
+    #[clap(short = 'k', long, value_parser = value_parser!(u8).range(1..=MAX_K_2BIT as i64))]
+    pub k: u8,
 
-    println!("Start: Writing counts to disk");
-    write_decoded_counts_matrix(
-        &prepared_counts,
-        &kmer_specs,
-        &motifs_by_k,
-        &opt.output_dir,
-        opt.save_sparse,
-    )?;
+    /// Average the scores in windows of this size instead of writing every position [integer]
+    #[clap(long, value_parser = value_parser!(u64).range(1..))]
+    pub window_size: Option<u64>,
 
-    // Write bins BED file
-    if !opt.global {
-        println!("Start: Writing window coordinates to disk");
-        let mut bed_writer = BufWriter::new(
-            File::create(&opt.output_dir.join("bins.bed")).context("Create bed fail")?,
-        );
-        for (chr, start, end, _, overlap_perc) in &bin_info {
-            writeln!(bed_writer, "{}\t{}\t{}\t{}", chr, start, end, overlap_perc)
-                .context("Write bed line fail")?;
-        }
-    }
+    /// bedGraph file to write [path]
+    #[clap(short = 'o', long)]
+    pub output: PathBuf,
 
-    // Print summary statistics and execution time
-    let elapsed = start_time.elapsed();
-    println!("Elapsed time: {:.2?}", elapsed);
-    Ok(())
+    /// Number of threads to use (increases RAM usage) [integer]
+    #[clap(short = 't', long, default_value = "1")]
+    pub n_threads: usize,
 }
 
-/* ---------- main routine -------------------------------------------- */
+#[derive(Args)]
+struct ValidateBedArgs {
+    /// BED file of windows [path]
+    pub bed: PathBuf,
 
-/// * windows  -  Optional slice of tuples with (start, end, original_idx)
-fn process_chrom(
-    chr: &str,
-    opt: &Cli,
-    kmer_specs: &HashMap<u8, KmerSpec>,
-    windows: Option<&[(u64, u64, u64)]>,
-    // gc_bins: usize,
-    blacklist_intervals: &[(u64, u64)],
-) -> anyhow::Result<(
-    Vec<FxHashMap<Kmer, BigCount>>,
-    Vec<(String, u64, u64, u64, f64)>,
-)> {
-    let mut seq_bytes = read_seq(&opt.ref_2bit, chr)?;
-    apply_blacklist_mask_to_seq(&mut seq_bytes, &blacklist_intervals);
-    let chrom_len = seq_bytes.len() as usize;
-    let positional_codes_by_k: HashMap<u8, KmerCodes> = build_codes_per_k(&seq_bytes, kmer_specs);
+    /// Reference file with the chromosome lengths: 2bit or FASTA [path]
+    #[clap(short = 'r', long = "ref")]
+    pub reference: PathBuf,
 
-    // Delete seq_bytes from memory
-    drop(seq_bytes);
+    /// Format of the reference file [auto, 2bit, fasta]
+    #[clap(long, value_enum, default_value = "auto")]
+    pub ref_format: RefFormat,
 
-    // Calculate window coordinates for all windowing options
-    let windows: Vec<(u64, u64, u64)> = if let Some(sz) = opt.by_size {
-        // by-size
-        let num_windows = ((chrom_len + sz - 1) / sz) as usize;
-        (0..num_windows)
-            .map(|s| ((s * sz) as u64, (sz + s * sz) as u64, s as u64))
-            .collect()
-    } else if opt.by_bed.is_some() {
-        // by-bed
-        windows.unwrap().to_owned()
-    } else {
-        // global
-        vec![(0, chrom_len as u64, 0u64)]
-    };
+    /// Clamp windows to the chromosome ends and drop empty windows [flag]
+    #[clap(long)]
+    pub clamp: bool,
 
-    let num_windows = windows.len();
+    /// Merge overlapping and duplicate windows [flag]
+    #[clap(long)]
+    pub merge: bool,
 
-    let mut counts_by_window = vec![FxHashMap::<Kmer, BigCount>::default(); num_windows];
+    /// Write the (fixed) windows with their extra columns to this BED [path]
+    #[clap(short = 'o', long)]
+    pub output: Option<PathBuf>,
+}
 
-    let mut encs: SmallVec<[Enc; 8]> = SmallVec::new();
-    for (&k, spec) in kmer_specs {
-        encs.push(Enc {
-            k,
-            codes: &positional_codes_by_k[&k],
-            none: spec.sentinel_none(),
-            n: spec.sentinel_n(),
-        });
+#[derive(Args)]
+struct QueryArgs {
+    /// `counts_long.tsv.gz` or an output directory containing it [path]
+    pub input: PathBuf,
+
+    /// Region as `chrom`, `chrom:start` or `chrom:start-end` (1-based, inclusive) [string]
+    ///
+    /// E.g. 'chr1:1,000,000-2,000,000'.
+    #[clap(long, short = 'r')]
+    pub region: String,
+
+    /// Only print rows of these kmer sizes [integer]
+    #[clap(long, short = 'k', num_args = 1.., value_delimiter = ',')]
+    pub k: Vec<u8>,
+
+    /// Print the header line first. [flag]
+    #[clap(long)]
+    pub header: bool,
+}
+
+#[derive(Args)]
+struct ShuffleArgs {
+    #[command(flatten)]
+    pub count: CountArgs,
+
+    /// Number of shuffled backgrounds to generate [integer]
+    #[clap(long, default_value = "1", value_parser = value_parser!(u64).range(1..), help_heading = "Shuffling")]
+    pub n_shuffles: u64,
+}
+
+/// Inputs and output directory of the read subcommands
+#[derive(Args)]
+struct IOArgs {
+    /// Read files; each gives one row (or histogram) of the output [path]
+    #[clap(short = 'i', long, num_args = 1.., required = true, help_heading = "Core")]
+    pub input: Vec<PathBuf>,
+
+    /// Directory to write the outputs to [path]
+    #[clap(short = 'o', long, help_heading = "Output")]
+    pub output_dir: PathBuf,
+
+    /// Replace the contents of a non-empty `--output-dir`. [flag]
+    #[clap(long, help_heading = "Output")]
+    pub force: bool,
+}
+
+impl IOArgs {
+    /// The inputs as given, in row order
+    pub fn input_names(&self) -> Vec<String> {
+        self.input
+            .iter()
+            .map(|path| path.display().to_string())
+            .collect()
     }
 
-    count_kmers_by_window(&mut counts_by_window, &encs, &windows, chrom_len as u64);
+    pub fn input_paths(&self) -> Vec<&Path> {
+        self.input.iter().map(PathBuf::as_path).collect()
+    }
+}
 
-    let bin_info = {
-        // build bin_info from the exact BED windows
-        let mut bl_ptr = 0;
-        let mut bin_info = Vec::with_capacity(num_windows);
-        for (_b, (win_start, mut win_end, original_win_idx)) in windows.iter().cloned().enumerate()
+/// Read filters of the read subcommands (the flag and mapping quality
+/// filters only apply to BAM inputs)
+#[derive(Args)]
+struct ReadFilteringArgs {
+    /// Skip reads with a lower mapping quality [integer]
+    #[clap(long, default_value = "0", help_heading = "Filtering")]
+    pub min_mapq: u8,
+
+    /// Skip reads without all of these SAM flag bits [integer]
+    #[clap(long, default_value = "0", help_heading = "Filtering")]
+    pub require_flags: u16,
+
+    /// Skip reads with any of these SAM flag bits [integer]
+    ///
+    /// The default of 3844 skips unmapped, secondary, QC-failed, duplicate
+    /// and supplementary reads; `consensus-depth` defaults to 2820, which
+    /// keeps the duplicates.
+    #[clap(long, default_value = "3844", help_heading = "Filtering")]
+    pub exclude_flags: u16,
+
+    /// Skip reads with shorter sequences [integer]
+    #[clap(long, default_value = "0", help_heading = "Filtering")]
+    pub min_read_length: usize,
+}
+
+impl ReadFilteringArgs {
+    pub fn filter(&self) -> ReadFilter {
+        ReadFilter {
+            min_mapq: self.min_mapq,
+            require_flags: self.require_flags,
+            exclude_flags: self.exclude_flags,
+            min_length: self.min_read_length,
+        }
+    }
+}
+
+#[derive(Args)]
+struct FastqMersArgs {
+    #[command(flatten)]
+    pub io: IOArgs,
+
+    #[command(flatten)]
+    pub filtering: ReadFilteringArgs,
+
+    /// K-mer sizes, e.g. `3` or `1-4,6` [integer]
+    #[clap(short = 'k', long, num_args = 1.., value_delimiter = ',', required = true, value_parser = |s: &str| parse_kmer_size_range(s, MAX_K_2BIT), help_heading = "Core")]
+    pub kmer_sizes: Vec<RangeInclusive<u8>>,
+
+    /// Number of threads to use [integer]
+    #[clap(short = 't', long, default_value = "1", help_heading = "Core")]
+    pub n_threads: usize,
+}
+
+#[derive(Args)]
+struct BamMotifsArgs {
+    #[command(flatten)]
+    pub io: IOArgs,
+
+    #[command(flatten)]
+    pub filtering: ReadFilteringArgs,
+
+    /// End motif sizes, e.g. `4` or `1-4` [integer]
+    #[clap(short = 'k', long, num_args = 1.., value_delimiter = ',', required = true, value_parser = |s: &str| parse_kmer_size_range(s, MAX_K_2BIT), help_heading = "Core")]
+    pub kmer_sizes: Vec<RangeInclusive<u8>>,
+}
+
+#[derive(Args)]
+struct FragsizeArgs {
+    #[command(flatten)]
+    pub io: IOArgs,
+
+    #[command(flatten)]
+    pub filtering: ReadFilteringArgs,
+
+    /// Longest fragment length to count; longer fragments are only reported [integer]
+    #[clap(long, default_value = "1000", value_parser = value_parser!(u64).range(1..), help_heading = "Core")]
+    pub max_fragment_length: u64,
+}
+
+#[derive(Args)]
+struct ConsensusDepthArgs {
+    #[command(flatten)]
+    pub io: IOArgs,
+
+    #[command(flatten)]
+    pub filtering: ReadFilteringArgs,
+}
+
+/// How soft-masked (lower-case) reference bases are counted
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+enum MaskMode {
+    /// Count masked bases like any other base
+    Ignore,
+    /// Treat masked bases as N
+    Exclude,
+    /// Count kmers overlapping masked bases into separate matrices
+    Separate,
+}
+
+/// Which strands of the reference kmers are counted on
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+enum StrandMode {
+    /// Count the forward strand only
+    Forward,
+    /// Add the reverse-strand counts without collapsing reverse complements
+    Both,
+    /// Count the reverse strand into separate matrices
+    Separate,
+}
+
+/// Options for counting kmers in a reference
+#[derive(Args, Clone)]
+#[clap(group = ArgGroup::new("ref_input").required(true).args(&["reference", "ref_fasta"]).multiple(false))]
+#[clap(group = ArgGroup::new("windows").required(true).args(&["by_size", "n_windows_per_chrom", "by_bed", "by_gtf", "by_anchors", "by_cytoband", "global", "per_sequence"]).multiple(false))]
+#[clap(group = ArgGroup::new("chrom_select").args(&["chromosomes", "chromosomes_file", "chromosomes_regex", "all_chromosomes"]).multiple(false))]
+struct CountArgs {
+    /// Read options from a TOML (or `.yaml`/`.yml`) config file [path]
+    ///
+    /// `key = value` lines (`key: value` in YAML) with option names as keys
+    /// (e.g. `kmer_sizes = [3, 5]`, `blacklist = ["a.bed", "b.bed"]`,
+    /// `canonical = true`); unknown keys are rejected. Options given on
+    /// the command line override the file. The resolved options of every
+    /// run are written to `<output-dir>/config.toml`.
+    #[clap(long, help_heading = "Core")]
+    pub config: Option<PathBuf>,
+
+    /// Reference file: 2bit or FASTA [path]
+    /// E.g., "hg38.2bit" or "hg38.fa"
+    ///
+    /// FASTA files are read via their samtools `.fai` index
+    /// (built in memory when missing). Compressed FASTA (`.fa.gz`) is
+    /// supported: bgzip files with `.fai` and `.gzi` indices are read with
+    /// random access, other gzip files are decompressed into memory.
+    ///
+    /// 2bit files can also be read from an `http(s)://` URL (e.g. UCSC
+    /// goldenPath); only the needed byte ranges are downloaded.
+    #[clap(
+        short = 'r',
+        long = "ref",
+        visible_alias = "ref-2bit",
+        value_parser,
+        group = "ref_input",
+        help_heading = "Core"
+    )]
+    pub reference: Option<PathBuf>,
+
+    /// Format of the reference file [auto, 2bit, fasta]
+    ///
+    /// 'auto' detects the format from the file extension.
+    #[clap(long, value_enum, default_value = "auto", help_heading = "Core")]
+    pub ref_format: RefFormat,
+
+    /// (Multi-)FASTA reference file, e.g. a transcriptome [path]
+    ///
+    /// Every record is treated as a chromosome and all records
+    /// are processed unless chromosomes are specified.
+    /// The file is read into memory once.
+    #[clap(long, value_parser, group = "ref_input", help_heading = "Core")]
+    pub ref_fasta: Option<PathBuf>,
+
+    /// Output directory for results. May be an `s3://` or `gs://` URL
+    /// when built with the `object-storage` feature. [path]
+    #[clap(
+        short = 'o',
+        long,
+        value_parser,
+        required = true,
+        help_heading = "Core"
+    )]
+    pub output_dir: PathBuf,
+
+    /// Replace the contents of a non-empty `--output-dir`. [flag]
+    ///
+    /// Outputs are always written to a temporary directory next to
+    /// `--output-dir` and moved into place once the run succeeds.
+    #[clap(long, help_heading = "Core")]
+    pub force: bool,
+
+    /// List of K-mer sizes [integer].
+    ///
+    /// Ranges expand to every size in them: `1-4,6,8` is `1,2,3,4,6,8`.
+    /// When counting for many kmer-sizes (>8) consider splitting
+    /// into multiple runs for speed and RAM purposes.
+    /// k-mer sizes above 31 are numbered in a dictionary of the observed
+    /// k-mers and require `--save-sparse`.
+    #[clap(short = 'k', long, num_args = 1.., value_parser = |s: &str| parse_kmer_size_range(s, MAX_K), value_delimiter = ',', required=true, help_heading="Core")]
+    pub kmer_sizes: Vec<RangeInclusive<u8>>,
+
+    /// Only count the motifs in this file (one per line) [path]
+    ///
+    /// Motifs can have different lengths, which must all be among
+    /// `--kmer-sizes`. The outputs only have columns for the listed motifs
+    /// (in canonical form with `--canonical`). IUPAC codes (e.g. `WGCW`) are
+    /// expanded and their concrete motifs summed into the motif's column.
+    #[clap(long, help_heading = "Core")]
+    pub motif_list: Option<PathBuf>,
+
+    /// Report the 96 SBS trinucleotide contexts (requires `-k 3`) [flag]
+    ///
+    /// Columns are the pyrimidine-centered contexts (e.g. `A[C>T]G`) in the
+    /// COSMIC order used by mutational-signature tools (e.g. SigProfiler).
+    /// Each context counts its trinucleotide on both strands, so the three
+    /// substitutions of a trinucleotide share a count.
+    #[clap(long, conflicts_with = "motif_list", help_heading = "Core")]
+    pub sbs96: bool,
+
+    /// Skip the motifs in this file (one per line) [path]
+    ///
+    /// E.g. homopolymers or low-complexity k-mers. The motifs are not
+    /// counted and have no output columns. Motif lengths must be among
+    /// `--kmer-sizes` and IUPAC codes are expanded.
+    #[clap(long, conflicts_with_all = ["motif_list", "sbs96"], help_heading = "Core")]
+    pub exclude_motifs: Option<PathBuf>,
+
+    /// Number of threads to use (increases RAM usage) [integer]
+    ///
+    /// Chromosomes are counted in parallel (longest first), as are the
+    /// windows of a chromosome and pieces of long windows (e.g. with
+    /// `--global`).
+    #[clap(short = 't', long, default_value = "1", help_heading = "Core")]
+    pub n_threads: usize,
+
+    /// Directory for caching encoded kmer codes between runs [path]
+    ///
+    /// Per-chromosome code vectors are stored together with a hash of the
+    /// (blacklist-masked) sequence and reused by later runs with other
+    /// window or output options. Stale caches are rebuilt automatically.
+    /// Not used by `shuffle-background`.
+    #[clap(long, value_parser, help_heading = "Performance")]
+    pub code_cache: Option<PathBuf>,
+
+    /// Directory for checkpoints of finished chromosomes [path]
+    ///
+    /// The counts of every finished chromosome are stored here, and a
+    /// re-run with identical options (and seeds) skips the chromosomes
+    /// with a checkpoint, e.g. to resume an interrupted whole-genome run.
+    /// Checkpoints of other options are recomputed. Supports k-mer sizes
+    /// up to 31.
+    #[clap(
+        long,
+        value_parser,
+        conflicts_with = "anchor_profile",
+        help_heading = "Performance"
+    )]
+    pub checkpoint_dir: Option<PathBuf>,
+
+    /// Limit the estimated memory of concurrently counted chromosomes to
+    /// this many gigabytes [number]
+    ///
+    /// Each chromosome's memory is estimated from its length, the storage
+    /// width of each kmer size and the expected number of distinct kmers.
+    /// Chromosomes wait to start until their estimate fits, so fewer run
+    /// at once than `--n-threads` when they are large. A chromosome larger
+    /// than the limit runs alone.
+    #[clap(long, help_heading = "Performance")]
+    pub max_memory: Option<f64>,
+
+    /// Spill the counts of finished chromosomes to temporary files in this
+    /// directory [path]
+    ///
+    /// Counts are written as sorted `(window, kmer, count)` triplets and
+    /// merged into the sparse matrices at the end, instead of being kept
+    /// in memory until all chromosomes are counted. For runs with many
+    /// windows and large kmer sizes. Requires `--save-sparse`.
+    #[clap(
+        long,
+        value_parser,
+        requires = "save_sparse",
+        conflicts_with_all = [
+            "global", "stream_output", "split_by_chromosome", "bundle", "rarefy",
+            "motif_list", "motif_groups", "track_motifs", "diversity",
+            "jellyfish_dump", "spectrum", "anchor_profile",
+        ],
+        help_heading = "Performance"
+    )]
+    pub spill_dir: Option<PathBuf>,
+
+    /// Report the wall time and peak memory of each stage. [flag]
+    ///
+    /// Writes `profile.tsv` with the time spent loading sequences,
+    /// encoding kmers, counting, preparing (decoding) counts and writing
+    /// outputs, per chromosome and in total, and prints the totals.
+    /// Chromosomes are counted concurrently, so per-chromosome times
+    /// overlap. Peak memory is the peak RSS of the process (Linux only).
+    #[clap(long, help_heading = "Performance")]
+    pub profile: bool,
+
+    /// Write progress events as JSON lines to this file, or stderr without one [path]
+    ///
+    /// Every line has the `event` (`stage`, `chromosome` or `done`), the
+    /// current `stage`, `chromosomes_done` and `chromosomes_total`, the
+    /// `fraction` of reference bases counted, `elapsed_secs` and the
+    /// `eta_secs` of counting, for workflow tools tracking the run.
+    #[clap(
+        long,
+        num_args = 0..=1,
+        default_missing_value = "-",
+        value_name = "PATH",
+        help_heading = "Performance"
+    )]
+    pub progress_json: Option<PathBuf>,
+
+    /// Validate the inputs and print the plan without counting. [flag]
+    ///
+    /// Loads the windows, blacklists and reference headers, checks the
+    /// options and prints the windows per chromosome, the matrix
+    /// dimensions and dense and sparse array sizes per kmer size and the
+    /// estimated peak memory. Nothing is written.
+    #[clap(long, help_heading = "Performance")]
+    pub dry_run: bool,
+
+    /// Encode and count chromosomes in chunks of this many bases [integer]
+    ///
+    /// Bounds the memory used for kmer codes (8 bytes per base and kmer
+    /// size for large k) regardless of chromosome length. Chunks overlap
+    /// by the largest kmer size - 1, so counts are identical to unchunked
+    /// runs. The sequence itself is still read in full.
+    #[clap(long, value_parser = value_parser!(u64).range(1..), conflicts_with = "code_cache", help_heading = "Performance")]
+    pub chunk_size: Option<u64>,
+
+    /// Use a fixed window size [integer or list]
+    ///
+    /// Several sizes (e.g. '10000,100000') give one set of outputs per size
+    /// in `<output-dir>/size_<size>/`, counted from a single encoding of
+    /// each chromosome.
+    #[clap(
+        long = "by-size",
+        alias = "by",
+        value_parser,
+        value_delimiter = ',',
+        group = "windows",
+        help_heading = "Windows (select one)"
+    )]
+    pub by_size: Option<Vec<usize>>,
+
+    /// Distance between the starts of consecutive `--by-size` windows.
+    /// Smaller than the window size gives overlapping sliding windows [integer]
+    #[clap(
+        long,
+        value_parser = clap::value_parser!(u64).range(1..),
+        conflicts_with_all = ["n_windows_per_chrom", "by_bed", "by_gtf", "by_anchors", "by_cytoband", "global", "per_sequence"],
+        help_heading = "Windows (select one)"
+    )]
+    pub step: Option<u64>,
+
+    /// How to handle the shorter last `--by-size` window of each chromosome
+    /// [keep, drop, extend, merge]
+    ///
+    /// 'extend' moves its start back to give it the full size (overlapping
+    /// the previous window). 'merge' adds the remaining bases to the
+    /// previous window.
+    #[clap(
+        long,
+        value_enum,
+        default_value = "keep",
+        help_heading = "Windows (select one)"
+    )]
+    pub last_window: LastWindow,
+
+    /// Start the `--by-size` tiling at this position instead of 0, e.g. for
+    /// half-window shifted bins. The bases before it form a shorter first
+    /// window, handled by `--last-window` [integer]
+    #[clap(
+        long,
+        conflicts_with_all = ["n_windows_per_chrom", "by_bed", "by_gtf", "by_anchors", "by_cytoband", "global", "per_sequence"],
+        help_heading = "Windows (select one)"
+    )]
+    pub window_offset: Option<u64>,
+
+    /// Divide each chromosome into this many equally sized windows [integer]
+    ///
+    /// Window boundaries are rounded down to whole bases. Chromosomes
+    /// shorter than the number of windows get fewer (1 bp) windows.
+    #[clap(
+        long,
+        value_parser = clap::value_parser!(u64).range(1..),
+        group = "windows",
+        help_heading = "Windows (select one)"
+    )]
+    pub n_windows_per_chrom: Option<u64>,
+
+    /// Use a BED file of windows [path]
+    ///
+    /// Columns after `end` (name, score, strand, ...) are appended to
+    /// `bins.bed`, which then starts with a `#chrom` header line.
+    #[clap(
+        long = "by-bed",
+        value_parser,
+        group = "windows",
+        help_heading = "Windows (select one)"
+    )]
+    pub by_bed: Option<PathBuf>,
+
+    /// Clamp `--by-bed` windows to the chromosome ends and drop empty
+    /// windows (start >= end) [flag]
+    #[clap(
+        long,
+        conflicts_with_all = ["by_size", "n_windows_per_chrom", "by_gtf", "by_anchors", "by_cytoband", "global", "per_sequence"],
+        help_heading = "Windows (select one)"
+    )]
+    pub clamp_windows: bool,
+
+    /// Merge overlapping and duplicate `--by-bed` windows [flag]
+    ///
+    /// A merged window keeps the extra columns of its first window.
+    #[clap(
+        long,
+        conflicts_with_all = ["by_size", "n_windows_per_chrom", "by_gtf", "by_anchors", "by_cytoband", "global", "per_sequence"],
+        help_heading = "Windows (select one)"
+    )]
+    pub merge_overlapping_windows: bool,
+
+    /// Count `--by-bed` BED12 windows on their concatenated blocks [flag]
+    ///
+    /// E.g. the spliced sequence of a transcript, including kmers spanning
+    /// exon junctions. Rows without block columns use their full span.
+    #[clap(
+        long,
+        conflicts_with_all = ["by_size", "n_windows_per_chrom", "by_gtf", "by_anchors", "by_cytoband", "global", "per_sequence", "split_at_blacklist"],
+        help_heading = "Windows (select one)"
+    )]
+    pub bed12: bool,
+
+    /// Order of `--by-bed`, `--by-gtf`, `--by-anchors` and `--by-cytoband`
+    /// windows in the outputs [input, sorted]
+    ///
+    /// 'input' keeps the order of the file, including interleaved
+    /// chromosomes and duplicate windows. 'sorted' orders windows by
+    /// chromosome (as selected) and position.
+    #[clap(
+        long,
+        value_enum,
+        default_value = "input",
+        help_heading = "Windows (select one)"
+    )]
+    pub window_order: WindowOrder,
+
+    /// Use features of a GTF/GFF3 file as windows [path]
+    ///
+    /// The feature type is set with `--feature`. The feature ID, score
+    /// ('.') and strand are appended to `bins.bed` as BED name, score and
+    /// strand columns.
+    #[clap(
+        long,
+        value_parser,
+        group = "windows",
+        help_heading = "Windows (select one)"
+    )]
+    pub by_gtf: Option<PathBuf>,
+
+    /// Feature of `--by-gtf` to use as windows [gene, exon, CDS, promoter]
+    ///
+    /// 'promoter' windows span `--promoter-flank` bases on both sides of
+    /// the transcription start site of each gene.
+    #[clap(
+        long,
+        value_enum,
+        default_value = "gene",
+        help_heading = "Windows (select one)"
+    )]
+    pub feature: GtfFeature,
+
+    /// Bases on each side of the TSS in `--feature promoter` windows [integer]
+    #[clap(long, default_value = "1000", help_heading = "Windows (select one)")]
+    pub promoter_flank: u64,
+
+    /// Attribute holding the `--by-gtf` feature ID, e.g. 'gene_id',
+    /// 'exon_id' or 'transcript_id'. Falls back to the GFF3 'ID' [string]
+    #[clap(long, default_value = "gene_id", help_heading = "Windows (select one)")]
+    pub feature_id: String,
+
+    /// Use windows around anchor positions (e.g. TSSs) of a BED or TSV file [path]
+    ///
+    /// BED rows are anchored at their start; TSV rows are 'chrom pos [name]'
+    /// with a 0-based position. Each window spans the anchor and `--flank`
+    /// bases on each side, and the anchor name is written to `bins.bed`.
+    #[clap(
+        long,
+        value_parser,
+        group = "windows",
+        help_heading = "Windows (select one)"
+    )]
+    pub by_anchors: Option<PathBuf>,
+
+    /// Bases on each side of the anchor in `--by-anchors` windows [integer]
+    #[clap(long, default_value = "1000", help_heading = "Windows (select one)")]
+    pub flank: u64,
+
+    /// Also write k-mer counts by offset from the `--by-anchors` anchors,
+    /// summed over anchors
+    ///
+    /// Writes an offsets × motifs matrix per k-mer size to `anchor_profile/`
+    /// (rows listed in `offsets.txt`) for e.g. nucleosome- or TSS-relative
+    /// k-mer periodicity. Counts k-mers starting at each offset on the
+    /// forward strand.
+    #[clap(
+        long,
+        requires = "by_anchors",
+        conflicts_with = "stream_output",
+        help_heading = "Windows (select one)"
+    )]
+    pub anchor_profile: bool,
+
+    /// Use the bands of a UCSC `cytoBand.txt` file as windows [path]
+    ///
+    /// The band (or arm) name is written to `bins.bed`.
+    #[clap(
+        long,
+        value_parser,
+        group = "windows",
+        help_heading = "Windows (select one)"
+    )]
+    pub by_cytoband: Option<PathBuf>,
+
+    /// Windows of `--by-cytoband` [band, arm]
+    ///
+    /// 'arm' windows span all bands of each chromosome arm (p and q).
+    #[clap(
+        long,
+        value_enum,
+        default_value = "band",
+        help_heading = "Windows (select one)"
+    )]
+    pub cytoband_level: CytobandLevel,
+
+    /// Use a single genome-wide window [flag]
+    #[clap(
+        long = "global",
+        group = "windows",
+        help_heading = "Windows (select one)"
+    )]
+    pub global: bool,
+
+    /// Use one window per reference sequence, e.g. per transcript [flag]
+    ///
+    /// Processes all sequences in the reference unless chromosomes are
+    /// specified.
+    #[clap(long, group = "windows", help_heading = "Windows (select one)")]
+    pub per_sequence: bool,
+
+    /// Names of chromosomes to process (comma-separated or repeated). E.g. 'chr1,chr2,chr3'.
+    ///
+    /// When no chromosomes are specified, it defaults to chr1..chr22 for
+    /// human references (those containing all of chr1..chr22) and to all
+    /// sequences in the reference otherwise.
+    #[clap(long, num_args = 1.., value_parser, value_delimiter = ',', group = "chrom_select", help_heading="Chromosome Selection (select max. one)")]
+    pub chromosomes: Option<Vec<String>>,
+
+    /// File with chromosome names to process (one per line).
+    #[arg(
+        long,
+        value_parser,
+        group = "chrom_select",
+        help_heading = "Chromosome Selection (select max. one)"
+    )]
+    pub chromosomes_file: Option<PathBuf>,
+
+    /// Process all sequences in the reference whose name matches this
+    /// regular expression, in file order [regex]
+    /// E.g. '^chr[0-9]+$' for numbered chromosomes.
+    #[arg(
+        long,
+        value_parser,
+        group = "chrom_select",
+        help_heading = "Chromosome Selection (select max. one)"
+    )]
+    pub chromosomes_regex: Option<String>,
+
+    /// Process all sequences in the reference, in file order [flag]
+    ///
+    /// Sequence names are read from the 2bit/FASTA index.
+    #[arg(
+        long,
+        group = "chrom_select",
+        help_heading = "Chromosome Selection (select max. one)"
+    )]
+    pub all_chromosomes: bool,
+
+    /// Exclude chromosomes matching any of these regular expressions
+    /// (comma-separated or repeated) [regex]
+    ///
+    /// Applied after the selection above. E.g. '_alt$,_random$,^chrUn_'.
+    #[arg(
+        long,
+        value_parser,
+        num_args = 1..,
+        value_delimiter = ',',
+        action = ArgAction::Append,
+        help_heading = "Chromosome Selection"
+    )]
+    pub exclude_chromosomes: Option<Vec<String>>,
+
+    /// Chromosome alias table [path]
+    ///
+    /// Each line lists tab-separated names of the same sequence
+    /// (e.g. 'chr1<TAB>1<TAB>NC_000001.11'), such as UCSC's `chromAlias.txt`.
+    /// Chromosome names in `--chromosomes`, BED and GTF files are translated
+    /// to the names used in the reference.
+    #[arg(long, value_parser, help_heading = "Chromosome Selection")]
+    pub chrom_alias: Option<PathBuf>,
+
+    /// Translate between UCSC and Ensembl chromosome names [flag]
+    ///
+    /// Adds or removes the 'chr' prefix and maps 'chrM' <-> 'MT' so that e.g.
+    /// a blacklist using '1' matches a reference using 'chr1'.
+    /// Entries from `--chrom-alias` take precedence.
+    #[arg(long, help_heading = "Chromosome Selection")]
+    pub chrom_alias_ucsc_ensembl: bool,
+
+    /// Skip selected chromosomes that are not in the reference [flag]
+    ///
+    /// Skipped chromosomes are reported and written to
+    /// `skipped_chromosomes.txt`. By default, missing chromosomes are an
+    /// error, raised before any counting starts.
+    #[arg(long, help_heading = "Chromosome Selection")]
+    pub skip_missing_chromosomes: bool,
+
+    /// Optional BED files of blacklisted regions [path]
+    #[clap(short = 'b', long, value_parser, num_args = 1.., action = ArgAction::Append, help_heading="Filtering")]
+    pub blacklist: Option<Vec<PathBuf>>,
+
+    /// Minimum size of blacklist intervals to load (bp) [integer]
+    #[clap(
+        long,
+        alias = "bl-min-size",
+        default_value = "1",
+        help_heading = "Filtering"
+    )]
+    pub blacklist_min_size: u64,
+
+    /// Drop windows with a larger fraction of blacklisted bases [0-1]
+    ///
+    /// Dropped windows are removed from the count matrices and `bins.bed`
+    /// and listed in `dropped_windows.bed` instead.
+    #[clap(long, requires = "blacklist", help_heading = "Filtering")]
+    pub max_blacklist_overlap: Option<f64>,
+
+    /// Split windows at blacklisted regions instead of masking them [flag]
+    ///
+    /// Each window is replaced by its sub-windows of non-blacklisted
+    /// sequence, which keep the window's `--by-bed` columns.
+    #[clap(long, requires = "blacklist", help_heading = "Filtering")]
+    pub split_at_blacklist: bool,
+
+    /// How to count soft-masked (lower-case, e.g. repeat) bases [ignore, exclude, separate]
+    ///
+    /// 'exclude' treats them as N. 'separate' counts kmers without masked
+    /// bases in the main output and kmers overlapping masked bases in
+    /// `<output-dir>/masked/`.
+    #[clap(long, value_enum, default_value = "ignore", help_heading = "Filtering")]
+    pub mask_mode: MaskMode,
+
+    /// Mask low-complexity sequence found with a DUST-like scorer [flag]
+    ///
+    /// Windows of `--dust-window` bases whose triplet score exceeds
+    /// `--dust-threshold` are masked like blacklisted regions. The masked
+    /// A/C/G/T bases of every window are reported in the `low_complexity`
+    /// column of `window_positions.tsv`.
+    #[clap(long, help_heading = "Filtering")]
+    pub mask_low_complexity: bool,
+
+    /// Window size of `--mask-low-complexity` [integer]
+    #[clap(
+        long,
+        default_value = "64",
+        value_parser = value_parser!(u64).range(4..),
+        requires = "mask_low_complexity",
+        help_heading = "Filtering"
+    )]
+    pub dust_window: u64,
+
+    /// Score above which `--mask-low-complexity` masks a window [number]
+    #[clap(
+        long,
+        default_value = "20",
+        requires = "mask_low_complexity",
+        help_heading = "Filtering"
+    )]
+    pub dust_threshold: f64,
+
+    /// Collapse each kmer with its reverse-complement. [flag]
+    ///
+    /// The lexicographically lowest kmer is used.
+    #[clap(short = 'c', long, help_heading = "Core")]
+    canonical: bool,
+
+    /// Count windows on the '-' strand on their reverse complement. [flag]
+    ///
+    /// Motif counts are then reported in the orientation of the feature.
+    /// Requires windows with a strand, i.e. `--by-bed` with a 6th (strand)
+    /// column or `--by-gtf`.
+    #[clap(long, conflicts_with = "canonical", help_heading = "Core")]
+    pub stranded: bool,
+
+    /// Strands to count kmers on [forward, both, separate]
+    ///
+    /// 'both' adds the reverse-strand counts to every kmer (without
+    /// collapsing like `--canonical`). 'separate' writes the reverse-strand
+    /// counts to `<output-dir>/reverse/`.
+    #[clap(
+        long,
+        value_enum,
+        default_value = "forward",
+        conflicts_with = "canonical",
+        help_heading = "Core"
+    )]
+    pub strand: StrandMode,
+
+    /// Convert the reference in silico before counting [CT, GA]
+    ///
+    /// 'CT' converts C to T and 'GA' converts G to A, giving k-mer
+    /// backgrounds in bisulfite space for methylation sequencing. The
+    /// conversion applies after `--vcf` variants.
+    #[clap(long, value_enum, help_heading = "Core")]
+    pub bisulfite: Option<Bisulfite>,
+
+    /// Treat sequences as circular (e.g. chrM, plasmids, viruses). [flag or list]
+    ///
+    /// K-mers wrapping around the sequence end are counted.
+    /// Without names, all sequences are circular. E.g. '--circular chrM'.
+    #[clap(long, num_args = 0.., value_delimiter = ',', help_heading = "Core")]
+    pub circular: Option<Vec<String>>,
+
+    /// Which k-mers belong to a window [within-window, starts-in-window]
+    ///
+    /// 'within-window' counts the k-mers lying entirely in the window.
+    /// 'starts-in-window' also counts k-mers running past the window end,
+    /// so every k-mer of tiling windows is counted exactly once.
+    #[clap(
+        long,
+        value_enum,
+        default_value = "within-window",
+        conflicts_with = "bed12",
+        help_heading = "Core"
+    )]
+    pub kmer_boundary: KmerBoundary,
+
+    /// Only count (w,k) minimizers instead of every k-mer [flag]
+    ///
+    /// Of every `--window-w` consecutive k-mers, only the one with the
+    /// lowest hashed code is counted (once, even when selected by several
+    /// runs). Shrinks the outputs for large k while keeping the composition.
+    #[clap(
+        long,
+        requires = "window_w",
+        conflicts_with_all = ["chunk_size", "bed12"],
+        help_heading = "Core"
+    )]
+    pub minimizers: bool,
+
+    /// Only count the k-mers starting at every Nth position [integer]
+    ///
+    /// Positions that are multiples of N (from the sequence start) are
+    /// counted, approximating the composition at a fraction of the runtime.
+    /// Effective lengths only include the counted positions; other A/C/G/T
+    /// bases are reported as `skipped` in `window_positions.tsv`.
+    #[clap(
+        long,
+        value_parser = value_parser!(u64).range(2..),
+        conflicts_with = "bed12",
+        help_heading = "Core"
+    )]
+    pub position_stride: Option<u64>,
+
+    /// Number of consecutive k-mers to select each minimizer from [integer]
+    #[clap(long, value_parser = value_parser!(u64).range(1..), requires = "minimizers", help_heading = "Core")]
+    pub window_w: Option<u64>,
+
+    /// Save counts as sparse-array. [flag]
+    ///
+    /// For large kmer-sizes, we cannot save dense arrays with all motifs
+    /// unless we have a LOT of RAM and storage space. Enable this
+    /// flag to save as a COO sparse array that can be opened in
+    /// python via `scipy.sparse.load_npz()`.
+    #[clap(long, help_heading = "Core")]
+    pub save_sparse: bool,
+
+    /// Layout of sparse arrays [coo|csr]
+    ///
+    /// 'csr' stores compressed rows (`indptr`/`indices`/`data`), which
+    /// loads faster and supports slicing windows without conversion.
+    #[clap(
+        long,
+        value_enum,
+        default_value = "coo",
+        requires = "save_sparse",
+        help_heading = "Core"
+    )]
+    pub sparse_format: SparseFormat,
+
+    /// Pack all count outputs into a single `counts_bundle.npz`. [flag]
+    ///
+    /// Matrices, motif lists, `bins.bed` etc. are stored as entries of
+    /// the archive together with a `manifest.tsv` entry, instead of
+    /// being written as separate files.
+    #[clap(long, help_heading = "Output")]
+    pub bundle: bool,
+
+    /// Element type of the `.npy`/`.npz` count arrays [u16|u32|u64|f32|f64]
+    ///
+    /// Smaller types save disk space. Writing fails if a count cannot be
+    /// stored exactly in the chosen type.
+    #[clap(long, value_enum, default_value = "u64", help_heading = "Output")]
+    pub count_dtype: CountDtype,
+
+    /// Scaling of the written counts [none|frequency|per-kb]
+    ///
+    /// 'frequency' divides by the number of counted kmers in the window
+    /// (per k). 'per-kb' gives counts per 1000 effective (non-N,
+    /// non-blacklisted) bases. Normalized values are written as f64
+    /// (f32 with `--count-dtype f32`).
+    #[clap(long, value_enum, default_value = "none", help_heading = "Output")]
+    pub normalize: Normalization,
+
+    /// Append each chromosome's rows to the outputs as soon as it is counted. [flag]
+    ///
+    /// Memory no longer grows with the number of windows. Columns always
+    /// include all (canonical) motifs of each k, rows follow the order in
+    /// which chromosomes finish (as listed in `bins.bed`) and only npy and
+    /// tsv output formats are supported.
+    #[clap(
+        long,
+        conflicts_with_all = ["global", "by_bed", "by_gtf", "by_anchors", "by_cytoband", "save_sparse", "rarefy", "bundle"],
+        help_heading = "Output"
+    )]
+    pub stream_output: bool,
+
+    /// Write the outputs of each chromosome to its own subdirectory. [flag]
+    ///
+    /// E.g. `chr1/k3_counts.npy` and `chr1/bins.bed`. All chromosomes
+    /// share the same motif columns. Characters other than ASCII letters,
+    /// digits, `.`, `_` and `-` in chromosome names are replaced by `_` in
+    /// the subdirectory names.
+    #[clap(
+        long,
+        conflicts_with_all = ["global", "stream_output", "bundle"],
+        help_heading = "Output"
+    )]
+    pub split_by_chromosome: bool,
+
+    /// Write `manifest.sha256` and `manifest.tsv` with the checksum and size of every output file. [flag]
+    ///
+    /// `manifest.sha256` is in the `sha256sum` format (`<sha256>  <file>`
+    /// with paths relative to `--output-dir`), so transfers of large
+    /// matrices can be verified with `sha256sum -c manifest.sha256` in the
+    /// output directory. `manifest.tsv` lists the `file` and its `bytes`.
+    #[clap(long, help_heading = "Output")]
+    pub checksums: bool,
+
+    /// Format of the count matrices [npy|tsv|arrow|long|hdf5]
+    ///
+    /// 'tsv' writes `k<k>_counts.tsv` tables (windows × motifs) with a
+    /// motif header row and the window coordinates as the first columns,
+    /// e.g. for reading in R or spreadsheets.
+    /// 'arrow' writes the same tables as Arrow IPC (Feather v2) files that
+    /// can be memory-mapped by pyarrow, polars and R's arrow package.
+    /// 'long' writes a single `counts_long.tsv` with one
+    /// `chrom, start, end, k, motif, count` row per non-zero count.
+    /// 'hdf5' writes a single `counts.h5` with a compressed `k<k>/counts`
+    /// matrix and `k<k>/motifs` per k and the window coordinates in
+    /// `windows/`; it requires building with `--features hdf5`.
+    #[clap(
+        long,
+        value_enum,
+        default_value = "npy",
+        conflicts_with = "save_sparse",
+        help_heading = "Output"
+    )]
+    pub output_format: OutputFormat,
+
+    /// BGZF-compress the long-format table and write a tabix index. [flag]
+    ///
+    /// Writes `counts_long.tsv.gz` and `counts_long.tsv.gz.tbi` for region
+    /// queries (e.g. `tabix counts_long.tsv.gz chr1:1-100000`). The index
+    /// is skipped with a warning when windows are not sorted.
+    #[clap(long, help_heading = "Output")]
+    pub bgzip: bool,
+
+    /// Rarefy each window to this many counts per k [integer]
+    ///
+    /// Counts are subsampled without replacement, making windows with
+    /// different numbers of valid positions comparable (e.g. for diversity
+    /// metrics). Windows with fewer counts are left unchanged.
+    /// The depth and seed are written to `rarefy.tsv`.
+    #[clap(long, value_parser = value_parser!(u64).range(1..), help_heading = "Output")]
+    pub rarefy: Option<u64>,
+
+    /// Random seed for `--rarefy` and `--shuffle-windows` [integer]
+    ///
+    /// When not specified, a random seed is drawn (and recorded).
+    #[clap(long, help_heading = "Output")]
+    pub seed: Option<u64>,
+
+    /// Also count length-matched random windows as a background [flag]
+    ///
+    /// Every window gets a random window of the same length on the same
+    /// chromosome, placed outside blacklisted regions. The background is
+    /// written to `shuffled_windows/` with the same rows as the main
+    /// outputs. Uses `--seed`.
+    #[clap(
+        long,
+        conflicts_with_all = ["global", "per_sequence", "bed12", "stream_output"],
+        help_heading = "Shuffling"
+    )]
+    pub shuffle_windows: bool,
+
+    /// TSV mapping motifs to group labels (`motif<TAB>group`) [path]
+    ///
+    /// Writes an additional `k<k>_group_counts.npy` matrix
+    /// (windows × groups) with summed counts per group.
+    /// With `--canonical`, motifs are mapped to their canonical form.
+    #[clap(long, value_parser, help_heading = "Output")]
+    pub motif_groups: Option<PathBuf>,
+
+    /// Only write the group matrices, not the full motif matrices. [flag]
+    #[clap(long, requires = "motif_groups", help_heading = "Output")]
+    pub groups_only: bool,
+
+    /// Motifs to write as bedGraph tracks, e.g. 'CG,AATAAA' [list]
+    ///
+    /// Writes `track_<motif>.bedGraph` with the count of the motif in
+    /// every window (scaled by `--normalize`). Motif lengths must be among
+    /// the kmer sizes. Convert to bigWig with UCSC `bedGraphToBigWig`.
+    #[clap(
+        long,
+        num_args = 1..,
+        value_delimiter = ',',
+        conflicts_with = "global",
+        help_heading = "Output"
+    )]
+    pub track_motifs: Vec<String>,
+
+    /// Write the k-mer diversity of every window [flag]
+    ///
+    /// Writes `k<k>_diversity.tsv` with the Shannon entropy (bits),
+    /// fraction of possible motifs observed and linguistic complexity
+    /// (observed motifs / min(possible motifs, k-mers)) of every window,
+    /// in the row order of the count matrices.
+    #[clap(long, conflicts_with = "stream_output", help_heading = "Output")]
+    pub diversity: bool,
+
+    /// Write the homopolymer and short tandem repeat content of every window [flag]
+    ///
+    /// Writes `homopolymer_runs.npy` (windows × run lengths 1 to 20+) with
+    /// the number of homopolymer runs of each length and `str_bases.npy`
+    /// (windows × unit lengths 1 to 6) with the bases in perfect tandem
+    /// repeats (at least 3 units and 12 bases), computed on the sequence
+    /// before masking. Runs are clipped to the windows.
+    #[clap(
+        long,
+        conflicts_with_all = ["global", "stream_output"],
+        help_heading = "Output"
+    )]
+    pub repeat_content: bool,
+
+    /// Scan every window with the position weight matrices in a MEME or
+    /// JASPAR file [path]
+    ///
+    /// Writes `pwm_hits.npy` (windows × PWMs) with the number of positions
+    /// scoring at least `--pwm-threshold` on either strand, `pwm_scores.npy`
+    /// with the sum of their log2-odds scores and the PWM names in
+    /// `pwm_names.txt`. Sites must lie within a window and contain no N or
+    /// blacklisted bases.
+    #[clap(
+        long,
+        value_parser,
+        conflicts_with_all = ["global", "stream_output"],
+        help_heading = "Output"
+    )]
+    pub pwms: Option<PathBuf>,
+
+    /// Relative `--pwms` score threshold between the lowest (0) and
+    /// highest (1) possible score of each PWM [float]
+    #[clap(
+        long,
+        default_value = "0.8",
+        requires = "pwms",
+        help_heading = "Output"
+    )]
+    pub pwm_threshold: f64,
+
+    /// Also write the `--global` counts as Jellyfish dumps. [flag]
+    ///
+    /// Writes `k<k>_jellyfish.txt` with `motif count` lines (as from
+    /// `jellyfish dump -c`) for every counted motif. Counts are not
+    /// affected by `--normalize`.
+    #[clap(long, requires = "global", help_heading = "Output")]
+    pub jellyfish_dump: bool,
+
+    /// Also write the k-mer spectrum of `--global` counts. [flag]
+    ///
+    /// Writes `k<k>_spectrum.tsv` with the number of distinct motifs
+    /// occurring n times as header-less `n<TAB>n_motifs` lines (as from
+    /// `jellyfish histo`, e.g. for GenomeScope).
+    #[clap(long, requires = "global", help_heading = "Output")]
+    pub spectrum: bool,
+
+    /// GTF/GFF3 file for labelling windows with overlapping features [path]
+    ///
+    /// Adds a column to `bins.bed` with the comma-separated names of
+    /// all overlapping features ('.' when there are none).
+    #[clap(long, value_parser, help_heading = "Annotation")]
+    pub annotate_gtf: Option<PathBuf>,
+
+    /// Feature type (3rd GTF column) used for annotation [string]
+    #[clap(long, default_value = "gene", help_heading = "Annotation")]
+    pub annotate_feature: String,
+
+    /// Attribute holding the feature name, e.g. 'gene_id' or 'Name' [string]
+    #[clap(long, default_value = "gene_name", help_heading = "Annotation")]
+    pub annotate_attribute: String,
+
+    /// VCF (optionally gzipped) with variants to apply to the reference [path]
+    ///
+    /// Counts k-mers of a personalized genome. SNVs (and equal-length MNVs)
+    /// are applied; records failing FILTER or with mismatching REF alleles
+    /// are skipped. Window and blacklist coordinates stay in reference space.
+    #[clap(long, value_parser, help_heading = "Variants")]
+    pub vcf: Option<PathBuf>,
+
+    /// Sample in the VCF whose non-reference alleles are applied [string]
+    ///
+    /// When not specified, the first ALT allele of every record is applied.
+    #[clap(long, requires = "vcf", help_heading = "Variants")]
+    pub vcf_sample: Option<String>,
+
+    /// Also apply small insertions and deletions from the VCF [flag]
+    #[clap(long, requires = "vcf", help_heading = "Variants")]
+    pub vcf_indels: bool,
+
+    /// All options as given or defaulted, recorded in `run_info.json`
+    #[clap(skip)]
+    pub resolved_options: Vec<(String, Vec<String>)>,
+}
+
+impl CountArgs {
+    /// The `--kmer-sizes` with ranges expanded
+    pub fn kmer_size_list(&self) -> Vec<u8> {
+        expand_kmer_sizes(&self.kmer_sizes)
+    }
+
+    /// Files and directories the run reads, or writes besides the outputs,
+    /// which the output directory must not contain
+    pub fn input_paths(&self) -> Vec<&Path> {
+        let mut paths: Vec<&Path> = [
+            &self.config,
+            &self.reference,
+            &self.ref_fasta,
+            &self.motif_list,
+            &self.exclude_motifs,
+            &self.code_cache,
+            &self.checkpoint_dir,
+            &self.spill_dir,
+            &self.progress_json,
+            &self.by_bed,
+            &self.by_gtf,
+            &self.by_anchors,
+            &self.by_cytoband,
+            &self.chromosomes_file,
+            &self.chrom_alias,
+            &self.motif_groups,
+            &self.pwms,
+            &self.annotate_gtf,
+            &self.vcf,
+        ]
+        .into_iter()
+        .flatten()
+        .map(PathBuf::as_path)
+        .collect();
+        paths.extend(self.blacklist.iter().flatten().map(PathBuf::as_path));
+        paths
+    }
+
+    /// Whether windows are read from a file (`--by-bed`, `--by-gtf`,
+    /// `--by-anchors` or `--by-cytoband`) and carry an original index
+    pub fn has_window_file(&self) -> bool {
+        self.by_bed.is_some()
+            || self.by_gtf.is_some()
+            || self.by_anchors.is_some()
+            || self.by_cytoband.is_some()
+    }
+
+    /// Whether `chr` should be treated as a circular sequence
+    pub fn is_circular(&self, chr: &str) -> bool {
+        match &self.circular {
+            Some(names) => names.is_empty() || names.iter().any(|n| n == chr),
+            None => false,
+        }
+    }
+
+    /// Returns the final chromosome list, in priority order:
+    /// 1) from `--chromosomes-file`
+    /// 2) from `--chromosomes`
+    /// 3) names in `available` matching `--chromosomes-regex`
+    /// 4) all of `available` with `--all-chromosomes`, `--per-sequence` or `--ref-fasta`
+    /// 5) `chr1`..`chr22` when the reference contains all of them (human)
+    /// 6) all of `available`
+    ///
+    /// Chromosomes matching `--exclude-chromosomes` are then removed.
+    pub fn resolve_chromosomes(&self, available: &[String]) -> anyhow::Result<Vec<String>> {
+        let mut chromosomes = self.select_chromosomes(available)?;
+        if let Some(patterns) = &self.exclude_chromosomes {
+            let exclude = RegexSet::new(patterns)
+                .context("parsing --exclude-chromosomes regular expressions")?;
+            chromosomes.retain(|chr| !exclude.is_match(chr));
+        }
+        if chromosomes.is_empty() {
+            bail!("No chromosomes left to process after selection");
+        }
+        Ok(chromosomes)
+    }
+
+    fn select_chromosomes(&self, available: &[String]) -> anyhow::Result<Vec<String>> {
+        if let Some(file) = &self.chromosomes_file {
+            let text: String = std::fs::read_to_string(file)
+                .context(format!("reading chromosome file {:?}", file))?;
+            let list: Vec<String> = text
+                .lines()
+                .map(str::trim)
+                .filter(|l| !l.is_empty() && !l.starts_with('#'))
+                .map(String::from)
+                .collect();
+            Ok(list)
+        } else if let Some(chrs) = &self.chromosomes {
+            Ok(chrs.clone())
+        } else if let Some(pattern) = &self.chromosomes_regex {
+            let re = Regex::new(pattern).context("parsing --chromosomes-regex")?;
+            Ok(available
+                .iter()
+                .filter(|chr| re.is_match(chr))
+                .cloned()
+                .collect())
+        } else if self.all_chromosomes || self.per_sequence || self.ref_fasta.is_some() {
+            Ok(available.to_vec())
+        } else {
+            let autosomes: Vec<String> = (1..=22).map(|i| format!("chr{}", i)).collect();
+            if autosomes.iter().all(|chr| available.contains(chr)) {
+                Ok(autosomes)
+            } else {
+                println!(
+                    "Reference does not contain chr1..chr22; processing all {} sequences",
+                    available.len()
+                );
+                Ok(available.to_vec())
+            }
+        }
+    }
+}
+
+fn main() {
+    // Catch and handle errors
+    // Ensures that tempfile has time to remove the tmp dir
+    if let Err(e) = run() {
+        eprintln!("{:?}", e);
+        match interrupted_by(&e) {
+            Some(interrupted) => std::process::exit(interrupted.exit_code()),
+            None => std::process::exit(1),
+        }
+    }
+    std::process::exit(0);
+}
+
+/// Values of every option of `command` in `matches`, including defaults, by option id
+fn resolved_options(command: &clap::Command, matches: &ArgMatches) -> Vec<(String, Vec<String>)> {
+    command
+        .get_arguments()
+        .filter_map(|arg| {
+            let id = arg.get_id().as_str();
+            let values = matches.try_get_raw(id).ok()??;
+            let values = values.map(|v| v.to_string_lossy().into_owned()).collect();
+            Some((id.to_string(), values))
+        })
+        .collect()
+}
+
+fn run() -> Result<()> {
+    let start_time = Instant::now();
+    let mut command = Cli::command()
+        .mut_subcommand("estimate", |estimate| {
+            estimate.mut_arg("output_dir", |arg| arg.required(false).default_value("."))
+        })
+        // Family sizes count the duplicates
+        .mut_subcommand("consensus-depth", |consensus| {
+            consensus.mut_arg("exclude_flags", |arg| arg.default_value("2820"))
+        });
+    let args = with_config_args(&command, std::env::args_os().collect())?;
+    let matches = command.clone().get_matches_from(args);
+    install_handlers()?;
+    let cli = Cli::from_arg_matches(&matches)?;
+    let options = match matches.subcommand() {
+        Some((name, sub_matches)) => {
+            resolved_options(command.find_subcommand(name).unwrap(), sub_matches)
+        }
+        None => resolved_options(&command, &matches),
+    };
+
+    match (cli.command, cli.count) {
+        (Some(Command::Query(args)), _) => return query_counts(&args),
+        (Some(Command::ValidateBed(args)), _) => return validate_bed(&args),
+        (Some(Command::Merge(args)), _) => return merge_outputs(&args),
+        (Some(Command::Aggregate(args)), _) => return aggregate_output(&args),
+        (Some(Command::Compare(args)), _) => return compare_outputs(&args),
+        (Some(Command::Motifs(args)), _) => return print_motifs(&args),
+        (Some(Command::Encode(args)), _) => return encode_motifs(&args),
+        (Some(Command::Decode(args)), _) => return decode_codes(&args),
+        (Some(Command::GenerateCompletions(args)), _) => {
+            return generate_completions(command, &args)
+        }
+        (Some(Command::GenerateMan(args)), _) => return generate_man(command, &args),
+        (Some(Command::Uniqueness(args)), _) => {
+            init_thread_pool(args.n_threads)?;
+            uniqueness_track(&args)?;
+        }
+        (Some(Command::RefKmers(opt)), _) => ref_kmers(*opt, options)?,
+        (Some(Command::Estimate(opt)), _) => ref_kmers(
+            CountArgs {
+                dry_run: true,
+                ..*opt
+            },
+            options,
+        )?,
+        (None, Some(opt)) => ref_kmers(opt, options)?,
+        (None, None) => command
+            .error(
+                ErrorKind::MissingRequiredArgument,
+                "counting options or a subcommand are required",
+            )
+            .exit(),
+        (Some(Command::FastqMers(args)), _) => {
+            init_thread_pool(args.n_threads)?;
+            fastq_mers(&args)?;
+        }
+        (Some(Command::BamMotifs(args)), _) => bam_motifs(&args)?,
+        (Some(Command::Fragsize(args)), _) => fragsize(&args)?,
+        (Some(Command::ConsensusDepth(args)), _) => consensus_depth(&args)?,
+        (Some(Command::ShuffleBackground(mut args)), _) => {
+            args.count.resolved_options = options;
+            init_thread_pool(args.count.n_threads)?;
+            let seed = args.count.seed.unwrap_or_else(rand::random);
+            // Every shuffle has the same plan
+            if args.count.dry_run {
+                return count_reference(
+                    &args.count,
+                    Some(seed.wrapping_add(1)),
+                    &ProgressLog::disabled(),
+                );
+            }
+            let progress = ProgressLog::open(args.count.progress_json.as_deref())?;
+            let staged = StagedOutputDir::new(
+                &args.count.output_dir,
+                args.count.force,
+                &args.count.input_paths(),
+            )?;
+            args.count.output_dir = staged.path().to_path_buf();
+            let mut info = File::create(args.count.output_dir.join("shuffle.tsv"))
+                .context("Create shuffle info fail")?;
+            writeln!(info, "n_shuffles\t{}\nseed\t{}", args.n_shuffles, seed)
+                .context("Write shuffle info fail")?;
+            for i in 1..=args.n_shuffles {
+                println!("Start: Shuffled background {}/{}", i, args.n_shuffles);
+                progress.stage(&format!("shuffle_{}", i));
+                let mut opt = args.count.clone();
+                opt.output_dir = args.count.output_dir.join(format!("shuffle_{}", i));
+                count_reference(&opt, Some(seed.wrapping_add(i)), &progress)?;
+            }
+            if args.count.checksums {
+                write_checksum_manifest(&args.count.output_dir)?;
+            }
+            staged.commit()?;
+            progress.finish();
+        }
+    }
+
+    // Print summary statistics and execution time
+    let elapsed = start_time.elapsed();
+    println!("Elapsed time: {:.2?}", elapsed);
+    Ok(())
+}
+
+/// Count the reference kmers of the windows into a staged output directory
+fn ref_kmers(mut opt: CountArgs, options: Vec<(String, Vec<String>)>) -> Result<()> {
+    opt.resolved_options = options;
+    init_thread_pool(opt.n_threads)?;
+    if opt.dry_run {
+        return count_reference(&opt, None, &ProgressLog::disabled());
+    }
+    let progress = ProgressLog::open(opt.progress_json.as_deref())?;
+    let staged = StagedOutputDir::new(&opt.output_dir, opt.force, &opt.input_paths())?;
+    opt.output_dir = staged.path().to_path_buf();
+    count_reference(&opt, None, &progress)?;
+    if opt.checksums {
+        write_checksum_manifest(&opt.output_dir)?;
+    }
+    staged.commit()?;
+    progress.finish();
+    Ok(())
+}
+
+/// Loaded inputs of a run, for `--dry-run`
+struct DryRunPlan<'a> {
+    chromosomes: &'a [String],
+    chrom_lengths: &'a [u64],
+    windows_map: Option<&'a WindowMap>,
+    blacklist_map: &'a HashMap<String, Vec<(u64, u64)>>,
+    kmer_specs: &'a BTreeMap<u8, KmerSpec>,
+    motif_list: Option<&'a MotifList>,
+    motif_groups: Option<&'a MotifGroups>,
+    excluded_motifs: Option<&'a HashSet<String>>,
+}
+
+/// Print the windows per chromosome, motif columns and estimated array
+/// sizes per k and the estimated peak memory of a run
+fn print_dry_run_plan(opt: &CountArgs, plan: &DryRunPlan) {
+    println!("Dry run: the inputs are valid; nothing is counted or written");
+    // Length-matched random windows double every window set
+    let copies = if opt.shuffle_windows { 2 } else { 1 };
+    let mut set_windows: Vec<Vec<u64>> = Vec::new();
+    println!("chrom\tlength\twindows");
+    for (chr, &len) in plan.chromosomes.iter().zip(plan.chrom_lengths) {
+        let windows = plan
+            .windows_map
+            .and_then(|m| m.get(chr).map(|v| v.as_slice()));
+        let blacklist = plan
+            .blacklist_map
+            .get(chr)
+            .map_or(&[][..], |v| v.as_slice());
+        let sets = chrom_window_sets(opt, len, windows, blacklist);
+        set_windows.resize(sets.len(), Vec::new());
+        for (lens, set) in set_windows.iter_mut().zip(&sets) {
+            lens.extend(set.iter().map(|&(s, e, _)| e.min(len).saturating_sub(s)));
+        }
+        let n: usize = sets.iter().map(Vec::len).sum();
+        println!("{}\t{}\t{}", chr, len, n * copies);
+    }
+    let n_rows: u64 = set_windows
+        .iter()
+        .map(|lens| lens.len() as u64)
+        .sum::<u64>()
+        * copies as u64;
+    println!(
+        "Windows: {} in {} window set(s)",
+        n_rows,
+        set_windows.len() * copies
+    );
+
+    // Counts of a window are at most its length
+    let max_len = set_windows.iter().flatten().copied().max().unwrap_or(0);
+    let fitting_dtype = match max_len {
+        len if len <= u16::MAX as u64 => "u16",
+        len if len <= u32::MAX as u64 => "u32",
+        _ => "u64",
+    };
+    println!(
+        "Longest window: {} bp; its counts fit in --count-dtype {}",
+        max_len, fitting_dtype
+    );
+
+    let dtype_bytes = opt.count_dtype.bytes();
+    println!("k\trows\tcolumns\tmax_nonzero\tdense_bytes\tsparse_bytes");
+    let mut largest_dense = 0;
+    for &k in plan.kmer_specs.keys() {
+        let columns = if let Some(list) = plan.motif_list {
+            list.columns.get(&k).map_or(0, |c| c.len() as u64)
+        } else if let Some(groups) = plan.motif_groups {
+            groups.labels_for_k(k as usize).len() as u64
+        } else {
+            let excluded = plan
+                .excluded_motifs
+                .map_or(0, |e| e.iter().filter(|m| m.len() == k as usize).count());
+            (n_possible_motifs(k, opt.canonical) as u64).saturating_sub(excluded as u64)
+        };
+        // Saturating, as dense arrays of large k are out of reach anyway
+        let cells = n_rows.saturating_mul(columns);
+        largest_dense = largest_dense.max(cells.saturating_mul(8));
+        // At most one non-zero per k-mer position and column
+        let max_nonzero: u64 = set_windows
+            .iter()
+            .flatten()
+            .map(|&len| (len + 1).saturating_sub(k as u64).min(columns))
+            .sum::<u64>()
+            * copies as u64;
+        // Row and column indices of 8 bytes per non-zero
+        let sparse = max_nonzero * (16 + dtype_bytes);
+        let dense = cells.saturating_mul(dtype_bytes);
+        println!(
+            "{}\t{}\t{}\t{}\t{}\t{}",
+            k, n_rows, columns, max_nonzero, dense, sparse
+        );
+    }
+
+    let counting = estimate_peak_chrom_bytes(
+        plan.chrom_lengths,
+        plan.kmer_specs,
+        opt.n_threads,
+        opt.max_memory.map(|gb| (gb * 1e9) as u64),
+    );
+    let window_counts =
+        estimate_window_counts_bytes(set_windows.iter().flatten().copied(), plan.kmer_specs)
+            * copies as u64;
+    let matrix = if opt.save_sparse { 0 } else { largest_dense };
+    println!(
+        "Estimated peak memory: {:.2} GB",
+        counting
+            .saturating_add(window_counts)
+            .saturating_add(matrix) as f64
+            / 1e9
+    );
+}
+
+/// Print the problems of a window BED and write the fixed windows
+fn validate_bed(args: &ValidateBedArgs) -> Result<()> {
+    let source = open_sequence_source(&args.reference, args.ref_format)?;
+    let names = source.names();
+    let (mut windows, columns) = load_windows_with_columns(&args.bed, &names, None)?;
+    let chrom_lengths = names
+        .iter()
+        .map(|chr| Ok((chr.clone(), source.length(chr)?)))
+        .collect::<Result<HashMap<_, _>>>()?;
+
+    let problems = validate_windows(&windows, &chrom_lengths);
+    let mut out = BufWriter::new(std::io::stdout().lock());
+    write_window_problems(&mut out, &problems)?;
+    out.flush()?;
+    if problems.is_empty() {
+        eprintln!("No window issues found");
+    } else {
+        eprintln!(
+            "Found {} window issue(s): {}",
+            problems.len(),
+            summarize_window_problems(&problems)
+        );
+    }
+
+    if args.clamp {
+        let removed = clamp_windows(&mut windows, &chrom_lengths);
+        eprintln!("Removed {} empty window(s)", removed);
+    }
+    if args.merge {
+        let merged = merge_overlapping_windows(&mut windows);
+        eprintln!("Merged {} overlapping window(s)", merged);
+    }
+    if let Some(path) = &args.output {
+        // Back in original order
+        let mut rows: Vec<(&String, u64, u64, u64)> = windows
+            .iter()
+            .flat_map(|(chr, wins)| wins.iter().map(move |&(s, e, idx)| (chr, s, e, idx)))
+            .collect();
+        rows.sort_unstable_by_key(|&(_, s, _, idx)| (idx, s));
+        let mut writer =
+            BufWriter::new(File::create(path).context(format!("Creating {:?}", path))?);
+        for (chr, start, end, idx) in rows {
+            write!(writer, "{}\t{}\t{}", chr, start, end).context("Write bed line fail")?;
+            for value in &columns.values[idx as usize] {
+                write!(writer, "\t{}", value).context("Write bed line fail")?;
+            }
+            writeln!(writer).context("Write bed line fail")?;
+        }
+        writer.flush().context("Write bed line fail")?;
+    }
+    Ok(())
+}
+
+/// Count the k-mers of the reference and write their uniqueness track
+fn uniqueness_track(args: &UniquenessArgs) -> Result<()> {
+    let source = open_sequence_source(&args.reference, args.ref_format)?;
+    let names = source.names();
+    let kmer_specs = build_kmer_specs(&[args.k])?;
+    let spec = &kmer_specs[&args.k];
+
+    println!("Start: Counting k-mers genome-wide");
+    let frequencies = names
+        .par_iter()
+        .map(|chr| -> Result<KmerFrequencies> {
+            let mut frequencies = KmerFrequencies::default();
+            add_kmer_frequencies(&mut frequencies, &source.read(chr)?, spec);
+            Ok(frequencies)
+        })
+        .try_reduce(KmerFrequencies::default, |a, b| {
+            let (mut a, b) = if a.len() < b.len() { (b, a) } else { (a, b) };
+            for (code, count) in b {
+                let total = a.entry(code).or_insert(0);
+                *total = total.saturating_add(count);
+            }
+            Ok(a)
+        })?;
+
+    println!("Start: Writing uniqueness scores to {:?}", args.output);
+    let mut writer =
+        BufWriter::new(File::create(&args.output).context(format!("Creating {:?}", args.output))?);
+    for chr in &names {
+        let scores = uniqueness_scores(&source.read(chr)?, spec, &frequencies);
+        let intervals = match args.window_size {
+            Some(size) => window_means(&scores, size),
+            None => score_runs(&scores),
+        };
+        for (start, end, score) in intervals {
+            writeln!(writer, "{}\t{}\t{}\t{}", chr, start, end, score)
+                .context("Write bedGraph line fail")?;
+        }
+    }
+    writer.flush().context("Write bedGraph line fail")?;
+    Ok(())
+}
+
+/// Print the completion script of `args.shell` for `command`
+fn generate_completions(mut command: clap::Command, args: &GenerateCompletionsArgs) -> Result<()> {
+    clap_complete::generate(
+        args.shell,
+        &mut command,
+        "reference",
+        &mut std::io::stdout(),
+    );
+    Ok(())
+}
+
+/// Print the man page of `command` or write the pages of it and its
+/// subcommands to `args.output_dir`
+fn generate_man(command: clap::Command, args: &GenerateManArgs) -> Result<()> {
+    match &args.output_dir {
+        Some(dir) => {
+            create_dir_all(dir).context(format!("Creating {:?}", dir))?;
+            clap_mangen::generate_to(command, dir)
+                .context(format!("Write man pages to {:?} fail", dir))
+        }
+        None => clap_mangen::Man::new(command)
+            .render(&mut std::io::stdout())
+            .context("Write man page fail"),
+    }
+}
+
+/// Write the k-mer counts of every input (one row each) with the input
+/// list to `output_dir`
+fn write_read_kmer_counts(
+    counts: &[FxHashMap<Kmer, BigCount>],
+    kmer_specs: &BTreeMap<u8, KmerSpec>,
+    io: &IOArgs,
+    output_dir: &Path,
+) -> Result<()> {
+    let (prepared, motifs_by_k) = prepare_code_counts(counts, false, kmer_specs);
+    write_decoded_counts_matrix(
+        &prepared,
+        &motifs_by_k,
+        output_dir,
+        None,
+        &CountWriteOptions::counts(),
+    )?;
+    write_lines(&output_dir.join("inputs.txt"), &io.input_names())
+}
+
+/// Count the k-mers of the reads of every FASTQ file
+fn fastq_mers(args: &FastqMersArgs) -> Result<()> {
+    let kmer_specs = build_kmer_specs(&expand_kmer_sizes(&args.kmer_sizes))?;
+    let filter = args.filtering.filter();
+    let staged = StagedOutputDir::new(&args.io.output_dir, args.io.force, &args.io.input_paths())?;
+    let mut counts = Vec::with_capacity(args.io.input.len());
+    let mut seq = Vec::new();
+    for path in &args.io.input {
+        let mut reader = FastqReader::open(path)?;
+        let mut counter = ReadKmerCounter::all_kmers(&kmer_specs);
+        let (mut n_reads, mut n_kept) = (0u64, 0u64);
+        while reader
+            .read_seq(&mut seq)
+            .context(format!("Reading {:?}", path))?
+        {
+            n_reads += 1;
+            if n_reads.is_multiple_of(1 << 20) {
+                check_interrupted()?;
+            }
+            if filter.keeps_length(seq.len()) {
+                n_kept += 1;
+                counter.add_read(&seq);
+            }
+        }
+        println!("Counted {} of {} reads of {:?}", n_kept, n_reads, path);
+        counts.push(counter.finish());
+    }
+    write_read_kmer_counts(&counts, &kmer_specs, &args.io, staged.path())?;
+    staged.commit()?;
+    Ok(())
+}
+
+/// Count the fragment end motifs of the reads of every BAM file
+fn bam_motifs(args: &BamMotifsArgs) -> Result<()> {
+    let kmer_specs = build_kmer_specs(&expand_kmer_sizes(&args.kmer_sizes))?;
+    let width = kmer_specs.keys().max().copied().unwrap_or(1) as usize;
+    let filter = args.filtering.filter();
+    let staged = StagedOutputDir::new(&args.io.output_dir, args.io.force, &args.io.input_paths())?;
+    let mut counts = Vec::with_capacity(args.io.input.len());
+    for path in &args.io.input {
+        let mut counter = ReadKmerCounter::read_starts(&kmer_specs);
+        let (mut n_reads, mut n_kept) = (0u64, 0u64);
+        for record in BamReader::open(path)? {
+            let record = record.context(format!("Reading {:?}", path))?;
+            n_reads += 1;
+            if n_reads.is_multiple_of(1 << 20) {
+                check_interrupted()?;
+            }
+            if filter.keeps(&record) {
+                n_kept += 1;
+                counter.add_read_start(&fragment_end(&record, width));
+            }
+        }
+        println!(
+            "Counted the end motifs of {} of {} reads of {:?}",
+            n_kept, n_reads, path
+        );
+        counts.push(counter.finish());
+    }
+    write_read_kmer_counts(&counts, &kmer_specs, &args.io, staged.path())?;
+    staged.commit()?;
+    Ok(())
+}
+
+/// Write the fragment length histogram of every BAM file
+fn fragsize(args: &FragsizeArgs) -> Result<()> {
+    let filter = args.filtering.filter();
+    let staged = StagedOutputDir::new(&args.io.output_dir, args.io.force, &args.io.input_paths())?;
+    let mut histograms = Vec::with_capacity(args.io.input.len());
+    for (path, name) in args.io.input.iter().zip(args.io.input_names()) {
+        let (lengths, n_longer) =
+            fragment_lengths(BamReader::open(path)?, &filter, args.max_fragment_length)
+                .context(format!("Reading {:?}", path))?;
+        if n_longer > 0 {
+            eprintln!(
+                "Warning: {} fragments of {:?} are longer than --max-fragment-length {} and were not counted",
+                n_longer, path, args.max_fragment_length
+            );
+        }
+        check_interrupted()?;
+        histograms.push((name, lengths));
+    }
+    write_histograms(
+        &staged.path().join("fragment_lengths.tsv"),
+        "length",
+        &histograms,
+    )?;
+    staged.commit()?;
+    Ok(())
+}
+
+/// Write the duplicate family size histogram of every BAM file
+fn consensus_depth(args: &ConsensusDepthArgs) -> Result<()> {
+    let filter = args.filtering.filter();
+    let staged = StagedOutputDir::new(&args.io.output_dir, args.io.force, &args.io.input_paths())?;
+    let mut histograms = Vec::with_capacity(args.io.input.len());
+    for (path, name) in args.io.input.iter().zip(args.io.input_names()) {
+        let sizes =
+            family_sizes(BamReader::open(path)?, &filter).context(format!("Reading {:?}", path))?;
+        check_interrupted()?;
+        histograms.push((name, sizes));
+    }
+    write_histograms(
+        &staged.path().join("family_sizes.tsv"),
+        "family_size",
+        &histograms,
+    )?;
+    staged.commit()?;
+    Ok(())
+}
+
+/// Print the motif columns of every k in matrix order
+fn print_motifs(args: &MotifsArgs) -> Result<()> {
+    let kmer_sizes = expand_kmer_sizes(&args.kmer_sizes);
+    let kmer_specs = build_kmer_specs(&kmer_sizes)?;
+    let motif_list = if args.sbs96 {
+        if kmer_sizes != [3] {
+            bail!("--sbs96 requires --kmer-sizes 3");
+        }
+        Some(sbs96_motifs(args.canonical))
+    } else {
+        args.motif_list
+            .as_ref()
+            .map(|path| load_motif_list(path, args.canonical))
+            .transpose()?
+    };
+    let motifs_by_k = match motif_list {
+        Some(list) => {
+            if let Some(k) = kmer_sizes.iter().find(|k| !list.columns.contains_key(k)) {
+                bail!("--motif-list has no motifs of k-mer size {}", k);
+            }
+            if let Some((k, motifs)) = list
+                .columns
+                .iter()
+                .find(|(k, _)| !kmer_specs.contains_key(k))
+            {
+                bail!(
+                    "Motif {} in --motif-list has length {}, which is not among --kmer-sizes",
+                    motifs[0],
+                    k
+                );
+            }
+            list.columns
+        }
+        None => motif_universe(&kmer_specs, args.canonical),
+    };
+    let excluded: Option<HashSet<String>> = args
+        .exclude_motifs
+        .as_ref()
+        .map(|path| load_motif_list(path, args.canonical))
+        .transpose()?
+        .map(|list| list.concrete_by_k().into_values().flatten().collect());
+    let motifs_by_k = without_excluded_motifs(motifs_by_k, excluded.as_ref());
+
+    let mut out = BufWriter::new(std::io::stdout().lock());
+    writeln!(out, "k\tcolumn\tmotif")?;
+    for (k, motifs) in &motifs_by_k {
+        for (column, motif) in motifs.iter().enumerate() {
+            writeln!(out, "{}\t{}\t{}", k, column, motif)?;
+        }
+    }
+    out.flush()?;
+    Ok(())
+}
+
+/// Print the codes of motifs
+fn encode_motifs(args: &EncodeArgs) -> Result<()> {
+    let spec = build_kmer_specs(&[args.k])?.remove(&args.k).unwrap();
+    let codes = args
+        .motifs
+        .iter()
+        .map(|motif| spec.encode_kmer(motif))
+        .collect::<Result<Vec<u64>>>()?;
+    print_code_rows(&spec, &codes)
+}
+
+/// Print the motifs of codes
+fn decode_codes(args: &DecodeArgs) -> Result<()> {
+    let spec = build_kmer_specs(&[args.k])?.remove(&args.k).unwrap();
+    for &code in &args.codes {
+        if !spec.is_kmer_code(code) && spec.sentinel_meaning(code).is_none() {
+            bail!("Code {} is not a k-mer code of k={}", code, args.k);
+        }
+    }
+    print_code_rows(&spec, &args.codes)
+}
+
+/// Print `k, motif, code, sentinel` rows, after the codec on stderr
+fn print_code_rows(spec: &KmerSpec, codes: &[u64]) -> Result<()> {
+    eprintln!(
+        "k={}: {:?} codes in {:?}; sentinels: {} (no full k-mer), {} (k-mer with N)",
+        spec.k,
+        spec.encoding(),
+        spec.width(),
+        spec.sentinel_none(),
+        spec.sentinel_n()
+    );
+    let mut out = BufWriter::new(std::io::stdout().lock());
+    writeln!(out, "k\tmotif\tcode\tsentinel")?;
+    for &code in codes {
+        let (motif, sentinel) = match spec.sentinel_meaning(code) {
+            Some(meaning) => (".".to_string(), meaning),
+            None => (spec.decode_kmer(code), "."),
+        };
+        writeln!(out, "{}\t{}\t{}\t{}", spec.k, motif, code, sentinel)?;
+    }
+    out.flush()?;
+    Ok(())
+}
+
+/// Sum the count matrices of several output directories
+fn merge_outputs(args: &MergeArgs) -> Result<()> {
+    let inputs: Vec<&Path> = args.inputs.iter().map(PathBuf::as_path).collect();
+    let staged = StagedOutputDir::new(&args.output_dir, args.force, &inputs)?;
+    let merged = merge_runs(&args.inputs, staged.path(), args.count_dtype)?;
+    staged.commit()?;
+    println!(
+        "Merged {} matrices of {} runs into {:?}",
+        merged.len(),
+        args.inputs.len(),
+        args.output_dir
+    );
+    Ok(())
+}
+
+/// Sum the windows of an output directory into coarser bins
+fn aggregate_output(args: &AggregateArgs) -> Result<()> {
+    let targets = match (&args.target_bed, args.bin_size) {
+        (Some(bed), _) => TargetBins::Regions(read_regions(bed)?),
+        (None, Some(size)) => TargetBins::Size(size),
+        (None, None) => bail!("Either --bin-size or --target-bed is required"),
+    };
+    let mut inputs = vec![args.input.as_path()];
+    inputs.extend(args.target_bed.as_deref());
+    let staged = StagedOutputDir::new(&args.output_dir, args.force, &inputs)?;
+    let summary = aggregate_run(&args.input, staged.path(), &targets, args.count_dtype)?;
+    staged.commit()?;
+    if summary.n_unassigned > 0 {
+        eprintln!(
+            "Warning: {} of {} windows are not fully contained in any bin and were left out",
+            summary.n_unassigned, summary.n_windows
+        );
+    }
+    println!(
+        "Aggregated {} windows into {} bins for {} matrices in {:?}",
+        summary.n_windows - summary.n_unassigned,
+        summary.n_bins,
+        summary.matrices.len(),
+        args.output_dir
+    );
+    Ok(())
+}
+
+/// Compare the count matrices of two output directories
+fn compare_outputs(args: &CompareArgs) -> Result<()> {
+    if !(args.pseudocount.is_finite() && args.pseudocount >= 0.0) {
+        bail!("--pseudocount must be a non-negative number");
+    }
+    let staged = StagedOutputDir::new(&args.output_dir, args.force, &[&args.a, &args.b])?;
+    let (comparisons, skipped) = compare_runs(&args.a, &args.b, staged.path(), args.pseudocount)?;
+    staged.commit()?;
+    if !skipped.is_empty() {
+        eprintln!(
+            "Warning: Skipped matrices found in only one run: {}",
+            skipped.join(", ")
+        );
+    }
+    for c in &comparisons {
+        println!(
+            "{}: {} of {} cells differ (total {} vs. {}, pearson {:.4})",
+            c.matrix,
+            c.n_changed,
+            c.n_windows * c.n_motifs,
+            c.total_a,
+            c.total_b,
+            c.pearson
+        );
+    }
+    Ok(())
+}
+
+/// Print the rows of a tabix-indexed long-format table overlapping a region
+fn query_counts(args: &QueryArgs) -> Result<()> {
+    let path = if args.input.is_dir() {
+        args.input.join("counts_long.tsv.gz")
+    } else {
+        args.input.clone()
+    };
+    let (chrom, beg, end) = parse_region(&args.region)?;
+    let ks: Vec<String> = args.k.iter().map(u8::to_string).collect();
+    let mut out = BufWriter::new(std::io::stdout().lock());
+    if args.header {
+        let mut header = String::new();
+        BufReader::new(MultiGzDecoder::new(
+            File::open(&path).context(format!("Opening {:?}", path))?,
+        ))
+        .read_line(&mut header)
+        .context(format!("Reading {:?}", path))?;
+        write!(out, "{}", header)?;
+    }
+    query_lines(&path, &chrom, beg, end, |line| {
+        if !ks.is_empty() && !ks.iter().any(|k| line.split('\t').nth(3) == Some(k)) {
+            return Ok(());
+        }
+        writeln!(out, "{}", line).context("Writing query output")
+    })?;
+    out.flush().context("Writing query output")
+}
+
+/// Configure global thread‐pool size
+fn init_thread_pool(n_threads: usize) -> Result<()> {
+    rayon::ThreadPoolBuilder::new()
+        .num_threads(n_threads)
+        .build_global()
+        .context("building Rayon thread pool")
+}
+
+/// Run `task` on the index of every chromosome in parallel and return the
+/// results in chromosome order.
+///
+/// Longest chromosomes are started first so they don't finish last on one
+/// busy thread while the others idle. With `--max-memory`, chromosomes only
+/// start once their estimated memory fits the budget.
+fn run_chromosomes<T: Send>(
+    opt: &CountArgs,
+    chrom_lengths: &[u64],
+    kmer_specs: &BTreeMap<u8, KmerSpec>,
+    task: impl Fn(usize) -> Result<T> + Sync,
+) -> Result<Vec<T>> {
+    let mut order: Vec<usize> = (0..chrom_lengths.len()).collect();
+    order.sort_by_key(|&idx| std::cmp::Reverse(chrom_lengths[idx]));
+    // Chromosomes in progress finish (and store their checkpoints) on SIGINT/SIGTERM
+    let task = |idx: usize| -> Result<(usize, T)> {
+        check_interrupted()?;
+        Ok((idx, task(idx)?))
+    };
+    let mut results: Vec<(usize, T)> = match opt.max_memory {
+        Some(gb) => MemoryBudget::new((gb * 1e9) as u64).run(
+            order,
+            |idx| estimate_chrom_bytes(chrom_lengths[idx], kmer_specs),
+            opt.n_threads,
+            task,
+        )?,
+        None => order
+            .into_iter()
+            .par_bridge()
+            .map(task)
+            .collect::<Result<_>>()?, // short-circuits on the first Err
+    };
+    results.sort_unstable_by_key(|(idx, _)| *idx);
+    Ok(results.into_iter().map(|(_, r)| r).collect())
+}
+
+/// Count kmers for all windows and write the outputs
+///
+/// * `shuffle_seed` - When given, window sequences are dinucleotide-shuffled first
+/// * `progress` - Receives the `--progress-json` events
+fn count_reference(
+    opt: &CountArgs,
+    shuffle_seed: Option<u64>,
+    progress: &ProgressLog,
+) -> Result<()> {
+    if opt.output_format == OutputFormat::Hdf5 && !cfg!(feature = "hdf5") {
+        bail!("--output-format hdf5 requires HDF5 support; rebuild with `cargo install --features hdf5`");
+    }
+    let run_start = Instant::now();
+    let profiler = Profiler::new(opt.profile);
+    progress.stage("load");
+    // Multi-FASTA references are read once and shared between threads
+    let source: Box<dyn SequenceSource> = if let Some(fasta) = &opt.ref_fasta {
+        println!("Start: Loading FASTA records");
+        Box::new(InMemoryFastaSource::read(fasta)?)
+    } else {
+        let path = opt.reference.as_ref().unwrap();
+        open_sequence_source(path, opt.ref_format)?
+    };
+    let reference_names = source.names();
+    let aliases = if opt.chrom_alias.is_some() || opt.chrom_alias_ucsc_ensembl {
+        let mut aliases = match &opt.chrom_alias {
+            Some(path) => load_chrom_aliases(path, &reference_names)?,
+            None => ChromAliases::default(),
+        };
+        if opt.chrom_alias_ucsc_ensembl {
+            aliases.add_ucsc_ensembl(&reference_names);
+        }
+        Some(aliases)
+    } else {
+        None
+    };
+    // Names given twice, or as aliases of each other, are counted once
+    let mut chromosomes = aliases
+        .as_ref()
+        .unwrap_or(&ChromAliases::default())
+        .resolve_all(&opt.resolve_chromosomes(&reference_names)?);
+
+    // Check for missing chromosomes before any counting starts
+    let in_ref: HashSet<&str> = reference_names.iter().map(String::as_str).collect();
+    let missing: Vec<String> = chromosomes
+        .iter()
+        .filter(|chr| !in_ref.contains(chr.as_str()))
+        .cloned()
+        .collect();
+    if !missing.is_empty() && !opt.skip_missing_chromosomes {
+        bail!(
+            "{} chromosome(s) not found in the reference: {} \
+             (use --skip-missing-chromosomes to skip them)",
+            missing.len(),
+            missing.join(", ")
+        );
+    }
+    chromosomes.retain(|chr| in_ref.contains(chr.as_str()));
+    if chromosomes.is_empty() {
+        bail!("None of the selected chromosomes are in the reference");
+    }
+    let pb = Arc::new(ProgressBar::new(chromosomes.len() as u64));
+    pb.set_style(
+        ProgressStyle::default_bar()
+            .template("       {bar:40} {pos}/{len} [{elapsed_precise}] {msg}")
+            .unwrap(),
+    );
+
+    // Every shuffled background gets its own checkpoints
+    let checkpoint_dir = opt.checkpoint_dir.as_ref().map(|dir| match shuffle_seed {
+        Some(seed) => dir.join(format!("shuffle_{seed}")),
+        None => dir.clone(),
+    });
+    // Create output directory
+    if !opt.dry_run {
+        create_dir_all(&opt.output_dir).context("Cannot create output_dir")?;
+        if let Some(dir) = &opt.code_cache {
+            create_dir_all(dir).context("Cannot create code_cache directory")?;
+        }
+        if let Some(dir) = &checkpoint_dir {
+            create_dir_all(dir).context("Cannot create checkpoint directory")?;
+        }
+    }
+    if !missing.is_empty() {
+        eprintln!(
+            "Warning: Skipping {} chromosome(s) not found in the reference: {}",
+            missing.len(),
+            missing.join(", ")
+        );
+    }
+    if !missing.is_empty() && !opt.dry_run {
+        let mut text = missing.join("\n");
+        text.push('\n');
+        std::fs::write(opt.output_dir.join("skipped_chromosomes.txt"), text)
+            .context("writing skipped_chromosomes.txt")?;
+    }
+
+    // Load blacklist intervals if provided
+    let blacklist_map = if let Some(beds) = &opt.blacklist {
+        println!("Start: Loading blacklists");
+        load_blacklists(beds, opt.blacklist_min_size, &chromosomes, aliases.as_ref())?
+    } else {
+        HashMap::new()
+    };
+
+    // Anchor positions per chromosome for `--anchor-profile`
+    let mut anchor_positions: HashMap<String, Vec<u64>> = HashMap::new();
+    let (windows_map, bed_columns) = if let Some(bed) = &opt.by_bed {
+        println!("Start: Loading window coordinates");
+        let (mut windows, columns) =
+            load_windows_with_columns(bed, &chromosomes, aliases.as_ref())?;
+        let chrom_lengths = chromosomes
+            .iter()
+            .map(|chr| Ok((chr.clone(), source.length(chr)?)))
+            .collect::<Result<HashMap<_, _>>>()?;
+        check_bed_windows(&mut windows, &chrom_lengths, opt)?;
+        (Some(windows), Some(columns))
+    } else if let Some(gtf) = &opt.by_gtf {
+        println!("Start: Loading annotation windows");
+        let records = load_gtf(gtf, opt.feature.gtf_type(), &chromosomes, aliases.as_ref())?;
+        let flank = (opt.feature == GtfFeature::Promoter).then_some(opt.promoter_flank);
+        let (windows, columns) = feature_windows(&records, &chromosomes, &opt.feature_id, flank);
+        if columns.values.is_empty() {
+            bail!(
+                "No {} features on the selected chromosomes in {:?}",
+                opt.feature.gtf_type(),
+                gtf
+            );
+        }
+        (Some(windows), Some(columns))
+    } else if let Some(path) = &opt.by_anchors {
+        println!("Start: Loading anchor positions");
+        let anchors = load_anchors(path, &chromosomes, aliases.as_ref())?;
+        if anchors.is_empty() {
+            bail!("No anchors on the selected chromosomes in {:?}", path);
+        }
+        let (windows, columns) = anchor_windows(&anchors, &chromosomes, opt.flank);
+        if opt.anchor_profile {
+            anchor_positions = anchors_by_chrom(&anchors);
+        }
+        (Some(windows), Some(columns))
+    } else if let Some(path) = &opt.by_cytoband {
+        println!("Start: Loading cytobands");
+        let bands = load_cytobands(path, &chromosomes, aliases.as_ref())?;
+        let (windows, columns) = cytoband_windows(&bands, &chromosomes, opt.cytoband_level);
+        if columns.values.is_empty() {
+            bail!("No cytobands on the selected chromosomes in {:?}", path);
+        }
+        (Some(windows), Some(columns))
+    } else {
+        (None, None)
+    };
+
+    let blocks = match (&windows_map, &bed_columns) {
+        (Some(windows), Some(columns)) if opt.bed12 => {
+            if opt.mask_mode == MaskMode::Separate {
+                bail!("--bed12 cannot be combined with --mask-mode separate");
+            }
+            bed12_blocks(windows, columns)?
+        }
+        _ => Vec::new(),
+    };
+
+    // Original indices of '-' strand windows to count on the reverse complement
+    let minus_strand = if opt.stranded {
+        bed_columns
+            .as_ref()
+            .and_then(BedColumns::minus_strand)
+            .context("--stranded requires windows with a strand column (--by-bed with 6 columns or --by-gtf)")?
+    } else {
+        Vec::new()
+    };
+
+    let feature_map = if let Some(gtf) = &opt.annotate_gtf {
+        println!("Start: Loading annotation features");
+        let records = load_gtf(gtf, &opt.annotate_feature, &chromosomes, aliases.as_ref())?;
+        Some(build_feature_intervals(&records, &opt.annotate_attribute))
+    } else {
+        None
+    };
+
+    let motif_list = if opt.sbs96 {
+        if opt.kmer_size_list() != [3] {
+            bail!("--sbs96 requires --kmer-sizes 3");
+        }
+        Some(sbs96_motifs(opt.canonical))
+    } else {
+        opt.motif_list
+            .as_ref()
+            .map(|path| load_motif_list(path, opt.canonical))
+            .transpose()?
+    };
+
+    let excluded_motifs = opt
+        .exclude_motifs
+        .as_ref()
+        .map(|path| load_motif_list(path, opt.canonical))
+        .transpose()?
+        .map(|list| list.concrete_by_k());
+
+    let motif_groups = opt
+        .motif_groups
+        .as_ref()
+        .map(|path| load_motif_groups(path, opt.canonical))
+        .transpose()?;
+
+    let variants_map = if let Some(vcf) = &opt.vcf {
+        println!("Start: Loading variants");
+        load_vcf(
+            vcf,
+            &chromosomes,
+            opt.vcf_sample.as_deref(),
+            opt.vcf_indels,
+            aliases.as_ref(),
+        )?
+    } else {
+        HashMap::new()
+    };
+
+    let kmer_specs: BTreeMap<u8, KmerSpec> = build_kmer_specs(&opt.kmer_size_list())?;
+    let listed_codes = match &motif_list {
+        Some(list) => {
+            for (k, motifs) in &list.columns {
+                if !kmer_specs.contains_key(k) {
+                    bail!(
+                        "Motif {} in --motif-list has length {}, which is not among --kmer-sizes",
+                        motifs[0],
+                        k
+                    );
+                }
+            }
+            if let Some(k) = opt
+                .kmer_size_list()
+                .iter()
+                .find(|k| !list.columns.contains_key(k))
+            {
+                bail!("--motif-list has no motifs of k-mer size {}", k);
+            }
+            Some(MotifCodes::new(&list.concrete_by_k(), &kmer_specs))
+        }
+        None => None,
+    };
+    let excluded_codes = match &excluded_motifs {
+        Some(excluded) => {
+            for (k, motifs) in excluded {
+                if !kmer_specs.contains_key(k) {
+                    bail!(
+                        "Motif {} in --exclude-motifs has length {}, which is not among --kmer-sizes",
+                        motifs[0],
+                        k
+                    );
+                }
+            }
+            Some(MotifCodes::new(excluded, &kmer_specs))
+        }
+        None => None,
+    };
+    let excluded_motifs: Option<HashSet<String>> =
+        excluded_motifs.map(|excluded| excluded.into_values().flatten().collect());
+    if opt.minimizers && opt.mask_mode == MaskMode::Separate {
+        bail!("--minimizers cannot be combined with --mask-mode separate");
+    }
+    if opt.kmer_size_list().iter().any(|&k| k > MAX_K_2BIT) {
+        if !opt.save_sparse {
+            bail!("k-mer sizes above {} require --save-sparse", MAX_K_2BIT);
+        }
+        if opt.code_cache.is_some() {
+            bail!("--code-cache supports k-mer sizes up to {}", MAX_K_2BIT);
+        }
+        if opt.checkpoint_dir.is_some() {
+            bail!("--checkpoint-dir supports k-mer sizes up to {}", MAX_K_2BIT);
+        }
+    }
+    if opt.bgzip && opt.output_format != OutputFormat::Long {
+        bail!("--bgzip requires --output-format long");
+    }
+    // clap counts `requires = "global"` as met by any member of the windows group
+    if opt.jellyfish_dump && !opt.global {
+        bail!("--jellyfish-dump requires --global");
+    }
+    if opt.spectrum && !opt.global {
+        bail!("--spectrum requires --global");
+    }
+    if opt
+        .by_size
+        .iter()
+        .flatten()
+        .any(|&size| opt.window_offset.unwrap_or(0) >= size as u64)
+    {
+        bail!("--window-offset must be smaller than --by-size");
+    }
+    if let Some(sizes) = &opt.by_size {
+        if sizes.iter().collect::<HashSet<_>>().len() < sizes.len() {
+            bail!("Duplicate --by-size window sizes");
+        }
+    }
+    if opt.stream_output && opt.by_size.as_ref().is_some_and(|sizes| sizes.len() > 1) {
+        bail!("--stream-output supports a single --by-size");
+    }
+    if opt.stream_output && opt.strand == StrandMode::Separate {
+        bail!("--stream-output cannot be combined with --strand separate");
+    }
+    if opt
+        .max_blacklist_overlap
+        .is_some_and(|frac| !(0.0..=1.0).contains(&frac))
+    {
+        bail!("--max-blacklist-overlap must be between 0 and 1");
+    }
+    if !(0.0..=1.0).contains(&opt.pwm_threshold) {
+        bail!("--pwm-threshold must be between 0 and 1");
+    }
+    if opt.max_memory.is_some_and(|gb| gb.is_nan() || gb <= 0.0) {
+        bail!("--max-memory must be positive");
+    }
+    if opt.spill_dir.is_some() {
+        if opt.normalize != Normalization::None {
+            bail!("--spill-dir cannot be combined with --normalize");
+        }
+        if opt.mask_mode == MaskMode::Separate {
+            bail!("--spill-dir cannot be combined with --mask-mode separate");
+        }
+        if opt.has_window_file() && opt.window_order == WindowOrder::Input {
+            bail!("--spill-dir requires --window-order sorted with window files");
+        }
+    }
+    let pwms = match &opt.pwms {
+        Some(path) => {
+            println!("Start: Loading PWMs");
+            load_pwms(path)?
+        }
+        None => Vec::new(),
+    };
+    let pwm_min_scores: Vec<f64> = pwms
+        .iter()
+        .map(|pwm| pwm.score_threshold(opt.pwm_threshold))
+        .collect();
+    let pwm_names: Vec<String> = pwms.iter().map(|pwm| pwm.name.clone()).collect();
+    for motif in &opt.track_motifs {
+        if !motif
+            .bytes()
+            .all(|b| matches!(b.to_ascii_uppercase(), b'A' | b'C' | b'G' | b'T'))
+        {
+            bail!("Track motif {} must only contain A, C, G and T", motif);
+        }
+        if !kmer_specs.contains_key(&(motif.len() as u8)) {
+            bail!(
+                "Track motif {} has length {}, which is not among --kmer-sizes",
+                motif,
+                motif.len()
+            );
+        }
+    }
+
+    if opt.dry_run {
+        let chrom_lengths = chromosomes
+            .iter()
+            .map(|chr| source.length(chr))
+            .collect::<Result<Vec<u64>>>()?;
+        let plan = DryRunPlan {
+            chromosomes: &chromosomes,
+            chrom_lengths: &chrom_lengths,
+            windows_map: windows_map.as_ref(),
+            blacklist_map: &blacklist_map,
+            kmer_specs: &kmer_specs,
+            motif_list: motif_list.as_ref(),
+            motif_groups: motif_groups.as_ref(),
+            excluded_motifs: excluded_motifs.as_ref(),
+        };
+        print_dry_run_plan(opt, &plan);
+        return Ok(());
+    }
+
+    let chrom_lengths = chromosomes
+        .iter()
+        .map(|chr| source.length(chr))
+        .collect::<Result<Vec<u64>>>()?;
+
+    // Main loop: process each autosome
+    println!("Start: Counting per chromosome");
+    progress.start_counting(chromosomes.len() as u64, chrom_lengths.iter().sum());
+
+    pb.set_position(0);
+
+    // Drawn once so the seeds in `run_info.json` match the outputs
+    let seed = opt.seed.unwrap_or_else(rand::random);
+    let rarefy_seed = opt.rarefy.map(|_| seed);
+    let windows_seed = opt.shuffle_windows.then_some(seed);
+
+    let chrom_counters: Mutex<BTreeMap<String, ChromCounters>> = Mutex::new(BTreeMap::new());
+    let dropped_windows: Mutex<Vec<BinInfo>> = Mutex::new(Vec::new());
+    let anchor_profile: Option<Mutex<Vec<WindowCounts>>> = opt
+        .anchor_profile
+        .then(|| Mutex::new(vec![WindowCounts::default(); 2 * opt.flank as usize + 1]));
+    // Length and counts of one chromosome
+    let count_chrom = |chr: &String| -> Result<(u64, Vec<ChromResult>)> {
+        let seq_bytes = profiler.time(Some(chr), Stage::Load, || source.read(chr))?;
+        let length = seq_bytes.len() as u64;
+        let sets = process_chrom(
+            chr,
+            seq_bytes,
+            opt,
+            &kmer_specs,
+            ChromInputs {
+                windows: windows_map
+                    .as_ref()
+                    .and_then(|m| m.get(chr).map(|v| v.as_slice())),
+                //gc_bins,
+                blacklist_intervals: blacklist_map.get(chr).map(|v| v.as_slice()).unwrap_or(&[]),
+                variants: variants_map.get(chr).map(|v| v.as_slice()).unwrap_or(&[]),
+                minus_strand: &minus_strand,
+                blocks: &blocks,
+                motif_codes: listed_codes.as_ref(),
+                excluded_codes: excluded_codes.as_ref(),
+                anchors: anchor_positions
+                    .get(chr)
+                    .map(|v| v.as_slice())
+                    .unwrap_or(&[]),
+                anchor_profile: anchor_profile.as_ref(),
+                pwms: &pwms,
+                pwm_min_scores: &pwm_min_scores,
+                profiler: &profiler,
+            },
+            shuffle_seed,
+            windows_seed,
+        )?;
+        Ok((length, sets))
+    };
+    // Checkpoints are only reused by runs with the same options and seeds
+    let checkpoint_fingerprint = options_fingerprint(
+        &opt.resolved_options,
+        &[
+            "checkpoint_dir",
+            "n_threads",
+            "max_memory",
+            "output_dir",
+            "force",
+            "profile",
+            "progress_json",
+            "config",
+        ],
+        &[shuffle_seed, windows_seed],
+    );
+    // One result per window set (i.e. per `--by-size` size)
+    let process = |chr: &String| -> Result<Vec<ChromResult>> {
+        let checkpoint = checkpoint_dir.as_ref().map(|dir| checkpoint_path(dir, chr));
+        let checkpointed = match &checkpoint {
+            Some(path) => load_checkpoint::<(u64, Vec<ChromResult>)>(path, checkpoint_fingerprint)?,
+            None => None,
+        };
+        let (length, mut sets) = match checkpointed {
+            // A checkpoint of another length is from another reference
+            Some((length, sets)) if length == source.length(chr)? => (length, sets),
+            _ => {
+                let done = count_chrom(chr)?;
+                if let Some(path) = &checkpoint {
+                    store_checkpoint(path, checkpoint_fingerprint, &done)?;
+                }
+                done
+            }
+        };
+        if let Some(max_overlap) = opt.max_blacklist_overlap {
+            for out in &mut sets {
+                let dropped = drop_blacklisted_windows(out, max_overlap);
+                dropped_windows.lock().unwrap().extend(dropped);
+            }
+        }
+        // Counters of the first (or only) window set
+        chrom_counters
+            .lock()
+            .unwrap()
+            .insert(chr.clone(), chrom_counters_of(length, &sets[0]));
+        pb.inc(1);
+        progress.chromosome_done(chr, length);
+        Ok(sets)
+    };
+
+    let outputs = CountOutputs {
+        kmer_specs: &kmer_specs,
+        motif_groups: motif_groups.as_ref(),
+        feature_map: feature_map.as_ref(),
+        bed_columns: bed_columns.as_ref().filter(|c| !c.is_empty()),
+        rarefy_seed,
+        motif_list: motif_list.as_ref(),
+        excluded_motifs: excluded_motifs.as_ref(),
+        pwm_names: &pwm_names,
+        profiler: &profiler,
+    };
+    let run_info = |chrom_counters: BTreeMap<String, ChromCounters>| -> Result<()> {
+        if opt.profile {
+            profiler.write_tsv(&opt.output_dir.join("profile.tsv"), &chromosomes)?;
+            let totals: Vec<String> = profiler
+                .totals()
+                .iter()
+                .map(|(stage, time)| format!("{} {:.2}s", stage.name(), time.secs))
+                .collect();
+            println!("Profile: {}", totals.join(", "));
+            if let Some(bytes) = peak_rss_bytes() {
+                println!("Peak memory: {:.2} GB", bytes as f64 / 1e9);
+            }
+        }
+        if let Some(max_overlap) = opt.max_blacklist_overlap {
+            let mut dropped = dropped_windows.lock().unwrap();
+            let chrom_order: HashMap<&str, usize> = chromosomes
+                .iter()
+                .enumerate()
+                .map(|(i, chr)| (chr.as_str(), i))
+                .collect();
+            dropped.sort_unstable_by_key(|info| {
+                (
+                    chrom_order[info.chrom.as_str()],
+                    info.start,
+                    info.original_idx,
+                )
+            });
+            if !dropped.is_empty() {
+                eprintln!(
+                    "Warning: Dropped {} window(s) with a blacklist overlap above {}",
+                    dropped.len(),
+                    max_overlap
+                );
+            }
+            let path = opt.output_dir.join("dropped_windows.bed");
+            let mut bed_writer = create_bins_bed(&path, &outputs)?;
+            write_bins_bed(&mut bed_writer, &dropped, &outputs)?;
+            bed_writer.flush().context("Write bed line fail")?;
+        }
+        write_chromosome_positions(
+            &opt.output_dir.join("chromosome_positions.tsv"),
+            &chromosomes,
+            &chrom_counters,
+        )?;
+        let config: Vec<(String, Vec<String>)> = opt
+            .resolved_options
+            .iter()
+            .filter(|(option, _)| option != "config")
+            .cloned()
+            .collect();
+        write_config(&opt.output_dir.join("config.toml"), &config)?;
+        let path = opt.ref_fasta.as_ref().or(opt.reference.as_ref()).unwrap();
+        let reference_sha256 = match is_url(path) {
+            true => None,
+            false => Some(sha256_file(path)?),
+        };
+        let seeds = [
+            ("rarefy", rarefy_seed),
+            ("shuffle", shuffle_seed),
+            ("shuffle_windows", windows_seed),
+        ]
+        .into_iter()
+        .filter_map(|(name, seed)| Some((name.to_string(), seed?)))
+        .collect();
+        RunInfo {
+            options: opt.resolved_options.clone(),
+            reference: path.display().to_string(),
+            reference_sha256,
+            chromosomes: chromosomes.clone(),
+            skipped_chromosomes: missing.clone(),
+            kmer_sizes: opt.kmer_size_list(),
+            seeds,
+            wall_time_secs: run_start.elapsed().as_secs_f64(),
+            chrom_counters,
+        }
+        .write(&opt.output_dir.join("run_info.json"))
+    };
+    if opt.stream_output {
+        // A single window set is ensured above
+        let process = |chr: &String| -> Result<ChromResult> { Ok(process(chr)?.swap_remove(0)) };
+        stream_count_outputs(opt, &chromosomes, &chrom_lengths, process, &outputs)?;
+        pb.finish_with_message("| Finished counting");
+        return run_info(chrom_counters.into_inner().unwrap());
+    }
+
+    let spill = match &opt.spill_dir {
+        Some(dir) => {
+            create_dir_all(dir).context("Cannot create spill directory")?;
+            Some(CountSpill::new(dir)?)
+        }
+        None => None,
+    };
+    let kmer_sizes: Vec<u8> = kmer_specs.keys().copied().collect();
+    let results: Vec<Vec<ChromResult>> =
+        run_chromosomes(opt, &chrom_lengths, &kmer_specs, |idx| {
+            let mut sets = process(&chromosomes[idx])?;
+            // Only the window info stays in memory
+            if let Some(spill) = &spill {
+                for (set, out) in sets.iter_mut().enumerate() {
+                    spill.store(set, idx, &std::mem::take(&mut out.0), &kmer_sizes)?;
+                }
+            }
+            Ok(sets)
+        })?;
+
+    pb.finish_with_message("| Finished counting");
+    check_interrupted()?;
+    progress.stage("write");
+
+    // Output directory of each window set
+    let set_dirs: Vec<PathBuf> = match &opt.by_size {
+        Some(sizes) if sizes.len() > 1 => sizes
+            .iter()
+            .map(|size| opt.output_dir.join(format!("size_{}", size)))
+            .collect(),
+        _ => vec![opt.output_dir.clone()],
+    };
+    // Random background windows follow the window sets
+    let set_dirs: Vec<PathBuf> = match windows_seed {
+        Some(_) => set_dirs
+            .iter()
+            .cloned()
+            .chain(set_dirs.iter().map(|dir| dir.join("shuffled_windows")))
+            .collect(),
+        None => set_dirs,
+    };
+    // Reverse-strand counts follow all other sets
+    let set_dirs: Vec<PathBuf> = match opt.strand {
+        StrandMode::Separate => set_dirs
+            .iter()
+            .cloned()
+            .chain(set_dirs.iter().map(|dir| dir.join("reverse")))
+            .collect(),
+        _ => set_dirs,
+    };
+    let mut results_by_set: Vec<Vec<ChromResult>> = set_dirs.iter().map(|_| Vec::new()).collect();
+    for sets in results {
+        for (set_results, out) in results_by_set.iter_mut().zip(sets) {
+            set_results.push(out);
+        }
+    }
+
+    for (set, (out_dir, results)) in set_dirs.iter().zip(results_by_set).enumerate() {
+        if set_dirs.len() > 1 {
+            create_dir_all(out_dir).context("Cannot create window set output directory")?;
+            println!("Start: Writing outputs to {:?}", out_dir);
+        }
+        let mut all_bins = Vec::new();
+        let mut masked_bins = Vec::new();
+        let mut bin_info = Vec::new();
+        let mut rows_by_chrom = Vec::new();
+        for (counts_by_bin, masked_by_bin, bin_vec, _) in results {
+            all_bins.push(counts_by_bin);
+            masked_bins.extend(masked_by_bin);
+            rows_by_chrom.push(bin_vec.len());
+            bin_info.extend(bin_vec);
+        }
+
+        if let Some(spill) = &spill {
+            write_spilled_outputs(
+                opt,
+                spill,
+                set,
+                &rows_by_chrom,
+                &bin_info,
+                out_dir,
+                &outputs,
+            )?;
+            continue;
+        }
+
+        if opt.mask_mode == MaskMode::Separate {
+            let masked_dir = out_dir.join("masked");
+            create_dir_all(&masked_dir).context("Cannot create masked output directory")?;
+            println!("Start: Writing soft-masked counts");
+            write_count_outputs(opt, masked_bins, bin_info.clone(), &masked_dir, &outputs)?;
+        }
+        write_count_outputs(opt, all_bins, bin_info, out_dir, &outputs)?;
+    }
+    if let Some(profile) = anchor_profile {
+        write_anchor_profile(opt, profile.into_inner().unwrap(), &outputs)?;
+    }
+
+    run_info(chrom_counters.into_inner().unwrap())
+}
+
+/// Write the `--anchor-profile` counts (one row per offset from the
+/// anchors) to `anchor_profile/`
+fn write_anchor_profile(
+    opt: &CountArgs,
+    profile: Vec<WindowCounts>,
+    outputs: &CountOutputs,
+) -> Result<()> {
+    let out_dir = opt.output_dir.join("anchor_profile");
+    create_dir_all(&out_dir).context("Cannot create anchor profile output directory")?;
+    println!("Start: Writing anchor profile to {:?}", out_dir);
+    let (mut prepared_counts, motifs_by_k) =
+        prepare_code_counts(&profile, opt.canonical, outputs.kmer_specs);
+    let motifs_by_k = match outputs.motif_list {
+        Some(list) => {
+            prepared_counts = list.sum_columns(prepared_counts);
+            list.columns.clone()
+        }
+        None => motifs_by_k,
+    };
+    let motifs_by_k = without_excluded_motifs(motifs_by_k, outputs.excluded_motifs);
+
+    let flank = opt.flank as i64;
+    let offsets: String = (-flank..=flank).map(|o| format!("{}\n", o)).collect();
+    std::fs::write(out_dir.join("offsets.txt"), offsets).context("Write offsets fail")?;
+    let write_options = CountWriteOptions {
+        dtype: opt.count_dtype,
+        row_scales: None,
+        motif_groups: outputs.motif_groups,
+        groups_only: opt.groups_only,
+        canonical: opt.canonical,
+        bgzip: false,
+    };
+    write_decoded_counts_matrix(
+        &prepared_counts,
+        &motifs_by_k,
+        &out_dir,
+        None,
+        &write_options,
+    )
+}
+
+/// Remove windows with a blacklist overlap above `max_overlap` from a
+/// chromosome result and return their metadata
+fn drop_blacklisted_windows(
+    (counts, masked, bin_info, _): &mut ChromResult,
+    max_overlap: f64,
+) -> Vec<BinInfo> {
+    let keep: Vec<bool> = bin_info
+        .iter()
+        .map(|info| info.overlap_perc <= max_overlap)
+        .collect();
+    let (kept, dropped): (Vec<_>, Vec<_>) = std::mem::take(bin_info)
+        .into_iter()
+        .partition(|info| info.overlap_perc <= max_overlap);
+    *bin_info = kept;
+    let mut keep_iter = keep.iter();
+    counts.retain(|_| *keep_iter.next().unwrap());
+    if let Some(masked) = masked {
+        let mut keep_iter = keep.iter();
+        masked.retain(|_| *keep_iter.next().unwrap());
+    }
+    dropped
+}
+
+/// Report problems with `--by-bed` windows in `window_issues.tsv` and fix
+/// them with `--clamp-windows` and `--merge-overlapping-windows`
+fn check_bed_windows(
+    windows: &mut WindowMap,
+    chrom_lengths: &HashMap<String, u64>,
+    opt: &CountArgs,
+) -> Result<()> {
+    let problems = validate_windows(windows, chrom_lengths);
+    if !problems.is_empty() {
+        eprintln!(
+            "Warning: Found {} window issue(s) in --by-bed ({}); see window_issues.tsv",
+            problems.len(),
+            summarize_window_problems(&problems)
+        );
+        let mut writer = BufWriter::new(
+            File::create(opt.output_dir.join("window_issues.tsv"))
+                .context("Create window issues fail")?,
+        );
+        write_window_problems(&mut writer, &problems)?;
+        writer.flush().context("Write window issues fail")?;
+    }
+    if opt.clamp_windows {
+        let removed = clamp_windows(windows, chrom_lengths);
+        println!(
+            "Clamped windows to chromosome ends; removed {} empty window(s)",
+            removed
+        );
+    }
+    if opt.merge_overlapping_windows {
+        let merged = merge_overlapping_windows(windows);
+        println!("Merged {} overlapping window(s)", merged);
+    }
+    Ok(())
+}
+
+/// Number of problems per issue, e.g. `2 overlap, 1 empty`
+fn summarize_window_problems(problems: &[WindowProblem]) -> String {
+    let mut by_issue: BTreeMap<WindowIssue, usize> = BTreeMap::new();
+    for problem in problems {
+        *by_issue.entry(problem.issue).or_insert(0) += 1;
+    }
+    by_issue
+        .iter()
+        .map(|(issue, n)| format!("{} {}", n, issue.as_str()))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Write window problems as a TSV with a header line
+fn write_window_problems(writer: &mut impl Write, problems: &[WindowProblem]) -> Result<()> {
+    writeln!(writer, "chrom\tstart\tend\twindow\tissue").context("Write window issues fail")?;
+    for p in problems {
+        writeln!(
+            writer,
+            "{}\t{}\t{}\t{}\t{}",
+            p.chrom,
+            p.start,
+            p.end,
+            p.original_idx,
+            p.issue.as_str()
+        )
+        .context("Write window issues fail")?;
+    }
+    Ok(())
+}
+
+/// Indices of `items` in the order of their `key`
+fn sorted_order<T, K: Ord>(items: &[T], key: impl Fn(&T) -> K) -> Vec<usize> {
+    let mut order: Vec<usize> = (0..items.len()).collect();
+    order.sort_by_key(|&i| key(&items[i]));
+    order
+}
+
+/// Reorder `items` so that the `i`th item is `items[order[i]]`
+fn permute<T>(items: Vec<T>, order: &[usize]) -> Vec<T> {
+    let mut items: Vec<Option<T>> = items.into_iter().map(Some).collect();
+    order
+        .iter()
+        .map(|&i| items[i].take().expect("each index once"))
+        .collect()
+}
+
+/// Per-chromosome counters for `run_info.json`
+fn chrom_counters_of(
+    length: u64,
+    (counts, masked, bin_info, positions): &ChromResult,
+) -> ChromCounters {
+    let mut kmers_by_k = BTreeMap::new();
+    for window in counts.iter().chain(masked.iter().flatten()) {
+        for (kmer, count) in window {
+            *kmers_by_k.entry(kmer.k).or_insert(0) += count;
+        }
+    }
+    ChromCounters {
+        length,
+        windows: bin_info.len() as u64,
+        effective_bases: bin_info.iter().map(|info| info.positions.counted).sum(),
+        kmers_by_k,
+        positions: *positions,
+    }
+}
+
+/// Inputs shared by all count matrices written in one run
+struct CountOutputs<'a> {
+    kmer_specs: &'a BTreeMap<u8, KmerSpec>,
+    motif_groups: Option<&'a MotifGroups>,
+    feature_map: Option<&'a HashMap<String, FeatureIntervals>>,
+    /// Extra columns of `--by-bed` windows to pass through to `bins.bed`
+    bed_columns: Option<&'a BedColumns>,
+    /// Seed for `--rarefy`
+    rarefy_seed: Option<u64>,
+    /// `--motif-list` motifs by k, used as the count matrix columns
+    motif_list: Option<&'a MotifList>,
+    /// `--exclude-motifs` motifs, left out of the count matrix columns
+    excluded_motifs: Option<&'a HashSet<String>>,
+    /// Names of the `--pwms` (empty without)
+    pwm_names: &'a [String],
+    /// Stage timings for `--profile`
+    profiler: &'a Profiler,
+}
+
+/// Remove the `--exclude-motifs` motifs from the count matrix columns
+fn without_excluded_motifs(
+    mut motifs_by_k: BTreeMap<u8, Vec<String>>,
+    excluded: Option<&HashSet<String>>,
+) -> BTreeMap<u8, Vec<String>> {
+    if let Some(excluded) = excluded {
+        for motifs in motifs_by_k.values_mut() {
+            motifs.retain(|motif| !excluded.contains(motif));
+        }
+    }
+    motifs_by_k
+}
+
+/// Merge the spilled counts of one window set into sparse count matrices
+/// and write them together with `bins.bed` etc. to `out_dir`
+///
+/// * `rows_by_chrom` - The number of windows of each chromosome
+fn write_spilled_outputs(
+    opt: &CountArgs,
+    spill: &CountSpill,
+    set: usize,
+    rows_by_chrom: &[usize],
+    bin_info: &[BinInfo],
+    out_dir: &Path,
+    outputs: &CountOutputs,
+) -> Result<()> {
+    println!("Start: Merging spilled counts");
+    let start = Instant::now();
+    let n_rows: usize = rows_by_chrom.iter().sum();
+    for &k in outputs.kmer_specs.keys() {
+        // Columns of the codes seen in any chromosome
+        let codes = spill.distinct_codes(set, rows_by_chrom.len(), k)?;
+        let (motif_of, motifs) = code_motifs(k, codes, opt.canonical, outputs.kmer_specs);
+        let motifs =
+            without_excluded_motifs(BTreeMap::from([(k, motifs)]), outputs.excluded_motifs)
+                .remove(&k)
+                .unwrap();
+        let motif_column: FxHashMap<&str, u64> = motifs
+            .iter()
+            .enumerate()
+            .map(|(i, motif)| (motif.as_str(), i as u64))
+            .collect();
+        let column_of: FxHashMap<u64, u64> = motif_of
+            .iter()
+            .filter_map(|(&code, motif)| Some((code, *motif_column.get(motif.as_str())?)))
+            .collect();
+
+        let triplets = spill
+            .merged(set, rows_by_chrom, k)
+            .filter_map(|triplet| match triplet {
+                Ok([row, code, count]) => column_of.get(&code).map(|&col| Ok((row, col, count))),
+                Err(err) => Some(Err(err)),
+            });
+        let tag = format!("k{}", k);
+        write_triplets_sparse(
+            triplets,
+            n_rows,
+            motifs.len(),
+            &tag,
+            out_dir,
+            opt.sparse_format,
+            opt.count_dtype,
+        )?;
+        if n_rows > 0 {
+            write_motif_lists(out_dir, &tag, &motifs, opt.canonical)?;
+        }
+    }
+    outputs.profiler.add(None, Stage::Write, start.elapsed());
+    // Window coordinates and per-window annotations (no count matrices)
+    write_count_files(opt, &[], &BTreeMap::new(), bin_info, 0, out_dir, outputs)
+}
+
+/// Decode, post-process and write one set of per-chromosome window counts
+/// (count matrices and `bins.bed`) to `out_dir`
+fn write_count_outputs(
+    opt: &CountArgs,
+    counts_by_chrom: Vec<Vec<FxHashMap<Kmer, BigCount>>>,
+    bin_info: Vec<BinInfo>,
+    out_dir: &Path,
+    outputs: &CountOutputs,
+) -> Result<()> {
+    println!("Start: Processing counts");
+
+    // Stage files for the bundle in a temporary directory inside `out_dir`
+    let bundle_stage = if opt.bundle {
+        Some(
+            tempfile::Builder::new()
+                .prefix(".bundle-")
+                .tempdir_in(out_dir)
+                .context("Create bundle staging directory fail")?,
+        )
+    } else {
+        None
+    };
+    // The staged files are read back, so they are never uploaded directly
+    let _bundle_route = bundle_stage.as_ref().map(|d| OutputRoute::local(d.path()));
+    let bundle_path = out_dir.join("counts_bundle.npz");
+    let out_dir = bundle_stage.as_ref().map_or(out_dir, |d| d.path());
+
+    // Collect results (in chromosome order) back into the global vector
+    let all_bins = counts_by_chrom.into_iter().flatten();
+    // Effective length of the single window in global mode
+    let global_length: u64 = bin_info.iter().map(|info| info.positions.counted).sum();
+    let mut bin_info = if opt.global { Vec::new() } else { bin_info };
+
+    // Convert to single hashmap for global
+    // Keep wrapped in vector to simplify writer
+    let all_bins: Vec<FxHashMap<Kmer, BigCount>> = if opt.global {
+        vec![merge_kmer_counts(all_bins)]
+    } else {
+        all_bins.collect()
+    };
+
+    // Decode once and get correct motifs (collapsed, N-filtered, etc.)
+    let (mut prepared_counts, motifs_by_k) = outputs.profiler.time(None, Stage::Prepare, || {
+        let (prepared_counts, motifs_by_k) =
+            prepare_code_counts(&all_bins, opt.canonical, outputs.kmer_specs);
+        match outputs.motif_list {
+            Some(list) => (list.sum_columns(prepared_counts), list.columns.clone()),
+            None => (prepared_counts, motifs_by_k),
+        }
+    });
+    drop(all_bins);
+    let motifs_by_k = without_excluded_motifs(motifs_by_k, outputs.excluded_motifs);
+
+    // Restore the input order of window files (windows are counted sorted
+    // by position per chromosome)
+    if opt.has_window_file() && opt.window_order == WindowOrder::Input {
+        println!("Start: Restoring the input order of windows");
+        // Sub-windows from `--split-at-blacklist` share an index and are
+        // kept in position order
+        let order = sorted_order(&bin_info, |info| (info.original_idx, info.start));
+        bin_info = permute(bin_info, &order);
+        prepared_counts = permute(prepared_counts, &order);
+    }
+
+    if let (Some(depth), Some(seed)) = (opt.rarefy, outputs.rarefy_seed) {
+        println!("Start: Rarefying counts");
+        let n_shallow = rarefy_counts(&mut prepared_counts, depth, seed);
+        if n_shallow > 0 {
+            eprintln!(
+                "Warning: {} window/k-mer size combinations had fewer than {} counts and were not rarefied",
+                n_shallow, depth
+            );
+        }
+        let mut info =
+            File::create(out_dir.join("rarefy.tsv")).context("Create rarefy info fail")?;
+        writeln!(
+            info,
+            "depth\t{}\nseed\t{}\nbelow_depth\t{}",
+            depth, seed, n_shallow
+        )
+        .context("Write rarefy info fail")?;
+    }
+
+    if opt.split_by_chromosome {
+        // Move rows into per-chromosome groups, in order of first appearance
+        let mut groups: Vec<(String, Vec<DecodedCounts>, Vec<BinInfo>)> = Vec::new();
+        let mut group_of: HashMap<String, usize> = HashMap::new();
+        for (counts, info) in prepared_counts.into_iter().zip(bin_info) {
+            let g = *group_of.entry(info.chrom.clone()).or_insert_with(|| {
+                groups.push((info.chrom.clone(), Vec::new(), Vec::new()));
+                groups.len() - 1
+            });
+            groups[g].1.push(counts);
+            groups[g].2.push(info);
+        }
+        let chroms: Vec<&str> = groups.iter().map(|(chr, _, _)| chr.as_str()).collect();
+        let dir_names = chromosome_dir_names(&chroms)?;
+        for ((chr, chr_counts, chr_info), dir_name) in groups.into_iter().zip(dir_names) {
+            let chr_dir = out_dir.join(dir_name);
+            create_dir_all(&chr_dir)
+                .context(format!("Cannot create output directory for {}", chr))?;
+            write_count_files(
+                opt,
+                &chr_counts,
+                &motifs_by_k,
+                &chr_info,
+                0,
+                &chr_dir,
+                outputs,
+            )?;
+        }
+    } else {
+        write_count_files(
+            opt,
+            &prepared_counts,
+            &motifs_by_k,
+            &bin_info,
+            global_length,
+            out_dir,
+            outputs,
+        )?;
+    }
+
+    if let Some(stage) = bundle_stage {
+        println!("Start: Bundling outputs into {:?}", bundle_path);
+        outputs.profiler.time(None, Stage::Write, || {
+            bundle_directory(stage.path(), &bundle_path)
+        })?;
+    }
+
+    Ok(())
+}
+
+/// Write the count matrices (or tables) and `bins.bed` of prepared windows
+/// to `out_dir`. `global_length` is the effective length of the single
+/// window in global mode.
+fn write_count_files(
+    opt: &CountArgs,
+    prepared_counts: &[DecodedCounts],
+    motifs_by_k: &BTreeMap<u8, Vec<String>>,
+    bin_info: &[BinInfo],
+    global_length: u64,
+    out_dir: &Path,
+    outputs: &CountOutputs,
+) -> Result<()> {
+    let start = Instant::now();
+    let effective_lengths: Vec<u64> = if opt.global {
+        vec![global_length]
+    } else {
+        bin_info.iter().map(|info| info.positions.counted).collect()
+    };
+    let row_scales = row_scales(prepared_counts, &effective_lengths, opt.normalize);
+    let write_options = CountWriteOptions {
+        dtype: opt.count_dtype,
+        row_scales: row_scales.as_ref(),
+        motif_groups: outputs.motif_groups,
+        groups_only: opt.groups_only,
+        canonical: opt.canonical,
+        bgzip: opt.bgzip,
+    };
+
+    let window_ids = if opt.global {
+        WindowIds::Global
+    } else {
+        WindowIds::Coordinates(
+            bin_info
+                .iter()
+                .map(|info| (info.chrom.clone(), info.start, info.end))
+                .collect(),
+        )
+    };
+
+    println!("Start: Writing counts to disk");
+    match opt.output_format {
+        OutputFormat::Npy => write_decoded_counts_matrix(
+            prepared_counts,
+            motifs_by_k,
+            out_dir,
+            opt.save_sparse.then_some(opt.sparse_format),
+            &write_options,
+        )?,
+        format => write_decoded_counts_table(
+            prepared_counts,
+            motifs_by_k,
+            &window_ids,
+            out_dir,
+            format,
+            &write_options,
+        )?,
+    }
+
+    // Write bins BED file
+    if !opt.global {
+        println!("Start: Writing window coordinates to disk");
+        let mut bed_writer = create_bins_bed(&out_dir.join("bins.bed"), outputs)?;
+        write_bins_bed(&mut bed_writer, bin_info, outputs)?;
+        bed_writer.flush().context("Write bed line fail")?;
+        let mut positions_writer = create_window_positions(out_dir)?;
+        write_window_positions(&mut positions_writer, bin_info)?;
+        positions_writer
+            .flush()
+            .context("Write window positions fail")?;
+    }
+
+    if opt.jellyfish_dump {
+        if let Some(window) = prepared_counts.first() {
+            write_jellyfish_dump(window, motifs_by_k, out_dir)?;
+        }
+    }
+
+    if opt.spectrum {
+        if let Some(window) = prepared_counts.first() {
+            write_kmer_spectrum(window, out_dir)?;
+        }
+    }
+
+    if opt.diversity {
+        println!("Start: Writing k-mer diversity to disk");
+        let n_possible: BTreeMap<u8, f64> = motifs_by_k
+            .iter()
+            .map(|(&k, motifs)| match outputs.motif_list {
+                Some(_) => (k, motifs.len() as f64),
+                None => (k, n_possible_motifs(k, opt.canonical)),
+            })
+            .collect();
+        write_kmer_diversity(prepared_counts, &n_possible, out_dir)?;
+    }
+
+    if opt.repeat_content {
+        println!("Start: Writing repeat content to disk");
+        let repeats: Vec<RepeatContent> = bin_info
+            .iter()
+            .map(|info| info.repeats.unwrap_or_default())
+            .collect();
+        write_repeat_content(&repeats, out_dir)?;
+    }
+
+    if !outputs.pwm_names.is_empty() {
+        println!("Start: Writing PWM hits to disk");
+        let hits: Vec<PwmHits> = bin_info
+            .iter()
+            .map(|info| info.pwm_hits.clone().unwrap_or_default())
+            .collect();
+        write_pwm_hits(&hits, outputs.pwm_names, out_dir)?;
+    }
+
+    if !opt.track_motifs.is_empty() {
+        let mut tracks = MotifTrackWriter::create(out_dir, &opt.track_motifs, opt.canonical)?;
+        tracks.write_rows(prepared_counts, &window_ids, row_scales.as_ref())?;
+        tracks.finish()?;
+    }
+    outputs.profiler.add(None, Stage::Write, start.elapsed());
+
+    Ok(())
+}
+
+/// Create `window_positions.tsv` in `out_dir` and write its header
+fn create_window_positions(out_dir: &Path) -> Result<BufWriter<File>> {
+    let mut writer = BufWriter::new(
+        File::create(out_dir.join("window_positions.tsv"))
+            .context("Create window positions fail")?,
+    );
+    writeln!(
+        writer,
+        "chrom\tstart\tend\t{}",
+        RefKmerExtractionCounters::TSV_HEADER
+    )
+    .context("Write window positions fail")?;
+    Ok(writer)
+}
+
+/// Write the position counters of `bin_info` as `window_positions.tsv` rows
+fn write_window_positions(writer: &mut impl Write, bin_info: &[BinInfo]) -> Result<()> {
+    for info in bin_info {
+        writeln!(
+            writer,
+            "{}\t{}\t{}\t{}",
+            info.chrom,
+            info.start,
+            info.end,
+            info.positions.tsv_fields()
+        )
+        .context("Write window positions fail")?;
+    }
+    Ok(())
+}
+
+/// Write the whole-chromosome position counters in order of `chromosomes`
+fn write_chromosome_positions(
+    path: &Path,
+    chromosomes: &[String],
+    chrom_counters: &BTreeMap<String, ChromCounters>,
+) -> Result<()> {
+    let mut text = format!("chrom\t{}\n", RefKmerExtractionCounters::TSV_HEADER);
+    for chr in chromosomes {
+        if let Some(counters) = chrom_counters.get(chr) {
+            text.push_str(&format!("{}\t{}\n", chr, counters.positions.tsv_fields()));
+        }
+    }
+    std::fs::write(path, text).context("Write chromosome positions fail")
+}
+
+/// Create a window BED like `bins.bed` at `path`.
+///
+/// With extra `--by-bed` columns, a `#`-prefixed header line names all columns.
+fn create_bins_bed(path: &Path, outputs: &CountOutputs) -> Result<BufWriter<File>> {
+    let mut bed_writer = BufWriter::new(File::create(path).context("Create bed fail")?);
+    if let Some(columns) = outputs.bed_columns {
+        let mut names = vec!["#chrom", "start", "end", "blacklist_overlap"];
+        names.extend(columns.names.iter().map(String::as_str));
+        if outputs.feature_map.is_some() {
+            names.push("features");
+        }
+        writeln!(bed_writer, "{}", names.join("\t")).context("Write bed line fail")?;
+    }
+    Ok(bed_writer)
+}
+
+/// Write `bin_info` as `bins.bed` lines, followed by the extra `--by-bed`
+/// columns and overlapping features
+fn write_bins_bed(
+    bed_writer: &mut impl Write,
+    bin_info: &[BinInfo],
+    outputs: &CountOutputs,
+) -> Result<()> {
+    for info in bin_info {
+        write!(
+            bed_writer,
+            "{}\t{}\t{}\t{}",
+            info.chrom, info.start, info.end, info.overlap_perc
+        )
+        .context("Write bed line fail")?;
+        if let Some(columns) = outputs.bed_columns {
+            write!(
+                bed_writer,
+                "\t{}",
+                columns.values[info.original_idx as usize].join("\t")
+            )
+            .context("Write bed line fail")?;
+        }
+        if let Some(features) = outputs.feature_map {
+            let names = features
+                .get(&info.chrom)
+                .map(|f| f.overlapping(info.start, info.end))
+                .unwrap_or_default();
+            let names = if names.is_empty() {
+                ".".to_string()
+            } else {
+                names.join(",")
+            };
+            write!(bed_writer, "\t{}", names).context("Write bed line fail")?;
+        }
+        writeln!(bed_writer).context("Write bed line fail")?;
+    }
+    Ok(())
+}
+
+/// Count chromosomes in parallel and append each chromosome's rows to the
+/// outputs as soon as it is done
+///
+/// Rows follow the completion order; `bins.bed` and `window_positions.tsv`
+/// are written in the same order so row `i` of every output is the same window.
+fn stream_count_outputs(
+    opt: &CountArgs,
+    chromosomes: &[String],
+    chrom_lengths: &[u64],
+    process: impl Fn(&String) -> Result<ChromResult> + Sync,
+    outputs: &CountOutputs,
+) -> Result<()> {
+    if matches!(
+        opt.output_format,
+        OutputFormat::Arrow | OutputFormat::Long | OutputFormat::Hdf5
+    ) {
+        bail!("--stream-output supports npy and tsv output formats");
+    }
+    if opt.mask_mode == MaskMode::Separate {
+        bail!("--stream-output cannot be combined with --mask-mode separate");
+    }
+    let motifs_by_k = match outputs.motif_list {
+        Some(list) => list.columns.clone(),
+        None => motif_universe(outputs.kmer_specs, opt.canonical),
+    };
+    let motifs_by_k = without_excluded_motifs(motifs_by_k, outputs.excluded_motifs);
+    let write_options = CountWriteOptions {
+        dtype: opt.count_dtype,
+        row_scales: None,
+        motif_groups: outputs.motif_groups,
+        groups_only: opt.groups_only,
+        canonical: opt.canonical,
+        bgzip: opt.bgzip,
+    };
+    let mut writer = StreamingCountWriter::create(
+        &opt.output_dir,
+        opt.output_format,
+        &motifs_by_k,
+        &write_options,
+        opt.normalize != Normalization::None,
+    )?;
+    let mut bed_writer = create_bins_bed(&opt.output_dir.join("bins.bed"), outputs)?;
+    let mut positions_writer = create_window_positions(&opt.output_dir)?;
+    let mut tracks = MotifTrackWriter::create(&opt.output_dir, &opt.track_motifs, opt.canonical)?;
+
+    let (tx, rx) = std::sync::mpsc::sync_channel(rayon::current_num_threads());
+    let process = &process;
+    std::thread::scope(|scope| -> Result<()> {
+        scope.spawn(move || {
+            run_chromosomes(opt, chrom_lengths, outputs.kmer_specs, |idx| {
+                // The receiver only hangs up after an error
+                let _ = tx.send((idx, process(&chromosomes[idx])));
+                Ok(())
+            })
+        });
+
+        for (idx, result) in rx {
+            let (counts_by_bin, _, bin_info, _) = result?;
+            let chr = Some(chromosomes[idx].as_str());
+            let prepared = outputs.profiler.time(chr, Stage::Prepare, || {
+                let (prepared, _) =
+                    prepare_code_counts(&counts_by_bin, opt.canonical, outputs.kmer_specs);
+                match outputs.motif_list {
+                    Some(list) => list.sum_columns(prepared),
+                    None => prepared,
+                }
+            });
+            let start = Instant::now();
+            let lengths: Vec<u64> = bin_info.iter().map(|info| info.positions.counted).collect();
+            let scales = row_scales(&prepared, &lengths, opt.normalize);
+            let window_ids = WindowIds::Coordinates(
+                bin_info
+                    .iter()
+                    .map(|info| (info.chrom.clone(), info.start, info.end))
+                    .collect(),
+            );
+            writer.write_rows(&prepared, &window_ids, scales.as_ref())?;
+            tracks.write_rows(&prepared, &window_ids, scales.as_ref())?;
+            write_bins_bed(&mut bed_writer, &bin_info, outputs)?;
+            write_window_positions(&mut positions_writer, &bin_info)?;
+            outputs.profiler.add(chr, Stage::Write, start.elapsed());
+        }
+        Ok(())
+    })?;
+
+    writer.finish()?;
+    tracks.finish()?;
+    bed_writer.flush().context("Write bed line fail")?;
+    positions_writer
+        .flush()
+        .context("Write window positions fail")?;
+    Ok(())
+}
+
+/* ---------- main routine -------------------------------------------- */
+
+/// Window metadata
+#[derive(Clone)]
+struct BinInfo {
+    chrom: String,
+    start: u64,
+    end: u64,
+    /// Index of the window in its window set before sorting
+    original_idx: u64,
+    /// Blacklist overlap as written to `bins.bed`
+    overlap_perc: f64,
+    /// Position counters; `counted` is the effective length
+    positions: RefKmerExtractionCounters,
+    /// With `--repeat-content`
+    repeats: Option<RepeatContent>,
+    /// With `--pwms`
+    pwm_hits: Option<PwmHits>,
+}
+
+impl Checkpoint for BinInfo {
+    fn write_to(&self, w: &mut impl Write) -> Result<()> {
+        self.chrom.write_to(w)?;
+        self.start.write_to(w)?;
+        self.end.write_to(w)?;
+        self.original_idx.write_to(w)?;
+        self.overlap_perc.write_to(w)?;
+        self.positions.write_to(w)?;
+        self.repeats.write_to(w)?;
+        self.pwm_hits.write_to(w)
+    }
+
+    fn read_from(r: &mut impl Read) -> Result<Self> {
+        Ok(BinInfo {
+            chrom: String::read_from(r)?,
+            start: u64::read_from(r)?,
+            end: u64::read_from(r)?,
+            original_idx: u64::read_from(r)?,
+            overlap_perc: f64::read_from(r)?,
+            positions: RefKmerExtractionCounters::read_from(r)?,
+            repeats: Option::read_from(r)?,
+            pwm_hits: Option::read_from(r)?,
+        })
+    }
+}
+
+/// Kmer counts of one window
+type WindowCounts = FxHashMap<Kmer, BigCount>;
+
+/// Per-window counts, optional soft-masked counts, metadata and position
+/// counters for one chromosome
+type ChromResult = (
+    Vec<WindowCounts>,
+    Option<Vec<WindowCounts>>,
+    Vec<BinInfo>,
+    RefKmerExtractionCounters,
+);
+
+/// Per-chromosome inputs loaded from BED/VCF files
+struct ChromInputs<'a> {
+    /// Optional slice of tuples with (start, end, original_idx)
+    windows: Option<&'a [(u64, u64, u64)]>,
+    // gc_bins: usize,
+    blacklist_intervals: &'a [(u64, u64)],
+    variants: &'a [Variant],
+    /// Whether each window (by original index) is counted on the reverse
+    /// complement; empty when not `--stranded`
+    minus_strand: &'a [bool],
+    /// BED12 blocks of each window (by original index); empty without
+    /// `--bed12`
+    blocks: &'a [Vec<(u64, u64)>],
+    /// Codes of the `--motif-list` motifs
+    motif_codes: Option<&'a MotifCodes>,
+    /// Codes of the `--exclude-motifs` motifs
+    excluded_codes: Option<&'a MotifCodes>,
+    /// Anchor positions for `--anchor-profile`
+    anchors: &'a [u64],
+    /// Counts by offset from the anchors, summed over chromosomes
+    anchor_profile: Option<&'a Mutex<Vec<WindowCounts>>>,
+    /// `--pwms` and their absolute score thresholds
+    pwms: &'a [Pwm],
+    pwm_min_scores: &'a [f64],
+    /// Stage timings for `--profile`
+    profiler: &'a Profiler,
+}
+
+/// Window coordinates of one chromosome for all windowing options. Multiple
+/// `--by-size` sizes give one window set each, counted together
+fn chrom_window_sets(
+    opt: &CountArgs,
+    chrom_len: u64,
+    windows: Option<&[(u64, u64, u64)]>,
+    blacklist_intervals: &[(u64, u64)],
+) -> Vec<Vec<(u64, u64, u64)>> {
+    let window_sets: Vec<Vec<(u64, u64, u64)>> = if let Some(sizes) = &opt.by_size {
+        // by-size
+        sizes
+            .iter()
+            .map(|&sz| {
+                let step = opt.step.unwrap_or(sz as u64);
+                size_windows(
+                    chrom_len,
+                    sz as u64,
+                    step,
+                    opt.window_offset.unwrap_or(0),
+                    opt.last_window,
+                )
+            })
+            .collect()
+    } else if let Some(n) = opt.n_windows_per_chrom {
+        vec![equal_windows(chrom_len, n)]
+    } else if opt.has_window_file() {
+        // by-bed, by-gtf, by-anchors or by-cytoband
+        vec![windows.unwrap_or(&[]).to_owned()]
+    } else {
+        // global or per-sequence
+        vec![vec![(0, chrom_len, 0u64)]]
+    };
+    if opt.split_at_blacklist {
+        window_sets
+            .iter()
+            .map(|windows| split_windows_at_intervals(windows, blacklist_intervals))
+            .collect()
+    } else {
+        window_sets
+    }
+}
+
+fn process_chrom(
+    chr: &str,
+    mut seq_bytes: Vec<u8>,
+    opt: &CountArgs,
+    kmer_specs: &BTreeMap<u8, KmerSpec>,
+    inputs: ChromInputs,
+    shuffle_seed: Option<u64>,
+    windows_seed: Option<u64>,
+) -> anyhow::Result<Vec<ChromResult>> {
+    let ChromInputs {
+        windows,
+        blacklist_intervals,
+        variants,
+        minus_strand,
+        blocks,
+        motif_codes,
+        excluded_codes,
+        anchors,
+        anchor_profile,
+        pwms,
+        pwm_min_scores,
+        profiler,
+    } = inputs;
+    // Length in reference coordinates
+    let chrom_len = seq_bytes.len();
+
+    // Personalize the sequence; indels shift coordinates, so windows and
+    // blacklist intervals are translated with `coord_map`
+    let mut coord_map = CoordinateMap::default();
+    if !variants.is_empty() {
+        let (personal, map, summary) = apply_variants(&seq_bytes, variants);
+        if summary.ref_mismatch + summary.overlapping > 0 {
+            eprintln!(
+                "Warning: {}: skipped {} variant(s) with mismatching REF and {} overlapping variant(s)",
+                chr, summary.ref_mismatch, summary.overlapping
+            );
+        }
+        seq_bytes = personal;
+        coord_map = map;
+    }
+    if let Some(conversion) = opt.bisulfite {
+        bisulfite_convert(&mut seq_bytes, conversion);
+    }
+    if opt.mask_mode == MaskMode::Exclude {
+        let masked = soft_masked_intervals(&seq_bytes);
+        mask_intervals_as_n(&mut seq_bytes, &masked);
+    }
+    let personal_len = seq_bytes.len();
+    let to_personal = |(s, e): (u64, u64)| (coord_map.to_personal(s), coord_map.to_personal(e));
+
+    let window_sets = chrom_window_sets(opt, chrom_len as u64, windows, blacklist_intervals);
+    // Length-matched random windows, one set per window set
+    let window_sets: Vec<_> = match windows_seed {
+        Some(seed) => {
+            let mut rng = StdRng::seed_from_u64(seed ^ fxhash::hash64(chr));
+            let random_sets: Vec<_> = window_sets
+                .iter()
+                .map(|windows| {
+                    random_windows(windows, chrom_len as u64, blacklist_intervals, &mut rng)
+                })
+                .collect();
+            window_sets.into_iter().chain(random_sets).collect()
+        }
+        None => window_sets,
+    };
+    let mut set_lengths: Vec<usize> = window_sets.iter().map(Vec::len).collect();
+    let windows = window_sets.concat();
+
+    // Windows in coordinates of the (personalized) sequence
+    let count_windows: Vec<(u64, u64, u64)> = if coord_map.is_identity() {
+        windows.clone()
+    } else {
+        windows
+            .iter()
+            .map(|&(s, e, idx)| {
+                let (s, e) = to_personal((s, e.min(chrom_len as u64)));
+                (s, e, idx)
+            })
+            .collect()
+    };
+
+    let to_range =
+        |(s, e): (u64, u64)| (s as usize).min(personal_len)..(e as usize).min(personal_len);
+    // Sequence ranges of each window: its BED12 blocks or its full span
+    let window_ranges = |&(s, e, original_idx): &(u64, u64, u64)| -> Vec<Range<usize>> {
+        match blocks.get(original_idx as usize).filter(|b| !b.is_empty()) {
+            Some(b) => b.iter().map(|&iv| to_range(to_personal(iv))).collect(),
+            None => vec![to_range((s, e))],
+        }
+    };
+    let count_acgt_in = |seq: &[u8], ranges: &[Range<usize>]| -> u64 {
+        ranges.iter().map(|r| count_acgt(&seq[r.clone()])).sum()
+    };
+    // A/C/G/T bases per window and chromosome before blacklist masking
+    let acgt: Vec<u64> = count_windows
+        .iter()
+        .map(|w| count_acgt_in(&seq_bytes, &window_ranges(w)))
+        .collect();
+    let chrom_acgt = count_acgt(&seq_bytes);
+    // Homopolymer and STR content per window of the unmasked sequence
+    let repeats: Vec<Option<RepeatContent>> = count_windows
+        .iter()
+        .map(|w| {
+            opt.repeat_content.then(|| {
+                let mut content = RepeatContent::default();
+                for range in window_ranges(w) {
+                    content += RepeatContent::of(&seq_bytes[range]);
+                }
+                content
+            })
+        })
+        .collect();
+    let low_complexity = if opt.mask_low_complexity {
+        dust_intervals(&seq_bytes, opt.dust_window as usize, opt.dust_threshold)
+    } else {
+        Vec::new()
+    };
+
+    if coord_map.is_identity() {
+        apply_blacklist_mask_to_seq(&mut seq_bytes, blacklist_intervals);
+    } else {
+        let translated: Vec<(u64, u64)> = blacklist_intervals
+            .iter()
+            .map(|&iv| to_personal(iv))
+            .collect();
+        apply_blacklist_mask_to_seq(&mut seq_bytes, &translated);
+    }
+
+    // Mask low-complexity stretches like blacklisted regions, keeping the
+    // A/C/G/T bases left by the blacklist to report them separately
+    let unmasked = opt.mask_low_complexity.then(|| {
+        let window_acgt: Vec<u64> = count_windows
+            .iter()
+            .map(|w| count_acgt_in(&seq_bytes, &window_ranges(w)))
+            .collect();
+        (window_acgt, count_acgt(&seq_bytes))
+    });
+    apply_blacklist_mask_to_seq(&mut seq_bytes, &low_complexity);
+
+    // Non-N, non-blacklisted bases per window (shuffling keeps these in place)
+    let positions: Vec<RefKmerExtractionCounters> = count_windows
+        .iter()
+        .zip(acgt)
+        .enumerate()
+        .map(|(i, (w, acgt))| {
+            let ranges = window_ranges(w);
+            let total = ranges.iter().map(|r| r.len() as u64).sum();
+            let counters =
+                RefKmerExtractionCounters::new(total, acgt, count_acgt_in(&seq_bytes, &ranges));
+            let counters = match &unmasked {
+                Some((window_acgt, _)) => counters.with_low_complexity(window_acgt[i]),
+                None => counters,
+            };
+            match opt.position_stride {
+                Some(stride) => counters.with_position_stride(
+                    ranges
+                        .into_iter()
+                        .map(|r| count_acgt_strided(&seq_bytes, r, stride as usize))
+                        .sum(),
+                ),
+                None => counters,
+            }
+        })
+        .collect();
+    let chrom_positions =
+        RefKmerExtractionCounters::new(personal_len as u64, chrom_acgt, count_acgt(&seq_bytes));
+    let chrom_positions = match &unmasked {
+        Some((_, chrom_unmasked)) => chrom_positions.with_low_complexity(*chrom_unmasked),
+        None => chrom_positions,
+    };
+    let chrom_positions = match opt.position_stride {
+        Some(stride) => chrom_positions.with_position_stride(count_acgt_strided(
+            &seq_bytes,
+            0..personal_len,
+            stride as usize,
+        )),
+        None => chrom_positions,
+    };
+
+    // Record soft-masked positions before shuffling upper-cases the sequence
+    let soft_masked = if opt.mask_mode == MaskMode::Separate {
+        soft_masked_intervals(&seq_bytes)
+    } else {
+        Vec::new()
+    };
+
+    // Dinucleotide-shuffle each window for background generation
+    if let Some(seed) = shuffle_seed {
+        let chr_seed = seed ^ fxhash::hash64(chr);
+        for &(win_start, win_end, original_idx) in &count_windows {
+            let s = (win_start as usize).min(personal_len);
+            let e = (win_end as usize).min(personal_len);
+            let mut rng = StdRng::seed_from_u64(chr_seed.wrapping_add(original_idx));
+            shuffle_segments(&mut seq_bytes[s..e], &mut rng);
+        }
+    }
+
+    // PWM hits per window of the sequence that is counted
+    let pwm_hits: Vec<Option<PwmHits>> = count_windows
+        .iter()
+        .map(|w| {
+            (!pwms.is_empty()).then(|| {
+                let ranges = window_ranges(w);
+                let seqs: Vec<&[u8]> = ranges.iter().map(|r| &seq_bytes[r.clone()]).collect();
+                PwmHits::of(pwms, pwm_min_scores, &seqs)
+            })
+        })
+        .collect();
+
+    let circular = opt.is_circular(chr);
+    let code_cache = match shuffle_seed {
+        None => opt.code_cache.as_deref(),
+        Some(_) => None,
+    };
+    let streaming = can_stream_counts(opt, kmer_specs, circular, code_cache);
+    let code_options = CodeOptions {
+        circular,
+        code_cache,
+        chunk_size: opt.chunk_size,
+        minimizer_w: opt.window_w.map(|w| w as usize),
+        position_stride: opt.position_stride.map(|s| s as usize),
+        boundary: opt.kmer_boundary,
+        // Reverse complements of motifs are counted to become motifs later
+        motif_codes: motif_codes.map(|codes| {
+            match opt.canonical || opt.stranded || opt.strand != StrandMode::Forward {
+                true => &codes.either_strand,
+                false => &codes.listed,
+            }
+        }),
+        // Only skipped when every k-mer of a code ends up excluded
+        excluded_codes: excluded_codes.and_then(|codes| {
+            match (
+                opt.canonical,
+                opt.stranded || opt.strand != StrandMode::Forward,
+            ) {
+                (true, _) => Some(&codes.either_strand),
+                (false, false) => Some(&codes.listed),
+                (false, true) => None,
+            }
+        }),
+        streaming,
+        profiler,
+    };
+    let mut counts_by_window =
+        count_seq_windows(chr, &seq_bytes, kmer_specs, &count_windows, &code_options)?;
+
+    // Kmers by offset from the anchors, summed over the anchors
+    let mut profile = Vec::new();
+    if anchor_profile.is_some() {
+        let codes_by_k = seq_codes(chr, &seq_bytes, kmer_specs, &code_options)?;
+        let personal_anchors: Vec<u64> = anchors
+            .iter()
+            .map(|&pos| coord_map.to_personal(pos))
+            .collect();
+        profile = vec![WindowCounts::default(); 2 * opt.flank as usize + 1];
+        count_kmers_by_offset(
+            &mut profile,
+            &encs_of(&codes_by_k, kmer_specs),
+            &personal_anchors,
+            opt.flank,
+        );
+    }
+
+    // Split into kmers without and with soft-masked bases
+    let mut masked_counts = if opt.mask_mode == MaskMode::Separate {
+        mask_intervals_as_n(&mut seq_bytes, &soft_masked);
+        let code_options = CodeOptions {
+            code_cache: None,
+            ..code_options
+        };
+        let unmasked =
+            count_seq_windows(chr, &seq_bytes, kmer_specs, &count_windows, &code_options)?;
+        for (all, unmasked) in counts_by_window.iter_mut().zip(&unmasked) {
+            for (kmer, count) in all.iter_mut() {
+                *count = count.saturating_sub(unmasked.get(kmer).copied().unwrap_or(0));
+            }
+            all.retain(|_, count| *count > 0);
+        }
+        Some(std::mem::replace(&mut counts_by_window, unmasked))
+    } else {
+        None
+    };
+
+    // Count BED12 windows on their concatenated blocks
+    if !blocks.is_empty() {
+        for (i, w) in count_windows.iter().enumerate() {
+            if !blocks[w.2 as usize].is_empty() {
+                counts_by_window[i] =
+                    count_kmers_in_blocks(&seq_bytes, &window_ranges(w), kmer_specs);
+            }
+        }
+    }
+
+    // Delete seq_bytes from memory
+    drop(seq_bytes);
+
+    // Report '-' strand windows in their own orientation
+    if !minus_strand.is_empty() {
+        for (i, &(_, _, original_idx)) in count_windows.iter().enumerate() {
+            if !minus_strand[original_idx as usize] {
+                continue;
+            }
+            let counts = std::mem::take(&mut counts_by_window[i]);
+            counts_by_window[i] = revcomp_kmer_counts(counts, kmer_specs);
+            if let Some(masked) = masked_counts.as_mut() {
+                masked[i] = revcomp_kmer_counts(std::mem::take(&mut masked[i]), kmer_specs);
+            }
+        }
+    }
+
+    // Collapse reverse complements on the codes before decoding
+    if opt.canonical {
+        for counts in counts_by_window
+            .iter_mut()
+            .chain(masked_counts.iter_mut().flatten())
+            .chain(profile.iter_mut())
+        {
+            *counts = canonical_kmer_counts(std::mem::take(counts), kmer_specs);
+        }
+    }
+
+    // Count the reverse strand, either into the same counts or as extra
+    // window sets
+    match opt.strand {
+        StrandMode::Forward => {}
+        StrandMode::Both => {
+            for counts in counts_by_window
+                .iter_mut()
+                .chain(masked_counts.iter_mut().flatten())
+                .chain(profile.iter_mut())
+            {
+                *counts = both_strand_kmer_counts(std::mem::take(counts), kmer_specs);
+            }
+        }
+        StrandMode::Separate => {
+            for counts in std::iter::once(&mut counts_by_window).chain(masked_counts.as_mut()) {
+                let reverse: Vec<_> = counts
+                    .iter()
+                    .map(|c| revcomp_kmer_counts(c.clone(), kmer_specs))
+                    .collect();
+                counts.extend(reverse);
+            }
+        }
+    }
+
+    // Drop reverse complements of `--motif-list` motifs counted above (and
+    // unlisted k-mers of BED12 windows)
+    if let Some(codes) = motif_codes {
+        for counts in counts_by_window
+            .iter_mut()
+            .chain(masked_counts.iter_mut().flatten())
+            .chain(profile.iter_mut())
+        {
+            counts.retain(|kmer, _| codes.is_listed(kmer));
+        }
+    }
+    if let Some(codes) = excluded_codes {
+        for counts in counts_by_window
+            .iter_mut()
+            .chain(masked_counts.iter_mut().flatten())
+            .chain(profile.iter_mut())
+        {
+            counts.retain(|kmer, _| !codes.is_listed(kmer));
+        }
+    }
+    if let Some(total) = anchor_profile {
+        let mut total = total.lock().unwrap();
+        for (sums, counts) in total.iter_mut().zip(profile) {
+            for (kmer, count) in counts {
+                *sums.entry(kmer).or_insert(0) += count;
+            }
+        }
+    }
+
+    let num_windows = windows.len();
+
+    let bin_info = {
+        // build bin_info from the exact BED windows
+        let mut bl_ptr = 0;
+        let mut prev_start = 0;
+        let mut bin_info = Vec::with_capacity(num_windows);
+        for (
+            (((win_start, mut win_end, original_win_idx), win_positions), win_repeats),
+            win_pwm_hits,
+        ) in windows
+            .iter()
+            .cloned()
+            .zip(positions)
+            .zip(repeats)
+            .zip(pwm_hits)
         {
+            // The blacklist pointer only moves forward; restart it for the
+            // next window set and for unsorted random windows
+            if win_start < prev_start {
+                bl_ptr = blacklist_intervals.partition_point(|&(_, e)| e <= win_start);
+            }
+            prev_start = win_start;
             win_end = win_end.min(chrom_len as u64);
             let overlap_perc =
                 compute_blacklist_overlap(blacklist_intervals, win_start, win_end, &mut bl_ptr);
-            bin_info.push((
-                chr.to_string(),
-                win_start,
-                win_end,
-                original_win_idx,
+            bin_info.push(BinInfo {
+                chrom: chr.to_string(),
+                start: win_start,
+                end: win_end,
+                original_idx: original_win_idx,
                 overlap_perc,
-            )); // total,
+                positions: win_positions,
+                repeats: win_repeats,
+                pwm_hits: win_pwm_hits,
+            });
         }
         bin_info
     };
+    // Reverse-strand sets share the metadata of the forward sets
+    let bin_info = match opt.strand {
+        StrandMode::Separate => {
+            set_lengths.extend_from_within(..);
+            [bin_info.clone(), bin_info].concat()
+        }
+        _ => bin_info,
+    };
+
+    // Split into one result per window set
+    let mut counts_by_window = counts_by_window.into_iter();
+    let mut masked_counts = masked_counts.map(Vec::into_iter);
+    let mut bin_info = bin_info.into_iter();
+    Ok(set_lengths
+        .into_iter()
+        .map(|n| {
+            (
+                counts_by_window.by_ref().take(n).collect(),
+                masked_counts.as_mut().map(|m| m.by_ref().take(n).collect()),
+                bin_info.by_ref().take(n).collect(),
+                chrom_positions,
+            )
+        })
+        .collect())
+}
+
+/// How positional kmer codes are built by `count_seq_windows`
+#[derive(Clone, Copy)]
+struct CodeOptions<'a> {
+    circular: bool,
+    /// Directory for reusing encoded kmer codes between runs
+    code_cache: Option<&'a Path>,
+    /// Encode and count `chunk_size` positions at a time
+    chunk_size: Option<u64>,
+    /// Only count the (w,k) minimizers of runs of `w` positions
+    minimizer_w: Option<usize>,
+    /// Only count the k-mers at positions that are multiples of this
+    position_stride: Option<usize>,
+    /// Whether k-mers running past the window end are counted
+    boundary: KmerBoundary,
+    /// Only count these codes by k (`--motif-list`)
+    motif_codes: Option<&'a BTreeMap<u8, FxHashSet<u64>>>,
+    /// Skip these codes by k (`--exclude-motifs`)
+    excluded_codes: Option<&'a BTreeMap<u8, FxHashSet<u64>>>,
+    /// Count straight from the rolling codes without positional code
+    /// vectors (see `can_stream_counts`)
+    streaming: bool,
+    /// Stage timings for `--profile`
+    profiler: &'a Profiler,
+}
+
+/// Whether windows can be counted straight from the rolling codes.
+///
+/// `--global`, `--per-sequence` and non-overlapping `--by-size` windows need
+/// no positional lookup, unless positional codes are requested (cached,
+/// chunked, circular or minimizer codes) or k-mers need a dictionary.
+fn can_stream_counts(
+    opt: &CountArgs,
+    kmer_specs: &BTreeMap<u8, KmerSpec>,
+    circular: bool,
+    code_cache: Option<&Path>,
+) -> bool {
+    let windows = match &opt.by_size {
+        Some(sizes) => sizes
+            .iter()
+            .all(|&size| opt.step.unwrap_or(size as u64) >= size as u64),
+        None => opt.global || opt.per_sequence,
+    };
+    windows
+        && !circular
+        && code_cache.is_none()
+        && opt.chunk_size.is_none()
+        && opt.window_w.is_none()
+        && kmer_specs
+            .values()
+            .all(|spec| spec.encoding() != Encoding::Dictionary)
+}
+
+/// Keep the codes selected by `options` (every `position_stride`-th
+/// position, listed and not excluded motifs).
+///
+/// `offset` is the sequence position of the first code
+fn filter_codes(
+    codes_by_k: &mut BTreeMap<u8, KmerCodes>,
+    kmer_specs: &BTreeMap<u8, KmerSpec>,
+    options: &CodeOptions,
+    offset: usize,
+) {
+    if let Some(stride) = options.position_stride {
+        for (k, codes) in codes_by_k.iter_mut() {
+            keep_strided_codes(codes, &kmer_specs[k], stride, offset);
+        }
+    }
+    if let Some(listed) = options.motif_codes {
+        for (k, codes) in codes_by_k.iter_mut() {
+            keep_listed_codes(codes, &kmer_specs[k], &listed[k]);
+        }
+    }
+    if let Some(excluded) = options.excluded_codes {
+        for (k, codes) in codes_by_k.iter_mut() {
+            if let Some(excluded) = excluded.get(k) {
+                drop_excluded_codes(codes, &kmer_specs[k], excluded);
+            }
+        }
+    }
+}
+
+/// Build the filtered positional kmer codes of all of `seq`
+fn seq_codes(
+    chr: &str,
+    seq: &[u8],
+    kmer_specs: &BTreeMap<u8, KmerSpec>,
+    options: &CodeOptions,
+) -> Result<BTreeMap<u8, KmerCodes>> {
+    let mut positional_codes_by_k: BTreeMap<u8, KmerCodes> = if let Some(dir) = options.code_cache {
+        load_or_build_codes_per_k(dir, chr, seq, kmer_specs, options.circular)?
+    } else if options.circular {
+        build_codes_per_k_circular(seq, kmer_specs)
+    } else {
+        build_codes_per_k(seq, kmer_specs)
+    };
+    // Keep only the codes of minimizers
+    if let Some(w) = options.minimizer_w {
+        for (k, codes) in positional_codes_by_k.iter_mut() {
+            keep_minimizers(codes, &kmer_specs[k], w);
+        }
+    }
+    filter_codes(&mut positional_codes_by_k, kmer_specs, options, 0);
+    Ok(positional_codes_by_k)
+}
+
+/// One `Enc` per kmer size of `codes_by_k`
+fn encs_of<'a>(
+    codes_by_k: &'a BTreeMap<u8, KmerCodes>,
+    kmer_specs: &BTreeMap<u8, KmerSpec>,
+) -> SmallVec<[Enc<'a>; 8]> {
+    kmer_specs
+        .iter()
+        .map(|(&k, spec)| Enc {
+            k,
+            codes: &codes_by_k[&k],
+            none: spec.sentinel_none(),
+            n: spec.sentinel_n(),
+        })
+        .collect()
+}
+
+/// Build positional kmer codes for `seq` and count them per window
+fn count_seq_windows(
+    chr: &str,
+    seq: &[u8],
+    kmer_specs: &BTreeMap<u8, KmerSpec>,
+    windows: &[(u64, u64, u64)],
+    options: &CodeOptions,
+) -> Result<Vec<WindowCounts>> {
+    let chrom_len = seq.len() as u64;
+    let layout = WindowLayout {
+        circular: options.circular,
+        boundary: options.boundary,
+        ..WindowLayout::new(windows, chrom_len)
+    };
+    let mut counts_by_window = vec![WindowCounts::default(); windows.len()];
+    if options.streaming {
+        let keep = |k: u8, pos: usize, code: u64| {
+            options
+                .position_stride
+                .is_none_or(|stride| pos.is_multiple_of(stride))
+                && options
+                    .motif_codes
+                    .is_none_or(|listed| listed[&k].contains(&code))
+                && !options
+                    .excluded_codes
+                    .is_some_and(|excluded| excluded.get(&k).is_some_and(|e| e.contains(&code)))
+        };
+        let specs: Vec<&KmerSpec> = kmer_specs.values().collect();
+        let source = KmerSource::Sequence {
+            seq,
+            specs: &specs,
+            keep: &keep,
+        };
+        options.profiler.time(Some(chr), Stage::Count, || {
+            count_kmers(&mut counts_by_window, &layout, source)
+        });
+        return Ok(counts_by_window);
+    }
+    if let Some(chunk_size) = options.chunk_size {
+        let mut chunk_start = 0;
+        while chunk_start < chrom_len {
+            let chunk_end = (chunk_start + chunk_size).min(chrom_len);
+            let codes_by_k = options.profiler.time(Some(chr), Stage::Encode, || {
+                let mut codes_by_k = build_codes_per_k_chunk(
+                    seq,
+                    kmer_specs,
+                    chunk_start as usize,
+                    chunk_end as usize,
+                    options.circular,
+                );
+                filter_codes(&mut codes_by_k, kmer_specs, options, chunk_start as usize);
+                codes_by_k
+            });
+            options.profiler.time(Some(chr), Stage::Count, || {
+                let source = KmerSource::Codes {
+                    encs: &encs_of(&codes_by_k, kmer_specs),
+                    chunk: (chunk_start, chunk_end),
+                };
+                count_kmers(&mut counts_by_window, &layout, source)
+            });
+            chunk_start = chunk_end;
+        }
+        return Ok(counts_by_window);
+    }
 
-    Ok((counts_by_window, bin_info))
+    let positional_codes_by_k = options.profiler.time(Some(chr), Stage::Encode, || {
+        seq_codes(chr, seq, kmer_specs, options)
+    })?;
+    options.profiler.time(Some(chr), Stage::Count, || {
+        let source = KmerSource::Codes {
+            encs: &encs_of(&positional_codes_by_k, kmer_specs),
+            chunk: (0, chrom_len),
+        };
+        count_kmers(&mut counts_by_window, &layout, source)
+    });
+    Ok(counts_by_window)
 }
@@ -0,0 +1,149 @@
+use anyhow::{bail, Context, Result};
+use flate2::read::MultiGzDecoder;
+use std::fs::File;
+use std::io::{BufReader, ErrorKind, Read};
+use std::path::Path;
+
+/// Read is paired in sequencing
+pub const FLAG_PAIRED: u16 = 0x1;
+/// Read is unmapped
+pub const FLAG_UNMAPPED: u16 = 0x4;
+/// Read is on the reverse strand
+pub const FLAG_REVERSE: u16 = 0x10;
+/// First read of a pair
+pub const FLAG_READ1: u16 = 0x40;
+
+/// The fields of a BAM alignment record used by the read tools
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BamRecord {
+    /// Reference index (`-1` when unmapped)
+    pub ref_id: i32,
+    /// 0-based leftmost position
+    pub pos: i64,
+    pub mapq: u8,
+    pub flag: u16,
+    /// Signed template (fragment) length; 0 when unknown
+    pub tlen: i64,
+    /// Read sequence in reference orientation (`=ACMGRSVTWYHKDBN` letters)
+    pub seq: Vec<u8>,
+}
+
+impl BamRecord {
+    pub fn is_reverse(&self) -> bool {
+        self.flag & FLAG_REVERSE != 0
+    }
+}
+
+/// Sequential reader of (BGZF-compressed) BAM files.
+///
+/// Only the header and the fixed fields and sequence of records are
+/// decoded; CIGAR, qualities and tags are skipped.
+pub struct BamReader<R: Read> {
+    inner: R,
+    /// Reference names and lengths from the header
+    references: Vec<(String, u64)>,
+    block: Vec<u8>,
+}
+
+impl BamReader<MultiGzDecoder<BufReader<File>>> {
+    pub fn open(path: &Path) -> Result<Self> {
+        let f = File::open(path).context(format!("Opening BAM file {:?}", path))?;
+        Self::new(MultiGzDecoder::new(BufReader::new(f)))
+            .context(format!("Reading BAM header of {:?}", path))
+    }
+}
+
+impl<R: Read> BamReader<R> {
+    /// Read the header from the decompressed BAM stream `inner`
+    pub fn new(mut inner: R) -> Result<Self> {
+        let mut magic = [0u8; 4];
+        inner.read_exact(&mut magic).context("Missing BAM magic")?;
+        if &magic != b"BAM\x01" {
+            bail!("Not a BAM file");
+        }
+        let l_text = read_i32(&mut inner)?;
+        std::io::copy(&mut (&mut inner).take(l_text as u64), &mut std::io::sink())?;
+        let n_ref = read_i32(&mut inner)?;
+        let mut references = Vec::with_capacity(n_ref.max(0) as usize);
+        for _ in 0..n_ref {
+            let l_name = read_i32(&mut inner)?;
+            let mut name = vec![0u8; l_name as usize];
+            inner.read_exact(&mut name)?;
+            let name = String::from_utf8_lossy(name.strip_suffix(b"\0").unwrap_or(&name));
+            let l_ref = read_i32(&mut inner)?;
+            references.push((name.into_owned(), l_ref as u64));
+        }
+        Ok(BamReader {
+            inner,
+            references,
+            block: Vec::new(),
+        })
+    }
+
+    /// Reference names and lengths in header order
+    pub fn references(&self) -> &[(String, u64)] {
+        &self.references
+    }
+
+    /// The next record or `None` at the end of the file
+    pub fn read_record(&mut self) -> Result<Option<BamRecord>> {
+        let mut size = [0u8; 4];
+        match self.inner.read_exact(&mut size) {
+            Ok(()) => {}
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e).context("Reading BAM record"),
+        }
+        let block_size = i32::from_le_bytes(size);
+        if block_size < 32 {
+            bail!("Invalid BAM record size {}", block_size);
+        }
+        self.block.resize(block_size as usize, 0);
+        self.inner
+            .read_exact(&mut self.block)
+            .context("Truncated BAM record")?;
+        parse_record(&self.block)
+    }
+}
+
+impl<R: Read> Iterator for BamReader<R> {
+    type Item = Result<BamRecord>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_record().transpose()
+    }
+}
+
+fn read_i32(r: &mut impl Read) -> Result<i32> {
+    let mut b = [0u8; 4];
+    r.read_exact(&mut b).context("Truncated BAM header")?;
+    Ok(i32::from_le_bytes(b))
+}
+
+fn parse_record(block: &[u8]) -> Result<Option<BamRecord>> {
+    let i32_at = |at: usize| i32::from_le_bytes(block[at..at + 4].try_into().unwrap());
+    let u16_at = |at: usize| u16::from_le_bytes(block[at..at + 2].try_into().unwrap());
+    let l_read_name = block[8] as usize;
+    let n_cigar_op = u16_at(12) as usize;
+    let l_seq = i32_at(16).max(0) as usize;
+    let seq_start = 32 + l_read_name + 4 * n_cigar_op;
+    let seq_end = seq_start + l_seq.div_ceil(2);
+    if seq_end + l_seq > block.len() {
+        bail!("Truncated BAM record");
+    }
+    const BASES: &[u8; 16] = b"=ACMGRSVTWYHKDBN";
+    let seq = (0..l_seq)
+        .map(|i| {
+            let byte = block[seq_start + i / 2];
+            let code = if i % 2 == 0 { byte >> 4 } else { byte & 0xF };
+            BASES[code as usize]
+        })
+        .collect();
+    Ok(Some(BamRecord {
+        ref_id: i32_at(0),
+        pos: i32_at(4) as i64,
+        mapq: block[9],
+        flag: u16_at(14),
+        tlen: i32_at(28) as i64,
+        seq,
+    }))
+}
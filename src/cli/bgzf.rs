@@ -0,0 +1,105 @@
+use anyhow::Context;
+use flate2::{write::DeflateEncoder, Compression, Crc};
+use std::io::{self, Write};
+
+/// Uncompressed bytes per BGZF block (as used by `bgzip`)
+const BLOCK_DATA_LEN: usize = 0xff00;
+
+/// Largest allowed size of a compressed block
+const MAX_BLOCK_LEN: usize = 1 << 16;
+
+/// Empty block that marks the end of a BGZF file
+const EOF_BLOCK: [u8; 28] = [
+    0x1f, 0x8b, 0x08, 0x04, 0x00, 0x00, 0x00, 0x00, 0x00, 0xff, 0x06, 0x00, 0x42, 0x43, 0x02, 0x00,
+    0x1b, 0x00, 0x03, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+];
+
+/// Writer of BGZF (blocked gzip) files, readable by `gzip`/`zcat` and
+/// indexable with `tabix`.
+///
+/// `virtual_offset()` gives the position of the next byte as
+/// `compressed block offset << 16 | offset within the block`.
+pub struct BgzfWriter<W: Write> {
+    inner: W,
+    /// Uncompressed data of the current block
+    buf: Vec<u8>,
+    /// Compressed offset of the current block
+    block_offset: u64,
+}
+
+impl<W: Write> BgzfWriter<W> {
+    pub fn new(inner: W) -> Self {
+        BgzfWriter {
+            inner,
+            buf: Vec::with_capacity(BLOCK_DATA_LEN),
+            block_offset: 0,
+        }
+    }
+
+    /// Virtual offset of the next written byte
+    pub fn virtual_offset(&self) -> u64 {
+        (self.block_offset << 16) | self.buf.len() as u64
+    }
+
+    /// Compress and write the current block
+    fn write_block(&mut self) -> io::Result<()> {
+        if self.buf.is_empty() {
+            return Ok(());
+        }
+        let mut data = deflate(&self.buf, Compression::default())?;
+        if data.len() + 26 > MAX_BLOCK_LEN {
+            // Incompressible data; stored blocks always fit
+            data = deflate(&self.buf, Compression::none())?;
+        }
+        let block_len = data.len() + 26;
+        let mut crc = Crc::new();
+        crc.update(&self.buf);
+
+        let mut header = [
+            0x1f, 0x8b, 0x08, 0x04, 0x00, 0x00, 0x00, 0x00, 0x00, 0xff, 0x06, 0x00, 0x42, 0x43,
+            0x02, 0x00, 0x00, 0x00,
+        ];
+        header[16..18].copy_from_slice(&((block_len - 1) as u16).to_le_bytes());
+        self.inner.write_all(&header)?;
+        self.inner.write_all(&data)?;
+        self.inner.write_all(&crc.sum().to_le_bytes())?;
+        self.inner
+            .write_all(&(self.buf.len() as u32).to_le_bytes())?;
+        self.block_offset += block_len as u64;
+        self.buf.clear();
+        Ok(())
+    }
+
+    /// Write the last block and the end-of-file marker
+    pub fn finish(mut self) -> anyhow::Result<W> {
+        self.write_block().context("Writing BGZF block")?;
+        self.inner
+            .write_all(&EOF_BLOCK)
+            .context("Writing BGZF end-of-file block")?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
+
+impl<W: Write> Write for BgzfWriter<W> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        let n = data.len().min(BLOCK_DATA_LEN - self.buf.len());
+        self.buf.extend_from_slice(&data[..n]);
+        if self.buf.len() == BLOCK_DATA_LEN {
+            self.write_block()?;
+        }
+        Ok(n)
+    }
+
+    /// Only flushes complete blocks; the current block is written by `finish`
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Raw deflate stream of `data`
+fn deflate(data: &[u8], level: Compression) -> io::Result<Vec<u8>> {
+    let mut encoder = DeflateEncoder::new(Vec::with_capacity(data.len() / 2), level);
+    encoder.write_all(data)?;
+    encoder.finish()
+}
@@ -0,0 +1,447 @@
+use anyhow::{bail, Context};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// Whether an output "path" is an `s3://` or `gs://` URL
+pub fn is_object_store_url(path: &Path) -> bool {
+    path.to_str()
+        .is_some_and(|p| p.starts_with("s3://") || p.starts_with("gs://"))
+}
+
+/// Split an `s3://bucket/prefix` or `gs://bucket/prefix` URL into
+/// `(scheme, bucket, prefix)`; the prefix has no leading or trailing `/`
+pub fn parse_object_store_url(url: &str) -> anyhow::Result<(&str, &str, &str)> {
+    let Some((scheme, rest)) = url.split_once("://") else {
+        bail!("Invalid object storage URL {}", url);
+    };
+    if !matches!(scheme, "s3" | "gs") {
+        bail!(
+            "Unsupported object storage URL {} (use s3:// or gs://)",
+            url
+        );
+    }
+    let (bucket, prefix) = rest.split_once('/').unwrap_or((rest, ""));
+    if bucket.is_empty() {
+        bail!("Object storage URL {} has no bucket", url);
+    }
+    Ok((scheme, bucket, prefix.trim_matches('/')))
+}
+
+/// A file created by `create_output`: a local file or, below a directory
+/// staged for object storage, a multipart upload
+pub enum OutputFile {
+    Local(BufWriter<File>),
+    #[cfg(feature = "object-storage")]
+    Remote(Box<BufWriter<store::ObjectWriter>>),
+}
+
+impl OutputFile {
+    /// Flush the file and, for uploads, complete the object
+    pub fn finish(self) -> anyhow::Result<()> {
+        match self {
+            OutputFile::Local(mut out) => Ok(out.flush()?),
+            #[cfg(feature = "object-storage")]
+            OutputFile::Remote(out) => out.into_inner().map_err(|e| e.into_error())?.finish(),
+        }
+    }
+}
+
+impl Write for OutputFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            OutputFile::Local(out) => out.write(buf),
+            #[cfg(feature = "object-storage")]
+            OutputFile::Remote(out) => out.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            OutputFile::Local(out) => out.flush(),
+            #[cfg(feature = "object-storage")]
+            OutputFile::Remote(out) => out.flush(),
+        }
+    }
+}
+
+/// A file that `create_output` uploaded instead of writing it locally
+#[derive(Debug, Clone)]
+pub struct StreamedFile {
+    /// Local path the upload stands in for
+    pub path: PathBuf,
+    pub sha256: String,
+    pub bytes: u64,
+}
+
+/// Directories registered with `OutputRoute`: files below them are uploaded
+/// to the store or, for `None`, kept local. The deepest directory wins.
+type Routes = Vec<(PathBuf, Option<Arc<ObjectStoreDir>>)>;
+
+static ROUTES: Mutex<Routes> = Mutex::new(Vec::new());
+
+/// Where `create_output` writes the files below a directory; the
+/// registration is removed when dropped
+pub struct OutputRoute {
+    dir: PathBuf,
+}
+
+impl OutputRoute {
+    /// Upload files created with `create_output` below `dir` to `remote`
+    pub fn upload(dir: &Path, remote: Arc<ObjectStoreDir>) -> Self {
+        Self::register(dir, Some(remote))
+    }
+
+    /// Write files below `dir` locally even when a parent directory is
+    /// uploaded, e.g. files that are read again before the run ends
+    pub fn local(dir: &Path) -> Self {
+        Self::register(dir, None)
+    }
+
+    fn register(dir: &Path, remote: Option<Arc<ObjectStoreDir>>) -> Self {
+        ROUTES.lock().unwrap().push((dir.to_path_buf(), remote));
+        Self {
+            dir: dir.to_path_buf(),
+        }
+    }
+}
+
+impl Drop for OutputRoute {
+    fn drop(&mut self) {
+        let mut routes = ROUTES.lock().unwrap();
+        if let Some(i) = routes.iter().rposition(|(dir, _)| *dir == self.dir) {
+            routes.remove(i);
+        }
+    }
+}
+
+/// Uploading directory (and the directory itself) that `path` is below
+fn route(path: &Path) -> Option<(PathBuf, Arc<ObjectStoreDir>)> {
+    let routes = ROUTES.lock().unwrap();
+    let (dir, remote) = routes
+        .iter()
+        .filter(|(dir, _)| path.starts_with(dir))
+        .max_by_key(|(dir, _)| dir.components().count())?;
+    Some((dir.clone(), Arc::clone(remote.as_ref()?)))
+}
+
+/// Create `path` for writing.
+///
+/// Below a directory staged for object storage (see `OutputRoute`), the
+/// file is streamed to the store as a multipart upload instead, so large
+/// matrices never touch the local disk. Call `OutputFile::finish` when done.
+pub fn create_output(path: &Path) -> anyhow::Result<OutputFile> {
+    match route(path) {
+        Some((dir, remote)) => remote.create(path, path.strip_prefix(&dir)?),
+        None => {
+            let file = File::create(path).context(format!("Create {:?} fail", path))?;
+            Ok(OutputFile::Local(BufWriter::new(file)))
+        }
+    }
+}
+
+/// Files below `dir` that `create_output` uploaded instead of writing
+/// them locally
+pub fn streamed_files(dir: &Path) -> Vec<StreamedFile> {
+    let remotes: Vec<_> = ROUTES
+        .lock()
+        .unwrap()
+        .iter()
+        .filter_map(|(_, remote)| remote.clone())
+        .collect();
+    remotes
+        .iter()
+        .flat_map(|remote| remote.streamed())
+        .filter(|file| file.path.starts_with(dir))
+        .collect()
+}
+
+#[cfg(feature = "object-storage")]
+mod store {
+    use super::{parse_object_store_url, OutputFile, StreamedFile};
+    use anyhow::Context;
+    use object_store::{path::Path as ObjectPath, ObjectStore, WriteMultipart};
+    use sha2::{Digest, Sha256};
+    use std::collections::HashSet;
+    use std::fs::File;
+    use std::io::{self, BufWriter, Read, Write};
+    use std::path::{Path, PathBuf};
+    use std::sync::{Arc, Mutex};
+
+    /// Bytes per uploaded part
+    const PART_SIZE: usize = 8 << 20;
+
+    /// Parts uploaded concurrently per file
+    const MAX_CONCURRENT_PARTS: usize = 4;
+
+    /// Bucket and prefix of an output URL with a client configured from
+    /// the environment (`AWS_*` or `GOOGLE_*` variables)
+    pub struct ObjectStoreDir {
+        url: String,
+        store: Box<dyn ObjectStore>,
+        prefix: ObjectPath,
+        runtime: tokio::runtime::Runtime,
+        /// Objects written with `create` during the run
+        streamed: Mutex<Vec<(ObjectPath, StreamedFile)>>,
+    }
+
+    impl ObjectStoreDir {
+        pub fn open(url: &str) -> anyhow::Result<Self> {
+            let (scheme, bucket, prefix) = parse_object_store_url(url)?;
+            let store: Box<dyn ObjectStore> = match scheme {
+                "s3" => Box::new(
+                    object_store::aws::AmazonS3Builder::from_env()
+                        .with_bucket_name(bucket)
+                        .build()
+                        .context(format!("Configuring S3 client for {}", url))?,
+                ),
+                _ => Box::new(
+                    object_store::gcp::GoogleCloudStorageBuilder::from_env()
+                        .with_bucket_name(bucket)
+                        .build()
+                        .context(format!("Configuring GCS client for {}", url))?,
+                ),
+            };
+            let runtime = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .context("Starting object storage runtime fail")?;
+            Ok(Self {
+                url: url.to_string(),
+                store,
+                prefix: ObjectPath::from(prefix),
+                runtime,
+                streamed: Mutex::new(Vec::new()),
+            })
+        }
+
+        /// Whether the URL points at the bucket root
+        pub fn is_bucket_root(&self) -> bool {
+            self.prefix.as_ref().is_empty()
+        }
+
+        /// Location of `rel` (a path relative to the staging directory)
+        fn location(&self, rel: &Path) -> ObjectPath {
+            rel.components().fold(self.prefix.clone(), |path, c| {
+                path.child(c.as_os_str().to_string_lossy().as_ref())
+            })
+        }
+
+        /// Start a multipart upload of `rel`, standing in for the local `path`
+        pub fn create(self: &Arc<Self>, path: &Path, rel: &Path) -> anyhow::Result<OutputFile> {
+            let location = self.location(rel);
+            let upload = self
+                .runtime
+                .block_on(self.store.put_multipart(&location))
+                .context(format!("Starting upload of {}", location))?;
+            let writer = ObjectWriter {
+                dir: Arc::clone(self),
+                path: path.to_path_buf(),
+                location,
+                upload: WriteMultipart::new_with_chunk_size(upload, PART_SIZE),
+                hasher: Sha256::new(),
+                bytes: 0,
+            };
+            Ok(OutputFile::Remote(Box::new(BufWriter::with_capacity(
+                PART_SIZE, writer,
+            ))))
+        }
+
+        /// Files uploaded with `create` so far
+        pub fn streamed(&self) -> Vec<StreamedFile> {
+            let streamed = self.streamed.lock().unwrap();
+            streamed.iter().map(|(_, file)| file.clone()).collect()
+        }
+
+        /// All objects below the prefix
+        pub fn list(&self) -> anyhow::Result<Vec<ObjectPath>> {
+            self.runtime.block_on(async {
+                let mut objects = Vec::new();
+                let mut pending = vec![self.prefix.clone()];
+                while let Some(prefix) = pending.pop() {
+                    let listing = self
+                        .store
+                        .list_with_delimiter(Some(&prefix))
+                        .await
+                        .context(format!("Listing {}", self.url))?;
+                    objects.extend(listing.objects.into_iter().map(|o| o.location));
+                    pending.extend(listing.common_prefixes);
+                }
+                Ok(objects)
+            })
+        }
+
+        /// Upload every file below `dir` with multipart uploads, then
+        /// delete objects below the prefix that were neither part of the
+        /// upload nor written with `create` (unless the prefix is empty)
+        pub fn replace_with(&self, dir: &Path) -> anyhow::Result<()> {
+            let mut files = Vec::new();
+            collect_files(dir, &mut files)?;
+            files.sort();
+            let mut uploaded: HashSet<ObjectPath> = {
+                let streamed = self.streamed.lock().unwrap();
+                streamed
+                    .iter()
+                    .map(|(location, _)| location.clone())
+                    .collect()
+            };
+            for file in &files {
+                let location = self.location(file.strip_prefix(dir)?);
+                self.upload_file(file, &location)?;
+                uploaded.insert(location);
+            }
+            // Never clear a whole bucket (`StagedOutputDir` refuses `--force` there)
+            if self.is_bucket_root() {
+                return Ok(());
+            }
+            for stale in self.list()? {
+                if !uploaded.contains(&stale) {
+                    self.runtime
+                        .block_on(self.store.delete(&stale))
+                        .context(format!("Deleting previous output {}", stale))?;
+                }
+            }
+            Ok(())
+        }
+
+        fn upload_file(&self, file: &Path, location: &ObjectPath) -> anyhow::Result<()> {
+            let mut reader = File::open(file).context(format!("Opening {:?}", file))?;
+            self.runtime.block_on(async {
+                let upload = self
+                    .store
+                    .put_multipart(location)
+                    .await
+                    .context(format!("Starting upload of {}", location))?;
+                let mut writer = WriteMultipart::new_with_chunk_size(upload, PART_SIZE);
+                let mut buf = vec![0u8; PART_SIZE];
+                loop {
+                    let n = reader
+                        .read(&mut buf)
+                        .context(format!("Reading {:?}", file))?;
+                    if n == 0 {
+                        break;
+                    }
+                    writer.wait_for_capacity(MAX_CONCURRENT_PARTS).await?;
+                    writer.write(&buf[..n]);
+                }
+                writer
+                    .finish()
+                    .await
+                    .context(format!("Uploading {:?} to {}", file, location))?;
+                anyhow::Ok(())
+            })
+        }
+    }
+
+    /// Multipart upload of a single object, fed through `Write`
+    pub struct ObjectWriter {
+        dir: Arc<ObjectStoreDir>,
+        path: PathBuf,
+        location: ObjectPath,
+        upload: WriteMultipart,
+        hasher: Sha256,
+        bytes: u64,
+    }
+
+    impl ObjectWriter {
+        /// Complete the upload and record it with its checksum
+        pub fn finish(self) -> anyhow::Result<()> {
+            let ObjectWriter {
+                dir,
+                path,
+                location,
+                upload,
+                hasher,
+                bytes,
+            } = self;
+            dir.runtime
+                .block_on(upload.finish())
+                .context(format!("Uploading {}", location))?;
+            let sha256 = hasher
+                .finalize()
+                .iter()
+                .map(|b| format!("{b:02x}"))
+                .collect();
+            let file = StreamedFile {
+                path,
+                sha256,
+                bytes,
+            };
+            dir.streamed.lock().unwrap().push((location, file));
+            Ok(())
+        }
+    }
+
+    impl Write for ObjectWriter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            let upload = &mut self.upload;
+            self.dir
+                .runtime
+                .block_on(async {
+                    upload.wait_for_capacity(MAX_CONCURRENT_PARTS).await?;
+                    upload.write(buf);
+                    Ok::<_, object_store::Error>(())
+                })
+                .map_err(|e| io::Error::other(format!("Uploading {}: {}", self.location, e)))?;
+            self.hasher.update(buf);
+            self.bytes += buf.len() as u64;
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn collect_files(dir: &Path, files: &mut Vec<PathBuf>) -> anyhow::Result<()> {
+        for entry in std::fs::read_dir(dir).context(format!("Reading {:?}", dir))? {
+            let entry = entry?;
+            if entry.file_type()?.is_dir() {
+                collect_files(&entry.path(), files)?;
+            } else {
+                files.push(entry.path());
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "object-storage")]
+pub use store::ObjectStoreDir;
+
+/// Stand-in used when built without the `object-storage` feature
+#[cfg(not(feature = "object-storage"))]
+pub struct ObjectStoreDir;
+
+#[cfg(not(feature = "object-storage"))]
+impl ObjectStoreDir {
+    pub fn open(url: &str) -> anyhow::Result<Self> {
+        parse_object_store_url(url)?;
+        bail!(
+            "Writing to {} requires object storage support; \
+             rebuild with `cargo install --features object-storage`",
+            url
+        )
+    }
+
+    pub fn list(&self) -> anyhow::Result<Vec<String>> {
+        unreachable!()
+    }
+
+    pub fn is_bucket_root(&self) -> bool {
+        unreachable!()
+    }
+
+    pub fn create(self: &Arc<Self>, _path: &Path, _rel: &Path) -> anyhow::Result<OutputFile> {
+        unreachable!()
+    }
+
+    pub fn streamed(&self) -> Vec<StreamedFile> {
+        unreachable!()
+    }
+
+    pub fn replace_with(&self, _dir: &Path) -> anyhow::Result<()> {
+        unreachable!()
+    }
+}
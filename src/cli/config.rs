@@ -0,0 +1,400 @@
+use anyhow::{bail, Context, Result};
+use clap::{Arg, ArgAction, Command};
+use serde::de::{self, DeserializeSeed, Deserializer, MapAccess, SeqAccess, Visitor};
+use std::collections::HashSet;
+use std::ffi::OsString;
+use std::fmt::Write as _;
+use std::path::{Path, PathBuf};
+
+/// Format of a `--config` file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigFormat {
+    Toml,
+    Yaml,
+}
+
+impl ConfigFormat {
+    /// YAML for `.yaml` and `.yml` files, TOML otherwise
+    pub fn of(path: &Path) -> Self {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("yaml" | "yml") => ConfigFormat::Yaml,
+            _ => ConfigFormat::Toml,
+        }
+    }
+}
+
+/// Options of a `--config` file as `(key, values)` in file order.
+///
+/// Config files are flat TOML (`key = value`) or YAML (`key: value`)
+/// documents whose values are strings, numbers, booleans or arrays of
+/// those. Keys are option ids (`kmer_sizes`) or long names (`kmer-sizes`)
+/// of `command`; other keys are rejected with the position in the file.
+pub fn parse_config(
+    text: &str,
+    format: ConfigFormat,
+    command: &Command,
+) -> Result<Vec<(String, Vec<String>)>> {
+    let entries = ConfigEntries { command };
+    match format {
+        ConfigFormat::Toml => {
+            let deserializer = toml::Deserializer::parse(text)?;
+            Ok(entries.deserialize(deserializer)?)
+        }
+        ConfigFormat::Yaml => Ok(entries.deserialize(serde_yaml::Deserializer::from_str(text))?),
+    }
+}
+
+/// Read and parse a config file (see `parse_config`)
+pub fn load_config(path: &Path, command: &Command) -> Result<Vec<(String, Vec<String>)>> {
+    let text = std::fs::read_to_string(path).context(format!("Reading config file {:?}", path))?;
+    parse_config(&text, ConfigFormat::of(path), command)
+        .context(format!("Parsing config file {:?}", path))
+}
+
+/// The option of `command` set by the config `key`
+fn find_option<'a>(command: &'a Command, key: &str) -> Option<&'a Arg> {
+    let id = key.replace('-', "_");
+    command
+        .get_arguments()
+        .find(|a| a.get_id() == id.as_str() || a.get_long() == Some(key))
+}
+
+/// Deserializes the top-level mapping of a config file
+struct ConfigEntries<'a> {
+    command: &'a Command,
+}
+
+impl<'de> DeserializeSeed<'de> for ConfigEntries<'_> {
+    type Value = Vec<(String, Vec<String>)>;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        deserializer.deserialize_any(self)
+    }
+}
+
+impl<'de> Visitor<'de> for ConfigEntries<'_> {
+    type Value = Vec<(String, Vec<String>)>;
+
+    fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str("`key = value` options")
+    }
+
+    /// An empty YAML document
+    fn visit_none<E: de::Error>(self) -> Result<Self::Value, E> {
+        Ok(Vec::new())
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+        let mut entries: Vec<(String, Vec<String>)> = Vec::new();
+        while let Some(key) = map.next_key_seed(ConfigKey {
+            command: self.command,
+        })? {
+            if entries.iter().any(|(k, _)| *k == key) {
+                return Err(de::Error::custom(format!(
+                    "'{}' is given more than once",
+                    key
+                )));
+            }
+            let values = map.next_value_seed(ConfigValues)?;
+            entries.push((key, values));
+        }
+        Ok(entries)
+    }
+}
+
+/// A key naming an option of `command`
+struct ConfigKey<'a> {
+    command: &'a Command,
+}
+
+impl<'de> DeserializeSeed<'de> for ConfigKey<'_> {
+    type Value = String;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        deserializer.deserialize_str(self)
+    }
+}
+
+impl<'de> Visitor<'de> for ConfigKey<'_> {
+    type Value = String;
+
+    fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str("an option name")
+    }
+
+    // Failing here (rather than after deserializing) lets the parsers
+    // report the position of the key
+    fn visit_str<E: de::Error>(self, key: &str) -> Result<Self::Value, E> {
+        match find_option(self.command, key) {
+            Some(_) => Ok(key.to_string()),
+            None => Err(E::custom(format!("unknown option '{}'", key))),
+        }
+    }
+}
+
+/// The values of a scalar or (flat) array
+struct ConfigValues;
+
+impl<'de> DeserializeSeed<'de> for ConfigValues {
+    type Value = Vec<String>;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        deserializer.deserialize_any(self)
+    }
+}
+
+impl<'de> Visitor<'de> for ConfigValues {
+    type Value = Vec<String>;
+
+    fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str("a string, number, boolean or an array of those")
+    }
+
+    fn visit_bool<E: de::Error>(self, v: bool) -> Result<Self::Value, E> {
+        ConfigScalar.visit_bool(v).map(|v| vec![v])
+    }
+
+    fn visit_i64<E: de::Error>(self, v: i64) -> Result<Self::Value, E> {
+        ConfigScalar.visit_i64(v).map(|v| vec![v])
+    }
+
+    fn visit_u64<E: de::Error>(self, v: u64) -> Result<Self::Value, E> {
+        ConfigScalar.visit_u64(v).map(|v| vec![v])
+    }
+
+    fn visit_f64<E: de::Error>(self, v: f64) -> Result<Self::Value, E> {
+        ConfigScalar.visit_f64(v).map(|v| vec![v])
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<Self::Value, E> {
+        ConfigScalar.visit_str(v).map(|v| vec![v])
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+        let mut values = Vec::new();
+        while let Some(value) = seq.next_element_seed(ConfigScalar)? {
+            values.push(value);
+        }
+        Ok(values)
+    }
+}
+
+/// A string, number or boolean as its command-line value
+struct ConfigScalar;
+
+impl<'de> DeserializeSeed<'de> for ConfigScalar {
+    type Value = String;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        deserializer.deserialize_any(self)
+    }
+}
+
+impl<'de> Visitor<'de> for ConfigScalar {
+    type Value = String;
+
+    fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str("a string, number or boolean")
+    }
+
+    fn visit_bool<E: de::Error>(self, v: bool) -> Result<Self::Value, E> {
+        Ok(v.to_string())
+    }
+
+    fn visit_i64<E: de::Error>(self, v: i64) -> Result<Self::Value, E> {
+        Ok(v.to_string())
+    }
+
+    fn visit_u64<E: de::Error>(self, v: u64) -> Result<Self::Value, E> {
+        Ok(v.to_string())
+    }
+
+    fn visit_f64<E: de::Error>(self, v: f64) -> Result<Self::Value, E> {
+        Ok(v.to_string())
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<Self::Value, E> {
+        Ok(v.to_string())
+    }
+}
+
+/// Insert the options of the `--config` file in `args` (program name
+/// first) before the command-line options, leaving out those given on the
+/// command line, which override the file.
+///
+/// The options belong to the subcommand given as the first argument, or
+/// to `command` itself. Arguments without `--config`, or of a command
+/// without a `config` option, are returned as they are.
+pub fn with_config_args(command: &Command, args: Vec<OsString>) -> Result<Vec<OsString>> {
+    let (command, first) = match args.get(1).and_then(|arg| arg.to_str()) {
+        Some(name) => match command.find_subcommand(name) {
+            Some(sub) => (sub, 2),
+            None => (command, 1),
+        },
+        None => (command, 1),
+    };
+    let Some(long) = command
+        .get_arguments()
+        .find(|arg| arg.get_id() == "config")
+        .and_then(Arg::get_long)
+    else {
+        return Ok(args);
+    };
+    let user_args = &args[first.min(args.len())..];
+    let flag = format!("--{}", long);
+    let mut path = None;
+    for (i, arg) in user_args.iter().enumerate() {
+        let arg = arg.to_string_lossy();
+        if arg == "--" {
+            break;
+        }
+        if arg == flag {
+            path = user_args.get(i + 1).map(PathBuf::from);
+        } else if let Some(value) = arg.strip_prefix(&format!("{}=", flag)) {
+            path = Some(PathBuf::from(value));
+        }
+    }
+    let Some(path) = path else {
+        return Ok(args);
+    };
+    let entries = load_config(&path, command)?;
+    let given = given_options(command, user_args);
+    let extra = config_args(command, &entries, &given)
+        .context(format!("Applying config file {:?}", path))?;
+    let mut out = args[..first.min(args.len())].to_vec();
+    out.extend(extra);
+    out.extend_from_slice(user_args);
+    Ok(out)
+}
+
+/// Ids of the options of `command` in the command-line arguments `args`
+pub fn given_options(command: &Command, args: &[OsString]) -> HashSet<String> {
+    let mut given = HashSet::new();
+    for arg in args {
+        let arg = arg.to_string_lossy();
+        if arg == "--" {
+            break;
+        }
+        if let Some(name) = arg.strip_prefix("--") {
+            let name = name.split_once('=').map_or(name, |(name, _)| name);
+            let found = command.get_arguments().find(|a| {
+                a.get_long() == Some(name)
+                    || a.get_all_aliases()
+                        .is_some_and(|aliases| aliases.contains(&name))
+            });
+            if let Some(a) = found {
+                given.insert(a.get_id().to_string());
+            }
+        } else if let Some(shorts) = arg.strip_prefix('-') {
+            // Bundled short flags, up to the first option taking a value
+            for c in shorts.chars() {
+                let found = command.get_arguments().find(|a| {
+                    a.get_short() == Some(c)
+                        || a.get_all_short_aliases()
+                            .is_some_and(|aliases| aliases.contains(&c))
+                });
+                let Some(a) = found else { break };
+                given.insert(a.get_id().to_string());
+                if a.get_action().takes_values() {
+                    break;
+                }
+            }
+        }
+    }
+    given
+}
+
+/// Command-line arguments for the config `entries` of `command`.
+///
+/// Keys are option ids (`kmer_sizes`) or long names (`kmer-sizes`).
+/// Options in `given` and values equal to the defaults are left out, and
+/// `false` flags are not set.
+pub fn config_args(
+    command: &Command,
+    entries: &[(String, Vec<String>)],
+    given: &HashSet<String>,
+) -> Result<Vec<OsString>> {
+    let mut args = Vec::new();
+    for (key, values) in entries {
+        let Some(arg) = find_option(command, key) else {
+            bail!("Unknown option '{}'", key);
+        };
+        let Some(long) = arg.get_long() else {
+            bail!("'{}' cannot be set in a config file", key);
+        };
+        if arg.get_id() == "config" {
+            bail!("Config files cannot include other config files");
+        }
+        if given.contains(arg.get_id().as_str()) {
+            continue;
+        }
+        if !arg.get_action().takes_values() {
+            match values.as_slice() {
+                [value] if value == "true" => args.push(format!("--{}", long).into()),
+                [value] if value == "false" => {}
+                _ => bail!("Expected true or false for '{}'", key),
+            }
+            continue;
+        }
+        let defaults: Vec<String> = arg
+            .get_default_values()
+            .iter()
+            .map(|v| v.to_string_lossy().into_owned())
+            .collect();
+        if !defaults.is_empty() && *values == defaults {
+            continue;
+        }
+        match (values.as_slice(), arg.get_action()) {
+            ([], _) => args.push(format!("--{}", long).into()),
+            (values, ArgAction::Append) => {
+                args.extend(values.iter().map(|v| format!("--{}={}", long, v).into()))
+            }
+            ([value], _) => args.push(format!("--{}={}", long, value).into()),
+            (values, _) => {
+                args.push(format!("--{}", long).into());
+                args.extend(values.iter().map(OsString::from));
+            }
+        }
+    }
+    Ok(args)
+}
+
+/// Write `(key, values)` options as a config file that `parse_config` reads
+pub fn write_config(path: &Path, options: &[(String, Vec<String>)]) -> Result<()> {
+    let mut text = String::from("# Resolved options of this run; reuse with `--config <file>`\n");
+    for (key, values) in options {
+        let values: Vec<String> = values.iter().map(|v| toml_value(v)).collect();
+        match values.as_slice() {
+            [value] => writeln!(text, "{} = {}", key, value)?,
+            values => writeln!(text, "{} = [{}]", key, values.join(", "))?,
+        }
+    }
+    std::fs::write(path, text).context(format!("Writing config file {:?}", path))
+}
+
+/// Bare booleans and numbers, other values as quoted strings
+fn toml_value(value: &str) -> String {
+    let bare = value == "true"
+        || value == "false"
+        || (value.parse::<f64>().is_ok() && value.chars().all(is_bare_char));
+    if bare {
+        return value.to_string();
+    }
+    let mut quoted = String::from('"');
+    for c in value.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\t' => quoted.push_str("\\t"),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Characters of bare (unquoted) values
+fn is_bare_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '+' | '.' | ':')
+}
@@ -0,0 +1,66 @@
+use crate::cli::io::open_maybe_gz;
+use anyhow::{bail, Context, Result};
+use std::io::BufRead;
+use std::path::Path;
+
+/// Sequential reader of the sequences of a (gzipped) FASTQ file
+pub struct FastqReader {
+    inner: Box<dyn BufRead>,
+    line: String,
+    n_records: u64,
+}
+
+impl FastqReader {
+    pub fn open(path: &Path) -> Result<Self> {
+        let inner = open_maybe_gz(path).context(format!("Opening FASTQ file {:?}", path))?;
+        Ok(Self::new(inner))
+    }
+
+    pub fn new(inner: Box<dyn BufRead>) -> Self {
+        FastqReader {
+            inner,
+            line: String::new(),
+            n_records: 0,
+        }
+    }
+
+    /// Read the sequence of the next record into `seq`. Returns `false` at
+    /// the end of the file.
+    pub fn read_seq(&mut self, seq: &mut Vec<u8>) -> Result<bool> {
+        // Header, skipping blank lines between records
+        loop {
+            self.line.clear();
+            if self.inner.read_line(&mut self.line)? == 0 {
+                return Ok(false);
+            }
+            if !self.line.trim_end().is_empty() {
+                break;
+            }
+        }
+        self.n_records += 1;
+        if !self.line.starts_with('@') {
+            bail!(
+                "FASTQ record {} does not start with '@': {}",
+                self.n_records,
+                self.line.trim_end()
+            );
+        }
+        seq.clear();
+        self.next_line("sequence")?;
+        seq.extend_from_slice(self.line.trim_end().as_bytes());
+        self.next_line("separator")?;
+        if !self.line.starts_with('+') {
+            bail!("FASTQ record {} has no '+' separator line", self.n_records);
+        }
+        self.next_line("quality")?;
+        Ok(true)
+    }
+
+    fn next_line(&mut self, part: &str) -> Result<()> {
+        self.line.clear();
+        if self.inner.read_line(&mut self.line)? == 0 {
+            bail!("FASTQ record {} has no {} line", self.n_records, part);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,75 @@
+use anyhow::{Context, Result};
+use signal_hook::consts::{SIGINT, SIGTERM};
+use std::fmt;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, OnceLock};
+
+/// Whether a signal was received and which one (0 for none)
+#[derive(Default)]
+struct Received {
+    any: Arc<AtomicBool>,
+    signal: Arc<AtomicUsize>,
+}
+
+static RECEIVED: OnceLock<Received> = OnceLock::new();
+
+fn received() -> &'static Received {
+    RECEIVED.get_or_init(Received::default)
+}
+
+/// Handle SIGINT and SIGTERM by recording the signal, so the run can stop
+/// at the next chromosome and clean up. A second signal exits at once.
+pub fn install_handlers() -> Result<()> {
+    let received = received();
+    for sig in [SIGINT, SIGTERM] {
+        // Registered first so it only exits when an earlier signal was received
+        signal_hook::flag::register_conditional_shutdown(sig, 128 + sig, Arc::clone(&received.any))
+            .context("Installing signal handlers")?;
+        signal_hook::flag::register(sig, Arc::clone(&received.any))
+            .context("Installing signal handlers")?;
+        signal_hook::flag::register_usize(sig, Arc::clone(&received.signal), sig as usize)
+            .context("Installing signal handlers")?;
+    }
+    Ok(())
+}
+
+/// Error of a run stopped by a signal
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Interrupted {
+    pub signal: i32,
+}
+
+impl Interrupted {
+    /// Exit code of the process: 128 + the signal number, as shells use
+    pub fn exit_code(&self) -> i32 {
+        128 + self.signal
+    }
+}
+
+impl fmt::Display for Interrupted {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self.signal {
+            SIGINT => "SIGINT",
+            SIGTERM => "SIGTERM",
+            _ => "a signal",
+        };
+        write!(f, "Interrupted by {}", name)
+    }
+}
+
+impl std::error::Error for Interrupted {}
+
+/// Fail with `Interrupted` once a handled signal was received
+pub fn check_interrupted() -> Result<()> {
+    match received().signal.load(Ordering::Relaxed) {
+        0 => Ok(()),
+        sig => Err(Interrupted { signal: sig as i32 }.into()),
+    }
+}
+
+/// The `Interrupted` error in the chain of `err`, if any
+pub fn interrupted_by(err: &anyhow::Error) -> Option<Interrupted> {
+    err.chain()
+        .find_map(|cause| cause.downcast_ref::<Interrupted>())
+        .copied()
+}
@@ -1,17 +1,732 @@
-use anyhow::Context;
+use crate::cli::cloud::{is_object_store_url, ObjectStoreDir, OutputRoute};
+use crate::cli::remote::{is_url, HttpRangeReader};
+use anyhow::{bail, Context};
+use clap::ValueEnum;
+use flate2::read::MultiGzDecoder;
+use memmap2::Mmap;
 
-use std::path::Path;
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{BufRead, BufReader, Cursor, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
 use twobit::TwoBitFile;
 // BAM
 
+// Reference sequences
+
+/// A reference from which whole sequences can be read by name.
+///
+/// Implementations must be shareable between threads; each `read` call
+/// is independent (e.g. opens its own file handle).
+pub trait SequenceSource: Send + Sync {
+    /// Names of all sequences, in file order
+    fn names(&self) -> Vec<String>;
+
+    /// Read the full sequence `chr`; soft-masked bases are lower-case
+    fn read(&self, chr: &str) -> anyhow::Result<Vec<u8>>;
+
+    /// Length of the sequence `chr`
+    fn length(&self, chr: &str) -> anyhow::Result<u64> {
+        Ok(self.read(chr)?.len() as u64)
+    }
+}
+
+/// Reference file format
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum RefFormat {
+    /// Detect from the file extension
+    Auto,
+    /// UCSC 2bit
+    #[value(name = "2bit")]
+    TwoBit,
+    /// FASTA with a samtools `.fai` index (built in memory when missing).
+    /// May be gzip- or bgzip-compressed.
+    Fasta,
+}
+
+/// Open a reference as a `SequenceSource`.
+///
+/// With `RefFormat::Auto`, `.2bit` files are read as 2bit and
+/// `.fa`/`.fasta`/`.fna` files (optionally `.gz`/`.bgz`) as FASTA.
+///
+/// `http(s)://` URLs to 2bit files are read with HTTP range requests.
+///
+/// Compressed FASTA is detected from its content:
+/// * bgzip with both `.fai` and `.gzi` indices is read with random access.
+/// * Otherwise (plain gzip, or bgzip without indices) the whole file is
+///   decompressed into memory once.
+pub fn open_sequence_source(
+    path: &Path,
+    format: RefFormat,
+) -> anyhow::Result<Box<dyn SequenceSource>> {
+    let format = match format {
+        RefFormat::Auto => detect_ref_format(path)?,
+        f => f,
+    };
+    if is_url(path) {
+        if format != RefFormat::TwoBit {
+            bail!("Only 2bit references can be read from a URL");
+        }
+        let url = path.to_str().unwrap();
+        return Ok(Box::new(TwoBitSource::open_url(url)?));
+    }
+    Ok(match format {
+        RefFormat::TwoBit => Box::new(TwoBitSource::open(path)?),
+        _ if is_gzipped(path)? => {
+            let fai_path = sidecar_path(path, "fai");
+            let gzi_path = sidecar_path(path, "gzi");
+            if fai_path.exists() && gzi_path.exists() {
+                Box::new(BgzfFastaSource::open(path)?)
+            } else {
+                Box::new(InMemoryFastaSource::read(path)?)
+            }
+        }
+        _ => Box::new(IndexedFastaSource::open(path)?),
+    })
+}
+
+/// Guess the reference format from the file extension
+pub fn detect_ref_format(path: &Path) -> anyhow::Result<RefFormat> {
+    let name = path
+        .file_name()
+        .map(|n| n.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    let name = name
+        .strip_suffix(".gz")
+        .or_else(|| name.strip_suffix(".bgz"))
+        .unwrap_or(&name);
+    if name.ends_with(".2bit") {
+        Ok(RefFormat::TwoBit)
+    } else if [".fa", ".fasta", ".fna"]
+        .iter()
+        .any(|ext| name.ends_with(ext))
+    {
+        Ok(RefFormat::Fasta)
+    } else {
+        bail!(
+            "Cannot detect reference format of {:?}; specify --ref-format",
+            path
+        )
+    }
+}
+
+/// Whether a file starts with the gzip magic bytes (also true for bgzip)
+pub fn is_gzipped(path: &Path) -> anyhow::Result<bool> {
+    let mut f = File::open(path).context(format!("opening {:?}", path))?;
+    let mut magic = [0u8; 2];
+    let n = f.read(&mut magic)?;
+    Ok(n == 2 && magic == [0x1f, 0x8b])
+}
+
+/// Path of an index next to `path`, e.g. `genome.fa.gz` → `genome.fa.gz.fai`
+fn sidecar_path(path: &Path, ext: &str) -> PathBuf {
+    PathBuf::from(format!("{}.{}", path.display(), ext))
+}
+
+/// Open a file for buffered reading, transparently decompressing gzip/bgzip
+pub fn open_maybe_gz(path: &Path) -> anyhow::Result<Box<dyn BufRead>> {
+    let f = File::open(path).context(format!("opening {:?}", path))?;
+    Ok(if is_gzipped(path)? {
+        Box::new(BufReader::new(MultiGzDecoder::new(f)))
+    } else {
+        Box::new(BufReader::new(f))
+    })
+}
+
 // Reference 2bit file
 
+/// Read a sequence from a 2bit file, keeping soft-masked bases lower-case
 pub fn read_seq(path: &Path, chr: &str) -> anyhow::Result<Vec<u8>> {
     // open once
-    let mut tb = TwoBitFile::open(path).context("opening 2bit")?;
+    let mut tb = TwoBitFile::open(path)
+        .context("opening 2bit")?
+        .enable_softmask(true);
     // Get reference sequence once
     let seq = tb
         .read_sequence(chr, ..)
         .context(format!("extracting reference seq for {}", chr))?;
-    Ok(seq.as_bytes().to_vec())
+    Ok(seq.into_bytes())
+}
+
+/// Memory-mapped file bytes that can be shared between 2bit readers
+#[derive(Clone)]
+pub struct SharedMmap(Arc<Mmap>);
+
+impl AsRef<[u8]> for SharedMmap {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+/// Creates a new reader of the underlying 2bit file
+type ReaderFactory<R> = Box<dyn Fn() -> anyhow::Result<R> + Send + Sync>;
+
+/// 2bit reference read through a pool of readers.
+///
+/// Readers (with their parsed index) are reused between sequence reads and
+/// at most one is created per concurrent `read`.
+///
+/// * `TwoBitSource::open` memory-maps a local file once, so parallel reads
+///   share the OS page cache instead of reopening and copying the file.
+/// * `TwoBitSource::open_url` fetches only the needed byte ranges of a
+///   remote file over HTTP(S).
+pub struct TwoBitSource<R: Read + Seek> {
+    location: String,
+    names: Vec<String>,
+    lengths: HashMap<String, u64>,
+    new_reader: ReaderFactory<R>,
+    /// Idle readers
+    pool: Mutex<Vec<TwoBitFile<R>>>,
+}
+
+impl<R: Read + Seek + Send> TwoBitSource<R> {
+    fn with_factory(location: String, new_reader: ReaderFactory<R>) -> anyhow::Result<Self> {
+        let tb = TwoBitFile::new(new_reader()?).context(format!("opening 2bit {}", location))?;
+        let names = tb.chrom_names();
+        let lengths = names
+            .iter()
+            .cloned()
+            .zip(tb.chrom_sizes().into_iter().map(|len| len as u64))
+            .collect();
+        Ok(Self {
+            location,
+            names,
+            lengths,
+            new_reader,
+            pool: Mutex::new(vec![tb]),
+        })
+    }
+}
+
+impl TwoBitSource<Cursor<SharedMmap>> {
+    pub fn open(path: &Path) -> anyhow::Result<Self> {
+        let f = File::open(path).context(format!("opening 2bit {:?}", path))?;
+        // SAFETY: the reference must not be modified while it is mapped;
+        // the same assumption is made when reading it in parts.
+        let mmap = unsafe { Mmap::map(&f) }.context(format!("memory-mapping 2bit {:?}", path))?;
+        let mmap = SharedMmap(Arc::new(mmap));
+        Self::with_factory(
+            format!("{:?}", path),
+            Box::new(move || Ok(Cursor::new(mmap.clone()))),
+        )
+    }
+}
+
+impl TwoBitSource<HttpRangeReader> {
+    pub fn open_url(url: &str) -> anyhow::Result<Self> {
+        let url = url.to_string();
+        Self::with_factory(url.clone(), Box::new(move || HttpRangeReader::open(&url)))
+    }
+}
+
+impl<R: Read + Seek + Send> SequenceSource for TwoBitSource<R> {
+    fn names(&self) -> Vec<String> {
+        self.names.clone()
+    }
+
+    fn read(&self, chr: &str) -> anyhow::Result<Vec<u8>> {
+        let idle = self.pool.lock().unwrap().pop();
+        let tb = match idle {
+            Some(tb) => tb,
+            None => TwoBitFile::new((self.new_reader)()?)
+                .context(format!("opening 2bit {}", self.location))?,
+        };
+        let mut tb = tb.enable_softmask(true);
+        let seq = tb
+            .read_sequence(chr, ..)
+            .context(format!("extracting reference seq for {}", chr))?;
+        self.pool.lock().unwrap().push(tb);
+        Ok(seq.into_bytes())
+    }
+
+    fn length(&self, chr: &str) -> anyhow::Result<u64> {
+        self.lengths.get(chr).copied().context(format!(
+            "sequence {} not found in 2bit {}",
+            chr, self.location
+        ))
+    }
+}
+
+// Reference (multi-)FASTA file
+
+/// One line of a samtools `.fai` index
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FaiEntry {
+    pub name: String,
+    pub length: u64,
+    pub offset: u64,
+    pub line_bases: u64,
+    pub line_width: u64,
+}
+
+/// Parse a samtools `.fai` index
+pub fn read_fai(path: &Path) -> anyhow::Result<Vec<FaiEntry>> {
+    let content =
+        std::fs::read_to_string(path).context(format!("reading FASTA index {:?}", path))?;
+    let mut entries = Vec::new();
+    for line in content.lines().filter(|l| !l.trim().is_empty()) {
+        let cols: Vec<&str> = line.split('\t').collect();
+        if cols.len() < 5 {
+            bail!("Malformed FASTA index line: {}", line);
+        }
+        let num = |i: usize| -> anyhow::Result<u64> {
+            cols[i]
+                .parse()
+                .context(format!("Parsing FASTA index line: {}", line))
+        };
+        entries.push(FaiEntry {
+            name: cols[0].to_string(),
+            length: num(1)?,
+            offset: num(2)?,
+            line_bases: num(3)?,
+            line_width: num(4)?,
+        });
+    }
+    Ok(entries)
+}
+
+/// Build a `.fai`-equivalent index by scanning a FASTA file.
+///
+/// Fails when a record has lines of differing lengths (other than the last),
+/// as such files cannot be randomly accessed.
+pub fn build_fai<R: BufRead>(mut reader: R) -> anyhow::Result<Vec<FaiEntry>> {
+    let mut entries: Vec<FaiEntry> = Vec::new();
+    let mut offset = 0u64;
+    let mut line = Vec::new();
+    // Whether the previous line of the current record was shorter than line_bases
+    let mut short_line_seen = false;
+    loop {
+        line.clear();
+        let n = reader.read_until(b'\n', &mut line)? as u64;
+        if n == 0 {
+            break;
+        }
+        if line[0] == b'>' {
+            let header = String::from_utf8_lossy(&line[1..]);
+            let name = header.split_whitespace().next().unwrap_or("").to_string();
+            entries.push(FaiEntry {
+                name,
+                length: 0,
+                offset: offset + n,
+                line_bases: 0,
+                line_width: 0,
+            });
+            short_line_seen = false;
+        } else if let Some(entry) = entries.last_mut() {
+            let bases = line.iter().filter(|&&b| b != b'\n' && b != b'\r').count() as u64;
+            if bases > 0 {
+                if entry.line_bases == 0 {
+                    entry.line_bases = bases;
+                    entry.line_width = n;
+                } else if short_line_seen || bases > entry.line_bases {
+                    bail!(
+                        "FASTA record {} has lines of different lengths; \
+                         please reformat or index it with `samtools faidx`",
+                        entry.name
+                    );
+                }
+                short_line_seen = bases < entry.line_bases;
+                entry.length += bases;
+            }
+        }
+        offset += n;
+    }
+    Ok(entries)
+}
+
+/// FASTA reference with random access through a `.fai` index.
+///
+/// Uses `<path>.fai` when present, otherwise the index is built in memory.
+pub struct IndexedFastaSource {
+    path: PathBuf,
+    index: Vec<FaiEntry>,
+    lookup: HashMap<String, usize>,
+}
+
+impl IndexedFastaSource {
+    pub fn open(path: &Path) -> anyhow::Result<Self> {
+        let fai_path = sidecar_path(path, "fai");
+        let index = if fai_path.exists() {
+            read_fai(&fai_path)?
+        } else {
+            let f = File::open(path).context(format!("opening FASTA {:?}", path))?;
+            build_fai(BufReader::new(f)).context(format!("indexing FASTA {:?}", path))?
+        };
+        let lookup = index
+            .iter()
+            .enumerate()
+            .map(|(i, e)| (e.name.clone(), i))
+            .collect();
+        Ok(Self {
+            path: path.to_path_buf(),
+            index,
+            lookup,
+        })
+    }
+}
+
+impl SequenceSource for IndexedFastaSource {
+    fn names(&self) -> Vec<String> {
+        self.index.iter().map(|e| e.name.clone()).collect()
+    }
+
+    fn length(&self, chr: &str) -> anyhow::Result<u64> {
+        let idx = self.lookup.get(chr).context(format!(
+            "sequence {} not found in FASTA {:?}",
+            chr, self.path
+        ))?;
+        Ok(self.index[*idx].length)
+    }
+
+    fn read(&self, chr: &str) -> anyhow::Result<Vec<u8>> {
+        let entry = &self.index[*self.lookup.get(chr).context(format!(
+            "sequence {} not found in FASTA {:?}",
+            chr, self.path
+        ))?];
+        if entry.length == 0 {
+            return Ok(Vec::new());
+        }
+        // Bytes spanned by the sequence including line breaks
+        let full_lines = entry.length / entry.line_bases;
+        let rest = entry.length % entry.line_bases;
+        let span = full_lines * entry.line_width + rest;
+
+        let mut f = File::open(&self.path).context(format!("opening FASTA {:?}", self.path))?;
+        f.seek(SeekFrom::Start(entry.offset))?;
+        let mut raw = Vec::with_capacity(span as usize);
+        f.take(span)
+            .read_to_end(&mut raw)
+            .context(format!("extracting reference seq for {}", chr))?;
+        raw.retain(|&b| b != b'\n' && b != b'\r');
+        if raw.len() as u64 != entry.length {
+            bail!(
+                "FASTA record {} is shorter than its index says; is the .fai stale?",
+                chr
+            );
+        }
+        Ok(raw)
+    }
+}
+
+/// Parse a bgzip `.gzi` index into `(compressed, uncompressed)` block offsets.
+///
+/// The implicit first block `(0, 0)` is included.
+pub fn read_gzi(path: &Path) -> anyhow::Result<Vec<(u64, u64)>> {
+    let bytes = std::fs::read(path).context(format!("reading bgzip index {:?}", path))?;
+    let word = |i: usize| -> anyhow::Result<u64> {
+        let b = bytes
+            .get(i * 8..i * 8 + 8)
+            .context(format!("Truncated bgzip index {:?}", path))?;
+        Ok(u64::from_le_bytes(b.try_into().unwrap()))
+    };
+    let n = word(0)? as usize;
+    let mut blocks = Vec::with_capacity(n + 1);
+    blocks.push((0, 0));
+    for i in 0..n {
+        blocks.push((word(1 + 2 * i)?, word(2 + 2 * i)?));
+    }
+    Ok(blocks)
+}
+
+/// bgzip-compressed FASTA with random access through `.fai` and `.gzi`
+/// indices (as written by `samtools faidx` on a bgzipped file).
+pub struct BgzfFastaSource {
+    path: PathBuf,
+    index: Vec<FaiEntry>,
+    lookup: HashMap<String, usize>,
+    /// `(compressed, uncompressed)` block start offsets, sorted
+    blocks: Vec<(u64, u64)>,
+}
+
+impl BgzfFastaSource {
+    pub fn open(path: &Path) -> anyhow::Result<Self> {
+        let index = read_fai(&sidecar_path(path, "fai"))?;
+        let blocks = read_gzi(&sidecar_path(path, "gzi"))?;
+        let lookup = index
+            .iter()
+            .enumerate()
+            .map(|(i, e)| (e.name.clone(), i))
+            .collect();
+        Ok(Self {
+            path: path.to_path_buf(),
+            index,
+            lookup,
+            blocks,
+        })
+    }
+}
+
+impl SequenceSource for BgzfFastaSource {
+    fn names(&self) -> Vec<String> {
+        self.index.iter().map(|e| e.name.clone()).collect()
+    }
+
+    fn length(&self, chr: &str) -> anyhow::Result<u64> {
+        let idx = self.lookup.get(chr).context(format!(
+            "sequence {} not found in FASTA {:?}",
+            chr, self.path
+        ))?;
+        Ok(self.index[*idx].length)
+    }
+
+    fn read(&self, chr: &str) -> anyhow::Result<Vec<u8>> {
+        let entry = &self.index[*self.lookup.get(chr).context(format!(
+            "sequence {} not found in FASTA {:?}",
+            chr, self.path
+        ))?];
+        if entry.length == 0 {
+            return Ok(Vec::new());
+        }
+        let full_lines = entry.length / entry.line_bases;
+        let rest = entry.length % entry.line_bases;
+        let span = full_lines * entry.line_width + rest;
+
+        // Last block starting at or before the record
+        let b = self.blocks.partition_point(|&(_, u)| u <= entry.offset) - 1;
+        let (c_off, u_off) = self.blocks[b];
+
+        let mut f = File::open(&self.path).context(format!("opening FASTA {:?}", self.path))?;
+        f.seek(SeekFrom::Start(c_off))?;
+        let mut decoder = MultiGzDecoder::new(BufReader::new(f));
+        std::io::copy(
+            &mut (&mut decoder).take(entry.offset - u_off),
+            &mut std::io::sink(),
+        )?;
+        let mut raw = Vec::with_capacity(span as usize);
+        decoder
+            .take(span)
+            .read_to_end(&mut raw)
+            .context(format!("extracting reference seq for {}", chr))?;
+        raw.retain(|&b| b != b'\n' && b != b'\r');
+        if raw.len() as u64 != entry.length {
+            bail!(
+                "FASTA record {} is shorter than its index says; are the .fai/.gzi stale?",
+                chr
+            );
+        }
+        Ok(raw)
+    }
+}
+
+/// Read every record of a (multi-)FASTA file into memory, in file order.
+///
+/// * The record name is the first whitespace-separated token of the header.
+/// * Duplicate record names result in an error.
+/// * gzip/bgzip-compressed files are decompressed on the fly.
+///
+/// Intended for files with many short records (transcripts, amplicons),
+/// where reading everything once is cheaper than per-record lookups.
+pub fn read_fasta_records(path: &Path) -> anyhow::Result<Vec<(String, Vec<u8>)>> {
+    let reader = open_maybe_gz(path).context(format!("opening FASTA {:?}", path))?;
+    let mut records: Vec<(String, Vec<u8>)> = Vec::new();
+    let mut seen: HashSet<String> = HashSet::new();
+    for line in reader.lines() {
+        let l = line.context("reading FASTA line")?;
+        let l = l.trim_end();
+        if let Some(header) = l.strip_prefix('>') {
+            let name = header.split_whitespace().next().unwrap_or("").to_string();
+            if name.is_empty() {
+                bail!("FASTA record without a name in {:?}", path);
+            }
+            if !seen.insert(name.clone()) {
+                bail!("Duplicate FASTA record name {}", name);
+            }
+            records.push((name, Vec::new()));
+        } else if l.is_empty() || l.starts_with(';') {
+            continue;
+        } else if let Some((_, seq)) = records.last_mut() {
+            seq.extend_from_slice(l.as_bytes());
+        } else {
+            bail!("FASTA sequence data before the first header in {:?}", path);
+        }
+    }
+    Ok(records)
+}
+
+/// Multi-FASTA reference held entirely in memory
+pub struct InMemoryFastaSource {
+    names: Vec<String>,
+    records: HashMap<String, Vec<u8>>,
+}
+
+impl InMemoryFastaSource {
+    pub fn read(path: &Path) -> anyhow::Result<Self> {
+        let records = read_fasta_records(path)?;
+        let names = records.iter().map(|(name, _)| name.clone()).collect();
+        Ok(Self {
+            names,
+            records: records.into_iter().collect(),
+        })
+    }
+}
+
+impl SequenceSource for InMemoryFastaSource {
+    fn names(&self) -> Vec<String> {
+        self.names.clone()
+    }
+
+    fn read(&self, chr: &str) -> anyhow::Result<Vec<u8>> {
+        self.records
+            .get(chr)
+            .cloned()
+            .context(format!("FASTA record {} not found", chr))
+    }
+
+    fn length(&self, chr: &str) -> anyhow::Result<u64> {
+        self.records
+            .get(chr)
+            .map(|seq| seq.len() as u64)
+            .context(format!("FASTA record {} not found", chr))
+    }
+}
+
+// Output directory
+
+/// Temporary directory next to the output directory that replaces it on success.
+///
+/// A failed run leaves the output directory untouched and the staging
+/// directory is removed when dropped. Directories holding the working
+/// directory or any of the run's inputs are never replaced.
+///
+/// For `s3://` and `gs://` output directories, outputs are staged in the
+/// system temporary directory and uploaded on `commit`, except for the
+/// files created with `create_output`, which are uploaded as they are written.
+pub struct StagedOutputDir {
+    staging: tempfile::TempDir,
+    output_dir: PathBuf,
+    remote: Option<(Arc<ObjectStoreDir>, OutputRoute)>,
+}
+
+impl StagedOutputDir {
+    /// Stage outputs for `output_dir`, refusing to replace a non-empty
+    /// directory unless `force` is set.
+    ///
+    /// `inputs` are the files and directories the run reads (or writes
+    /// besides the outputs); `output_dir` must not contain any of them.
+    pub fn new(output_dir: &Path, force: bool, inputs: &[&Path]) -> anyhow::Result<Self> {
+        if is_object_store_url(output_dir) {
+            let url = output_dir.to_string_lossy();
+            let remote = Arc::new(ObjectStoreDir::open(&url)?);
+            if force && remote.is_bucket_root() {
+                bail!(
+                    "Refusing to replace the contents of the bucket {} (add a prefix to the URL)",
+                    url
+                );
+            }
+            if !force && !remote.list()?.is_empty() {
+                bail!(
+                    "Output location {} is not empty (use --force to replace its contents)",
+                    url
+                );
+            }
+            let staging = tempfile::Builder::new()
+                .prefix("reference-output-")
+                .tempdir()
+                .context("Create output staging directory fail")?;
+            let route = OutputRoute::upload(staging.path(), Arc::clone(&remote));
+            return Ok(Self {
+                staging,
+                output_dir: output_dir.to_path_buf(),
+                remote: Some((remote, route)),
+            });
+        }
+        // Existing directories are resolved fully, e.g. for `-o ..`
+        let output_dir = if output_dir.exists() {
+            output_dir.canonicalize()
+        } else {
+            std::path::absolute(output_dir)
+        }
+        .context(format!("Resolving output directory {:?}", output_dir))?;
+        let (Some(parent), Some(name)) = (output_dir.parent(), output_dir.file_name()) else {
+            bail!(
+                "Output directory {:?} must not be a root directory",
+                output_dir
+            );
+        };
+        if output_dir.exists() {
+            if !output_dir.is_dir() {
+                bail!("Output directory {:?} is not a directory", output_dir);
+            }
+            check_replaceable(&output_dir, inputs)?;
+            let is_empty = std::fs::read_dir(&output_dir)
+                .context(format!("Reading output directory {:?}", output_dir))?
+                .next()
+                .is_none();
+            if !is_empty && !force {
+                bail!(
+                    "Output directory {:?} is not empty (use --force to replace its contents)",
+                    output_dir
+                );
+            }
+        }
+        std::fs::create_dir_all(parent).context(format!("Cannot create {:?}", parent))?;
+        let staging = tempfile::Builder::new()
+            .prefix(&format!(".{}.tmp-", name.to_string_lossy()))
+            .tempdir_in(parent)
+            .context("Create output staging directory fail")?;
+        Ok(Self {
+            staging,
+            output_dir,
+            remote: None,
+        })
+    }
+
+    /// Directory to write the outputs to
+    pub fn path(&self) -> &Path {
+        self.staging.path()
+    }
+
+    /// Move the staged outputs to the output directory, replacing it
+    pub fn commit(self) -> anyhow::Result<()> {
+        if let Some((remote, _)) = &self.remote {
+            return remote.replace_with(self.staging.path());
+        }
+        let staging = self.staging.keep();
+        let output_dir = self.output_dir;
+        // Move the old directory aside so the swap is a single rename
+        let old = output_dir
+            .exists()
+            .then(|| PathBuf::from(format!("{}.old-{}", staging.display(), std::process::id())));
+        if let Some(old) = &old {
+            std::fs::rename(&output_dir, old)
+                .context(format!("Moving previous {:?} aside", output_dir))?;
+        }
+        std::fs::rename(&staging, &output_dir).context(format!(
+            "Moving outputs from {:?} to {:?}",
+            staging, output_dir
+        ))?;
+        if let Some(old) = old {
+            std::fs::remove_dir_all(&old)
+                .context(format!("Removing previous outputs {:?}", old))?;
+        }
+        Ok(())
+    }
+}
+
+/// Fail when replacing `output_dir` (canonical) would remove the working directory or
+/// any of `inputs` (e.g. `-o .` or `-o ..`). Inputs that do not exist
+/// locally (e.g. URLs) are skipped.
+fn check_replaceable(output_dir: &Path, inputs: &[&Path]) -> anyhow::Result<()> {
+    let cwd = std::env::current_dir()
+        .and_then(|cwd| cwd.canonicalize())
+        .context("Resolving the working directory")?;
+    if cwd.starts_with(output_dir) {
+        bail!(
+            "Output directory {:?} contains the working directory; use a dedicated output directory",
+            output_dir
+        );
+    }
+    for input in inputs {
+        if let Ok(input) = input.canonicalize() {
+            if input.starts_with(output_dir) {
+                bail!(
+                    "Output directory {:?} contains the input {:?}; use a dedicated output directory",
+                    output_dir,
+                    input
+                );
+            }
+        }
+    }
+    Ok(())
 }
@@ -1,6 +1,13 @@
+pub mod bam;
+pub mod bgzf;
+pub mod cloud;
+pub mod config;
+pub mod fastq;
+pub mod interrupt;
 pub mod io;
-
+pub mod remote;
+pub mod tabix;
 
 pub type Count = u64;
 
-pub type BigCount = u64;
\ No newline at end of file
+pub type BigCount = u64;
@@ -0,0 +1,127 @@
+use anyhow::{bail, Context};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::Path;
+
+/// Bytes fetched per HTTP range request (unless a read asks for more)
+pub const HTTP_BLOCK_SIZE: u64 = 4 << 20;
+
+/// Whether a reference "path" is an `http://` or `https://` URL
+pub fn is_url(path: &Path) -> bool {
+    path.to_str()
+        .is_some_and(|p| p.starts_with("http://") || p.starts_with("https://"))
+}
+
+/// Read + Seek over a remote file using HTTP range requests.
+///
+/// Reads are served from a single cached block of at least
+/// `HTTP_BLOCK_SIZE` bytes, so sequential small reads (as done when
+/// decoding a 2bit record) only trigger one request per block.
+pub struct HttpRangeReader {
+    url: String,
+    agent: ureq::Agent,
+    len: u64,
+    pos: u64,
+    /// Start offset and bytes of the cached block
+    block_start: u64,
+    block: Vec<u8>,
+}
+
+impl HttpRangeReader {
+    /// Connect to `url` and determine the file size.
+    ///
+    /// Fails when the server does not support range requests.
+    pub fn open(url: &str) -> anyhow::Result<Self> {
+        let agent = ureq::AgentBuilder::new().build();
+        let mut reader = Self {
+            url: url.to_string(),
+            agent,
+            len: 0,
+            pos: 0,
+            block_start: 0,
+            block: Vec::new(),
+        };
+        let (block, total) = reader.fetch(0, HTTP_BLOCK_SIZE)?;
+        reader.len = total;
+        reader.block = block;
+        Ok(reader)
+    }
+
+    /// Total size of the remote file
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Fetch up to `n` bytes from `start`; returns the bytes and total file size
+    fn fetch(&self, start: u64, n: u64) -> anyhow::Result<(Vec<u8>, u64)> {
+        let end = start + n.max(1) - 1;
+        let response = self
+            .agent
+            .get(&self.url)
+            .set("Range", &format!("bytes={}-{}", start, end))
+            .call()
+            .context(format!("requesting {}", self.url))?;
+        if response.status() != 206 {
+            bail!(
+                "Server for {} does not support HTTP range requests (status {})",
+                self.url,
+                response.status()
+            );
+        }
+        // Content-Range: bytes <start>-<end>/<total>
+        let total = response
+            .header("Content-Range")
+            .and_then(|r| r.rsplit('/').next())
+            .and_then(|t| t.trim().parse::<u64>().ok())
+            .context(format!("missing Content-Range header from {}", self.url))?;
+        let mut bytes = Vec::with_capacity(n as usize);
+        response
+            .into_reader()
+            .take(n)
+            .read_to_end(&mut bytes)
+            .context(format!("reading response from {}", self.url))?;
+        Ok((bytes, total))
+    }
+}
+
+impl Read for HttpRangeReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos >= self.len || buf.is_empty() {
+            return Ok(0);
+        }
+        let block_end = self.block_start + self.block.len() as u64;
+        if self.pos < self.block_start || self.pos >= block_end {
+            let n = (buf.len() as u64).max(HTTP_BLOCK_SIZE);
+            let (block, _) = self
+                .fetch(self.pos, n)
+                .map_err(|e| io::Error::other(format!("{:#}", e)))?;
+            if block.is_empty() {
+                return Ok(0);
+            }
+            self.block_start = self.pos;
+            self.block = block;
+        }
+        let offset = (self.pos - self.block_start) as usize;
+        let n = buf.len().min(self.block.len() - offset);
+        buf[..n].copy_from_slice(&self.block[offset..offset + n]);
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl Seek for HttpRangeReader {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let new_pos = match pos {
+            SeekFrom::Start(p) => Some(p),
+            SeekFrom::End(d) => self.len.checked_add_signed(d),
+            SeekFrom::Current(d) => self.pos.checked_add_signed(d),
+        };
+        self.pos = new_pos.ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "seek to a negative position")
+        })?;
+        Ok(self.pos)
+    }
+}
@@ -0,0 +1,300 @@
+use crate::cli::bgzf::BgzfWriter;
+use anyhow::{bail, Context, Result};
+use flate2::read::MultiGzDecoder;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+/// Bases per linear index window
+const LINEAR_SHIFT: u32 = 14;
+
+/// `format` field of BED-like files with 0-based, half-open coordinates
+const FORMAT_UCSC: i32 = 0x10000;
+
+/// Tabix bin of the interval `[beg, end)` (UCSC binning scheme)
+pub fn reg2bin(beg: u64, end: u64) -> u32 {
+    let end = end.max(beg + 1) - 1;
+    for (shift, offset) in [(14, 4681), (17, 585), (20, 73), (23, 9), (26, 1)] {
+        if beg >> shift == end >> shift {
+            return offset + (beg >> shift) as u32;
+        }
+    }
+    0
+}
+
+/// All bins that may hold records overlapping `[beg, end)`
+pub fn reg2bins(beg: u64, end: u64) -> Vec<u32> {
+    let end = end.min(1 << 29);
+    if beg >= end {
+        return Vec::new();
+    }
+    let end = end - 1;
+    let mut bins = Vec::new();
+    for (shift, offset) in [(29, 0), (26, 1), (23, 9), (20, 73), (17, 585), (14, 4681)] {
+        bins.extend((offset + (beg >> shift) as u32)..=(offset + (end >> shift) as u32));
+    }
+    bins
+}
+
+/// Index of one sequence
+#[derive(Debug, Default)]
+struct SeqIndex {
+    /// Bin → chunks of `(start, end)` virtual offsets
+    bins: BTreeMap<u32, Vec<(u64, u64)>>,
+    /// Smallest virtual offset of records overlapping each 16 kb window
+    linear: Vec<Option<u64>>,
+}
+
+/// Builds a tabix (`.tbi`) index for a BGZF-compressed, BED-like file
+/// (chrom, 0-based start, end in the first three columns) while it is written.
+///
+/// Records must be added in file order, sorted by start within each
+/// chromosome and with the lines of each chromosome kept together.
+#[derive(Debug, Default)]
+pub struct TabixIndexBuilder {
+    names: Vec<String>,
+    seqs: Vec<SeqIndex>,
+    /// Start of the last added record, for the sort check
+    last: Option<u64>,
+}
+
+impl TabixIndexBuilder {
+    /// Add a record spanning virtual offsets `[vstart, vend)`
+    pub fn add(&mut self, chrom: &str, beg: u64, end: u64, vstart: u64, vend: u64) -> Result<()> {
+        if self.names.last().map(String::as_str) != Some(chrom) {
+            if self.names.iter().any(|n| n == chrom) {
+                bail!(
+                    "Records of {} are not contiguous; cannot build a tabix index",
+                    chrom
+                );
+            }
+            self.names.push(chrom.to_string());
+            self.seqs.push(SeqIndex::default());
+            self.last = None;
+        }
+        if self.last.is_some_and(|last| beg < last) {
+            bail!(
+                "Records of {} are not sorted by start; cannot build a tabix index",
+                chrom
+            );
+        }
+        self.last = Some(beg);
+
+        let seq = self.seqs.last_mut().unwrap();
+        let chunks = seq.bins.entry(reg2bin(beg, end)).or_default();
+        match chunks.last_mut() {
+            Some(chunk) if chunk.1 == vstart => chunk.1 = vend,
+            _ => chunks.push((vstart, vend)),
+        }
+        let first = (beg >> LINEAR_SHIFT) as usize;
+        let last = ((end.max(beg + 1) - 1) >> LINEAR_SHIFT) as usize;
+        if seq.linear.len() <= last {
+            seq.linear.resize(last + 1, None);
+        }
+        for slot in &mut seq.linear[first..=last] {
+            slot.get_or_insert(vstart);
+        }
+        Ok(())
+    }
+
+    /// Write the index as a BGZF-compressed `.tbi` file
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let file = File::create(path).context(format!("Create {:?} fail", path))?;
+        let mut out = BgzfWriter::new(BufWriter::new(file));
+        let mut names = Vec::new();
+        for name in &self.names {
+            names.extend_from_slice(name.as_bytes());
+            names.push(0);
+        }
+        out.write_all(b"TBI\x01")?;
+        // n_ref, format, col_seq, col_beg, col_end, meta char, skipped lines
+        for v in [
+            self.names.len() as i32,
+            FORMAT_UCSC,
+            1,
+            2,
+            3,
+            b'#' as i32,
+            0,
+            names.len() as i32,
+        ] {
+            out.write_all(&v.to_le_bytes())?;
+        }
+        out.write_all(&names)?;
+        for seq in &self.seqs {
+            out.write_all(&(seq.bins.len() as i32).to_le_bytes())?;
+            for (bin, chunks) in &seq.bins {
+                out.write_all(&bin.to_le_bytes())?;
+                out.write_all(&(chunks.len() as i32).to_le_bytes())?;
+                for (beg, end) in chunks {
+                    out.write_all(&beg.to_le_bytes())?;
+                    out.write_all(&end.to_le_bytes())?;
+                }
+            }
+            out.write_all(&(seq.linear.len() as i32).to_le_bytes())?;
+            // Windows without records point at the next record
+            let mut next = 0u64;
+            let mut offsets = vec![0u64; seq.linear.len()];
+            for (i, slot) in seq.linear.iter().enumerate().rev() {
+                next = slot.unwrap_or(next);
+                offsets[i] = next;
+            }
+            for off in offsets {
+                out.write_all(&off.to_le_bytes())?;
+            }
+        }
+        out.finish().context(format!("Writing {:?}", path))?;
+        Ok(())
+    }
+}
+
+/// A tabix (`.tbi`) index read from disk
+#[derive(Debug, Default)]
+pub struct TabixIndex {
+    seqs: HashMap<String, SeqIndex>,
+}
+
+impl TabixIndex {
+    pub fn read(path: &Path) -> Result<Self> {
+        let mut bytes = Vec::new();
+        MultiGzDecoder::new(File::open(path).context(format!("Opening tabix index {:?}", path))?)
+            .read_to_end(&mut bytes)
+            .context(format!("Reading tabix index {:?}", path))?;
+        let mut pos = 0usize;
+        let mut take = |n: usize| -> Result<&[u8]> {
+            let b = bytes
+                .get(pos..pos + n)
+                .context(format!("Truncated tabix index {:?}", path))?;
+            pos += n;
+            Ok(b)
+        };
+        if take(4)? != b"TBI\x01" {
+            bail!("{:?} is not a tabix index", path);
+        }
+        let mut header = [0i32; 8];
+        for v in &mut header {
+            *v = i32::from_le_bytes(take(4)?.try_into()?);
+        }
+        let [n_ref, _format, col_seq, col_beg, _col_end, _meta, _skip, l_nm] = header;
+        if (col_seq, col_beg) != (1, 2) {
+            bail!("Tabix index {:?} is not for chrom/start/end columns", path);
+        }
+        let names: Vec<String> = take(l_nm as usize)?
+            .split(|&b| b == 0)
+            .filter(|n| !n.is_empty())
+            .map(|n| String::from_utf8_lossy(n).into_owned())
+            .collect();
+        let mut seqs = HashMap::new();
+        for name in names.into_iter().take(n_ref as usize) {
+            let mut seq = SeqIndex::default();
+            let n_bin = i32::from_le_bytes(take(4)?.try_into()?);
+            for _ in 0..n_bin {
+                let bin = u32::from_le_bytes(take(4)?.try_into()?);
+                let n_chunk = i32::from_le_bytes(take(4)?.try_into()?);
+                let mut chunks = Vec::with_capacity(n_chunk as usize);
+                for _ in 0..n_chunk {
+                    let beg = u64::from_le_bytes(take(8)?.try_into()?);
+                    let end = u64::from_le_bytes(take(8)?.try_into()?);
+                    chunks.push((beg, end));
+                }
+                seq.bins.insert(bin, chunks);
+            }
+            let n_intv = i32::from_le_bytes(take(4)?.try_into()?);
+            for _ in 0..n_intv {
+                seq.linear
+                    .push(Some(u64::from_le_bytes(take(8)?.try_into()?)));
+            }
+            seqs.insert(name, seq);
+        }
+        Ok(TabixIndex { seqs })
+    }
+
+    /// Virtual offset to start reading from for records of `chrom`
+    /// overlapping `[beg, end)`, `None` when there are none
+    pub fn query_start(&self, chrom: &str, beg: u64, end: u64) -> Option<u64> {
+        let seq = self.seqs.get(chrom)?;
+        let min_off = seq
+            .linear
+            .get((beg >> LINEAR_SHIFT) as usize)
+            .copied()
+            .flatten()
+            .unwrap_or(0);
+        reg2bins(beg, end)
+            .iter()
+            .filter_map(|bin| seq.bins.get(bin))
+            .flatten()
+            .filter(|&&(_, chunk_end)| chunk_end > min_off)
+            .map(|&(chunk_beg, _)| chunk_beg.max(min_off))
+            .min()
+    }
+}
+
+/// Parse a `chrom`, `chrom:start` or `chrom:start-end` region with
+/// 1-based, inclusive coordinates (thousands separators allowed) into a
+/// 0-based, half-open interval
+pub fn parse_region(region: &str) -> Result<(String, u64, u64)> {
+    let Some((chrom, range)) = region.rsplit_once(':') else {
+        return Ok((region.to_string(), 0, u64::MAX));
+    };
+    let range = range.replace(',', "");
+    let parse = |v: &str| -> Result<u64> {
+        v.parse()
+            .context(format!("Invalid position {:?} in region {}", v, region))
+    };
+    let (start, end) = match range.split_once('-') {
+        Some((start, end)) => (parse(start)?, parse(end)?),
+        None => (parse(&range)?, u64::MAX),
+    };
+    if start == 0 || start > end {
+        bail!("Invalid region {}: start must be >= 1 and <= end", region);
+    }
+    Ok((chrom.to_string(), start - 1, end))
+}
+
+/// Call `emit` with every line of the BGZF file at `path` whose
+/// `chrom, start, end` columns overlap `[beg, end)` on `chrom`, using the
+/// tabix index `<path>.tbi`.
+///
+/// Lines of a chromosome must be sorted by start (as required for the index).
+pub fn query_lines(
+    path: &Path,
+    chrom: &str,
+    beg: u64,
+    end: u64,
+    mut emit: impl FnMut(&str) -> Result<()>,
+) -> Result<()> {
+    let index = TabixIndex::read(Path::new(&format!("{}.tbi", path.display())))?;
+    let Some(voffset) = index.query_start(chrom, beg, end) else {
+        return Ok(());
+    };
+    let mut file = File::open(path).context(format!("Opening {:?}", path))?;
+    file.seek(SeekFrom::Start(voffset >> 16))?;
+    let mut reader = BufReader::new(MultiGzDecoder::new(file));
+    std::io::copy(
+        &mut (&mut reader).take(voffset & 0xffff),
+        &mut std::io::sink(),
+    )?;
+    for line in reader.lines() {
+        let line = line.context(format!("Reading {:?}", path))?;
+        if line.starts_with('#') {
+            continue;
+        }
+        let mut cols = line.splitn(4, '\t');
+        let (Some(line_chrom), Some(start), Some(stop)) = (cols.next(), cols.next(), cols.next())
+        else {
+            bail!("Malformed line in {:?}: {}", path, line);
+        };
+        let start: u64 = start
+            .parse()
+            .context(format!("Parsing start in {:?}", path))?;
+        let stop: u64 = stop.parse().context(format!("Parsing end in {:?}", path))?;
+        if line_chrom != chrom || start >= end {
+            break;
+        }
+        if stop > beg {
+            emit(&line)?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,293 @@
+use crate::cli::BigCount;
+use crate::reference::merge::{
+    copy_if_exists, count_matrices, read_counts_npy, write_summed_triplets, SparseCounts,
+};
+use crate::reference::write::{write_counts_npy, CountDtype};
+use anyhow::{bail, Context, Result};
+use ndarray::Array2;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+
+/// `(chrom, start, end)` of a window
+pub type Region = (String, u64, u64);
+
+/// Coarser bins to aggregate the windows of a run into
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TargetBins {
+    /// Tiles of this many bases from the start of every chromosome
+    Size(u64),
+    /// Arbitrary (possibly overlapping) regions, kept in their order
+    Regions(Vec<Region>),
+}
+
+/// The bins of an aggregation and the bins every window is added to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BinAssignment {
+    /// Bins (rows of the aggregated matrices)
+    pub bins: Vec<Region>,
+    /// Indices of the bins fully containing each window
+    pub bins_of_window: Vec<Vec<usize>>,
+}
+
+impl BinAssignment {
+    /// Number of windows in each bin
+    pub fn windows_per_bin(&self) -> Vec<u64> {
+        let mut n = vec![0; self.bins.len()];
+        for bin in self.bins_of_window.iter().flatten() {
+            n[*bin] += 1;
+        }
+        n
+    }
+}
+
+/// Assign `windows` to the target bins fully containing them.
+///
+/// Windows crossing a bin border (or outside all regions) are left out.
+/// With `TargetBins::Size`, only tiles with windows become bins, in the
+/// chromosome order of `windows`, and the last tile of a chromosome ends
+/// with its last window.
+pub fn assign_windows(windows: &[Region], targets: &TargetBins) -> BinAssignment {
+    match targets {
+        TargetBins::Size(size) => assign_to_tiles(windows, *size),
+        TargetBins::Regions(regions) => assign_to_regions(windows, regions),
+    }
+}
+
+fn assign_to_tiles(windows: &[Region], size: u64) -> BinAssignment {
+    let size = size.max(1);
+    let mut chrom_order: Vec<&str> = Vec::new();
+    let mut tiles: HashMap<&str, BTreeMap<u64, usize>> = HashMap::new();
+    let mut chrom_ends: HashMap<&str, u64> = HashMap::new();
+    let mut tile_of_window: Vec<Option<(&str, u64)>> = Vec::with_capacity(windows.len());
+    for (chrom, start, end) in windows {
+        let chrom = chrom.as_str();
+        if !tiles.contains_key(chrom) {
+            chrom_order.push(chrom);
+        }
+        let chrom_tiles = tiles.entry(chrom).or_default();
+        let chrom_end = chrom_ends.entry(chrom).or_default();
+        *chrom_end = (*chrom_end).max(*end);
+        let tile = start / size;
+        if *end > (tile + 1).saturating_mul(size) {
+            tile_of_window.push(None);
+            continue;
+        }
+        chrom_tiles.insert(tile, 0);
+        tile_of_window.push(Some((chrom, tile)));
+    }
+
+    let mut bins = Vec::new();
+    for chrom in chrom_order {
+        for (tile, index) in tiles.get_mut(chrom).unwrap().iter_mut() {
+            *index = bins.len();
+            let end = (tile + 1).saturating_mul(size).min(chrom_ends[chrom]);
+            bins.push((chrom.to_string(), tile * size, end));
+        }
+    }
+    let bins_of_window = tile_of_window
+        .into_iter()
+        .map(|tile| match tile {
+            Some((chrom, tile)) => vec![tiles[chrom][&tile]],
+            None => Vec::new(),
+        })
+        .collect();
+    BinAssignment {
+        bins,
+        bins_of_window,
+    }
+}
+
+fn assign_to_regions(windows: &[Region], regions: &[Region]) -> BinAssignment {
+    // Regions by chromosome, sorted by start
+    let mut by_chrom: HashMap<&str, Vec<(u64, u64, usize)>> = HashMap::new();
+    for (i, (chrom, start, end)) in regions.iter().enumerate() {
+        by_chrom
+            .entry(chrom.as_str())
+            .or_default()
+            .push((*start, *end, i));
+    }
+    for chrom_regions in by_chrom.values_mut() {
+        chrom_regions.sort_unstable();
+    }
+    let max_len = regions
+        .iter()
+        .map(|(_, start, end)| end.saturating_sub(*start))
+        .max()
+        .unwrap_or(0);
+
+    let bins_of_window = windows
+        .iter()
+        .map(|(chrom, start, end)| {
+            let Some(chrom_regions) = by_chrom.get(chrom.as_str()) else {
+                return Vec::new();
+            };
+            // Regions starting at or before the window; only those starting
+            // within `max_len` of the window end can contain it
+            let n_before = chrom_regions.partition_point(|&(s, _, _)| s <= *start);
+            let mut containing: Vec<usize> = chrom_regions[..n_before]
+                .iter()
+                .rev()
+                .take_while(|&&(s, _, _)| s.saturating_add(max_len) >= *end)
+                .filter(|&&(_, e, _)| e >= *end)
+                .map(|&(_, _, i)| i)
+                .collect();
+            containing.sort_unstable();
+            containing
+        })
+        .collect();
+    BinAssignment {
+        bins: regions.to_vec(),
+        bins_of_window,
+    }
+}
+
+/// The `chrom, start, end` columns of a BED file, skipping `#`, `track`
+/// and `browser` lines
+pub fn read_regions(path: &Path) -> Result<Vec<Region>> {
+    let f = File::open(path).context(format!("Opening {:?}", path))?;
+    let mut regions = Vec::new();
+    for (i, line) in BufReader::new(f).lines().enumerate() {
+        let line = line.context(format!("Reading {:?}", path))?;
+        if line.trim().is_empty()
+            || line.starts_with('#')
+            || line.starts_with("track")
+            || line.starts_with("browser")
+        {
+            continue;
+        }
+        let cols: Vec<&str> = line.split_whitespace().collect();
+        if cols.len() < 3 {
+            bail!("{:?} line {}: expected at least 3 columns", path, i + 1);
+        }
+        let start: u64 =
+            cols[1]
+                .parse()
+                .context(format!("{:?} line {}: parsing start", path, i + 1))?;
+        let end: u64 =
+            cols[2]
+                .parse()
+                .context(format!("{:?} line {}: parsing end", path, i + 1))?;
+        regions.push((cols[0].to_string(), start, end));
+    }
+    Ok(regions)
+}
+
+/// Summary of an aggregation
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AggregateSummary {
+    /// File names of the aggregated matrices
+    pub matrices: Vec<String>,
+    pub n_windows: usize,
+    /// Windows not contained in any bin (not counted)
+    pub n_unassigned: usize,
+    pub n_bins: usize,
+}
+
+/// Sum the window rows of the count matrices of the output directory
+/// `input` (with its `bins.bed`) into coarser `targets` bins in `out_dir`.
+///
+/// Every window is added to each bin fully containing it. Matrices keep
+/// their dense or sparse layout and are written with element type `dtype`
+/// along with the motif lists and a `bins.bed` of the bins and their
+/// number of windows.
+pub fn aggregate_run(
+    input: &Path,
+    out_dir: &Path,
+    targets: &TargetBins,
+    dtype: CountDtype,
+) -> Result<AggregateSummary> {
+    let bins_path = input.join("bins.bed");
+    if !bins_path.exists() {
+        bail!(
+            "{:?} has no bins.bed (e.g. a --global run); only windowed runs can be aggregated",
+            input
+        );
+    }
+    let windows = read_regions(&bins_path)?;
+    let matrices = count_matrices(input)?;
+    if matrices.is_empty() {
+        bail!(
+            "No count matrices (*_counts.npy or *_counts_sparse.npz) in {:?}",
+            input
+        );
+    }
+    let assignment = assign_windows(&windows, targets);
+    let n_bins = assignment.bins.len();
+
+    std::fs::create_dir_all(out_dir).context(format!("Creating {:?}", out_dir))?;
+    for matrix in &matrices {
+        let path = input.join(matrix.file_name());
+        if matrix.sparse {
+            let counts = SparseCounts::read(&path).context(format!("Reading {:?}", path))?;
+            check_rows(&path, counts.shape[0], windows.len())?;
+            let triplets = counts
+                .triplets
+                .iter()
+                .flat_map(|&(row, col, count)| {
+                    assignment.bins_of_window[row as usize]
+                        .iter()
+                        .map(move |&bin| (bin as u64, col, count))
+                })
+                .collect();
+            write_summed_triplets(
+                triplets,
+                n_bins,
+                counts.shape[1],
+                &matrix.prefix,
+                out_dir,
+                counts.format,
+                dtype,
+            )?;
+        } else {
+            let bytes = std::fs::read(&path).context(format!("Reading {:?}", path))?;
+            let counts: Array2<BigCount> =
+                read_counts_npy(&bytes).context(format!("Reading {:?}", path))?;
+            check_rows(&path, counts.nrows(), windows.len())?;
+            let mut sums = Array2::<BigCount>::zeros((n_bins, counts.ncols()));
+            for (row, bins) in counts.rows().into_iter().zip(&assignment.bins_of_window) {
+                for &bin in bins {
+                    let mut sum = sums.row_mut(bin);
+                    sum += &row;
+                }
+            }
+            write_counts_npy(&out_dir.join(matrix.file_name()), &sums, dtype)?;
+        }
+        for suffix in ["motifs.txt", "motifs.json"] {
+            copy_if_exists(input, out_dir, &format!("{}_{}", matrix.prefix, suffix))?;
+        }
+    }
+
+    let out_bed = out_dir.join("bins.bed");
+    let mut bed = BufWriter::new(File::create(&out_bed).context("Create bed fail")?);
+    writeln!(bed, "#chrom\tstart\tend\tn_windows").context("Write bed line fail")?;
+    for ((chrom, start, end), n) in assignment.bins.iter().zip(assignment.windows_per_bin()) {
+        writeln!(bed, "{}\t{}\t{}\t{}", chrom, start, end, n).context("Write bed line fail")?;
+    }
+    bed.flush().context("Write bed line fail")?;
+
+    Ok(AggregateSummary {
+        matrices: matrices.iter().map(|m| m.file_name()).collect(),
+        n_windows: windows.len(),
+        n_unassigned: assignment
+            .bins_of_window
+            .iter()
+            .filter(|bins| bins.is_empty())
+            .count(),
+        n_bins,
+    })
+}
+
+/// Fail unless a matrix has a row per window of `bins.bed`
+fn check_rows(path: &Path, n_rows: usize, n_windows: usize) -> Result<()> {
+    if n_rows != n_windows {
+        bail!(
+            "{:?} has {} rows but bins.bed has {} windows",
+            path,
+            n_rows,
+            n_windows
+        );
+    }
+    Ok(())
+}
@@ -1,33 +1,88 @@
-use anyhow::{Context, Result};
+use crate::reference::chrom_alias::ChromAliases;
+use anyhow::{bail, Context, Result};
 use std::fs::File;
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     io::{BufRead, BufReader},
     path::Path,
 };
 
+/// Per-chromosome windows as `(start, end, original_idx)`
+pub type WindowMap = HashMap<String, Vec<(u64, u64, u64)>>;
+
+/// Names of the BED columns after `end`
+const BED_COLUMN_NAMES: [&str; 9] = [
+    "name",
+    "score",
+    "strand",
+    "thickStart",
+    "thickEnd",
+    "itemRgb",
+    "blockCount",
+    "blockSizes",
+    "blockStarts",
+];
+
+/// Columns after `end` (name, score, strand, ...) of BED windows
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BedColumns {
+    /// Column names, e.g. `["name", "score", "strand"]`
+    pub names: Vec<String>,
+    /// Values of every window by original index. Rows with fewer columns
+    /// than others are padded with `.`.
+    pub values: Vec<Vec<String>>,
+}
+
+impl BedColumns {
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+
+    /// Whether each window (by original index) is on the `-` strand, or
+    /// `None` without a `strand` column
+    pub fn minus_strand(&self) -> Option<Vec<bool>> {
+        let col = self.names.iter().position(|n| n == "strand")?;
+        Some(self.values.iter().map(|row| row[col] == "-").collect())
+    }
+}
+
 /// Load windows from a BED file into a per-chromosome map
+///
+/// Chromosome names are translated to reference names with `aliases`.
 pub fn load_windows(
     bed: &Path,
-    chromosomes: &Vec<String>,
-) -> Result<HashMap<String, Vec<(u64, u64, u64)>>> {
+    chromosomes: &[String],
+    aliases: Option<&ChromAliases>,
+) -> Result<WindowMap> {
+    Ok(load_windows_with_columns(bed, chromosomes, aliases)?.0)
+}
+
+/// Like `load_windows` but also keeps the columns after `end` of every
+/// window, indexed by its original index
+pub fn load_windows_with_columns(
+    bed: &Path,
+    chromosomes: &[String],
+    aliases: Option<&ChromAliases>,
+) -> Result<(WindowMap, BedColumns)> {
     let f = File::open(bed).context("Opening window BED")?;
     let reader = BufReader::new(f);
-    let mut mapping: HashMap<String, Vec<(u64, u64, u64)>> = HashMap::new();
+    let mut mapping: WindowMap = HashMap::new();
+    let wanted: HashSet<&str> = chromosomes.iter().map(String::as_str).collect();
     // Ensure all chromosomes are added
     chromosomes.iter().for_each(|chr| {
         mapping.entry(chr.to_string()).or_default();
     });
     // Original interval index for reconstructing order
     let mut win_idx = 0u64;
+    let mut extra: Vec<Vec<String>> = Vec::new();
     for line in reader.lines() {
         let l = line?;
         if l.starts_with('#') {
             continue;
         }
         let cols: Vec<&str> = l.split_whitespace().collect();
-        let chr = cols[0];
-        if !chromosomes.contains(&chr.to_owned()) {
+        let chr = aliases.map_or(cols[0], |a| a.resolve(cols[0]));
+        if !wanted.contains(chr) {
             continue;
         }
         let start: u64 = cols[1].parse().context("Parsing window start")?;
@@ -36,11 +91,246 @@ pub fn load_windows(
             .entry(chr.to_string())
             .or_default()
             .push((start, end, win_idx));
+        extra.push(cols[3..].iter().map(|c| c.to_string()).collect());
         win_idx += 1;
     }
     for v in mapping.values_mut() {
         // Ensure sorted windows
         v.sort_unstable_by_key(|&(s, e, _)| (s, e));
     }
-    Ok(mapping)
+
+    let n_extra = extra.iter().map(Vec::len).max().unwrap_or(0);
+    for row in &mut extra {
+        row.resize(n_extra, ".".to_string());
+    }
+    let names = (0..n_extra)
+        .map(|i| match BED_COLUMN_NAMES.get(i) {
+            Some(name) => name.to_string(),
+            None => format!("column{}", i + 4),
+        })
+        .collect();
+    Ok((
+        mapping,
+        BedColumns {
+            names,
+            values: extra,
+        },
+    ))
+}
+
+/// Absolute `[start, end)` blocks (e.g. exons) of BED12 windows, by
+/// original window index.
+///
+/// Windows without block columns (`blockCount`, `blockSizes` and
+/// `blockStarts`) get no blocks. Blocks are clipped to the window end.
+pub fn bed12_blocks(windows: &WindowMap, columns: &BedColumns) -> Result<Vec<Vec<(u64, u64)>>> {
+    let parse_list = |list: &str, name: &str| -> Result<Vec<u64>> {
+        list.split(',')
+            .filter(|v| !v.is_empty())
+            .map(|v| v.parse().context(format!("Parsing BED12 {}", name)))
+            .collect()
+    };
+    let mut blocks = vec![Vec::new(); columns.values.len()];
+    for wins in windows.values() {
+        for &(start, end, idx) in wins {
+            let row = &columns.values[idx as usize];
+            if row.len() < 9 || row[6] == "." {
+                continue;
+            }
+            let count: usize = row[6].parse().context("Parsing BED12 blockCount")?;
+            let sizes = parse_list(&row[7], "blockSizes")?;
+            let starts = parse_list(&row[8], "blockStarts")?;
+            if sizes.len() != count || starts.len() != count {
+                bail!(
+                    "BED12 window {}-{} has blockCount {} but {} block sizes and {} block starts",
+                    start,
+                    end,
+                    count,
+                    sizes.len(),
+                    starts.len()
+                );
+            }
+            blocks[idx as usize] = starts
+                .iter()
+                .zip(&sizes)
+                .map(|(&offset, &size)| (start + offset, (start + offset + size).min(end)))
+                .collect();
+        }
+    }
+    Ok(blocks)
+}
+
+/// A single anchor position (e.g. a TSS) with its label
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Anchor {
+    pub chrom: String,
+    /// 0-based position
+    pub pos: u64,
+    pub name: String,
+}
+
+/// Load anchor positions from a BED or TSV file, in file order
+///
+/// * BED rows (`chrom start end [name ...]`) are anchored at `start`.
+/// * TSV rows (`chrom pos [name]`) use the 0-based `pos`.
+/// * Anchors without a name are labelled `.`.
+/// * Chromosome names are translated to reference names with `aliases`.
+pub fn load_anchors(
+    path: &Path,
+    chromosomes: &[String],
+    aliases: Option<&ChromAliases>,
+) -> Result<Vec<Anchor>> {
+    let f = File::open(path).context("Opening anchor file")?;
+    let reader = BufReader::new(f);
+    let wanted: HashSet<&str> = chromosomes.iter().map(String::as_str).collect();
+    let mut anchors = Vec::new();
+    for line in reader.lines() {
+        let l = line?;
+        if l.is_empty() || l.starts_with('#') {
+            continue;
+        }
+        let cols: Vec<&str> = l.split_whitespace().collect();
+        let chr = aliases.map_or(cols[0], |a| a.resolve(cols[0]));
+        if !wanted.contains(chr) {
+            continue;
+        }
+        let pos: u64 = cols
+            .get(1)
+            .context("Missing anchor position")?
+            .parse()
+            .context("Parsing anchor position")?;
+        // A numeric third column is a BED end
+        let name_col = match cols.get(2).map(|c| c.parse::<u64>()) {
+            Some(Ok(_)) => 3,
+            _ => 2,
+        };
+        anchors.push(Anchor {
+            chrom: chr.to_string(),
+            pos,
+            name: cols.get(name_col).unwrap_or(&".").to_string(),
+        });
+    }
+    Ok(anchors)
+}
+
+/// Problem with a window found by `validate_windows`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum WindowIssue {
+    /// `start >= end`
+    Empty,
+    /// `end` is beyond the chromosome end
+    BeyondChromEnd,
+    /// Same coordinates as another window
+    Duplicate,
+    /// Overlaps a window with an earlier (or equal) start
+    Overlap,
+}
+
+impl WindowIssue {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            WindowIssue::Empty => "empty",
+            WindowIssue::BeyondChromEnd => "beyond_chrom_end",
+            WindowIssue::Duplicate => "duplicate",
+            WindowIssue::Overlap => "overlap",
+        }
+    }
+}
+
+/// A window with a problem
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WindowProblem {
+    pub chrom: String,
+    pub start: u64,
+    pub end: u64,
+    pub original_idx: u64,
+    pub issue: WindowIssue,
+}
+
+/// Find empty, duplicate, overlapping and out-of-bounds windows.
+///
+/// * `windows` must be sorted by `(start, end)` per chromosome, as returned
+///   by `load_windows`.
+/// * Chromosomes missing from `chrom_lengths` are not checked for
+///   out-of-bounds windows.
+/// * Problems are ordered by original window index.
+pub fn validate_windows(
+    windows: &WindowMap,
+    chrom_lengths: &HashMap<String, u64>,
+) -> Vec<WindowProblem> {
+    let mut problems = Vec::new();
+    for (chr, wins) in windows {
+        let chrom_len = chrom_lengths.get(chr).copied().unwrap_or(u64::MAX);
+        let mut prev: Option<(u64, u64)> = None;
+        let mut max_end = 0;
+        for &(start, end, original_idx) in wins {
+            let mut report = |issue| {
+                problems.push(WindowProblem {
+                    chrom: chr.clone(),
+                    start,
+                    end,
+                    original_idx,
+                    issue,
+                })
+            };
+            if start >= end {
+                report(WindowIssue::Empty);
+                continue;
+            }
+            if end > chrom_len {
+                report(WindowIssue::BeyondChromEnd);
+            }
+            if prev == Some((start, end)) {
+                report(WindowIssue::Duplicate);
+            } else if start < max_end {
+                report(WindowIssue::Overlap);
+            }
+            prev = Some((start, end));
+            max_end = max_end.max(end);
+        }
+    }
+    problems.sort_unstable_by_key(|p| (p.original_idx, p.issue));
+    problems
+}
+
+/// Clamp window ends to the chromosome ends and remove empty windows
+/// (including windows starting beyond the chromosome end).
+///
+/// Returns the number of removed windows.
+pub fn clamp_windows(windows: &mut WindowMap, chrom_lengths: &HashMap<String, u64>) -> usize {
+    let mut removed = 0;
+    for (chr, wins) in windows.iter_mut() {
+        let chrom_len = chrom_lengths.get(chr).copied().unwrap_or(u64::MAX);
+        let n_before = wins.len();
+        wins.retain_mut(|(start, end, _)| {
+            *end = (*end).min(chrom_len);
+            *start < *end
+        });
+        removed += n_before - wins.len();
+    }
+    removed
+}
+
+/// Merge overlapping (including duplicate) windows. A merged window keeps
+/// the lowest original index of its windows.
+///
+/// `windows` must be sorted by `(start, end)` per chromosome. Returns the
+/// number of windows merged into others.
+pub fn merge_overlapping_windows(windows: &mut WindowMap) -> usize {
+    let mut merged = 0;
+    for wins in windows.values_mut() {
+        let mut out: Vec<(u64, u64, u64)> = Vec::with_capacity(wins.len());
+        for &(start, end, idx) in wins.iter() {
+            match out.last_mut() {
+                Some(last) if start < last.1 => {
+                    last.1 = last.1.max(end);
+                    last.2 = last.2.min(idx);
+                    merged += 1;
+                }
+                _ => out.push((start, end, idx)),
+            }
+        }
+        *wins = out;
+    }
+    merged
 }
@@ -0,0 +1,27 @@
+use clap::ValueEnum;
+
+/// In-silico bisulfite conversion of a reference strand
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Bisulfite {
+    /// Convert C to T (the converted forward strand)
+    #[value(alias = "CT")]
+    Ct,
+    /// Convert G to A (the converted reverse strand, in forward orientation)
+    #[value(alias = "GA")]
+    Ga,
+}
+
+/// Convert `seq` in place, keeping the case of soft-masked bases
+pub fn bisulfite_convert(seq: &mut [u8], conversion: Bisulfite) {
+    let (from, to) = match conversion {
+        Bisulfite::Ct => (b'C', b'T'),
+        Bisulfite::Ga => (b'G', b'A'),
+    };
+    for b in seq.iter_mut() {
+        if *b == from {
+            *b = to;
+        } else if *b == from.to_ascii_lowercase() {
+            *b = to.to_ascii_lowercase();
+        }
+    }
+}
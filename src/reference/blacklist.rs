@@ -1,5 +1,9 @@
+use crate::reference::chrom_alias::ChromAliases;
 use anyhow::{Context, Result};
-use std::{collections::HashMap, path::PathBuf};
+use std::{
+    collections::{HashMap, HashSet},
+    path::PathBuf,
+};
 
 /// Load blacklist intervals into a `HashMap` keyed by chromosome name.
 ///
@@ -7,13 +11,16 @@ use std::{collections::HashMap, path::PathBuf};
 ///   ignores any additional BED fields.
 /// * Lines that begin with `#`, `track`, `browser`, or are blank are skipped.
 /// * `chromosomes` is usually the autosome whitelist (e.g. `["chr1", … "chr22"]`).
+/// * Chromosome names are translated to reference names with `aliases`.
 pub fn load_blacklist(
     bed: &PathBuf,
     min_size: u64,
-    chromosomes: &Vec<String>,
+    chromosomes: &[String],
+    aliases: Option<&ChromAliases>,
 ) -> Result<HashMap<String, Vec<(u64, u64)>>> {
     // Create a map from chromosome name to its blacklist intervals
     let mut map: HashMap<String, Vec<(u64, u64)>> = HashMap::new();
+    let wanted: HashSet<&str> = chromosomes.iter().map(String::as_str).collect();
     let content =
         std::fs::read_to_string(bed).context(format!("Error reading blacklist BED {:?}", bed))?;
     for line in content.lines().map(str::trim) {
@@ -28,11 +35,11 @@ pub fn load_blacklist(
         // Take only the first three whitespace-separated fields
         let mut fields = line.split_whitespace();
         let chr = match fields.next() {
-            Some(c) => c.to_string(),
+            Some(c) => aliases.map_or(c, |a| a.resolve(c)).to_string(),
             None => continue, // Malformed line
         };
         // Skip non-autosomes
-        if !chromosomes.contains(&chr) {
+        if !wanted.contains(chr.as_str()) {
             continue;
         }
         // Parse start and end; skip line if either fails
@@ -62,11 +69,12 @@ pub fn load_blacklist(
 pub fn load_blacklists(
     beds: &[PathBuf],
     min_size: u64,
-    chromosomes: &Vec<String>,
+    chromosomes: &[String],
+    aliases: Option<&ChromAliases>,
 ) -> Result<HashMap<String, Vec<(u64, u64)>>> {
     let mut merged: HashMap<String, Vec<(u64, u64)>> = HashMap::new();
     for bed in beds {
-        let single = load_blacklist(bed, min_size, chromosomes)?;
+        let single = load_blacklist(bed, min_size, chromosomes, aliases)?;
         for (chr, mut ivs) in single {
             merged.entry(chr).or_default().append(&mut ivs);
         }
@@ -149,6 +157,90 @@ pub fn merge_intervals(ivs: Vec<(u64, u64)>) -> Vec<(u64, u64)> {
 /// Byte used for blacklisted bases in the reference sequence
 pub const BLACKLIST_BYTE: u8 = b'X';
 
+/// Intervals `[start, end)` of low-complexity sequence found with a
+/// DUST-like scorer.
+///
+/// Every `window`-base window is scored from the counts `c` of its
+/// overlapping triplets as `Σ c·(c − 1) / 2 / (l − 1)`, with `l` the number
+/// of triplets, and masked when scoring above `threshold`. Triplets with
+/// non-A/C/G/T bases are ignored. Returns sorted, merged intervals.
+pub fn dust_intervals(seq: &[u8], window: usize, threshold: f64) -> Vec<(u64, u64)> {
+    let mut intervals: Vec<(u64, u64)> = Vec::new();
+    if window < 4 || seq.len() < window {
+        return intervals;
+    }
+    let triplet = |i: usize| -> Option<usize> {
+        seq[i..i + 3].iter().try_fold(0, |code, b| {
+            let base = match b.to_ascii_uppercase() {
+                b'A' => 0,
+                b'C' => 1,
+                b'G' => 2,
+                b'T' => 3,
+                _ => return None,
+            };
+            Some(code * 4 + base)
+        })
+    };
+    let n_triplets = window - 2;
+    let mut counts = [0u64; 64];
+    // Σ c·(c − 1) / 2 and number of triplets in the current window
+    let mut pairs = 0u64;
+    let mut n_valid = 0u64;
+    for i in 0..=seq.len() - 3 {
+        if let Some(t) = triplet(i) {
+            pairs += counts[t];
+            counts[t] += 1;
+            n_valid += 1;
+        }
+        if i >= n_triplets {
+            if let Some(t) = triplet(i - n_triplets) {
+                counts[t] -= 1;
+                pairs -= counts[t];
+                n_valid -= 1;
+            }
+        }
+        if i + 1 < n_triplets || n_valid < 2 {
+            continue;
+        }
+        if pairs as f64 / (n_valid - 1) as f64 > threshold {
+            let start = (i + 1 - n_triplets) as u64;
+            let end = start + window as u64;
+            match intervals.last_mut() {
+                Some(last) if start <= last.1 => last.1 = end,
+                _ => intervals.push((start, end)),
+            }
+        }
+    }
+    intervals
+}
+
+/// Intervals `[start, end)` of soft-masked (lower-case) bases in `seq`
+pub fn soft_masked_intervals(seq: &[u8]) -> Vec<(u64, u64)> {
+    let mut intervals = Vec::new();
+    let mut i = 0;
+    while i < seq.len() {
+        if !seq[i].is_ascii_lowercase() {
+            i += 1;
+            continue;
+        }
+        let start = i;
+        while i < seq.len() && seq[i].is_ascii_lowercase() {
+            i += 1;
+        }
+        intervals.push((start as u64, i as u64));
+    }
+    intervals
+}
+
+/// Replace every base inside `intervals` with `N`
+pub fn mask_intervals_as_n(seq: &mut [u8], intervals: &[(u64, u64)]) {
+    for &(start, end) in intervals {
+        let s = (start as usize).min(seq.len());
+        let e = (end as usize).min(seq.len());
+        seq[s..e].fill(b'N');
+    }
+}
+
 /// Mask every base that falls inside a blacklist interval with `BLACKLIST_BYTE`.
 ///
 /// * `seq`         – mutable byte slice of the reference chromosome  
@@ -0,0 +1,280 @@
+use crate::reference::code_cache::safe_file_name;
+use crate::reference::counting::RefKmerExtractionCounters;
+use crate::reference::kmer_codec::Kmer;
+use crate::reference::pwm::PwmHits;
+use crate::reference::repeats::RepeatContent;
+use anyhow::{bail, Context, Result};
+use std::{
+    collections::HashMap,
+    fs::File,
+    hash::{BuildHasher, Hash, Hasher},
+    io::{BufReader, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+};
+
+/// File magic + format version of chromosome checkpoints
+const MAGIC: &[u8; 8] = b"RKCHKPT1";
+
+/// Values stored in checkpoints, in a little-endian binary format
+pub trait Checkpoint: Sized {
+    fn write_to(&self, w: &mut impl Write) -> Result<()>;
+    fn read_from(r: &mut impl Read) -> Result<Self>;
+}
+
+impl Checkpoint for u8 {
+    fn write_to(&self, w: &mut impl Write) -> Result<()> {
+        Ok(w.write_all(&[*self])?)
+    }
+    fn read_from(r: &mut impl Read) -> Result<Self> {
+        let mut buf = [0u8; 1];
+        r.read_exact(&mut buf)?;
+        Ok(buf[0])
+    }
+}
+
+impl Checkpoint for u64 {
+    fn write_to(&self, w: &mut impl Write) -> Result<()> {
+        Ok(w.write_all(&self.to_le_bytes())?)
+    }
+    fn read_from(r: &mut impl Read) -> Result<Self> {
+        let mut buf = [0u8; 8];
+        r.read_exact(&mut buf)?;
+        Ok(u64::from_le_bytes(buf))
+    }
+}
+
+impl Checkpoint for f64 {
+    fn write_to(&self, w: &mut impl Write) -> Result<()> {
+        self.to_bits().write_to(w)
+    }
+    fn read_from(r: &mut impl Read) -> Result<Self> {
+        Ok(f64::from_bits(u64::read_from(r)?))
+    }
+}
+
+impl Checkpoint for String {
+    fn write_to(&self, w: &mut impl Write) -> Result<()> {
+        (self.len() as u64).write_to(w)?;
+        Ok(w.write_all(self.as_bytes())?)
+    }
+    fn read_from(r: &mut impl Read) -> Result<Self> {
+        let len = u64::read_from(r)? as usize;
+        let mut buf = Vec::new();
+        r.take(len as u64).read_to_end(&mut buf)?;
+        if buf.len() != len {
+            bail!("Truncated string");
+        }
+        Ok(String::from_utf8(buf)?)
+    }
+}
+
+impl<T: Checkpoint> Checkpoint for Vec<T> {
+    fn write_to(&self, w: &mut impl Write) -> Result<()> {
+        (self.len() as u64).write_to(w)?;
+        self.iter().try_for_each(|v| v.write_to(w))
+    }
+    fn read_from(r: &mut impl Read) -> Result<Self> {
+        let len = u64::read_from(r)?;
+        (0..len).map(|_| T::read_from(r)).collect()
+    }
+}
+
+impl<T: Checkpoint> Checkpoint for Option<T> {
+    fn write_to(&self, w: &mut impl Write) -> Result<()> {
+        match self {
+            None => 0u8.write_to(w),
+            Some(v) => {
+                1u8.write_to(w)?;
+                v.write_to(w)
+            }
+        }
+    }
+    fn read_from(r: &mut impl Read) -> Result<Self> {
+        match u8::read_from(r)? {
+            0 => Ok(None),
+            1 => Ok(Some(T::read_from(r)?)),
+            tag => bail!("Invalid option tag {}", tag),
+        }
+    }
+}
+
+impl<T: Checkpoint + Copy + Default, const N: usize> Checkpoint for [T; N] {
+    fn write_to(&self, w: &mut impl Write) -> Result<()> {
+        self.iter().try_for_each(|v| v.write_to(w))
+    }
+    fn read_from(r: &mut impl Read) -> Result<Self> {
+        let mut out = [T::default(); N];
+        for v in &mut out {
+            *v = T::read_from(r)?;
+        }
+        Ok(out)
+    }
+}
+
+impl<K, V, S> Checkpoint for HashMap<K, V, S>
+where
+    K: Checkpoint + Eq + Hash,
+    V: Checkpoint,
+    S: BuildHasher + Default,
+{
+    fn write_to(&self, w: &mut impl Write) -> Result<()> {
+        (self.len() as u64).write_to(w)?;
+        self.iter().try_for_each(|(k, v)| {
+            k.write_to(w)?;
+            v.write_to(w)
+        })
+    }
+    fn read_from(r: &mut impl Read) -> Result<Self> {
+        let len = u64::read_from(r)?;
+        (0..len)
+            .map(|_| Ok((K::read_from(r)?, V::read_from(r)?)))
+            .collect()
+    }
+}
+
+/// Checkpoint of a tuple, field by field
+macro_rules! tuple_checkpoint {
+    ($($name:ident)+) => {
+        impl<$($name: Checkpoint),+> Checkpoint for ($($name,)+) {
+            #[allow(non_snake_case)]
+            fn write_to(&self, w: &mut impl Write) -> Result<()> {
+                let ($($name,)+) = self;
+                $($name.write_to(w)?;)+
+                Ok(())
+            }
+            fn read_from(r: &mut impl Read) -> Result<Self> {
+                Ok(($($name::read_from(r)?,)+))
+            }
+        }
+    };
+}
+
+tuple_checkpoint!(A B);
+tuple_checkpoint!(A B C D);
+tuple_checkpoint!(A B C D E F G H);
+
+impl Checkpoint for Kmer {
+    fn write_to(&self, w: &mut impl Write) -> Result<()> {
+        (self.k, self.code).write_to(w)
+    }
+    fn read_from(r: &mut impl Read) -> Result<Self> {
+        let (k, code) = <(u8, u64)>::read_from(r)?;
+        Ok(Kmer { k, code })
+    }
+}
+
+impl Checkpoint for RefKmerExtractionCounters {
+    fn write_to(&self, w: &mut impl Write) -> Result<()> {
+        [
+            self.total,
+            self.blacklisted,
+            self.ambiguous,
+            self.counted,
+            self.low_complexity,
+            self.skipped,
+        ]
+        .write_to(w)
+    }
+    fn read_from(r: &mut impl Read) -> Result<Self> {
+        let [total, blacklisted, ambiguous, counted, low_complexity, skipped] =
+            <[u64; 6]>::read_from(r)?;
+        Ok(RefKmerExtractionCounters {
+            total,
+            blacklisted,
+            ambiguous,
+            counted,
+            low_complexity,
+            skipped,
+        })
+    }
+}
+
+impl Checkpoint for RepeatContent {
+    fn write_to(&self, w: &mut impl Write) -> Result<()> {
+        self.homopolymer_runs.write_to(w)?;
+        self.str_bases.write_to(w)
+    }
+    fn read_from(r: &mut impl Read) -> Result<Self> {
+        Ok(RepeatContent {
+            homopolymer_runs: Checkpoint::read_from(r)?,
+            str_bases: Checkpoint::read_from(r)?,
+        })
+    }
+}
+
+impl Checkpoint for PwmHits {
+    fn write_to(&self, w: &mut impl Write) -> Result<()> {
+        self.hits.write_to(w)?;
+        self.scores.write_to(w)
+    }
+    fn read_from(r: &mut impl Read) -> Result<Self> {
+        Ok(PwmHits {
+            hits: Checkpoint::read_from(r)?,
+            scores: Checkpoint::read_from(r)?,
+        })
+    }
+}
+
+/// Fingerprint of the resolved `(option, values)` and seeds a run was
+/// started with. Options in `ignored` (e.g. the number of threads) don't
+/// change the results and are left out.
+pub fn options_fingerprint(
+    options: &[(String, Vec<String>)],
+    ignored: &[&str],
+    seeds: &[Option<u64>],
+) -> u64 {
+    let mut hasher = fxhash::FxHasher64::default();
+    for (option, values) in options {
+        if !ignored.contains(&option.as_str()) {
+            option.hash(&mut hasher);
+            values.hash(&mut hasher);
+        }
+    }
+    seeds.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Path of the checkpoint of one chromosome
+pub fn checkpoint_path(dir: &Path, chr: &str) -> PathBuf {
+    dir.join(format!("{}.checkpoint", safe_file_name(chr)))
+}
+
+/// Write a checkpoint made with options of `fingerprint`.
+///
+/// The file is written next to its final path and renamed into place,
+/// so interrupted runs never leave half-written checkpoints.
+pub fn store_checkpoint<T: Checkpoint>(path: &Path, fingerprint: u64, value: &T) -> Result<()> {
+    let tmp = path.with_extension("checkpoint.tmp");
+    {
+        let f = File::create(&tmp).context(format!("creating checkpoint {:?}", tmp))?;
+        let mut w = BufWriter::new(f);
+        w.write_all(MAGIC)?;
+        fingerprint.write_to(&mut w)?;
+        value
+            .write_to(&mut w)
+            .context(format!("writing checkpoint {:?}", tmp))?;
+        w.flush()?;
+    }
+    std::fs::rename(&tmp, path).context(format!("moving checkpoint into {:?}", path))?;
+    Ok(())
+}
+
+/// Read a checkpoint.
+///
+/// Returns `None` when the file is missing, truncated or was made with
+/// options of another fingerprint.
+pub fn load_checkpoint<T: Checkpoint>(path: &Path, fingerprint: u64) -> Result<Option<T>> {
+    if !path.exists() {
+        return Ok(None);
+    }
+    let f = File::open(path).context(format!("opening checkpoint {:?}", path))?;
+    let mut r = BufReader::new(f);
+    let mut magic = [0u8; 8];
+    if r.read_exact(&mut magic).is_err() || &magic != MAGIC {
+        return Ok(None);
+    }
+    if u64::read_from(&mut r).ok() != Some(fingerprint) {
+        return Ok(None);
+    }
+    Ok(T::read_from(&mut r).ok())
+}
@@ -0,0 +1,96 @@
+use anyhow::{Context, Result};
+use std::{
+    collections::{HashMap, HashSet},
+    path::Path,
+};
+
+/// Translation of chromosome names (e.g. `1`, `chr1`, `NC_000001.11`) to the
+/// names used by the reference.
+///
+/// Names that are not aliases resolve to themselves.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ChromAliases {
+    /// Alias → reference name
+    map: HashMap<String, String>,
+}
+
+impl ChromAliases {
+    /// The reference name of `name`
+    pub fn resolve<'a>(&'a self, name: &'a str) -> &'a str {
+        self.map.get(name).map_or(name, String::as_str)
+    }
+
+    /// The reference names of `names` in order, keeping only the first of
+    /// names that resolve to the same reference name (e.g. `1` and `chr1`)
+    pub fn resolve_all(&self, names: &[String]) -> Vec<String> {
+        let mut seen = HashSet::new();
+        names
+            .iter()
+            .map(|name| self.resolve(name))
+            .filter(|name| seen.insert(*name))
+            .map(str::to_string)
+            .collect()
+    }
+
+    /// Number of known aliases
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// Add built-in UCSC ↔ Ensembl translations for `reference_names`:
+    /// `chr` prefixes are added/removed and `chrM` ↔ `MT`.
+    ///
+    /// Existing aliases take precedence.
+    pub fn add_ucsc_ensembl(&mut self, reference_names: &[String]) {
+        let in_ref: HashSet<&str> = reference_names.iter().map(String::as_str).collect();
+        for name in reference_names {
+            let mut aliases = Vec::new();
+            match name.strip_prefix("chr") {
+                Some("M") => aliases.extend(["MT".to_string(), "M".to_string()]),
+                Some(rest) => aliases.push(rest.to_string()),
+                None if name == "MT" => aliases.extend(["chrM".to_string(), "chrMT".to_string()]),
+                None => aliases.push(format!("chr{}", name)),
+            }
+            for alias in aliases {
+                if !in_ref.contains(alias.as_str()) {
+                    self.map.entry(alias).or_insert_with(|| name.clone());
+                }
+            }
+        }
+    }
+}
+
+/// Load a chromosome alias table.
+///
+/// * Each line lists tab-separated names of the same sequence, in any
+///   column order (e.g. UCSC `chromAlias.txt`).
+/// * The name found in `reference_names` becomes the target of all other
+///   names on the line. Lines without a reference name are ignored.
+/// * Lines that begin with `#` or are blank are skipped.
+pub fn load_chrom_aliases(path: &Path, reference_names: &[String]) -> Result<ChromAliases> {
+    let content =
+        std::fs::read_to_string(path).context(format!("Error reading chrom aliases {:?}", path))?;
+    let in_ref: HashSet<&str> = reference_names.iter().map(String::as_str).collect();
+    let mut aliases = ChromAliases::default();
+    for line in content.lines() {
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let names: Vec<&str> = line
+            .split('\t')
+            .map(str::trim)
+            .filter(|n| !n.is_empty())
+            .collect();
+        let Some(target) = names.iter().find(|n| in_ref.contains(**n)) else {
+            continue;
+        };
+        for name in names.iter().filter(|n| *n != target) {
+            aliases.map.insert(name.to_string(), target.to_string());
+        }
+    }
+    Ok(aliases)
+}
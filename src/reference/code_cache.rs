@@ -0,0 +1,209 @@
+use crate::reference::kmer_codec::{
+    build_codes_per_k, build_codes_per_k_circular, Encoding, KmerCodes, KmerSpec, Width,
+};
+use anyhow::{Context, Result};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::{BufWriter, Read, Write},
+    path::{Path, PathBuf},
+};
+
+/// File magic + format version of cached code vectors
+///
+/// Version 2 added the encoding to the header and hashes the sequence with
+/// SHA-256.
+const MAGIC: &[u8; 8] = b"RKCODES2";
+
+/// See `sequence_hash`
+pub type SequenceHash = [u8; 32];
+
+/// SHA-256 of the (blacklist-masked) sequence that codes are built from.
+///
+/// The circular flag is part of the hash as it changes the codes at the end.
+/// A cryptographic hash keeps codes of another sequence from ever being
+/// loaded by a collision.
+pub fn sequence_hash(seq: &[u8], circular: bool) -> SequenceHash {
+    let mut hasher = Sha256::new();
+    hasher.update(seq);
+    hasher.update([circular as u8]);
+    hasher.update((seq.len() as u64).to_le_bytes());
+    hasher.finalize().into()
+}
+
+/// Path of the cache file for one chromosome and k
+pub fn cache_path(dir: &Path, chr: &str, k: u8) -> PathBuf {
+    dir.join(format!("{}.k{k}.codes", safe_file_name(chr)))
+}
+
+/// `chr` with characters other than ASCII letters, digits, `.`, `_` and
+/// `-` replaced by `_`, to keep file names safe for odd contig names.
+///
+/// Names of only dots (`.`, `..`) and the empty name become underscores, so
+/// the result is also safe as a directory name.
+pub fn safe_file_name(chr: &str) -> String {
+    if chr.chars().all(|c| c == '.') {
+        return "_".repeat(chr.len().max(1));
+    }
+    chr.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-') {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+fn encoding_byte(encoding: Encoding) -> u8 {
+    match encoding {
+        Encoding::Radix5 => 1,
+        Encoding::TwoBit => 2,
+        Encoding::Dictionary => 3,
+    }
+}
+
+fn width_bytes(width: Width) -> u8 {
+    match width {
+        Width::U8 => 1,
+        Width::U16 => 2,
+        Width::U32 => 4,
+        Width::U64 => 8,
+    }
+}
+
+/// Write one code vector to the cache.
+///
+/// The file is written next to its final path and renamed into place,
+/// so concurrent or interrupted runs never leave half-written caches.
+pub fn store_codes(
+    path: &Path,
+    spec: &KmerSpec,
+    codes: &KmerCodes,
+    seq_hash: &SequenceHash,
+) -> Result<()> {
+    let tmp = path.with_extension("codes.tmp");
+    {
+        let f = File::create(&tmp).context(format!("creating code cache {:?}", tmp))?;
+        let mut w = BufWriter::new(f);
+        w.write_all(MAGIC)?;
+        w.write_all(&[
+            spec.k as u8,
+            width_bytes(codes.width()),
+            encoding_byte(spec.encoding()),
+        ])?;
+        w.write_all(seq_hash)?;
+        w.write_all(&(codes.len() as u64).to_le_bytes())?;
+        match codes {
+            KmerCodes::U8(v) => w.write_all(v)?,
+            KmerCodes::U16(v) => v.iter().try_for_each(|c| w.write_all(&c.to_le_bytes()))?,
+            KmerCodes::U32(v) => v.iter().try_for_each(|c| w.write_all(&c.to_le_bytes()))?,
+            KmerCodes::U64(v) => v.iter().try_for_each(|c| w.write_all(&c.to_le_bytes()))?,
+        }
+        w.flush()?;
+    }
+    std::fs::rename(&tmp, path).context(format!("moving code cache into {:?}", path))?;
+    Ok(())
+}
+
+/// Read one code vector from the cache.
+///
+/// Returns `None` when the file is missing, of an older format version or
+/// was built from a different sequence, k, storage width or encoding.
+pub fn load_codes(
+    path: &Path,
+    spec: &KmerSpec,
+    seq_hash: &SequenceHash,
+) -> Result<Option<KmerCodes>> {
+    if !path.exists() {
+        return Ok(None);
+    }
+    let mut f = File::open(path).context(format!("opening code cache {:?}", path))?;
+    let mut header = [0u8; 51];
+    if f.read_exact(&mut header).is_err() || &header[..8] != MAGIC {
+        return Ok(None);
+    }
+    let [k, width, encoding] = [header[8], header[9], header[10]];
+    let hash = &header[11..43];
+    let len = u64::from_le_bytes(header[43..51].try_into().unwrap()) as usize;
+    if k as usize != spec.k
+        || width != width_bytes(spec.width())
+        || encoding != encoding_byte(spec.encoding())
+        || hash != seq_hash
+    {
+        return Ok(None);
+    }
+
+    let mut buf = Vec::with_capacity(len * width as usize);
+    f.read_to_end(&mut buf)
+        .context(format!("reading code cache {:?}", path))?;
+    if buf.len() != len * width as usize {
+        return Ok(None); // truncated
+    }
+
+    let codes = match spec.width() {
+        Width::U8 => KmerCodes::U8(buf),
+        Width::U16 => KmerCodes::U16(
+            buf.chunks_exact(2)
+                .map(|b| u16::from_le_bytes([b[0], b[1]]))
+                .collect(),
+        ),
+        Width::U32 => KmerCodes::U32(
+            buf.chunks_exact(4)
+                .map(|b| u32::from_le_bytes(b.try_into().unwrap()))
+                .collect(),
+        ),
+        Width::U64 => KmerCodes::U64(
+            buf.chunks_exact(8)
+                .map(|b| u64::from_le_bytes(b.try_into().unwrap()))
+                .collect(),
+        ),
+    };
+    Ok(Some(codes))
+}
+
+/// Load code vectors for every k from `dir`, building and caching the
+/// missing or stale ones. Dictionary codes are always built.
+pub fn load_or_build_codes_per_k(
+    dir: &Path,
+    chr: &str,
+    seq: &[u8],
+    specs: &BTreeMap<u8, KmerSpec>,
+    circular: bool,
+) -> Result<BTreeMap<u8, KmerCodes>> {
+    let seq_hash = sequence_hash(seq, circular);
+    let mut map = BTreeMap::new();
+    let mut missing: BTreeMap<u8, KmerSpec> = BTreeMap::new();
+    for (&k, spec) in specs {
+        // Dictionary numbers are only valid within one run
+        if spec.encoding() == Encoding::Dictionary {
+            missing.insert(k, spec.clone());
+            continue;
+        }
+        match load_codes(&cache_path(dir, chr, k), spec, &seq_hash)? {
+            Some(codes) => {
+                map.insert(k, codes);
+            }
+            None => {
+                missing.insert(k, spec.clone());
+            }
+        }
+    }
+
+    if !missing.is_empty() {
+        let built = if circular {
+            build_codes_per_k_circular(seq, &missing)
+        } else {
+            build_codes_per_k(seq, &missing)
+        };
+        for (k, codes) in built {
+            if specs[&k].encoding() != Encoding::Dictionary {
+                store_codes(&cache_path(dir, chr, k), &specs[&k], &codes, &seq_hash)?;
+            }
+            map.insert(k, codes);
+        }
+    }
+    Ok(map)
+}
@@ -0,0 +1,279 @@
+use crate::cli::BigCount;
+use crate::reference::aggregate::read_regions;
+use crate::reference::merge::{count_matrices, read_counts_npy, CountMatrix, SparseCounts};
+use crate::reference::write::write_npy;
+use anyhow::{bail, Context, Result};
+use ndarray::{Array2, Axis, Zip};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+/// Summary statistics of a matrix compared between two runs
+#[derive(Debug, Clone, PartialEq)]
+pub struct MatrixComparison {
+    /// File name of the compared matrix, e.g. `k3_counts.npy`
+    pub matrix: String,
+    pub n_windows: usize,
+    /// Motifs in either run
+    pub n_motifs: usize,
+    pub total_a: BigCount,
+    pub total_b: BigCount,
+    /// Window × motif cells with different counts
+    pub n_changed: u64,
+    pub max_abs_diff: u64,
+    pub mean_abs_diff: f64,
+    /// Pearson correlation of all cells (NaN when either run is constant)
+    pub pearson: f64,
+}
+
+/// Compare the count matrices of the output directories `a` and `b` and
+/// write the differences to `out_dir`.
+///
+/// The runs must have the same windows (the `chrom, start, end` of
+/// `bins.bed`). Matrices are matched by file name and their columns by
+/// motif, so runs with different motif lists (e.g. sparse outputs of two
+/// genomes) can be compared; a motif missing from a run counts as 0. For
+/// every matrix, `out_dir` gets (dense, over the motifs of `a` followed by
+/// those only in `b`):
+///
+/// * `<prefix>_diff.npy` - `b - a` as int64
+/// * `<prefix>_log2_ratio.npy` - `log2((b + pseudocount) / (a + pseudocount))`
+/// * `<prefix>_motifs.txt` - the motif of every column
+/// * `<prefix>_motif_summary.tsv` - per-motif totals, differences and log2 ratios
+///
+/// along with `bins.bed` and a `compare_summary.tsv` of the returned
+/// statistics. Matrices found in only one run are skipped and named in
+/// the second value.
+pub fn compare_runs(
+    a: &Path,
+    b: &Path,
+    out_dir: &Path,
+    pseudocount: f64,
+) -> Result<(Vec<MatrixComparison>, Vec<String>)> {
+    let bins_a = a.join("bins.bed");
+    let bins_b = b.join("bins.bed");
+    match (bins_a.exists(), bins_b.exists()) {
+        (true, true) => {
+            if read_regions(&bins_a)? != read_regions(&bins_b)? {
+                bail!("The windows (bins.bed) of {:?} and {:?} differ", a, b);
+            }
+        }
+        (false, false) => {}
+        _ => bail!(
+            "Only one of {:?} and {:?} has a bins.bed; the windows differ",
+            a,
+            b
+        ),
+    }
+
+    let matrices_a = count_matrices(a)?;
+    let matrices_b = count_matrices(b)?;
+    let names_b: BTreeSet<String> = matrices_b.iter().map(CountMatrix::file_name).collect();
+    let names_a: BTreeSet<String> = matrices_a.iter().map(CountMatrix::file_name).collect();
+    let skipped: Vec<String> = names_a.symmetric_difference(&names_b).cloned().collect();
+    let shared: Vec<&CountMatrix> = matrices_a
+        .iter()
+        .filter(|m| names_b.contains(&m.file_name()))
+        .collect();
+    if shared.is_empty() {
+        bail!("{:?} and {:?} have no count matrices in common", a, b);
+    }
+
+    std::fs::create_dir_all(out_dir).context(format!("Creating {:?}", out_dir))?;
+    let mut comparisons = Vec::with_capacity(shared.len());
+    for matrix in shared {
+        let motifs_a = read_motifs(a, &matrix.prefix)?;
+        let motifs_b = read_motifs(b, &matrix.prefix)?;
+        // Motifs of `a`, then those only in `b`
+        let mut motifs = motifs_a.clone();
+        let in_a: HashSet<&String> = motifs_a.iter().collect();
+        motifs.extend(motifs_b.iter().filter(|m| !in_a.contains(m)).cloned());
+        let counts_a = read_aligned(a, matrix, &motifs_a, &motifs)?;
+        let counts_b = read_aligned(b, matrix, &motifs_b, &motifs)?;
+        if counts_a.nrows() != counts_b.nrows() {
+            bail!(
+                "{} has {} rows in {:?} but {} in {:?}",
+                matrix.file_name(),
+                counts_a.nrows(),
+                a,
+                counts_b.nrows(),
+                b
+            );
+        }
+
+        let diff = Zip::from(&counts_b)
+            .and(&counts_a)
+            .map_collect(|&b, &a| b as i64 - a as i64);
+        let log2_ratio = Zip::from(&counts_b)
+            .and(&counts_a)
+            .map_collect(|&b, &a| log2_ratio(b, a, pseudocount));
+        let prefix = &matrix.prefix;
+        write_npy(&out_dir.join(format!("{prefix}_diff.npy")), &diff)?;
+        write_npy(
+            &out_dir.join(format!("{prefix}_log2_ratio.npy")),
+            &log2_ratio,
+        )?;
+        std::fs::write(
+            out_dir.join(format!("{prefix}_motifs.txt")),
+            motifs.iter().map(|m| format!("{m}\n")).collect::<String>(),
+        )
+        .context(format!("Write {}_motifs.txt fail", prefix))?;
+        write_motif_summary(
+            &out_dir.join(format!("{prefix}_motif_summary.tsv")),
+            &motifs,
+            &counts_a,
+            &counts_b,
+            pseudocount,
+        )?;
+
+        comparisons.push(compare_counts(
+            matrix.file_name(),
+            &counts_a,
+            &counts_b,
+            &diff,
+        ));
+    }
+
+    if bins_a.exists() {
+        std::fs::copy(&bins_a, out_dir.join("bins.bed"))
+            .context(format!("Copying {:?}", bins_a))?;
+    }
+    write_summary(&out_dir.join("compare_summary.tsv"), &comparisons)?;
+    Ok((comparisons, skipped))
+}
+
+/// `log2((b + pseudocount) / (a + pseudocount))`
+fn log2_ratio(b: BigCount, a: BigCount, pseudocount: f64) -> f64 {
+    ((b as f64 + pseudocount) / (a as f64 + pseudocount)).log2()
+}
+
+fn read_motifs(dir: &Path, prefix: &str) -> Result<Vec<String>> {
+    let path = dir.join(format!("{prefix}_motifs.txt"));
+    let text = std::fs::read_to_string(&path).context(format!("Reading {:?}", path))?;
+    Ok(text.lines().map(str::to_string).collect())
+}
+
+/// The counts of a matrix with its `own` motif columns moved to the
+/// columns of `motifs`
+fn read_aligned(
+    dir: &Path,
+    matrix: &CountMatrix,
+    own: &[String],
+    motifs: &[String],
+) -> Result<Array2<BigCount>> {
+    let path = dir.join(matrix.file_name());
+    let index: HashMap<&str, usize> = motifs
+        .iter()
+        .enumerate()
+        .map(|(i, m)| (m.as_str(), i))
+        .collect();
+    let column: Vec<usize> = own.iter().map(|m| index[m.as_str()]).collect();
+    let (n_rows, triplets) = if matrix.sparse {
+        let counts = SparseCounts::read(&path).context(format!("Reading {:?}", path))?;
+        (counts.shape[0], counts.triplets)
+    } else {
+        let bytes = std::fs::read(&path).context(format!("Reading {:?}", path))?;
+        let counts: Array2<BigCount> =
+            read_counts_npy(&bytes).context(format!("Reading {:?}", path))?;
+        let triplets = counts
+            .indexed_iter()
+            .filter(|(_, &count)| count > 0)
+            .map(|((row, col), &count)| (row as u64, col as u64, count))
+            .collect();
+        (counts.nrows(), triplets)
+    };
+    let mut aligned = Array2::<BigCount>::zeros((n_rows, motifs.len()));
+    for (row, col, count) in triplets {
+        let Some(&col) = column.get(col as usize) else {
+            bail!("{:?} has more columns than motifs", path);
+        };
+        aligned[[row as usize, col]] += count;
+    }
+    Ok(aligned)
+}
+
+fn compare_counts(
+    matrix: String,
+    a: &Array2<BigCount>,
+    b: &Array2<BigCount>,
+    diff: &Array2<i64>,
+) -> MatrixComparison {
+    let n_cells = diff.len().max(1) as f64;
+    let abs_diffs = diff.iter().map(|d| d.unsigned_abs());
+    let (mut sum_a, mut sum_b, mut sum_aa, mut sum_bb, mut sum_ab) = (0.0, 0.0, 0.0, 0.0, 0.0);
+    for (&a, &b) in a.iter().zip(b) {
+        let (a, b) = (a as f64, b as f64);
+        sum_a += a;
+        sum_b += b;
+        sum_aa += a * a;
+        sum_bb += b * b;
+        sum_ab += a * b;
+    }
+    let cov = sum_ab - sum_a * sum_b / n_cells;
+    let var_a = sum_aa - sum_a * sum_a / n_cells;
+    let var_b = sum_bb - sum_b * sum_b / n_cells;
+    MatrixComparison {
+        matrix,
+        n_windows: a.nrows(),
+        n_motifs: a.ncols(),
+        total_a: a.sum(),
+        total_b: b.sum(),
+        n_changed: diff.iter().filter(|&&d| d != 0).count() as u64,
+        max_abs_diff: abs_diffs.clone().max().unwrap_or(0),
+        mean_abs_diff: abs_diffs.map(|d| d as f64).sum::<f64>() / n_cells,
+        pearson: cov / (var_a * var_b).sqrt(),
+    }
+}
+
+/// Write `motif, total_a, total_b, diff, log2_ratio` per motif
+fn write_motif_summary(
+    path: &Path,
+    motifs: &[String],
+    a: &Array2<BigCount>,
+    b: &Array2<BigCount>,
+    pseudocount: f64,
+) -> Result<()> {
+    let f = File::create(path).context(format!("Create {:?} fail", path))?;
+    let mut w = BufWriter::new(f);
+    writeln!(w, "motif\ttotal_a\ttotal_b\tdiff\tlog2_ratio")?;
+    let totals_a = a.sum_axis(Axis(0));
+    let totals_b = b.sum_axis(Axis(0));
+    for ((motif, &total_a), &total_b) in motifs.iter().zip(&totals_a).zip(&totals_b) {
+        writeln!(
+            w,
+            "{}\t{}\t{}\t{}\t{}",
+            motif,
+            total_a,
+            total_b,
+            total_b as i64 - total_a as i64,
+            log2_ratio(total_b, total_a, pseudocount)
+        )?;
+    }
+    w.flush().context(format!("Writing {:?}", path))
+}
+
+fn write_summary(path: &Path, comparisons: &[MatrixComparison]) -> Result<()> {
+    let f = File::create(path).context(format!("Create {:?} fail", path))?;
+    let mut w = BufWriter::new(f);
+    writeln!(
+        w,
+        "matrix\tn_windows\tn_motifs\ttotal_a\ttotal_b\tn_changed\tmax_abs_diff\tmean_abs_diff\tpearson"
+    )?;
+    for c in comparisons {
+        writeln!(
+            w,
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+            c.matrix,
+            c.n_windows,
+            c.n_motifs,
+            c.total_a,
+            c.total_b,
+            c.n_changed,
+            c.max_abs_diff,
+            c.mean_abs_diff,
+            c.pearson
+        )?;
+    }
+    w.flush().context(format!("Writing {:?}", path))
+}
@@ -1,42 +1,591 @@
 use crate::{cli::BigCount, reference::kmer_codec::*};
+use clap::ValueEnum;
 use fxhash::FxHashMap;
+use rayon::prelude::*;
 use smallvec::SmallVec;
+use std::collections::BTreeMap;
 
-/// Count k-mers for every window on one chromosome
+/// Positions of a long window (e.g. a whole chromosome with `--global`)
+/// counted by one parallel task
+const PIECE_SIZE: u64 = 1 << 20;
+
+/// Consecutive windows counted by one parallel task when `count_kmers`
+/// derives overlapping windows from each other
+const SLIDING_GROUP_SIZE: usize = 1024;
+
+/// Most distinct k-mers per k reserved up front for one window; maps of
+/// longer windows grow beyond this as needed
+const MAX_RESERVED_KMERS: usize = 1 << 20;
+
+/// Expected number of distinct k-mers of size `k` in a window of
+/// `window_len` bases: one per position, at most the `4^k` possible
+/// k-mers, capped at `MAX_RESERVED_KMERS`
+pub fn expected_distinct_kmers(window_len: u64, k: u8) -> usize {
+    let positions = window_len.saturating_sub(k as u64 - 1);
+    let possible = 4u64.saturating_pow(k as u32);
+    positions.min(possible).min(MAX_RESERVED_KMERS as u64) as usize
+}
+
+/// Which k-mers belong to a window
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum KmerBoundary {
+    /// K-mers that lie entirely within the window
+    #[default]
+    WithinWindow,
+    /// K-mers that start in the window, including those extending past its
+    /// end (into the next window); every k-mer of tiling windows is counted
+    /// exactly once
+    StartsInWindow,
+}
+
+/// Windows of one chromosome and which of its k-mers belong to them
+#[derive(Clone, Copy, Debug)]
+pub struct WindowLayout<'a> {
+    /// (start, end, _original_idx) for every window
+    pub windows: &'a [(u64, u64, u64)],
+    /// Chromosome length (used to cap window ends)
+    pub chrom_len: u64,
+    /// K-mers may run past the chromosome end of windows ending there
+    /// (requires codes from `build_codes_per_k_circular`)
+    pub circular: bool,
+    pub boundary: KmerBoundary,
+}
+
+impl<'a> WindowLayout<'a> {
+    /// Linear `windows` counting the k-mers that lie entirely within them
+    pub fn new(windows: &'a [(u64, u64, u64)], chrom_len: u64) -> Self {
+        WindowLayout {
+            windows,
+            chrom_len,
+            circular: false,
+            boundary: KmerBoundary::WithinWindow,
+        }
+    }
+
+    /// Position that the k-mers of a window ending at `win_end` (capped at
+    /// the chromosome end) must end at or before
+    fn kmer_limit(&self, win_end: u64) -> u64 {
+        // Wrap-around k-mers belong to the window touching the end, and k-mers
+        // may run past the window end with `StartsInWindow`
+        if (self.circular && win_end == self.chrom_len)
+            || self.boundary == KmerBoundary::StartsInWindow
+        {
+            u64::MAX
+        } else {
+            win_end
+        }
+    }
+}
+
+/// Where `count_kmers` reads the k-mers of a chromosome from
+#[derive(Clone, Copy)]
+pub enum KmerSource<'a> {
+    /// Positional codes of the chromosome slice starting at `chunk.0`, which
+    /// must extend at least `max k - 1` bases past `chunk.1` (unless it ends
+    /// at the chromosome end). Only k-mers starting in `chunk` =
+    /// `[start, end)` are counted, so counting consecutive chunks gives the
+    /// same counts as a single pass.
+    Codes {
+        encs: &'a [Enc<'a>],
+        chunk: (u64, u64),
+    },
+    /// Rolling codes of the chromosome sequence, using memory for the
+    /// distinct k-mers only. All specs must be radix-5 or 2-bit encoded and
+    /// the windows must not be circular. `keep` tells whether to count the
+    /// k-mer `(k, position, code)`.
+    Sequence {
+        seq: &'a [u8],
+        specs: &'a [&'a KmerSpec],
+        keep: &'a (dyn Fn(u8, usize, u64) -> bool + Sync),
+    },
+}
+
+impl KmerSource<'_> {
+    fn new_tally(&self) -> KmerTally {
+        match self {
+            KmerSource::Codes { encs, .. } => KmerTally::new(encs.iter().map(|enc| enc.k)),
+            KmerSource::Sequence { specs, .. } => {
+                KmerTally::new(specs.iter().map(|spec| spec.k as u8))
+            }
+        }
+    }
+
+    /// Start positions of the k-mers that can be counted
+    fn range(&self, chrom_len: u64) -> (u64, u64) {
+        match self {
+            KmerSource::Codes { chunk, .. } => *chunk,
+            KmerSource::Sequence { .. } => (0, chrom_len),
+        }
+    }
+
+    /// Add the k-mers starting in `[first, last)` that end at or before
+    /// `limit` to `tally`
+    fn tally(&self, tally: &mut KmerTally, first: u64, last: u64, limit: u64) {
+        tally.reserve(last - first);
+        match *self {
+            KmerSource::Codes { encs, chunk } => {
+                for pos in first..last {
+                    for enc in encs {
+                        if pos + enc.k as u64 > limit {
+                            // k-mer would over-run
+                            continue;
+                        }
+                        let code = enc.codes.get((pos - chunk.0) as usize);
+                        if code == enc.none || code == enc.n {
+                            continue;
+                        }
+                        tally.add(Kmer { k: enc.k, code });
+                    }
+                }
+            }
+            KmerSource::Sequence { seq, specs, keep } => {
+                let max_k = specs.iter().map(|spec| spec.k).max().unwrap_or(1) as u64;
+                let stop = (last + max_k - 1).min(limit).min(seq.len() as u64);
+                let seq = &seq[first as usize..stop as usize];
+                for_each_kmer_code(seq, specs, |i, offset, code| {
+                    let spec = specs[i];
+                    let k = spec.k as u8;
+                    let pos = first as usize + offset;
+                    if (pos as u64) < last && code != spec.sentinel_n() && keep(k, pos, code) {
+                        tally.add(Kmer { k, code });
+                    }
+                });
+            }
+        }
+    }
+}
+
+/// Count the k-mers of every window of `layout` from `source`, adding them
+/// to `counts_by_window` (in the order of `layout.windows`).
+///
+/// Windows are counted in parallel, and windows longer than `PIECE_SIZE`
+/// are split into pieces counted in parallel. With the codes of a whole
+/// chromosome, overlapping windows (e.g. sliding windows from `--step`)
+/// are derived from the previous window (see `count_sliding_group`).
+pub fn count_kmers(
+    counts_by_window: &mut [FxHashMap<Kmer, BigCount>],
+    layout: &WindowLayout,
+    source: KmerSource,
+) {
+    match source {
+        KmerSource::Codes { encs, chunk } if chunk == (0, layout.chrom_len) => counts_by_window
+            .par_chunks_mut(SLIDING_GROUP_SIZE)
+            .zip(layout.windows.par_chunks(SLIDING_GROUP_SIZE))
+            .for_each(|(counts_by_window, windows)| {
+                count_sliding_group(counts_by_window, windows, layout, encs)
+            }),
+        _ => counts_by_window
+            .par_iter_mut()
+            .zip(layout.windows)
+            .for_each_init(
+                || source.new_tally(),
+                |tally, (counts, &window)| count_window(tally, counts, window, layout, &source),
+            ),
+    }
+}
+
+/// Add the k-mers of one window to `counts` (see `count_kmers`)
+fn count_window(
+    tally: &mut KmerTally,
+    counts: &mut FxHashMap<Kmer, BigCount>,
+    (win_start, win_end, _): (u64, u64, u64),
+    layout: &WindowLayout,
+    source: &KmerSource,
+) {
+    let win_end = win_end.min(layout.chrom_len);
+    let (range_start, range_end) = source.range(layout.chrom_len);
+    let first = win_start.max(range_start);
+    let last = win_end.min(range_end);
+    if first >= last {
+        return;
+    }
+    let limit = layout.kmer_limit(win_end);
+
+    if last - first <= PIECE_SIZE {
+        source.tally(tally, first, last, limit);
+        tally.drain_into(counts);
+        return;
+    }
+    let piece_counts = (first..last)
+        .step_by(PIECE_SIZE as usize)
+        .collect::<Vec<_>>()
+        .into_par_iter()
+        .fold(
+            || source.new_tally(),
+            |mut tally, start| {
+                source.tally(&mut tally, start, (start + PIECE_SIZE).min(last), limit);
+                tally
+            },
+        )
+        .map(KmerTally::into_counts)
+        .reduce(FxHashMap::default, merge_counts);
+    for (kmer, n) in piece_counts {
+        *counts.entry(kmer).or_insert(0) += n;
+    }
+}
+
+/// Sum two count maps, adding the smaller to the larger
+fn merge_counts(
+    a: FxHashMap<Kmer, BigCount>,
+    b: FxHashMap<Kmer, BigCount>,
+) -> FxHashMap<Kmer, BigCount> {
+    let (mut large, small) = if a.len() >= b.len() { (a, b) } else { (b, a) };
+    for (kmer, n) in small {
+        *large.entry(kmer).or_insert(0) += n;
+    }
+    large
+}
+
+/// Largest k counted in a flat array by `KmerTally`
+pub const DENSE_MAX_K: u8 = 8;
+
+/// Counts of a flat array and the codes counted since the last drain
+struct DenseCounts {
+    counts: Vec<BigCount>,
+    counted: Vec<u64>,
+}
+
+/// K-mer counter of one window at a time: a flat array indexed by code for
+/// k up to `DENSE_MAX_K` (a code space of at most `5^k`) and a hash map for
+/// larger k.
+///
+/// `drain_into` moves the counts to a window's map and only visits the
+/// codes counted since the last drain, so one tally is reused for all
+/// windows and every k-mer occurrence costs an array increment instead of
+/// a hash lookup. The hash map keeps its capacity between windows.
+pub struct KmerTally {
+    /// Flat counters by k (`None` for larger k)
+    dense: Vec<Option<DenseCounts>>,
+    sparse: FxHashMap<Kmer, BigCount>,
+    /// The k-mer sizes counted in `sparse`
+    sparse_ks: SmallVec<[u8; 8]>,
+}
+
+impl KmerTally {
+    /// Tally for the k-mer sizes `ks`
+    pub fn new(ks: impl IntoIterator<Item = u8>) -> Self {
+        let mut dense: Vec<Option<DenseCounts>> = (0..=DENSE_MAX_K).map(|_| None).collect();
+        let mut sparse_ks = SmallVec::new();
+        for k in ks {
+            if k > DENSE_MAX_K {
+                sparse_ks.push(k);
+                continue;
+            }
+            dense[k as usize] = Some(DenseCounts {
+                counts: vec![0; 5usize.pow(k as u32)],
+                counted: Vec::new(),
+            });
+        }
+        KmerTally {
+            dense,
+            sparse: FxHashMap::default(),
+            sparse_ks,
+        }
+    }
+
+    /// Reserve room for the expected distinct k-mers of a window of
+    /// `window_len` bases, so the hash map isn't rehashed while counting
+    pub fn reserve(&mut self, window_len: u64) {
+        let expected: usize = self
+            .sparse_ks
+            .iter()
+            .map(|&k| expected_distinct_kmers(window_len, k))
+            .sum();
+        self.sparse.reserve(expected);
+    }
+
+    /// Number of distinct k-mers counted since the last drain
+    fn n_counted(&self) -> usize {
+        let dense: usize = self.dense.iter().flatten().map(|d| d.counted.len()).sum();
+        dense + self.sparse.len()
+    }
+
+    #[inline]
+    pub fn add(&mut self, kmer: Kmer) {
+        match self.dense.get_mut(kmer.k as usize) {
+            Some(Some(dense)) => {
+                let count = &mut dense.counts[kmer.code as usize];
+                if *count == 0 {
+                    dense.counted.push(kmer.code);
+                }
+                *count += 1;
+            }
+            _ => *self.sparse.entry(kmer).or_insert(0) += 1,
+        }
+    }
+
+    /// Add the counts to `counts` and reset the tally
+    pub fn drain_into(&mut self, counts: &mut FxHashMap<Kmer, BigCount>) {
+        // Grow a window's map once (chunks add to maps with counts)
+        if counts.is_empty() {
+            counts.reserve(self.n_counted());
+        }
+        for (k, dense) in self.dense.iter_mut().enumerate() {
+            let Some(dense) = dense else { continue };
+            for code in dense.counted.drain(..) {
+                let n = std::mem::take(&mut dense.counts[code as usize]);
+                let kmer = Kmer { k: k as u8, code };
+                *counts.entry(kmer).or_insert(0) += n;
+            }
+        }
+        for (kmer, n) in self.sparse.drain() {
+            *counts.entry(kmer).or_insert(0) += n;
+        }
+    }
+
+    /// The counts as a new map
+    pub fn into_counts(mut self) -> FxHashMap<Kmer, BigCount> {
+        let mut counts = FxHashMap::default();
+        self.drain_into(&mut counts);
+        counts
+    }
+}
+
+/// Add the k-mers starting at each offset from `anchors` to `profile`.
 ///
-/// * `encs`       – slice of Enc {k, codes, none, n}
-/// * `windows`    – (start, end, _original_idx) for every window
-/// * `chrom_len`  – chromosome length (used to cap end)
+/// * `anchors` – anchor positions (e.g. TSSs) on one chromosome
+/// * `flank`   – offsets span `flank` bases on each side of the anchors
 ///
-/// Returns `Vec<FxHashMap<Kmer, BigCount>>` in the same order as `windows`.
-pub fn count_kmers_by_window(
-    counts_by_window: &mut Vec<FxHashMap<Kmer, BigCount>>,
+/// `profile[i]` counts the k-mers starting `i - flank` bases from an
+/// anchor, summed over anchors. Offsets outside the chromosome are skipped.
+pub fn count_kmers_by_offset(
+    profile: &mut [FxHashMap<Kmer, BigCount>],
     encs: &SmallVec<[Enc; 8]>,
-    windows: &[(u64, u64, u64)],
-    chrom_len: u64,
+    anchors: &[u64],
+    flank: u64,
 ) {
-    for (win_idx, &(win_start, mut win_end, _)) in windows.iter().enumerate() {
-        let counts = &mut counts_by_window[win_idx.clone()];
-        win_end = win_end.min(chrom_len as u64);
-
-        for ref_pos in win_start..win_end {
-            let remaining = win_end - ref_pos; // bp left in the window
+    for &anchor in anchors {
+        let first = anchor.saturating_sub(flank);
+        for (pos, counts) in
+            (first..=anchor + flank).zip(&mut profile[(first + flank - anchor) as usize..])
+        {
             for enc in encs {
-                let k = enc.k;
-                if remaining < enc.k as u64 {
-                    // k-mer would over-run
+                if pos as usize >= enc.codes.len() {
                     continue;
                 }
-                let code = enc.codes.get(ref_pos as usize);
-
+                let code = enc.codes.get(pos as usize);
                 if code == enc.none || code == enc.n {
                     continue;
                 }
+                *counts.entry(Kmer { k: enc.k, code }).or_insert(0) += 1;
+            }
+        }
+    }
+}
 
-                *counts.entry(Kmer { k, code }).or_insert(0) += 1;
+/// Count the k-mers of the concatenated `blocks` of `seq` (e.g. the exons
+/// of a BED12 transcript), including k-mers spanning block junctions.
+pub fn count_kmers_in_blocks(
+    seq: &[u8],
+    blocks: &[std::ops::Range<usize>],
+    kmer_specs: &BTreeMap<u8, KmerSpec>,
+) -> FxHashMap<Kmer, BigCount> {
+    let spliced: Vec<u8> = blocks
+        .iter()
+        .flat_map(|block| seq[block.clone()].iter().copied())
+        .collect();
+    let codes_by_k = build_codes_per_k(&spliced, kmer_specs);
+    let encs: SmallVec<[Enc; 8]> = kmer_specs
+        .iter()
+        .map(|(&k, spec)| Enc {
+            k,
+            codes: &codes_by_k[&k],
+            none: spec.sentinel_none(),
+            n: spec.sentinel_n(),
+        })
+        .collect();
+    let len = spliced.len() as u64;
+    let mut counts = vec![FxHashMap::default()];
+    let source = KmerSource::Codes {
+        encs: &encs,
+        chunk: (0, len),
+    };
+    count_kmers(&mut counts, &WindowLayout::new(&[(0, len, 0)], len), source);
+    counts.pop().unwrap()
+}
+
+/// Count one group of `windows` of `layout` from the codes of the whole
+/// chromosome (see `count_kmers`).
+///
+/// When a window starts and ends at or after the previous one, only k-mers
+/// leaving and entering the window are counted, so the cost per window
+/// depends on the step rather than the window size. Other windows (and
+/// windows with wrap-around k-mers) are counted directly. One running map
+/// is updated in place; windows store a copy of it.
+fn count_sliding_group(
+    counts_by_window: &mut [FxHashMap<Kmer, BigCount>],
+    windows: &[(u64, u64, u64)],
+    layout: &WindowLayout,
+    encs: &[Enc],
+) {
+    let WindowLayout {
+        chrom_len,
+        circular,
+        boundary,
+        ..
+    } = *layout;
+    let source = KmerSource::Codes {
+        encs,
+        chunk: (0, chrom_len),
+    };
+    // Positions `[lo, hi)` of the k-mers of `[start, end)`
+    let starts = |start: u64, end: u64, k: u8| match boundary {
+        KmerBoundary::WithinWindow => (start, (end + 1).saturating_sub(k as u64)),
+        KmerBoundary::StartsInWindow => (start, end),
+    };
+    let wraps = |end: u64| circular && end == chrom_len;
+    // Whether window `idx` is derived from window `idx - 1`
+    let slides = |idx: usize| {
+        if idx == 0 || idx >= windows.len() {
+            return false;
+        }
+        let (s0, e0, _) = windows[idx - 1];
+        let (s1, e1, _) = windows[idx];
+        let (e0, e1) = (e0.min(chrom_len), e1.min(chrom_len));
+        s1 >= s0 && e1 >= e0 && !wraps(e0) && !wraps(e1) && (s1 - s0) + (e1 - e0) < e1 - s1
+    };
+    let mut tally = source.new_tally();
+    // Counts of the current window, updated in place while windows slide
+    let mut running: FxHashMap<Kmer, BigCount> = FxHashMap::default();
+    for win_idx in 0..windows.len() {
+        if slides(win_idx) {
+            let (s0, e0, _) = windows[win_idx - 1];
+            let (s1, e1, _) = windows[win_idx];
+            let (e0, e1) = (e0.min(chrom_len), e1.min(chrom_len));
+            for enc in encs {
+                let valid = |pos: u64| {
+                    let code = enc.codes.get(pos as usize);
+                    (code != enc.none && code != enc.n).then_some(Kmer { k: enc.k, code })
+                };
+                let (lo0, hi0) = starts(s0, e0, enc.k);
+                let (lo1, hi1) = starts(s1, e1, enc.k);
+                // Leaving: counted before, starting before the new window
+                for pos in lo0..hi0.min(lo1) {
+                    if let Some(kmer) = valid(pos) {
+                        if let Some(count) = running.get_mut(&kmer) {
+                            *count -= 1;
+                            if *count == 0 {
+                                running.remove(&kmer);
+                            }
+                        }
+                    }
+                }
+                // Entering: ending after the previous window
+                for pos in hi0.max(lo1)..hi1 {
+                    if let Some(kmer) = valid(pos) {
+                        *running.entry(kmer).or_insert(0) += 1;
+                    }
+                }
             }
+        } else {
+            running.clear();
+            count_window(&mut tally, &mut running, windows[win_idx], layout, &source);
+        }
+        // Only a window the next one slides from keeps a copy; the last
+        // window of a run takes the map
+        if slides(win_idx + 1) {
+            counts_by_window[win_idx].clone_from(&running);
+        } else {
+            counts_by_window[win_idx] = std::mem::take(&mut running);
+        }
+    }
+}
+
+/// How the positions of a window (or chromosome) were used for counting.
+///
+/// `total = blacklisted + ambiguous + counted + low_complexity + skipped`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RefKmerExtractionCounters {
+    /// Bases in the window
+    pub total: u64,
+    /// A/C/G/T bases masked by the blacklist
+    pub blacklisted: u64,
+    /// N and other non-A/C/G/T bases (including soft-masked bases excluded
+    /// as N)
+    pub ambiguous: u64,
+    /// A/C/G/T bases that kmers are counted from
+    pub counted: u64,
+    /// A/C/G/T bases masked as low-complexity (`--mask-low-complexity`)
+    pub low_complexity: u64,
+    /// A/C/G/T bases between the positions of `--position-stride`
+    pub skipped: u64,
+}
+
+impl RefKmerExtractionCounters {
+    /// Column names of `tsv_fields`
+    pub const TSV_HEADER: &'static str =
+        "total\tblacklisted\tambiguous\tcounted\tlow_complexity\tskipped";
+
+    /// Counters of a window of `total` bases with `acgt` A/C/G/T bases of
+    /// which `counted` remain after blacklist masking
+    pub fn new(total: u64, acgt: u64, counted: u64) -> Self {
+        RefKmerExtractionCounters {
+            total,
+            blacklisted: acgt - counted,
+            ambiguous: total - acgt,
+            counted,
+            low_complexity: 0,
+            skipped: 0,
         }
     }
+
+    /// Keep only the `strided` counted bases on the positions of
+    /// `--position-stride` as counted and the rest as skipped
+    pub fn with_position_stride(mut self, strided: u64) -> Self {
+        self.skipped = self.counted - strided;
+        self.counted = strided;
+        self
+    }
+
+    /// Count the bases masked after blacklist masking as low-complexity
+    /// instead of blacklisted, given the `unmasked` A/C/G/T bases left by
+    /// the blacklist
+    pub fn with_low_complexity(mut self, unmasked: u64) -> Self {
+        self.low_complexity = unmasked - self.counted;
+        self.blacklisted -= self.low_complexity;
+        self
+    }
+
+    /// Tab-separated values in the order of `TSV_HEADER`
+    pub fn tsv_fields(&self) -> String {
+        format!(
+            "{}\t{}\t{}\t{}\t{}\t{}",
+            self.total,
+            self.blacklisted,
+            self.ambiguous,
+            self.counted,
+            self.low_complexity,
+            self.skipped
+        )
+    }
+}
+
+impl std::ops::AddAssign for RefKmerExtractionCounters {
+    fn add_assign(&mut self, other: Self) {
+        self.total += other.total;
+        self.blacklisted += other.blacklisted;
+        self.ambiguous += other.ambiguous;
+        self.counted += other.counted;
+        self.low_complexity += other.low_complexity;
+        self.skipped += other.skipped;
+    }
+}
+
+/// Number of A/C/G/T bases (any case) in `seq`
+pub fn count_acgt(seq: &[u8]) -> u64 {
+    seq.iter()
+        .filter(|b| matches!(b.to_ascii_uppercase(), b'A' | b'C' | b'G' | b'T'))
+        .count() as u64
+}
+
+/// Number of A/C/G/T bases (any case) in `seq[range]` at positions that are
+/// multiples of `stride`
+pub fn count_acgt_strided(seq: &[u8], range: std::ops::Range<usize>, stride: usize) -> u64 {
+    let first = range.start.next_multiple_of(stride);
+    (first..range.end)
+        .step_by(stride)
+        .filter(|&pos| matches!(seq[pos].to_ascii_uppercase(), b'A' | b'C' | b'G' | b'T'))
+        .count() as u64
 }
 
 /// Container for storing k, codes, and sentinels
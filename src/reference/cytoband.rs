@@ -0,0 +1,140 @@
+use crate::reference::bed::{BedColumns, WindowMap};
+use crate::reference::chrom_alias::ChromAliases;
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use std::{collections::HashSet, path::Path};
+
+/// One band of a UCSC `cytoBand.txt` file (0-based, half-open)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cytoband {
+    pub chrom: String,
+    pub start: u64,
+    pub end: u64,
+    /// Band name, e.g. `p36.33`
+    pub name: String,
+    /// Giemsa stain, e.g. `gneg` or `acen` (centromere)
+    pub stain: String,
+}
+
+/// Resolution of cytoband windows
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum CytobandLevel {
+    /// One window per band
+    #[default]
+    Band,
+    /// One window per chromosome arm (p and q)
+    Arm,
+}
+
+/// Load the bands of a UCSC `cytoBand.txt` file
+/// (`chrom start end name gieStain`), in file order.
+///
+/// * Only bands on `chromosomes` are kept.
+/// * Chromosome names are translated to reference names with `aliases`.
+pub fn load_cytobands(
+    path: &Path,
+    chromosomes: &[String],
+    aliases: Option<&ChromAliases>,
+) -> Result<Vec<Cytoband>> {
+    let content =
+        std::fs::read_to_string(path).context(format!("Error reading cytobands {:?}", path))?;
+    let wanted: HashSet<&str> = chromosomes.iter().map(String::as_str).collect();
+    let mut bands = Vec::new();
+    for (line_idx, line) in content.lines().enumerate() {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let cols: Vec<&str> = line.split('\t').collect();
+        if cols.len() < 3 {
+            continue; // Malformed line
+        }
+        let chrom = aliases.map_or(cols[0], |a| a.resolve(cols[0]));
+        if !wanted.contains(chrom) {
+            continue;
+        }
+        let start: u64 = cols[1]
+            .parse()
+            .context(format!("Parsing cytoband start on line {}", line_idx + 1))?;
+        let end: u64 = cols[2]
+            .parse()
+            .context(format!("Parsing cytoband end on line {}", line_idx + 1))?;
+        let column = |i: usize| match cols.get(i).map(|c| c.trim()) {
+            Some(c) if !c.is_empty() => c.to_string(),
+            _ => ".".to_string(),
+        };
+        bands.push(Cytoband {
+            chrom: chrom.to_string(),
+            start,
+            end,
+            name: column(3),
+            stain: column(4),
+        });
+    }
+    Ok(bands)
+}
+
+/// Build windows from cytobands, labelled with the band (or arm) name.
+///
+/// * `CytobandLevel::Band` gives one window per band with the BED columns
+///   `name` and `gieStain`.
+/// * `CytobandLevel::Arm` spans all bands of each arm (bands named `p...`
+///   or `q...`) with the column `name` (`p` or `q`). Bands of other names
+///   are skipped.
+/// * Windows are indexed in order of (first) appearance in `bands`.
+pub fn cytoband_windows(
+    bands: &[Cytoband],
+    chromosomes: &[String],
+    level: CytobandLevel,
+) -> (WindowMap, BedColumns) {
+    let mut mapping: WindowMap = chromosomes
+        .iter()
+        .map(|chr| (chr.to_string(), Vec::new()))
+        .collect();
+    let mut values: Vec<Vec<String>> = Vec::new();
+    match level {
+        CytobandLevel::Band => {
+            for band in bands {
+                mapping.entry(band.chrom.clone()).or_default().push((
+                    band.start,
+                    band.end,
+                    values.len() as u64,
+                ));
+                values.push(vec![band.name.clone(), band.stain.clone()]);
+            }
+        }
+        CytobandLevel::Arm => {
+            // (chrom, arm, start, end) in order of first appearance
+            let mut arms: Vec<(String, char, u64, u64)> = Vec::new();
+            for band in bands {
+                let Some(arm) = band.name.chars().next().filter(|c| matches!(c, 'p' | 'q')) else {
+                    continue;
+                };
+                match arms
+                    .iter_mut()
+                    .find(|(chrom, a, _, _)| *chrom == band.chrom && *a == arm)
+                {
+                    Some((_, _, start, end)) => {
+                        *start = (*start).min(band.start);
+                        *end = (*end).max(band.end);
+                    }
+                    None => arms.push((band.chrom.clone(), arm, band.start, band.end)),
+                }
+            }
+            for (chrom, arm, start, end) in arms {
+                mapping
+                    .entry(chrom)
+                    .or_default()
+                    .push((start, end, values.len() as u64));
+                values.push(vec![arm.to_string()]);
+            }
+        }
+    }
+    for v in mapping.values_mut() {
+        v.sort_unstable_by_key(|&(s, e, _)| (s, e));
+    }
+    let names = match level {
+        CytobandLevel::Band => vec!["name".to_string(), "gieStain".to_string()],
+        CytobandLevel::Arm => vec!["name".to_string()],
+    };
+    (mapping, BedColumns { names, values })
+}
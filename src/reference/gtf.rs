@@ -0,0 +1,236 @@
+use crate::reference::bed::{BedColumns, WindowMap};
+use crate::reference::chrom_alias::ChromAliases;
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use std::{
+    collections::{HashMap, HashSet},
+    path::Path,
+};
+
+/// One feature line from a GTF/GFF3 annotation.
+///
+/// Coordinates are converted to 0-based, half-open `[start, end)`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GtfRecord {
+    pub chrom: String,
+    pub feature: String,
+    pub start: u64,
+    pub end: u64,
+    pub strand: char,
+    pub attributes: String,
+}
+
+impl GtfRecord {
+    /// Look up an attribute value by key.
+    pub fn attribute(&self, key: &str) -> Option<&str> {
+        parse_attribute(&self.attributes, key)
+    }
+}
+
+/// Extract an attribute value from a GTF (`key "value";`) or
+/// GFF3 (`key=value;`) attribute column.
+pub fn parse_attribute<'a>(attributes: &'a str, key: &str) -> Option<&'a str> {
+    for field in attributes.split(';').map(str::trim) {
+        if field.is_empty() {
+            continue;
+        }
+        // GFF3: key=value
+        if let Some((k, v)) = field.split_once('=') {
+            if k.trim() == key {
+                return Some(v.trim());
+            }
+            continue;
+        }
+        // GTF: key "value"
+        if let Some((k, v)) = field.split_once(char::is_whitespace) {
+            if k == key {
+                return Some(v.trim().trim_matches('"'));
+            }
+        }
+    }
+    None
+}
+
+/// Load GTF/GFF3 records of a given feature type (e.g. `gene`).
+///
+/// * Lines starting with `#` or blank lines are skipped.
+/// * Only records on `chromosomes` are kept.
+/// * Chromosome names are translated to reference names with `aliases`.
+/// * Records are returned in file order.
+pub fn load_gtf(
+    path: &Path,
+    feature: &str,
+    chromosomes: &[String],
+    aliases: Option<&ChromAliases>,
+) -> Result<Vec<GtfRecord>> {
+    let content = std::fs::read_to_string(path).context(format!("Error reading GTF {:?}", path))?;
+    let wanted: HashSet<&str> = chromosomes.iter().map(String::as_str).collect();
+    let mut records = Vec::new();
+    for (line_idx, line) in content.lines().enumerate() {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let cols: Vec<&str> = line.split('\t').collect();
+        if cols.len() < 9 {
+            continue; // Malformed line
+        }
+        let chrom = aliases.map_or(cols[0], |a| a.resolve(cols[0]));
+        if cols[2] != feature || !wanted.contains(chrom) {
+            continue;
+        }
+        let start: u64 = cols[3]
+            .parse()
+            .context(format!("Parsing GTF start on line {}", line_idx + 1))?;
+        let end: u64 = cols[4]
+            .parse()
+            .context(format!("Parsing GTF end on line {}", line_idx + 1))?;
+        records.push(GtfRecord {
+            chrom: chrom.to_string(),
+            feature: cols[2].to_string(),
+            // 1-based inclusive -> 0-based half-open
+            start: start.saturating_sub(1),
+            end,
+            strand: cols[6].chars().next().unwrap_or('.'),
+            attributes: cols[8].to_string(),
+        });
+    }
+    Ok(records)
+}
+
+/// Sorted feature intervals of one chromosome for overlap queries.
+#[derive(Debug, Default)]
+pub struct FeatureIntervals {
+    /// `(start, end, name)` sorted by start
+    intervals: Vec<(u64, u64, String)>,
+    /// Length of the longest interval; bounds the backwards search
+    max_len: u64,
+}
+
+impl FeatureIntervals {
+    pub fn new(mut intervals: Vec<(u64, u64, String)>) -> Self {
+        intervals.sort_unstable();
+        let max_len = intervals
+            .iter()
+            .map(|(s, e, _)| e.saturating_sub(*s))
+            .max()
+            .unwrap_or(0);
+        Self { intervals, max_len }
+    }
+
+    /// Unique names of all features overlapping `[start, end)`, ordered by
+    /// feature start.
+    pub fn overlapping(&self, start: u64, end: u64) -> Vec<&str> {
+        let first = self
+            .intervals
+            .partition_point(|(s, _, _)| *s < start.saturating_sub(self.max_len));
+        let mut names: Vec<&str> = Vec::new();
+        for (s, e, name) in &self.intervals[first..] {
+            if *s >= end {
+                break;
+            }
+            if *e > start && !names.contains(&name.as_str()) {
+                names.push(name);
+            }
+        }
+        names
+    }
+}
+
+/// Build per-chromosome feature intervals labelled by `attribute`
+/// (e.g. `gene_name`). Records without the attribute are skipped.
+pub fn build_feature_intervals(
+    records: &[GtfRecord],
+    attribute: &str,
+) -> HashMap<String, FeatureIntervals> {
+    let mut by_chrom: HashMap<String, Vec<(u64, u64, String)>> = HashMap::new();
+    for rec in records {
+        if let Some(name) = rec.attribute(attribute) {
+            by_chrom.entry(rec.chrom.clone()).or_default().push((
+                rec.start,
+                rec.end,
+                name.to_string(),
+            ));
+        }
+    }
+    by_chrom
+        .into_iter()
+        .map(|(chr, ivs)| (chr, FeatureIntervals::new(ivs)))
+        .collect()
+}
+
+/// Annotation features to build windows from
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum GtfFeature {
+    Gene,
+    Exon,
+    #[value(name = "CDS")]
+    Cds,
+    /// Flanks around the transcription start site of each gene
+    Promoter,
+}
+
+impl GtfFeature {
+    /// Feature type (3rd GTF column) the windows are built from
+    pub fn gtf_type(&self) -> &'static str {
+        match self {
+            GtfFeature::Gene | GtfFeature::Promoter => "gene",
+            GtfFeature::Exon => "exon",
+            GtfFeature::Cds => "CDS",
+        }
+    }
+}
+
+/// Build windows from annotation `records` (in file order), labelled with
+/// their `id_attribute` (falling back to the GFF3 `ID`, else `.`).
+///
+/// * With `promoter_flank`, each window spans `promoter_flank` bases on
+///   both sides of the record's strand-aware start (the TSS of a gene).
+/// * Identical windows (e.g. an exon shared by transcripts) are kept once.
+/// * The returned columns are BED `name`, `score` (`.`) and `strand`.
+pub fn feature_windows(
+    records: &[GtfRecord],
+    chromosomes: &[String],
+    id_attribute: &str,
+    promoter_flank: Option<u64>,
+) -> (WindowMap, BedColumns) {
+    let mut mapping: WindowMap = chromosomes
+        .iter()
+        .map(|chr| (chr.to_string(), Vec::new()))
+        .collect();
+    let mut values = Vec::new();
+    let mut seen = HashSet::new();
+    for rec in records {
+        let (start, end) = match promoter_flank {
+            Some(flank) => {
+                let tss = if rec.strand == '-' {
+                    rec.end.saturating_sub(1)
+                } else {
+                    rec.start
+                };
+                (tss.saturating_sub(flank), tss + flank)
+            }
+            None => (rec.start, rec.end),
+        };
+        let id = rec
+            .attribute(id_attribute)
+            .or_else(|| rec.attribute("ID"))
+            .unwrap_or(".");
+        if !seen.insert((rec.chrom.as_str(), start, end, rec.strand, id)) {
+            continue;
+        }
+        mapping
+            .entry(rec.chrom.clone())
+            .or_default()
+            .push((start, end, values.len() as u64));
+        values.push(vec![
+            id.to_string(),
+            ".".to_string(),
+            rec.strand.to_string(),
+        ]);
+    }
+    for v in mapping.values_mut() {
+        v.sort_unstable_by_key(|&(s, e, _)| (s, e));
+    }
+    let names = ["name", "score", "strand"].map(String::from).to_vec();
+    (mapping, BedColumns { names, values })
+}
@@ -1,9 +1,11 @@
 use crate::cli::BigCount;
 use anyhow::{bail, Context, Result};
-use fxhash::FxHashMap;
-use std::collections::HashMap;
+use fxhash::{FxHashMap, FxHashSet};
+use std::collections::BTreeMap;
 use std::collections::HashSet;
 use std::hash::Hash;
+use std::ops::RangeInclusive;
+use std::sync::{Arc, RwLock};
 
 /// * `k`    – length
 /// * `code` – packed reference code in the narrowest type, promoted to u64
@@ -19,7 +21,7 @@ pub struct Kmer {
 impl Kmer {
     /// Human-readable string representation.
     /// Requires a `KmerSpec` table to know how to decode arbitrary k.
-    pub fn to_string(&self, specs: &HashMap<u8, KmerSpec>) -> String {
+    pub fn to_string(&self, specs: &BTreeMap<u8, KmerSpec>) -> String {
         specs[&self.k].decode_kmer(self.code)
     }
 }
@@ -28,7 +30,7 @@ pub const BASES: [char; 5] = ['A', 'C', 'G', 'T', 'N'];
 
 /// The narrowest integer width that can accommodate the code space for a k‑mer
 /// length, *plus* the two reserved sentinel values.
-#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, PartialOrd, Ord)]
 pub enum Width {
     U8,
     U16,
@@ -36,6 +38,99 @@ pub enum Width {
     U64,
 }
 
+impl Width {
+    /// Bytes per stored code
+    pub fn bytes(self) -> u64 {
+        match self {
+            Width::U8 => 1,
+            Width::U16 => 2,
+            Width::U32 => 4,
+            Width::U64 => 8,
+        }
+    }
+}
+
+/// How k‑mers are packed into codes.
+///
+/// Both encodings order bases A < C < G < T with the first base most
+/// significant, so codes sort like their k‑mers.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Encoding {
+    /// Base 5 with N as a fifth digit (k ≤ 27 in u64)
+    Radix5,
+    /// 2 bits per base (k ≤ 31 in u64). K‑mers overlapping a run of N (or
+    /// other non-A/C/G/T bases) get the N sentinel.
+    TwoBit,
+    /// Number of the observed canonical k‑mer in a `KmerDictionary` (k ≤ 63)
+    /// and its orientation in the low `DICTIONARY_TAG_BITS`. Codes do not
+    /// sort like their k‑mers.
+    Dictionary,
+}
+
+/// Bits of a dictionary code holding the k‑mer's orientation; the number of
+/// its canonical form is stored above them
+const DICTIONARY_TAG_BITS: u32 = 2;
+/// The k‑mer is its canonical form
+const TAG_CANONICAL: u64 = 0;
+/// The reverse complement of the k‑mer is its canonical form
+const TAG_REVCOMP: u64 = 1;
+/// The k‑mer is its own reverse complement
+const TAG_PALINDROME: u64 = 2;
+const TAG_MASK: u64 = (1 << DICTIONARY_TAG_BITS) - 1;
+
+/// Observed canonical k‑mers of an `Encoding::Dictionary` spec, packed 2
+/// bits per base into a u128 and numbered in order of first appearance.
+#[derive(Debug, Default)]
+pub struct KmerDictionary {
+    numbers: FxHashMap<u128, u64>,
+    kmers: Vec<u128>,
+}
+
+impl KmerDictionary {
+    /// Number of a packed k‑mer, adding it when first seen.
+    pub fn intern(&mut self, packed: u128) -> u64 {
+        let next = self.kmers.len() as u64;
+        *self.numbers.entry(packed).or_insert_with(|| {
+            self.kmers.push(packed);
+            next
+        })
+    }
+
+    /// Packed k‑mer of a number.
+    pub fn kmer(&self, number: u64) -> u128 {
+        self.kmers[number as usize]
+    }
+
+    /// Number of observed k‑mers.
+    pub fn len(&self) -> usize {
+        self.kmers.len()
+    }
+
+    /// Whether no k‑mers were observed.
+    pub fn is_empty(&self) -> bool {
+        self.kmers.is_empty()
+    }
+}
+
+/// Reverse complement of a k‑mer packed 2 bits per base.
+///
+/// Flips both bits of every base (A<->T, C<->G), reverses the bit order
+/// and swaps the bits of each base back.
+#[inline]
+fn revcomp_packed(packed: u128, k: usize) -> u128 {
+    const LOW_BITS: u128 = 0x5555_5555_5555_5555_5555_5555_5555_5555;
+    let rev = (!packed).reverse_bits();
+    (((rev >> 1) & LOW_BITS) | ((rev & LOW_BITS) << 1)) >> (128 - 2 * k)
+}
+
+/// Decode a k‑mer packed 2 bits per base.
+fn decode_packed(packed: u128, k: usize) -> String {
+    (0..k)
+        .rev()
+        .map(|pos| BASES[((packed >> (2 * pos)) & 3) as usize])
+        .collect()
+}
+
 /// Per-position code vector stored in the tightest possible type.
 #[derive(Debug)]
 pub enum KmerCodes {
@@ -46,6 +141,16 @@ pub enum KmerCodes {
 }
 
 impl KmerCodes {
+    /// `len` positions of `code` stored in `width`
+    pub fn filled(width: Width, len: usize, code: u64) -> Self {
+        match width {
+            Width::U8 => KmerCodes::U8(vec![code as u8; len]),
+            Width::U16 => KmerCodes::U16(vec![code as u16; len]),
+            Width::U32 => KmerCodes::U32(vec![code as u32; len]),
+            Width::U64 => KmerCodes::U64(vec![code; len]),
+        }
+    }
+
     /// Return the code at position `idx` as `u64`.
     #[inline]
     pub fn get(&self, idx: usize) -> u64 {
@@ -56,6 +161,52 @@ impl KmerCodes {
             KmerCodes::U64(v) => v[idx],
         }
     }
+
+    /// Number of positions.
+    pub fn len(&self) -> usize {
+        match self {
+            KmerCodes::U8(v) => v.len(),
+            KmerCodes::U16(v) => v.len(),
+            KmerCodes::U32(v) => v.len(),
+            KmerCodes::U64(v) => v.len(),
+        }
+    }
+
+    /// Whether there are no positions.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Storage width of the codes.
+    pub fn width(&self) -> Width {
+        match self {
+            KmerCodes::U8(_) => Width::U8,
+            KmerCodes::U16(_) => Width::U16,
+            KmerCodes::U32(_) => Width::U32,
+            KmerCodes::U64(_) => Width::U64,
+        }
+    }
+
+    /// Shorten the code vector to `len` positions.
+    pub fn truncate(&mut self, len: usize) {
+        match self {
+            KmerCodes::U8(v) => v.truncate(len),
+            KmerCodes::U16(v) => v.truncate(len),
+            KmerCodes::U32(v) => v.truncate(len),
+            KmerCodes::U64(v) => v.truncate(len),
+        }
+    }
+
+    /// Set the code at position `idx`; `code` must fit the storage width.
+    #[inline]
+    pub fn set(&mut self, idx: usize, code: u64) {
+        match self {
+            KmerCodes::U8(v) => v[idx] = code as u8,
+            KmerCodes::U16(v) => v[idx] = code as u16,
+            KmerCodes::U32(v) => v[idx] = code as u32,
+            KmerCodes::U64(v) => v[idx] = code,
+        }
+    }
 }
 
 /// One fully‑specified encoder/decoder for a particular k.
@@ -65,22 +216,175 @@ pub struct KmerSpec {
     pub k: usize,
     /// Integer width used for storage
     width: Width,
+    /// Packing of bases into codes
+    encoding: Encoding,
     /// Code used when no full k‑mer is available (chromosome ends)
     sentinel_none: u64,
     /// Code used when the window contains any ‘N’ base
     sentinel_n: u64,
+    /// Observed k‑mers with `Encoding::Dictionary`, shared between threads.
+    /// Every `build_codes` call numbers its k‑mers in a dictionary of its own
+    /// and merges it into this one once at the end.
+    dictionary: Option<Arc<RwLock<KmerDictionary>>>,
 }
 
 impl KmerSpec {
     /// Build per‑position codes for the provided reference sequence.
     pub fn build_codes(&self, seq: &[u8]) -> Vec<u64> {
-        build_codes(seq, self.k, self.sentinel_none, self.sentinel_n)
+        match self.encoding {
+            Encoding::Radix5 => build_codes(seq, self.k, self.sentinel_none, self.sentinel_n),
+            Encoding::TwoBit => build_codes_2bit(seq, self.k, self.sentinel_none, self.sentinel_n),
+            Encoding::Dictionary => build_codes_dictionary(
+                seq,
+                self.k,
+                self.sentinel_none,
+                self.sentinel_n,
+                self.dictionary(),
+            ),
+        }
     }
 
     /// Decode a single code back to its k‑mer string, returning all‑‘N’ if the
     /// code is one of the sentinels.
     pub fn decode_kmer(&self, code: u64) -> String {
-        decode_kmer(code, self.k, self.sentinel_none, self.sentinel_n)
+        if code == self.sentinel_none || code == self.sentinel_n {
+            return "N".repeat(self.k);
+        }
+        match self.encoding {
+            Encoding::Radix5 => decode_kmer(code, self.k, self.sentinel_none, self.sentinel_n),
+            Encoding::TwoBit => decode_packed(code as u128, self.k),
+            Encoding::Dictionary => {
+                let canonical = self
+                    .dictionary()
+                    .read()
+                    .unwrap()
+                    .kmer(code >> DICTIONARY_TAG_BITS);
+                match code & TAG_REVCOMP {
+                    0 => decode_packed(canonical, self.k),
+                    _ => decode_packed(revcomp_packed(canonical, self.k), self.k),
+                }
+            }
+        }
+    }
+
+    /// Code of the k‑mer `motif` (case-insensitive). K‑mers with N or other
+    /// non-A/C/G/T bases get the N sentinel.
+    pub fn encode_kmer(&self, motif: &str) -> Result<u64> {
+        if motif.len() != self.k {
+            bail!(
+                "Motif {} has length {}, not k={}",
+                motif,
+                motif.len(),
+                self.k
+            );
+        }
+        Ok(self.build_codes(motif.as_bytes())[0])
+    }
+
+    /// What a sentinel `code` stands for, or `None` for k‑mer codes
+    pub fn sentinel_meaning(&self, code: u64) -> Option<&'static str> {
+        if code == self.sentinel_none {
+            Some("no full k-mer (sequence end)")
+        } else if code == self.sentinel_n {
+            Some("k-mer with N")
+        } else {
+            None
+        }
+    }
+
+    /// Code of the reverse complement of the k‑mer with `code`. Sentinels
+    /// are returned unchanged.
+    pub fn revcomp_code(&self, code: u64) -> u64 {
+        if code == self.sentinel_none || code == self.sentinel_n {
+            return code;
+        }
+        match self.encoding {
+            Encoding::TwoBit => return revcomp_packed(code as u128, self.k) as u64,
+            Encoding::Dictionary => {
+                return match code & TAG_PALINDROME {
+                    0 => code ^ TAG_REVCOMP,
+                    _ => code,
+                };
+            }
+            Encoding::Radix5 => {}
+        }
+        let mut tmp = code;
+        let mut out = 0u64;
+        for _ in 0..self.k {
+            // Last base first, complemented (A<->T, C<->G)
+            out = out * 5 + (3 - tmp % 5);
+            tmp /= 5;
+        }
+        out
+    }
+
+    /// Code of the canonical form of the k‑mer with `code`: the
+    /// lexicographically smaller of the k‑mer and its reverse complement.
+    /// Sentinels are returned unchanged.
+    #[inline]
+    pub fn canonical_code(&self, code: u64) -> u64 {
+        if self.encoding == Encoding::Dictionary {
+            if code == self.sentinel_none || code == self.sentinel_n {
+                return code;
+            }
+            // The orientation is part of the code
+            return code & !TAG_REVCOMP;
+        }
+        // Codes sort like their k-mers
+        code.min(self.revcomp_code(code))
+    }
+
+    /// Public accessor for the storage width.
+    pub fn width(&self) -> Width {
+        self.width
+    }
+
+    /// Public accessor for the encoding.
+    pub fn encoding(&self) -> Encoding {
+        self.encoding
+    }
+
+    /// Number of codes of real k‑mers (`0..n_codes()`), including k‑mers
+    /// with N with `Encoding::Radix5`. With `Encoding::Dictionary`, the
+    /// bound of the codes of the k‑mers observed so far (see `is_kmer_code`).
+    pub fn n_codes(&self) -> u64 {
+        match self.encoding {
+            Encoding::Radix5 => 5u64.pow(self.k as u32),
+            Encoding::TwoBit => 4u64.pow(self.k as u32),
+            Encoding::Dictionary => {
+                (self.dictionary().read().unwrap().len() as u64) << DICTIONARY_TAG_BITS
+            }
+        }
+    }
+
+    /// Whether `code` is the code of a real k‑mer (observed so far, with
+    /// `Encoding::Dictionary`)
+    pub fn is_kmer_code(&self, code: u64) -> bool {
+        match self.encoding {
+            Encoding::Dictionary => {
+                let tag = code & TAG_MASK;
+                code < self.n_codes()
+                    && tag != TAG_MASK
+                    && (tag == TAG_PALINDROME) == self.is_palindrome(code)
+            }
+            _ => code < self.n_codes(),
+        }
+    }
+
+    /// Whether the canonical k‑mer of a dictionary code is its own reverse complement
+    fn is_palindrome(&self, code: u64) -> bool {
+        let canonical = self
+            .dictionary()
+            .read()
+            .unwrap()
+            .kmer(code >> DICTIONARY_TAG_BITS);
+        canonical == revcomp_packed(canonical, self.k)
+    }
+
+    fn dictionary(&self) -> &RwLock<KmerDictionary> {
+        self.dictionary
+            .as_ref()
+            .expect("dictionary-encoded spec has a dictionary")
     }
 
     /// Public accessor for the “no full k‑mer” sentinel.
@@ -94,33 +398,83 @@ impl KmerSpec {
     }
 }
 
+/// Highest k of the 2-bit encoding in u64
+pub const MAX_K_2BIT: u8 = 31;
+
+/// Highest k of the dictionary encoding (2 bits per base in u128)
+pub const MAX_K: u8 = 63;
+
+/// Parse a `--kmer-sizes` value: a size (`5`) or an inclusive range of
+/// sizes (`1-6`), from 1 to `max`
+pub fn parse_kmer_size_range(value: &str, max: u8) -> Result<RangeInclusive<u8>> {
+    let parse = |k: &str| -> Result<u8> {
+        let k: u8 = k
+            .trim()
+            .parse()
+            .context(format!("Invalid k-mer size '{}'", k.trim()))?;
+        if !(1..=max).contains(&k) {
+            bail!("k-mer size {k} is not in 1-{max}");
+        }
+        Ok(k)
+    };
+    let range = match value.split_once('-') {
+        Some((first, last)) => parse(first)?..=parse(last)?,
+        None => {
+            let k = parse(value)?;
+            k..=k
+        }
+    };
+    if range.is_empty() {
+        bail!("k-mer size range '{value}' is empty; write the smallest size first");
+    }
+    Ok(range)
+}
+
+/// The sizes of parsed `--kmer-sizes` values, in order
+pub fn expand_kmer_sizes(ranges: &[RangeInclusive<u8>]) -> Vec<u8> {
+    ranges.iter().cloned().flatten().collect()
+}
+
 /// Construct a `KmerSpec` for each k.
 ///
 /// * Duplicate sizes result in an error.
-pub fn build_kmer_specs(kmer_sizes: &[u8]) -> Result<HashMap<u8, KmerSpec>> {
+/// * The 2-bit encoding is chosen when it needs a narrower storage width
+///   than radix-5 (k = 7, 14 and 15) or radix-5 does not fit (k > 27).
+/// * k > 31 uses the dictionary encoding.
+pub fn build_kmer_specs(kmer_sizes: &[u8]) -> Result<BTreeMap<u8, KmerSpec>> {
     let mut seen = HashSet::new();
-    let mut specs = HashMap::new();
+    let mut specs = BTreeMap::new();
 
     for &k in kmer_sizes {
         if k < 1 {
             bail!("Illegal k-mer size {k}. Must be positive.");
         }
-        // TODO: Calculate actual limit possible!
-        if k > 27 {
-            bail!("k-mer size {k} is too large. Highest allowed k is 27");
+        if k > MAX_K {
+            bail!("k-mer size {k} is too large. Highest allowed k is {MAX_K}");
         }
         if !seen.insert(k) {
             bail!("Duplicate k-mer size {k}");
         }
-        let (width, sentinel_none, sentinel_n) =
-            choose_width(k as usize).context(format!("calculating dtype for k={:?}", k))?;
+        let (encoding, (width, sentinel_none, sentinel_n)) = if k > MAX_K_2BIT {
+            let sentinels = (Width::U64, u64::MAX, u64::MAX - 1);
+            (Encoding::Dictionary, sentinels)
+        } else {
+            let two_bit = choose_width_2bit(k as usize)
+                .context(format!("calculating dtype for k={:?}", k))?;
+            match choose_width(k as usize) {
+                Ok(radix5) if radix5.0 <= two_bit.0 => (Encoding::Radix5, radix5),
+                _ => (Encoding::TwoBit, two_bit),
+            }
+        };
         specs.insert(
             k,
             KmerSpec {
                 k: k as usize,
                 width,
+                encoding,
                 sentinel_none,
                 sentinel_n,
+                dictionary: (encoding == Encoding::Dictionary).then(Default::default),
             },
         );
     }
@@ -129,35 +483,156 @@ pub fn build_kmer_specs(kmer_sizes: &[u8]) -> Result<HashMap<u8, KmerSpec>> {
 
 /// Build one kmer code vector for every `KmerSpec` and store it in a map keyed by `k`.
 ///
-/// The vector is kept in the narrowest width dictated by `spec.width`.
-/// This preserves the RAM benefit of the width-selection logic.
-///
-/// The hash map key is always the `k` value of the corresponding spec.
+/// Radix‑5 and 2‑bit codes of all k are built in a single pass over `seq`
+/// and written straight into vectors of their storage width.
+pub fn build_codes_per_k(seq: &[u8], specs: &BTreeMap<u8, KmerSpec>) -> BTreeMap<u8, KmerCodes> {
+    let (dictionary, fused): (Vec<_>, Vec<_>) = specs
+        .iter()
+        .partition(|(_, spec)| spec.encoding == Encoding::Dictionary);
+
+    let fused_specs: Vec<&KmerSpec> = fused.iter().map(|&(_, spec)| spec).collect();
+    let mut map: BTreeMap<u8, KmerCodes> = fused
+        .iter()
+        .map(|&(&k, _)| k)
+        .zip(build_codes_fused(seq, &fused_specs))
+        .collect();
+
+    // Dictionary codes intern their k‑mers and are built one k at a time
+    for (&k, spec) in dictionary {
+        map.insert(k, KmerCodes::U64(spec.build_codes(seq)));
+    }
+
+    map
+}
+
+/// Build the radix‑5 and 2‑bit codes of `specs` in one pass over `seq`
+fn build_codes_fused(seq: &[u8], specs: &[&KmerSpec]) -> Vec<KmerCodes> {
+    let mut out: Vec<KmerCodes> = specs
+        .iter()
+        .map(|spec| KmerCodes::filled(spec.width, seq.len(), spec.sentinel_none))
+        .collect();
+    for_each_kmer_code(seq, specs, |i, start, code| out[i].set(start, code));
+    out
+}
+
+/// Call `f(i, start, code)` for every k‑mer of `seq` of each radix‑5 or
+/// 2‑bit spec `specs[i]`, in a single pass over `seq`.
 ///
-/// Example:
-/// ```rust
-/// let codes_by_k = build_codes_per_k(&seq_bytes, kmer_specs);
-/// let trinuc_codes = &codes_by_k[&3];
-/// let dinuc_codes  = &codes_by_k[&2];
-/// ```
-pub fn build_codes_per_k(seq: &[u8], specs: &HashMap<u8, KmerSpec>) -> HashMap<u8, KmerCodes> {
-    let mut map = HashMap::new();
-
-    for (k, spec) in specs {
-        // Generic builder returns Vec<u64>
-        let raw: Vec<u64> = spec.build_codes(seq);
-
-        // Down-cast into the tightest variant
-        let packed = match spec.width {
-            Width::U8 => KmerCodes::U8(raw.into_iter().map(|c| c as u8).collect()),
-            Width::U16 => KmerCodes::U16(raw.into_iter().map(|c| c as u16).collect()),
-            Width::U32 => KmerCodes::U32(raw.into_iter().map(|c| c as u32).collect()),
-            Width::U64 => KmerCodes::U64(raw),
-        };
+/// `seq` is encoded in blocks of `ENCODE_BLOCK` bases with `encode_bases`.
+/// Within a block, the rolling code of each k is updated in its own tight
+/// loop without branching on the encoding: 2‑bit codes shift and mask, and
+/// radix‑5 codes subtract the outgoing digit (read back from the encoded
+/// bases) instead of dividing. N runs are only tracked in blocks with an
+/// N. Like `build_codes` and `build_codes_2bit`, k‑mers with a
+/// non‑A/C/G/T base get `sentinel_n`. Positions where no full k‑mer starts
+/// are skipped, and the k‑mers of each block are reported one spec at a
+/// time.
+pub fn for_each_kmer_code(seq: &[u8], specs: &[&KmerSpec], mut f: impl FnMut(usize, usize, u64)) {
+    debug_assert!(specs
+        .iter()
+        .all(|spec| spec.encoding != Encoding::Dictionary));
+    let mut codes = vec![0u64; specs.len()];
+    // Encoded bases of the block, after the last `lookback` of the previous
+    // one (zeros, the leading digits of the first codes, before the first)
+    let lookback = specs.iter().map(|spec| spec.k).max().unwrap_or(1);
+    let mut encoded = vec![0u8; lookback + ENCODE_BLOCK];
+    // Position after the last N of each base of the block, and before it
+    let mut n_run_ends = vec![0usize; ENCODE_BLOCK];
+    let mut n_run_end = 0;
+    for (block_idx, block) in seq.chunks(ENCODE_BLOCK).enumerate() {
+        let block_start = block_idx * ENCODE_BLOCK;
+        let len = block.len();
+        encode_bases(block, &mut encoded[lookback..lookback + len]);
+        let bases = &encoded[lookback..lookback + len];
+        let has_n = bases.contains(&4);
+        if has_n {
+            for (offset, (&val, end)) in bases.iter().zip(&mut n_run_ends).enumerate() {
+                if val == 4 {
+                    n_run_end = block_start + offset + 1;
+                }
+                *end = n_run_end;
+            }
+        }
+        for (i, spec) in specs.iter().enumerate() {
+            let k = spec.k;
+            let mut emit = |offset: usize, code: u64| {
+                let pos = block_start + offset;
+                if pos + 1 >= k {
+                    // The k‑mer starts at `pos + 1 - k`
+                    let start = pos + 1 - k;
+                    let n_end = if has_n { n_run_ends[offset] } else { n_run_end };
+                    f(i, start, if n_end > start { spec.sentinel_n } else { code });
+                }
+            };
+            let mut code = codes[i];
+            if spec.encoding == Encoding::TwoBit {
+                let mask = (1u64 << (2 * k)) - 1;
+                for (offset, &val) in bases.iter().enumerate() {
+                    code = ((code << 2) | (val as u64 & 3)) & mask;
+                    emit(offset, code);
+                }
+            } else {
+                // Weight of the left‑most digit
+                let place = 5u64.pow((k - 1) as u32);
+                let outgoing = &encoded[lookback - k..lookback - k + len];
+                for (offset, (&val, &out)) in bases.iter().zip(outgoing).enumerate() {
+                    code = (code - out as u64 * place) * 5 + val as u64;
+                    emit(offset, code);
+                }
+            }
+            codes[i] = code;
+        }
+        // Keep the end of the block for the outgoing digits of the next
+        encoded.copy_within(len..len + lookback, 0);
+    }
+}
 
-        map.insert(*k, packed);
+/// Build code vectors for a **circular** sequence.
+///
+/// The sequence is virtually extended by `max(k) - 1` bases from its start,
+/// so k-mers wrapping around the end get a real code instead of
+/// `sentinel_none`. The returned vectors have the original sequence length.
+pub fn build_codes_per_k_circular(
+    seq: &[u8],
+    specs: &BTreeMap<u8, KmerSpec>,
+) -> BTreeMap<u8, KmerCodes> {
+    let max_k = specs.keys().copied().max().unwrap_or(1) as usize;
+    let mut extended = Vec::with_capacity(seq.len() + max_k - 1);
+    extended.extend_from_slice(seq);
+    // Repeat the start as often as needed (sequences can be shorter than k)
+    extended.extend(seq.iter().cycle().take(max_k - 1));
+    let mut map = build_codes_per_k(&extended, specs);
+    for codes in map.values_mut() {
+        codes.truncate(seq.len());
     }
+    map
+}
 
+/// Build codes for the positions `[start, end)` of `seq` only.
+///
+/// Reads up to `max k - 1` bases past `end` (wrapping around to the
+/// start of `seq` when `circular`), so the codes equal
+/// `build_codes_per_k(seq)[start..end]` (or the circular equivalent).
+pub fn build_codes_per_k_chunk(
+    seq: &[u8],
+    specs: &BTreeMap<u8, KmerSpec>,
+    start: usize,
+    end: usize,
+    circular: bool,
+) -> BTreeMap<u8, KmerCodes> {
+    let max_k = specs.keys().copied().max().unwrap_or(1) as usize;
+    let slice_end = (end + max_k - 1).min(seq.len());
+    let mut map = if circular && slice_end - end < max_k - 1 {
+        let mut extended = Vec::with_capacity(slice_end - start + max_k - 1);
+        extended.extend_from_slice(&seq[start..slice_end]);
+        extended.extend(seq.iter().cycle().take(max_k - 1 - (slice_end - end)));
+        build_codes_per_k(&extended, specs)
+    } else {
+        build_codes_per_k(&seq[start..slice_end], specs)
+    };
+    for codes in map.values_mut() {
+        codes.truncate(end - start);
+    }
     map
 }
 
@@ -169,10 +644,18 @@ pub fn build_codes_per_k(seq: &[u8], specs: &HashMap<u8, KmerSpec>) -> HashMap<u
 /// The top two codes of the chosen width are reserved as sentinels.
 pub fn choose_width(k: usize) -> Result<(Width, u64, u64)> {
     // `u128` is used so that 5^k never overflows during width selection.
-    // Even for k = 27 we have 5^k ≈ 7.4e18 < 2^128, so the calculation is safe.
+    // Even for k = 31 we have 5^k ≈ 4.7e21 < 2^128, so the calculation is safe.
     // The value is then compared to the MAX of each smaller integer type.
-    let max_real_code = 5u128.pow(k as u32) - 1; // Highest real code (no sentinels)
+    width_for_max_code(5u128.pow(k as u32) - 1)
+}
+
+/// Like `choose_width` for the 2-bit encoding (4^k real codes)
+pub fn choose_width_2bit(k: usize) -> Result<(Width, u64, u64)> {
+    width_for_max_code(4u128.pow(k as u32) - 1)
+}
 
+/// Narrowest width holding codes up to `max_real_code` plus two sentinels
+fn width_for_max_code(max_real_code: u128) -> Result<(Width, u64, u64)> {
     macro_rules! fits_in {
         ($ty:ty) => {
             max_real_code <= (<$ty>::MAX as u128 - 2)
@@ -208,6 +691,26 @@ static LUT: [u8; 256] = {
     t
 };
 
+/// Bases encoded at a time by `for_each_kmer_code`
+const ENCODE_BLOCK: usize = 4096;
+
+/// Encode every base of `seq` into `out` like `encode_base` (as `u8`).
+///
+/// Uses SIMD (AVX2 when detected at runtime, else SSE2, on x86_64 and NEON
+/// on aarch64) for 16 or 32 bases at a time, and the lookup table for the
+/// remaining bases.
+pub fn encode_bases(seq: &[u8], out: &mut [u8]) {
+    assert_eq!(
+        seq.len(),
+        out.len(),
+        "output must match the sequence length"
+    );
+    let done = simd::encode_bases(seq, out);
+    for (o, &b) in out[done..].iter_mut().zip(&seq[done..]) {
+        *o = LUT[b as usize];
+    }
+}
+
 /// Encode a single nucleotide into its base‑5 digit.
 ///
 /// - A or a → 0  
@@ -245,8 +748,8 @@ fn build_codes(seq: &[u8], k: usize, sentinel_none: u64, sentinel_n: u64) -> Vec
     let mut n_in_window: u32 = 0; // ‘N’ counter in current window
 
     // First full k-mer window
-    for i in 0..k {
-        let val = encode_base(seq[i]);
+    for &b in &seq[..k] {
+        let val = encode_base(b);
         if val == 4 {
             n_in_window += 1;
         }
@@ -278,7 +781,109 @@ fn build_codes(seq: &[u8], k: usize, sentinel_none: u64, sentinel_n: u64) -> Vec
 
     // Pad the tail where no full window fits
     // (exactly k-1 positions)
-    out.extend(std::iter::repeat(sentinel_none).take(k - 1));
+    out.extend(std::iter::repeat_n(sentinel_none, k - 1));
+
+    debug_assert_eq!(out.len(), chrom_len);
+    out
+}
+
+/// Build 2-bit codes for every left-aligned k-mer in `seq`.
+///
+/// Non-A/C/G/T bases are packed as A; k-mers overlapping such a base (i.e.
+/// starting after the end of the last N run minus k) get `sentinel_n`.
+/// The result length always equals `seq.len()`.
+fn build_codes_2bit(seq: &[u8], k: usize, sentinel_none: u64, sentinel_n: u64) -> Vec<u64> {
+    let chrom_len = seq.len();
+    if k > chrom_len {
+        return vec![sentinel_none; chrom_len];
+    }
+    let mask = (1u64 << (2 * k)) - 1;
+    let mut out = Vec::with_capacity(chrom_len);
+    let mut code: u64 = 0;
+    // Position after the last N seen so far
+    let mut n_run_end = 0;
+    for (i, &b) in seq.iter().enumerate() {
+        let val = encode_base(b);
+        if val == 4 {
+            n_run_end = i + 1;
+        }
+        code = ((code << 2) | (val & 3)) & mask;
+        if i + 1 >= k {
+            // The k-mer starts at `i + 1 - k`
+            out.push(if n_run_end > i + 1 - k {
+                sentinel_n
+            } else {
+                code
+            });
+        }
+    }
+    out.extend(std::iter::repeat_n(sentinel_none, k - 1));
+
+    debug_assert_eq!(out.len(), chrom_len);
+    out
+}
+
+/// Like `build_codes_2bit` with k‑mers packed into u128 and their
+/// canonical forms numbered in `dictionary`.
+///
+/// K‑mers are numbered in a dictionary local to this call, which is merged
+/// into `dictionary` once at the end, so threads only share the lock once
+/// per sequence.
+fn build_codes_dictionary(
+    seq: &[u8],
+    k: usize,
+    sentinel_none: u64,
+    sentinel_n: u64,
+    dictionary: &RwLock<KmerDictionary>,
+) -> Vec<u64> {
+    let chrom_len = seq.len();
+    if k > chrom_len {
+        return vec![sentinel_none; chrom_len];
+    }
+    let mask = (1u128 << (2 * k)) - 1;
+    let mut local = KmerDictionary::default();
+    let mut out = Vec::with_capacity(chrom_len);
+    let mut packed: u128 = 0;
+    // Reverse complement of `packed`
+    let mut rc: u128 = 0;
+    // Position after the last N seen so far
+    let mut n_run_end = 0;
+    for (i, &b) in seq.iter().enumerate() {
+        let val = encode_base(b);
+        if val == 4 {
+            n_run_end = i + 1;
+        }
+        packed = ((packed << 2) | (val & 3) as u128) & mask;
+        rc = (rc >> 2) | (((3 - (val & 3)) as u128) << (2 * (k - 1)));
+        if i + 1 >= k {
+            out.push(if n_run_end > i + 1 - k {
+                sentinel_n
+            } else {
+                // Packed k-mers sort like their k-mers
+                let (canonical, tag) = match packed.cmp(&rc) {
+                    std::cmp::Ordering::Less => (packed, TAG_CANONICAL),
+                    std::cmp::Ordering::Greater => (rc, TAG_REVCOMP),
+                    std::cmp::Ordering::Equal => (packed, TAG_PALINDROME),
+                };
+                (local.intern(canonical) << DICTIONARY_TAG_BITS) | tag
+            });
+        }
+    }
+
+    // Renumber in the shared dictionary
+    let numbers: Vec<u64> = {
+        let mut dictionary = dictionary.write().unwrap();
+        local
+            .kmers
+            .iter()
+            .map(|&kmer| dictionary.intern(kmer))
+            .collect()
+    };
+    for code in out.iter_mut().filter(|code| **code != sentinel_n) {
+        *code = (numbers[(*code >> DICTIONARY_TAG_BITS) as usize] << DICTIONARY_TAG_BITS)
+            | (*code & TAG_MASK);
+    }
+    out.extend(std::iter::repeat_n(sentinel_none, k - 1));
 
     debug_assert_eq!(out.len(), chrom_len);
     out
@@ -298,11 +903,24 @@ fn decode_kmer(code: u64, k: usize, sentinel_none: u64, sentinel_n: u64) -> Stri
     buf.into_iter().collect()
 }
 
+/// Sum per-window kmer code counts into one map (e.g. for `--global`)
+pub fn merge_kmer_counts(
+    all: impl IntoIterator<Item = FxHashMap<Kmer, BigCount>>,
+) -> FxHashMap<Kmer, BigCount> {
+    let mut merged: FxHashMap<Kmer, BigCount> = FxHashMap::default();
+    for counts in all {
+        for (kmer, cnt) in counts {
+            *merged.entry(kmer).or_insert(0) += cnt;
+        }
+    }
+    merged
+}
+
 /// Aggregate a list of `DecodedCounts` values into one by summing
 /// the motif counts for every k-mer size.
 pub fn merge_decoded_counts(all: Vec<DecodedCounts>) -> DecodedCounts {
     // Result containers: k  →  motif → count
-    let mut merged_counts: HashMap<u8, FxHashMap<String, BigCount>> = HashMap::new();
+    let mut merged_counts: BTreeMap<u8, FxHashMap<String, BigCount>> = BTreeMap::new();
 
     // Walk through every DecodedCounts provided by the caller
     for dc in all {
@@ -320,10 +938,123 @@ pub fn merge_decoded_counts(all: Vec<DecodedCounts>) -> DecodedCounts {
     }
 }
 
+/// Codes of listed motifs by k, for counting only those motifs
+#[derive(Debug, Clone, Default)]
+pub struct MotifCodes {
+    /// Codes of the motifs
+    pub listed: BTreeMap<u8, FxHashSet<u64>>,
+    /// Codes of the motifs and their reverse complements (k‑mers that
+    /// become listed motifs with `--canonical` or `--stranded`)
+    pub either_strand: BTreeMap<u8, FxHashSet<u64>>,
+}
+
+impl MotifCodes {
+    /// Encode `motifs_by_k`; motifs of sizes without a spec are skipped.
+    pub fn new(motifs_by_k: &BTreeMap<u8, Vec<String>>, specs: &BTreeMap<u8, KmerSpec>) -> Self {
+        let mut out = MotifCodes::default();
+        for (k, motifs) in motifs_by_k {
+            let Some(spec) = specs.get(k) else {
+                continue;
+            };
+            let listed = out.listed.entry(*k).or_default();
+            let either_strand = out.either_strand.entry(*k).or_default();
+            for motif in motifs {
+                let code = spec.build_codes(motif.as_bytes())[0];
+                listed.insert(code);
+                either_strand.insert(code);
+                either_strand.insert(spec.revcomp_code(code));
+            }
+        }
+        out
+    }
+
+    /// Whether `kmer` is a listed motif
+    pub fn is_listed(&self, kmer: &Kmer) -> bool {
+        self.listed
+            .get(&kmer.k)
+            .is_some_and(|codes| codes.contains(&kmer.code))
+    }
+}
+
+/// Replace all codes not in `listed` with the "no k‑mer" sentinel, so only
+/// listed k‑mers are counted.
+pub fn keep_listed_codes(codes: &mut KmerCodes, spec: &KmerSpec, listed: &FxHashSet<u64>) {
+    for pos in 0..codes.len() {
+        if !listed.contains(&codes.get(pos)) {
+            codes.set(pos, spec.sentinel_none());
+        }
+    }
+}
+
+/// Replace all codes in `excluded` with the "no k‑mer" sentinel, so excluded
+/// k‑mers are not counted.
+pub fn drop_excluded_codes(codes: &mut KmerCodes, spec: &KmerSpec, excluded: &FxHashSet<u64>) {
+    for pos in 0..codes.len() {
+        if excluded.contains(&codes.get(pos)) {
+            codes.set(pos, spec.sentinel_none());
+        }
+    }
+}
+
+/// Replace the codes of positions that are not multiples of `stride` with
+/// the "no k‑mer" sentinel. `offset` is the sequence position of the first
+/// code (e.g. of a chunk).
+pub fn keep_strided_codes(codes: &mut KmerCodes, spec: &KmerSpec, stride: usize, offset: usize) {
+    for pos in 0..codes.len() {
+        if !(offset + pos).is_multiple_of(stride) {
+            codes.set(pos, spec.sentinel_none());
+        }
+    }
+}
+
+/// Re-key k‑mer counts to the reverse-complement strand, e.g. for windows on
+/// the `-` strand.
+pub fn revcomp_kmer_counts(
+    counts: FxHashMap<Kmer, BigCount>,
+    kmer_specs: &BTreeMap<u8, KmerSpec>,
+) -> FxHashMap<Kmer, BigCount> {
+    counts
+        .into_iter()
+        .map(|(kmer, cnt)| {
+            let code = kmer_specs[&kmer.k].revcomp_code(kmer.code);
+            (Kmer { k: kmer.k, code }, cnt)
+        })
+        .collect()
+}
+
+/// Add the reverse-complement counts to k‑mer counts without collapsing,
+/// i.e. count k‑mers on both strands (palindromes are counted twice).
+pub fn both_strand_kmer_counts(
+    counts: FxHashMap<Kmer, BigCount>,
+    kmer_specs: &BTreeMap<u8, KmerSpec>,
+) -> FxHashMap<Kmer, BigCount> {
+    let mut out = counts.clone();
+    for (kmer, cnt) in counts {
+        let code = kmer_specs[&kmer.k].revcomp_code(kmer.code);
+        *out.entry(Kmer { k: kmer.k, code }).or_insert(0) += cnt;
+    }
+    out
+}
+
+/// Collapse k‑mer counts to canonical codes, summing the counts of each
+/// k‑mer and its reverse complement.
+pub fn canonical_kmer_counts(
+    counts: FxHashMap<Kmer, BigCount>,
+    kmer_specs: &BTreeMap<u8, KmerSpec>,
+) -> FxHashMap<Kmer, BigCount> {
+    let mut out: FxHashMap<Kmer, BigCount> = FxHashMap::default();
+    out.reserve(counts.len());
+    for (kmer, cnt) in counts {
+        let code = kmer_specs[&kmer.k].canonical_code(kmer.code);
+        *out.entry(Kmer { k: kmer.k, code }).or_insert(0) += cnt;
+    }
+    out
+}
+
 /// Per-k map of “reference” counts
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct DecodedCounts {
-    pub counts: HashMap<u8, FxHashMap<String, BigCount>>, // k  →  motif → count
+    pub counts: BTreeMap<u8, FxHashMap<String, BigCount>>, // k  →  motif → count
 }
 
 /// Split an aggregated `counts` map into per-k buckets.
@@ -334,9 +1065,9 @@ pub struct DecodedCounts {
 /// Returns one map for reference windows (“matches”) and one for mismatches.
 pub fn split_and_decode_counts(
     counts: &FxHashMap<Kmer, BigCount>,
-    kmer_specs: &HashMap<u8, KmerSpec>,
+    kmer_specs: &BTreeMap<u8, KmerSpec>,
 ) -> DecodedCounts {
-    let mut count_bins: HashMap<u8, FxHashMap<String, BigCount>> = HashMap::new();
+    let mut count_bins: BTreeMap<u8, FxHashMap<String, BigCount>> = BTreeMap::new();
 
     for (&kmer, &cnt) in counts {
         // Human-readable motif, e.g. "ACG"
@@ -352,3 +1083,147 @@ pub fn split_and_decode_counts(
 
     DecodedCounts { counts: count_bins }
 }
+
+/// SIMD versions of `encode_bases`. Each returns the number of leading
+/// bases it encoded (a multiple of its lane count); the rest are left to
+/// the caller.
+///
+/// Bases are lower-cased by setting bit 5 (which only maps `A`/`a`,
+/// `C`/`c`, etc. to the same letter) and compared to `a`, `c`, `g` and `t`.
+mod simd {
+    #[cfg(target_arch = "x86_64")]
+    pub fn encode_bases(seq: &[u8], out: &mut [u8]) -> usize {
+        if std::arch::is_x86_feature_detected!("avx2") {
+            // SAFETY: AVX2 support was checked above
+            unsafe { encode_bases_avx2(seq, out) }
+        } else {
+            encode_bases_sse2(seq, out)
+        }
+    }
+
+    #[cfg(target_arch = "aarch64")]
+    pub fn encode_bases(seq: &[u8], out: &mut [u8]) -> usize {
+        encode_bases_neon(seq, out)
+    }
+
+    #[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+    pub fn encode_bases(_seq: &[u8], _out: &mut [u8]) -> usize {
+        0
+    }
+
+    /// SSE2 is part of the x86_64 baseline
+    #[cfg(target_arch = "x86_64")]
+    fn encode_bases_sse2(seq: &[u8], out: &mut [u8]) -> usize {
+        use std::arch::x86_64::*;
+        let n = seq.len().min(out.len()) / 16 * 16;
+        // SAFETY: loads and stores are unaligned and within `..n` of both
+        // slices
+        unsafe {
+            let lower = _mm_set1_epi8(0x20);
+            let (a, c, g, t) = (
+                _mm_set1_epi8(b'a' as i8),
+                _mm_set1_epi8(b'c' as i8),
+                _mm_set1_epi8(b'g' as i8),
+                _mm_set1_epi8(b't' as i8),
+            );
+            let (one, two, three, four) = (
+                _mm_set1_epi8(1),
+                _mm_set1_epi8(2),
+                _mm_set1_epi8(3),
+                _mm_set1_epi8(4),
+            );
+            for i in (0..n).step_by(16) {
+                let bases = _mm_loadu_si128(seq.as_ptr().add(i) as *const __m128i);
+                let bases = _mm_or_si128(bases, lower);
+                let is_a = _mm_cmpeq_epi8(bases, a);
+                let is_c = _mm_cmpeq_epi8(bases, c);
+                let is_g = _mm_cmpeq_epi8(bases, g);
+                let is_t = _mm_cmpeq_epi8(bases, t);
+                let is_acgt = _mm_or_si128(_mm_or_si128(is_a, is_c), _mm_or_si128(is_g, is_t));
+                let codes = _mm_or_si128(
+                    _mm_or_si128(_mm_and_si128(is_c, one), _mm_and_si128(is_g, two)),
+                    _mm_or_si128(_mm_and_si128(is_t, three), _mm_andnot_si128(is_acgt, four)),
+                );
+                _mm_storeu_si128(out.as_mut_ptr().add(i) as *mut __m128i, codes);
+            }
+        }
+        n
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    #[target_feature(enable = "avx2")]
+    fn encode_bases_avx2(seq: &[u8], out: &mut [u8]) -> usize {
+        use std::arch::x86_64::*;
+        let n = seq.len().min(out.len()) / 32 * 32;
+        // SAFETY: loads and stores are unaligned and within `..n` of both
+        // slices
+        unsafe {
+            let lower = _mm256_set1_epi8(0x20);
+            let (a, c, g, t) = (
+                _mm256_set1_epi8(b'a' as i8),
+                _mm256_set1_epi8(b'c' as i8),
+                _mm256_set1_epi8(b'g' as i8),
+                _mm256_set1_epi8(b't' as i8),
+            );
+            let (one, two, three, four) = (
+                _mm256_set1_epi8(1),
+                _mm256_set1_epi8(2),
+                _mm256_set1_epi8(3),
+                _mm256_set1_epi8(4),
+            );
+            for i in (0..n).step_by(32) {
+                let bases = _mm256_loadu_si256(seq.as_ptr().add(i) as *const __m256i);
+                let bases = _mm256_or_si256(bases, lower);
+                let is_a = _mm256_cmpeq_epi8(bases, a);
+                let is_c = _mm256_cmpeq_epi8(bases, c);
+                let is_g = _mm256_cmpeq_epi8(bases, g);
+                let is_t = _mm256_cmpeq_epi8(bases, t);
+                let is_acgt =
+                    _mm256_or_si256(_mm256_or_si256(is_a, is_c), _mm256_or_si256(is_g, is_t));
+                let codes = _mm256_or_si256(
+                    _mm256_or_si256(_mm256_and_si256(is_c, one), _mm256_and_si256(is_g, two)),
+                    _mm256_or_si256(
+                        _mm256_and_si256(is_t, three),
+                        _mm256_andnot_si256(is_acgt, four),
+                    ),
+                );
+                _mm256_storeu_si256(out.as_mut_ptr().add(i) as *mut __m256i, codes);
+            }
+        }
+        n
+    }
+
+    /// NEON is part of the aarch64 baseline
+    #[cfg(target_arch = "aarch64")]
+    fn encode_bases_neon(seq: &[u8], out: &mut [u8]) -> usize {
+        use std::arch::aarch64::*;
+        let n = seq.len().min(out.len()) / 16 * 16;
+        // SAFETY: loads and stores are within `..n` of both slices
+        unsafe {
+            let lower = vdupq_n_u8(0x20);
+            let (a, c, g, t) = (
+                vdupq_n_u8(b'a'),
+                vdupq_n_u8(b'c'),
+                vdupq_n_u8(b'g'),
+                vdupq_n_u8(b't'),
+            );
+            let (one, two, three, four) =
+                (vdupq_n_u8(1), vdupq_n_u8(2), vdupq_n_u8(3), vdupq_n_u8(4));
+            for i in (0..n).step_by(16) {
+                let bases = vorrq_u8(vld1q_u8(seq.as_ptr().add(i)), lower);
+                let is_a = vceqq_u8(bases, a);
+                let is_c = vceqq_u8(bases, c);
+                let is_g = vceqq_u8(bases, g);
+                let is_t = vceqq_u8(bases, t);
+                let is_acgt = vorrq_u8(vorrq_u8(is_a, is_c), vorrq_u8(is_g, is_t));
+                let codes = vorrq_u8(
+                    vorrq_u8(vandq_u8(is_c, one), vandq_u8(is_g, two)),
+                    // `vbicq_u8(x, y)` is `x & !y`
+                    vorrq_u8(vandq_u8(is_t, three), vbicq_u8(four, is_acgt)),
+                );
+                vst1q_u8(out.as_mut_ptr().add(i), codes);
+            }
+        }
+        n
+    }
+}
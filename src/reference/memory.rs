@@ -0,0 +1,162 @@
+use anyhow::Result;
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Condvar, Mutex};
+
+use crate::reference::kmer_codec::KmerSpec;
+
+/// Bytes of one k-mer count in a window map (key, count and hash table
+/// overhead)
+const BYTES_PER_COUNT: u64 = 32;
+
+/// Rough peak memory of counting one chromosome of `len` bases: the
+/// sequence (and its masked copy), one positional code per base and k, and
+/// up to `len` (at most `4^k`) distinct k-mer counts per k
+pub fn estimate_chrom_bytes(len: u64, kmer_specs: &BTreeMap<u8, KmerSpec>) -> u64 {
+    let codes: u64 = kmer_specs
+        .values()
+        .map(|spec| len * spec.width().bytes())
+        .sum();
+    let counts: u64 = kmer_specs
+        .keys()
+        .map(|&k| len.min(4u64.saturating_pow(k as u32)) * BYTES_PER_COUNT)
+        .sum();
+    2 * len + codes + counts
+}
+
+/// Rough memory of the count maps of windows of `window_lens` bases, which
+/// are kept until the outputs are written: up to the window length (at most
+/// `4^k`) distinct k-mer counts per k
+pub fn estimate_window_counts_bytes(
+    window_lens: impl IntoIterator<Item = u64>,
+    kmer_specs: &BTreeMap<u8, KmerSpec>,
+) -> u64 {
+    window_lens
+        .into_iter()
+        .map(|len| {
+            kmer_specs
+                .keys()
+                .map(|&k| len.min(4u64.saturating_pow(k as u32)) * BYTES_PER_COUNT)
+                .sum::<u64>()
+        })
+        .sum()
+}
+
+/// Rough peak memory of counting chromosomes of `chrom_lengths` with up to
+/// `n_threads` at once, the largest first. With a `limit` (`--max-memory`),
+/// fewer run at once, but the largest chromosome always runs.
+pub fn estimate_peak_chrom_bytes(
+    chrom_lengths: &[u64],
+    kmer_specs: &BTreeMap<u8, KmerSpec>,
+    n_threads: usize,
+    limit: Option<u64>,
+) -> u64 {
+    let mut estimates: Vec<u64> = chrom_lengths
+        .iter()
+        .map(|&len| estimate_chrom_bytes(len, kmer_specs))
+        .collect();
+    estimates.sort_unstable_by(|a, b| b.cmp(a));
+    let concurrent: u64 = estimates.iter().take(n_threads.max(1)).sum();
+    match limit {
+        Some(limit) => concurrent.min(limit.max(estimates.first().copied().unwrap_or(0))),
+        None => concurrent,
+    }
+}
+
+/// A memory limit shared by concurrent tasks that reserve their estimated
+/// memory before they start
+#[derive(Debug)]
+pub struct MemoryBudget {
+    limit: u64,
+    reserved: Mutex<u64>,
+    released: Condvar,
+}
+
+/// Memory reserved from a `MemoryBudget`, released on drop
+#[derive(Debug)]
+pub struct Reservation<'a> {
+    budget: &'a MemoryBudget,
+    bytes: u64,
+}
+
+impl Drop for Reservation<'_> {
+    fn drop(&mut self) {
+        *self.budget.reserved.lock().unwrap() -= self.bytes;
+        self.budget.released.notify_all();
+    }
+}
+
+impl MemoryBudget {
+    pub fn new(limit: u64) -> Self {
+        MemoryBudget {
+            limit,
+            reserved: Mutex::new(0),
+            released: Condvar::new(),
+        }
+    }
+
+    /// Reserve `bytes`, waiting until they fit within the limit. Larger
+    /// reservations than the limit wait until nothing else is reserved.
+    pub fn reserve(&self, bytes: u64) -> Reservation<'_> {
+        let mut reserved = self.reserved.lock().unwrap();
+        while *reserved > 0 && *reserved + bytes > self.limit {
+            reserved = self.released.wait(reserved).unwrap();
+        }
+        *reserved += bytes;
+        Reservation {
+            budget: self,
+            bytes,
+        }
+    }
+
+    /// Run `task` on every item in `order` using up to `n_threads` threads.
+    ///
+    /// Items are started in `order` once their `bytes` fit the budget, so
+    /// fewer items run at once when they are large. Results are returned
+    /// in the order of `order`. Stops starting items after the first error.
+    ///
+    /// Runs on its own threads, not the rayon pool: waiting for memory
+    /// inside a rayon task could block a thread whose stack holds the task
+    /// it is waiting for.
+    pub fn run<T: Send>(
+        &self,
+        order: Vec<usize>,
+        bytes: impl Fn(usize) -> u64 + Sync,
+        n_threads: usize,
+        task: impl Fn(usize) -> Result<T> + Sync,
+    ) -> Result<Vec<T>> {
+        let n_items = order.len();
+        let queue = Mutex::new(order.into_iter().enumerate().collect::<VecDeque<_>>());
+        let failed = AtomicBool::new(false);
+        let results: Mutex<Vec<Option<Result<T>>>> =
+            Mutex::new((0..n_items).map(|_| None).collect());
+        std::thread::scope(|scope| {
+            for _ in 0..n_threads.clamp(1, n_items.max(1)) {
+                scope.spawn(|| loop {
+                    if failed.load(Ordering::Relaxed) {
+                        return;
+                    }
+                    // Take the next item and wait for its memory in turn
+                    let (slot, item, _reservation) = {
+                        let mut queue = queue.lock().unwrap();
+                        let Some((slot, item)) = queue.pop_front() else {
+                            return;
+                        };
+                        (slot, item, self.reserve(bytes(item)))
+                    };
+                    let result = task(item);
+                    if result.is_err() {
+                        failed.store(true, Ordering::Relaxed);
+                    }
+                    results.lock().unwrap()[slot] = Some(result);
+                });
+            }
+        });
+        results
+            .into_inner()
+            .unwrap()
+            .into_iter()
+            .flatten()
+            .collect()
+    }
+}
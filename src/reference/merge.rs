@@ -0,0 +1,479 @@
+use crate::cli::BigCount;
+use crate::reference::write::{
+    write_counts_npy, write_lines, write_motif_lists, write_triplets_sparse, CountDtype,
+    SparseFormat,
+};
+use anyhow::{bail, Context, Result};
+use ndarray::{Array, Array1, Array2, Dimension};
+use ndarray_npy::{ReadNpyError, ReadNpyExt, ReadableElement};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+/// A count matrix of an output directory
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CountMatrix {
+    /// Prefix of the matrix files, e.g. `k3` for `k3_counts.npy`
+    pub prefix: String,
+    /// `<prefix>_counts_sparse.npz` rather than `<prefix>_counts.npy`
+    pub sparse: bool,
+}
+
+impl CountMatrix {
+    pub fn file_name(&self) -> String {
+        if self.sparse {
+            format!("{}_counts_sparse.npz", self.prefix)
+        } else {
+            format!("{}_counts.npy", self.prefix)
+        }
+    }
+}
+
+/// The count matrices of an output directory, ordered by file name
+pub fn count_matrices(dir: &Path) -> Result<Vec<CountMatrix>> {
+    let mut matrices = Vec::new();
+    for entry in std::fs::read_dir(dir).context(format!("Reading directory {:?}", dir))? {
+        let name = entry?.file_name().to_string_lossy().into_owned();
+        if let Some(prefix) = name.strip_suffix("_counts_sparse.npz") {
+            matrices.push(CountMatrix {
+                prefix: prefix.to_string(),
+                sparse: true,
+            });
+        } else if let Some(prefix) = name.strip_suffix("_counts.npy") {
+            matrices.push(CountMatrix {
+                prefix: prefix.to_string(),
+                sparse: false,
+            });
+        }
+    }
+    matrices.sort_by_key(CountMatrix::file_name);
+    Ok(matrices)
+}
+
+/// Sum the count matrices of several output directories of runs with the
+/// same windows and kmer sizes (e.g. technical replicates) into `out_dir`.
+///
+/// All inputs must have the same matrices and the same `bins.bed` (or none,
+/// for `--global` runs). When the motif lists of a matrix differ, its
+/// columns are the union of the motifs (those of the first input first) and
+/// motifs missing from an input count as zero. Matrices are written in the
+/// layout of the first input with element type `dtype`, along with the
+/// shared `bins.bed` and the motif lists.
+///
+/// Returns the file names of the merged matrices.
+pub fn merge_runs(inputs: &[PathBuf], out_dir: &Path, dtype: CountDtype) -> Result<Vec<String>> {
+    let [first, rest @ ..] = inputs else {
+        bail!("No output directories to merge");
+    };
+    if rest.is_empty() {
+        bail!("At least two output directories are needed to merge");
+    }
+
+    if out_dir.exists() {
+        let out = out_dir.canonicalize()?;
+        for input in inputs {
+            if input
+                .canonicalize()
+                .context(format!("Opening {:?}", input))?
+                == out
+            {
+                bail!("The merged output cannot be written to input {:?}", input);
+            }
+        }
+    }
+
+    let matrices = count_matrices(first)?;
+    if matrices.is_empty() {
+        bail!(
+            "No count matrices (*_counts.npy or *_counts_sparse.npz) in {:?}",
+            first
+        );
+    }
+    let file_names: Vec<String> = matrices.iter().map(CountMatrix::file_name).collect();
+    for input in rest {
+        let other: Vec<String> = count_matrices(input)?
+            .iter()
+            .map(CountMatrix::file_name)
+            .collect();
+        if other != file_names {
+            bail!(
+                "{:?} has the count matrices [{}] but {:?} has [{}]",
+                input,
+                other.join(", "),
+                first,
+                file_names.join(", ")
+            );
+        }
+    }
+
+    // Validate everything before writing anything
+    let bins = read_if_exists(&first.join("bins.bed"))?;
+    for input in rest {
+        if read_if_exists(&input.join("bins.bed"))? != bins {
+            bail!(
+                "The windows (bins.bed) of {:?} differ from those of {:?}",
+                input,
+                first
+            );
+        }
+    }
+    let mut motif_columns = Vec::with_capacity(matrices.len());
+    for matrix in &matrices {
+        motif_columns.push(align_motifs(inputs, &matrix.prefix)?);
+    }
+
+    std::fs::create_dir_all(out_dir).context(format!("Creating {:?}", out_dir))?;
+    for (matrix, columns) in matrices.iter().zip(&motif_columns) {
+        let file_name = matrix.file_name();
+        let paths: Vec<PathBuf> = inputs.iter().map(|dir| dir.join(&file_name)).collect();
+        let maps = columns
+            .as_ref()
+            .map(|c| (c.columns.as_slice(), c.motifs.len()));
+        if matrix.sparse {
+            merge_sparse(&paths, maps, &matrix.prefix, out_dir, dtype)?;
+        } else {
+            merge_dense(&paths, maps, &out_dir.join(&file_name), dtype)?;
+        }
+        match columns {
+            Some(MotifColumns {
+                motifs,
+                canonical,
+                differ: true,
+                ..
+            }) => match canonical {
+                Some(canonical) => write_motif_lists(out_dir, &matrix.prefix, motifs, *canonical)?,
+                None => write_lines(
+                    &out_dir.join(format!("{}_motifs.txt", matrix.prefix)),
+                    motifs,
+                )?,
+            },
+            _ => {
+                for suffix in ["motifs.txt", "motifs.json"] {
+                    copy_if_exists(first, out_dir, &format!("{}_{}", matrix.prefix, suffix))?;
+                }
+            }
+        }
+    }
+    copy_if_exists(first, out_dir, "bins.bed")?;
+    Ok(file_names)
+}
+
+/// Merged column of every column of each input and the number of merged
+/// columns (see `MotifColumns`)
+type ColumnMaps<'a> = Option<(&'a [Vec<usize>], usize)>;
+
+/// Sum dense `.npy` matrices of equal shape (after moving their columns to
+/// the merged columns of `columns`)
+fn merge_dense(
+    paths: &[PathBuf],
+    columns: ColumnMaps,
+    out_path: &Path,
+    dtype: CountDtype,
+) -> Result<()> {
+    let mut sum: Option<Array2<BigCount>> = None;
+    for (i, path) in paths.iter().enumerate() {
+        let bytes = std::fs::read(path).context(format!("Reading {:?}", path))?;
+        let mut counts: Array2<BigCount> =
+            read_counts_npy(&bytes).context(format!("Reading {:?}", path))?;
+        if let Some((maps, n_cols)) = columns {
+            check_columns(path, counts.ncols(), &maps[i])?;
+            let mut aligned = Array2::<BigCount>::zeros((counts.nrows(), n_cols));
+            for (col, &to) in maps[i].iter().enumerate() {
+                aligned.column_mut(to).assign(&counts.column(col));
+            }
+            counts = aligned;
+        }
+        match &mut sum {
+            None => sum = Some(counts),
+            Some(sum) if sum.dim() != counts.dim() => bail!(
+                "{:?} has shape {:?} but the first matrix has shape {:?}",
+                path,
+                counts.dim(),
+                sum.dim()
+            ),
+            Some(sum) => *sum += &counts,
+        }
+    }
+    match sum {
+        Some(sum) => write_counts_npy(out_path, &sum, dtype),
+        None => Ok(()),
+    }
+}
+
+/// Sum sparse `.npz` matrices of equal shape (after moving their columns
+/// to the merged columns of `columns`). The format (COO or CSR) of the
+/// first matrix is kept.
+fn merge_sparse(
+    paths: &[PathBuf],
+    columns: ColumnMaps,
+    prefix: &str,
+    out_dir: &Path,
+    dtype: CountDtype,
+) -> Result<()> {
+    let mut triplets: Vec<(u64, u64, BigCount)> = Vec::new();
+    let mut layout: Option<(SparseFormat, [usize; 2])> = None;
+    for (i, path) in paths.iter().enumerate() {
+        let mut matrix = SparseCounts::read(path).context(format!("Reading {:?}", path))?;
+        if let Some((maps, n_cols)) = columns {
+            check_columns(path, matrix.shape[1], &maps[i])?;
+            for (_, col, _) in &mut matrix.triplets {
+                *col = maps[i][*col as usize] as u64;
+            }
+            matrix.shape[1] = n_cols;
+        }
+        match layout {
+            None => layout = Some((matrix.format, matrix.shape)),
+            Some((_, shape)) if shape != matrix.shape => bail!(
+                "{:?} has shape {:?} but the first matrix has shape {:?}",
+                path,
+                matrix.shape,
+                shape
+            ),
+            Some(_) => {}
+        }
+        triplets.extend(matrix.triplets);
+    }
+    let Some((format, [n_rows, n_cols])) = layout else {
+        return Ok(());
+    };
+
+    write_summed_triplets(triplets, n_rows, n_cols, prefix, out_dir, format, dtype)
+}
+
+/// The merged motif columns of a matrix
+struct MotifColumns {
+    /// Union of the motifs of all inputs, those of the first input first
+    motifs: Vec<String>,
+    /// Merged column of every column of each input
+    columns: Vec<Vec<usize>>,
+    /// `canonical` of the `<prefix>_motifs.json` files
+    canonical: Option<bool>,
+    /// Whether the motif lists of the inputs differ
+    differ: bool,
+}
+
+/// Align the `<prefix>_motifs.txt` lists of `inputs` on the union of their
+/// motifs, or `None` when the matrix has no motif list
+fn align_motifs(inputs: &[PathBuf], prefix: &str) -> Result<Option<MotifColumns>> {
+    let motifs_name = format!("{}_motifs.txt", prefix);
+    let json_name = format!("{}_motifs.json", prefix);
+    let mut lists: Vec<Option<Vec<String>>> = Vec::with_capacity(inputs.len());
+    let mut canonical = None;
+    for (i, input) in inputs.iter().enumerate() {
+        let list = read_if_exists(&input.join(&motifs_name))?
+            .map(|bytes| -> Result<Vec<String>> {
+                let text = String::from_utf8(bytes).context(format!("Reading {}", motifs_name))?;
+                Ok(text.lines().map(str::to_string).collect())
+            })
+            .transpose()?;
+        if i > 0 && list.is_some() != lists[0].is_some() {
+            bail!(
+                "{:?} and {:?} do not both have {}",
+                input,
+                inputs[0],
+                motifs_name
+            );
+        }
+        lists.push(list);
+        let json = match read_if_exists(&input.join(&json_name))? {
+            Some(bytes) => serde_json::from_slice::<serde_json::Value>(&bytes)
+                .context(format!("Reading {:?}", input.join(&json_name)))?["canonical"]
+                .as_bool(),
+            None => None,
+        };
+        if i == 0 {
+            canonical = json;
+        } else if json != canonical {
+            bail!(
+                "{:?} and {:?} differ in canonical (-c) motifs ({})",
+                input,
+                inputs[0],
+                json_name
+            );
+        }
+    }
+    let lists: Vec<Vec<String>> = match lists.into_iter().collect::<Option<_>>() {
+        Some(lists) => lists,
+        None => return Ok(None),
+    };
+
+    let mut motifs: Vec<String> = Vec::new();
+    let mut index: HashMap<String, usize> = HashMap::new();
+    let columns = lists
+        .iter()
+        .map(|list| {
+            list.iter()
+                .map(|motif| {
+                    *index.entry(motif.clone()).or_insert_with(|| {
+                        motifs.push(motif.clone());
+                        motifs.len() - 1
+                    })
+                })
+                .collect()
+        })
+        .collect();
+    let differ = lists.iter().any(|list| *list != motifs);
+    Ok(Some(MotifColumns {
+        motifs,
+        columns,
+        canonical,
+        differ,
+    }))
+}
+
+/// Fail unless a matrix has a column for every motif of its list
+fn check_columns(path: &Path, n_cols: usize, map: &[usize]) -> Result<()> {
+    if n_cols != map.len() {
+        bail!("{:?} has {} columns but {} motifs", path, n_cols, map.len());
+    }
+    Ok(())
+}
+
+/// Write `(row, col, count)` triplets in any order as
+/// `<prefix>_counts_sparse.npz`, summing the counts of the same cell
+pub fn write_summed_triplets(
+    mut triplets: Vec<(u64, u64, BigCount)>,
+    n_rows: usize,
+    n_cols: usize,
+    prefix: &str,
+    out_dir: &Path,
+    format: SparseFormat,
+    dtype: CountDtype,
+) -> Result<()> {
+    triplets.sort_unstable_by_key(|&(row, col, _)| (row, col));
+    let mut summed: Vec<(u64, u64, BigCount)> = Vec::with_capacity(triplets.len());
+    for (row, col, count) in triplets {
+        match summed.last_mut() {
+            Some(last) if (last.0, last.1) == (row, col) => last.2 += count,
+            _ => summed.push((row, col, count)),
+        }
+    }
+    write_triplets_sparse(
+        summed.into_iter().map(Ok),
+        n_rows,
+        n_cols,
+        prefix,
+        out_dir,
+        format,
+        dtype,
+    )
+}
+
+/// Entries of a `<prefix>_counts_sparse.npz` matrix
+pub struct SparseCounts {
+    pub format: SparseFormat,
+    pub shape: [usize; 2],
+    /// `(row, col, count)` of the stored entries
+    pub triplets: Vec<(u64, u64, BigCount)>,
+}
+
+impl SparseCounts {
+    pub fn read(path: &Path) -> Result<Self> {
+        let file = File::open(path).context(format!("Opening {:?}", path))?;
+        let mut npz = zip::ZipArchive::new(file)?;
+        let mut entry = |name: &str| -> Result<Vec<u8>> {
+            let mut bytes = Vec::new();
+            npz.by_name(name)
+                .context(format!("Missing {}", name))?
+                .read_to_end(&mut bytes)?;
+            Ok(bytes)
+        };
+
+        let shape = Array1::<i64>::read_npy(&entry("shape.npy")?[..])?;
+        let [n_rows, n_cols] = shape.as_slice().unwrap_or_default() else {
+            bail!("shape.npy does not hold two dimensions");
+        };
+        let shape = [
+            usize::try_from(*n_rows).context("Negative shape")?,
+            usize::try_from(*n_cols).context("Negative shape")?,
+        ];
+        let data: Array1<BigCount> = read_counts_npy(&entry("data.npy")?)?;
+        let (format, rows, cols): (SparseFormat, Vec<u64>, Vec<u64>) = match entry("indptr.npy") {
+            Ok(indptr) => {
+                let indptr = Array1::<u64>::read_npy(&indptr[..])?;
+                if indptr.len() != shape[0] + 1 {
+                    bail!("indptr.npy does not match the {} rows", shape[0]);
+                }
+                let rows = indptr
+                    .iter()
+                    .zip(indptr.iter().skip(1))
+                    .enumerate()
+                    .flat_map(|(row, (&beg, &end))| {
+                        std::iter::repeat_n(row as u64, end.saturating_sub(beg) as usize)
+                    })
+                    .collect();
+                let cols = Array1::<u64>::read_npy(&entry("indices.npy")?[..])?;
+                (SparseFormat::Csr, rows, cols.to_vec())
+            }
+            Err(_) => {
+                let rows = Array1::<u64>::read_npy(&entry("row.npy")?[..])?;
+                let cols = Array1::<u64>::read_npy(&entry("col.npy")?[..])?;
+                (SparseFormat::Coo, rows.to_vec(), cols.to_vec())
+            }
+        };
+        if rows.len() != data.len() || cols.len() != data.len() {
+            bail!("The index and data arrays differ in length");
+        }
+        if let Some((&row, &col)) = rows
+            .iter()
+            .zip(&cols)
+            .find(|&(&row, &col)| row as usize >= shape[0] || col as usize >= shape[1])
+        {
+            bail!("Entry ({}, {}) is outside the shape {:?}", row, col, shape);
+        }
+        let triplets = rows
+            .into_iter()
+            .zip(cols)
+            .zip(data)
+            .map(|((row, col), count)| (row, col, count))
+            .collect();
+        Ok(SparseCounts {
+            format,
+            shape,
+            triplets,
+        })
+    }
+}
+
+/// Read an unsigned integer `.npy` array (any `--count-dtype` but floats)
+/// as counts
+pub fn read_counts_npy<D: Dimension>(bytes: &[u8]) -> Result<Array<BigCount, D>> {
+    fn read_as<T: ReadableElement + Into<BigCount> + Copy, D: Dimension>(
+        bytes: &[u8],
+    ) -> Result<Option<Array<BigCount, D>>> {
+        match Array::<T, D>::read_npy(bytes) {
+            Ok(counts) => Ok(Some(counts.mapv(Into::into))),
+            Err(ReadNpyError::WrongDescriptor(_)) => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+    if let Some(counts) = read_as::<u64, D>(bytes)? {
+        return Ok(counts);
+    }
+    if let Some(counts) = read_as::<u32, D>(bytes)? {
+        return Ok(counts);
+    }
+    if let Some(counts) = read_as::<u16, D>(bytes)? {
+        return Ok(counts);
+    }
+    bail!("Only integer counts can be merged, not scaled (e.g. `--normalize`) or float values")
+}
+
+/// Contents of `path`, or `None` when it does not exist
+fn read_if_exists(path: &Path) -> Result<Option<Vec<u8>>> {
+    match std::fs::read(path) {
+        Ok(bytes) => Ok(Some(bytes)),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err).context(format!("Reading {:?}", path)),
+    }
+}
+
+/// Copy `name` from `from` to `to` when it exists
+pub fn copy_if_exists(from: &Path, to: &Path, name: &str) -> Result<()> {
+    let path = from.join(name);
+    if path.exists() {
+        std::fs::copy(&path, to.join(name)).context(format!("Copying {:?}", path))?;
+    }
+    Ok(())
+}
@@ -0,0 +1,55 @@
+use crate::reference::kmer_codec::{KmerCodes, KmerSpec};
+use std::collections::VecDeque;
+
+/// Order of k-mers when selecting minimizers. Codes are hashed so that
+/// low-complexity k-mers (e.g. poly-A) are not always selected.
+#[inline]
+fn minimizer_order(code: u64) -> u64 {
+    fxhash::hash64(&code)
+}
+
+/// Positions of the (w,k) minimizers of a code stream.
+///
+/// Every run of `w` consecutive positions (all positions when there are
+/// fewer) selects its k-mer with the lowest hashed code, the leftmost on
+/// ties. Positions without a k-mer (sentinels) are never selected. Returns
+/// whether each position is selected by at least one run.
+pub fn minimizer_positions(codes: &KmerCodes, spec: &KmerSpec, w: usize) -> Vec<bool> {
+    let len = codes.len();
+    let w = w.min(len).max(1);
+    let mut selected = vec![false; len];
+    // Candidates (order, position) with increasing order
+    let mut candidates: VecDeque<(u64, usize)> = VecDeque::new();
+    for pos in 0..len {
+        let code = codes.get(pos);
+        if code != spec.sentinel_none() && code != spec.sentinel_n() {
+            let order = minimizer_order(code);
+            while candidates.back().is_some_and(|&(o, _)| o > order) {
+                candidates.pop_back();
+            }
+            candidates.push_back((order, pos));
+        }
+        if pos + 1 < w {
+            continue;
+        }
+        let run_start = pos + 1 - w;
+        while candidates.front().is_some_and(|&(_, p)| p < run_start) {
+            candidates.pop_front();
+        }
+        if let Some(&(_, p)) = candidates.front() {
+            selected[p] = true;
+        }
+    }
+    selected
+}
+
+/// Replace the codes of all positions except the (w,k) minimizers with
+/// the "no k-mer" sentinel, so only minimizers are counted.
+pub fn keep_minimizers(codes: &mut KmerCodes, spec: &KmerSpec, w: usize) {
+    let selected = minimizer_positions(codes, spec, w);
+    for (pos, keep) in selected.into_iter().enumerate() {
+        if !keep {
+            codes.set(pos, spec.sentinel_none());
+        }
+    }
+}
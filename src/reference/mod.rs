@@ -1,6 +1,29 @@
+pub mod aggregate;
 pub mod bed;
+pub mod bisulfite;
 pub mod blacklist;
+pub mod checkpoint;
+pub mod chrom_alias;
+pub mod code_cache;
+pub mod compare;
 pub mod counting;
+pub mod cytoband;
+pub mod gtf;
 pub mod kmer_codec;
+pub mod memory;
+pub mod merge;
+pub mod minimizer;
+pub mod motif_groups;
 pub mod process_counts;
+pub mod profile;
+pub mod progress;
+pub mod pwm;
+pub mod reads;
+pub mod repeats;
+pub mod run_info;
+pub mod shuffle;
+pub mod spill;
+pub mod uniqueness;
+pub mod vcf;
+pub mod windows;
 pub mod write;
@@ -0,0 +1,306 @@
+use crate::cli::BigCount;
+use crate::reference::kmer_codec::DecodedCounts;
+use crate::reference::process_counts::canonical;
+use anyhow::{bail, Context, Result};
+use fxhash::FxHashMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::path::Path;
+
+/// Mapping of motifs to group labels, e.g. all CpG-containing 4-mers → "CpG+".
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MotifGroups {
+    /// Group labels in order of first appearance
+    pub labels: Vec<String>,
+    /// Motif → index into `labels`
+    pub index: FxHashMap<String, usize>,
+}
+
+impl MotifGroups {
+    /// Group labels for motifs of length `k` (in order of first appearance).
+    pub fn labels_for_k(&self, k: usize) -> Vec<String> {
+        let mut used = vec![false; self.labels.len()];
+        for (motif, &g) in &self.index {
+            if motif.len() == k {
+                used[g] = true;
+            }
+        }
+        self.labels
+            .iter()
+            .zip(used)
+            .filter(|(_, u)| *u)
+            .map(|(l, _)| l.clone())
+            .collect()
+    }
+}
+
+/// Load a two-column TSV (`motif<TAB>group`) of motif group labels.
+///
+/// * Lines that begin with `#` or are blank are skipped.
+/// * Motifs are upper-cased and, when `canonical_motifs` is set, converted to
+///   their canonical form so they match the collapsed columns.
+/// * A motif assigned to two different groups is an error.
+pub fn load_motif_groups(path: &Path, canonical_motifs: bool) -> Result<MotifGroups> {
+    let content =
+        std::fs::read_to_string(path).context(format!("Error reading motif groups {:?}", path))?;
+    let mut groups = MotifGroups::default();
+    let mut label_idx: FxHashMap<String, usize> = FxHashMap::default();
+    for line in content.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut fields = line.split('\t');
+        let (motif, label) = match (fields.next(), fields.next()) {
+            (Some(m), Some(l)) if !m.trim().is_empty() && !l.trim().is_empty() => {
+                (m.trim().to_uppercase(), l.trim().to_string())
+            }
+            _ => bail!("Motif group lines must be 'motif<TAB>group', got: {}", line),
+        };
+        if motif.chars().any(|c| !matches!(c, 'A' | 'C' | 'G' | 'T')) {
+            bail!(
+                "Motif {} in motif groups contains non-ACGT characters",
+                motif
+            );
+        }
+        let motif = if canonical_motifs {
+            canonical(motif)
+        } else {
+            motif
+        };
+        let next_idx = groups.labels.len();
+        let g = *label_idx.entry(label.clone()).or_insert_with(|| {
+            groups.labels.push(label.clone());
+            next_idx
+        });
+        if let Some(&prev) = groups.index.get(&motif) {
+            if prev != g {
+                bail!(
+                    "Motif {} is assigned to both '{}' and '{}'",
+                    motif,
+                    groups.labels[prev],
+                    groups.labels[g]
+                );
+            }
+        }
+        groups.index.insert(motif, g);
+    }
+    Ok(groups)
+}
+
+/// Bases matched by an IUPAC nucleotide code
+fn iupac_bases(code: char) -> Option<&'static str> {
+    Some(match code {
+        'A' => "A",
+        'C' => "C",
+        'G' => "G",
+        'T' => "T",
+        'R' => "AG",
+        'Y' => "CT",
+        'S' => "CG",
+        'W' => "AT",
+        'K' => "GT",
+        'M' => "AC",
+        'B' => "CGT",
+        'D' => "AGT",
+        'H' => "ACT",
+        'V' => "ACG",
+        'N' => "ACGT",
+        _ => return None,
+    })
+}
+
+/// Complement of an IUPAC nucleotide code
+fn iupac_complement(code: char) -> char {
+    match code {
+        'A' => 'T',
+        'T' => 'A',
+        'C' => 'G',
+        'G' => 'C',
+        'R' => 'Y',
+        'Y' => 'R',
+        'K' => 'M',
+        'M' => 'K',
+        'B' => 'V',
+        'V' => 'B',
+        'D' => 'H',
+        'H' => 'D',
+        other => other, // S, W and N are their own complements
+    }
+}
+
+/// Most concrete motifs a degenerate motif may expand to
+const MAX_EXPANSION: usize = 1 << 16;
+
+/// Expand an IUPAC-degenerate motif (e.g. `WGCW`) to its concrete motifs,
+/// in sorted order.
+pub fn expand_iupac(motif: &str) -> Result<Vec<String>> {
+    let mut expanded = vec![String::with_capacity(motif.len())];
+    for code in motif.chars() {
+        let bases =
+            iupac_bases(code).context(format!("Motif {} contains a non-IUPAC character", motif))?;
+        if expanded.len() * bases.len() > MAX_EXPANSION {
+            bail!(
+                "Motif {} expands to more than {} motifs",
+                motif,
+                MAX_EXPANSION
+            );
+        }
+        expanded = expanded
+            .iter()
+            .flat_map(|prefix| bases.chars().map(move |b| format!("{prefix}{b}")))
+            .collect();
+    }
+    Ok(expanded)
+}
+
+/// Motifs to count with `--motif-list`: the output columns (motifs,
+/// possibly IUPAC-degenerate) and the concrete motifs summed into each.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MotifList {
+    /// Column labels by length, in output order
+    pub columns: BTreeMap<u8, Vec<String>>,
+    /// Sorted concrete motifs of each column
+    pub concrete: HashMap<String, Vec<String>>,
+}
+
+impl MotifList {
+    /// Concrete motifs of all columns by length, deduplicated and sorted
+    pub fn concrete_by_k(&self) -> BTreeMap<u8, Vec<String>> {
+        let mut by_k: BTreeMap<u8, BTreeSet<&String>> = BTreeMap::new();
+        for motifs in self.concrete.values() {
+            for motif in motifs {
+                by_k.entry(motif.len() as u8).or_default().insert(motif);
+            }
+        }
+        by_k.into_iter()
+            .map(|(k, motifs)| (k, motifs.into_iter().cloned().collect()))
+            .collect()
+    }
+
+    /// Whether any column sums several concrete motifs
+    pub fn is_degenerate(&self) -> bool {
+        self.concrete
+            .iter()
+            .any(|(label, motifs)| motifs.len() != 1 || &motifs[0] != label)
+    }
+
+    /// Sum the counts of the concrete motifs into one count per column
+    pub fn sum_columns(&self, windows: Vec<DecodedCounts>) -> Vec<DecodedCounts> {
+        if !self.is_degenerate() {
+            return windows;
+        }
+        windows
+            .into_iter()
+            .map(|window| {
+                let counts = self
+                    .columns
+                    .iter()
+                    .map(|(k, labels)| {
+                        let concrete_counts = window.counts.get(k);
+                        let summed: FxHashMap<String, BigCount> = labels
+                            .iter()
+                            .map(|label| {
+                                let total = self.concrete[label]
+                                    .iter()
+                                    .filter_map(|m| concrete_counts?.get(m))
+                                    .sum();
+                                (label.clone(), total)
+                            })
+                            .filter(|(_, total)| *total > 0)
+                            .collect();
+                        (*k, summed)
+                    })
+                    .collect();
+                DecodedCounts { counts }
+            })
+            .collect()
+    }
+}
+
+/// Substitution types of the SBS-96 classification, in COSMIC order
+const SBS_SUBSTITUTIONS: [(char, char); 6] = [
+    ('C', 'A'),
+    ('C', 'G'),
+    ('C', 'T'),
+    ('T', 'A'),
+    ('T', 'C'),
+    ('T', 'G'),
+];
+
+/// The 96 single-base-substitution contexts (e.g. `A[C>T]G`) as the k=3
+/// columns, in the COSMIC ordering used by mutational-signature tools.
+///
+/// Every column counts its pyrimidine-centered trinucleotide on either
+/// strand (e.g. `ACG` and `CGT` for `A[C>T]G`), so the three substitutions
+/// of a trinucleotide share its count. With `canonical_motifs`, the
+/// concrete trinucleotides are in canonical form.
+pub fn sbs96_motifs(canonical_motifs: bool) -> MotifList {
+    let mut columns = Vec::with_capacity(96);
+    let mut concrete = HashMap::with_capacity(96);
+    for (from, to) in SBS_SUBSTITUTIONS {
+        for left in "ACGT".chars() {
+            for right in "ACGT".chars() {
+                let label = format!("{left}[{from}>{to}]{right}");
+                let trinucleotide = format!("{left}{from}{right}");
+                let rc: String = trinucleotide.chars().rev().map(iupac_complement).collect();
+                let motifs: BTreeSet<String> = if canonical_motifs {
+                    BTreeSet::from([canonical(trinucleotide)])
+                } else {
+                    BTreeSet::from([trinucleotide, rc])
+                };
+                concrete.insert(label.clone(), motifs.into_iter().collect());
+                columns.push(label);
+            }
+        }
+    }
+    MotifList {
+        columns: BTreeMap::from([(3, columns)]),
+        concrete,
+    }
+}
+
+/// Load a list of motifs (one per line, or the first column of a TSV such
+/// as a motif groups file).
+///
+/// * Lines that begin with `#` or are blank are skipped.
+/// * Motifs are upper-cased and may contain IUPAC codes (e.g. `WGCW`),
+///   which are expanded to the concrete motifs counted for the column.
+/// * When `canonical_motifs` is set, motifs and their concrete motifs are
+///   converted to their canonical form; motifs that become identical are
+///   merged.
+pub fn load_motif_list(path: &Path, canonical_motifs: bool) -> Result<MotifList> {
+    let content =
+        std::fs::read_to_string(path).context(format!("Error reading motif list {:?}", path))?;
+    let mut concrete: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
+    for line in content.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let motif = line.split_whitespace().next().unwrap().to_uppercase();
+        if motif.len() > u8::MAX as usize {
+            bail!("Motif {} in motif list is too long", motif);
+        }
+        let expanded = expand_iupac(&motif)?;
+        let (label, expanded) = if canonical_motifs {
+            let rc: String = motif.chars().rev().map(iupac_complement).collect();
+            let expanded = expanded.into_iter().map(canonical).collect();
+            (motif.min(rc), expanded)
+        } else {
+            (motif, expanded)
+        };
+        concrete.entry(label).or_default().extend(expanded);
+    }
+    let mut columns: BTreeMap<u8, Vec<String>> = BTreeMap::new();
+    for label in concrete.keys() {
+        columns
+            .entry(label.len() as u8)
+            .or_default()
+            .push(label.clone());
+    }
+    Ok(MotifList {
+        columns,
+        concrete: concrete
+            .into_iter()
+            .map(|(label, motifs)| (label, motifs.into_iter().collect()))
+            .collect(),
+    })
+}
@@ -1,19 +1,24 @@
+use clap::ValueEnum;
 use fxhash::FxHashMap;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use rand_distr::{Distribution, Hypergeometric};
+use rayon::prelude::*;
 
 use crate::cli::BigCount;
 
-use crate::reference::kmer_codec::{DecodedCounts, KmerSpec};
-use std::collections::{HashMap, HashSet};
+use crate::reference::kmer_codec::{DecodedCounts, Kmer, KmerSpec};
+use fxhash::FxHashSet;
+use std::collections::{BTreeMap, HashSet};
 
 fn prepare_kmer_category(
     windows: &[DecodedCounts],
-    kmer_specs: &HashMap<u8, KmerSpec>,
+    kmer_specs: &BTreeMap<u8, KmerSpec>,
     k: usize,
     canonical: bool,
     ensure_all: bool,
 ) -> (Vec<FxHashMap<String, BigCount>>, Vec<String>) {
-    // Extract the raw maps
-    let raw_bins = extract_bins(windows, k, canonical);
+    // Extract the raw maps (counts are already canonical with `canonical`)
+    let raw_bins = extract_bins(windows, k);
 
     // Build the (canonical) motif list once, if requested.
     let base_motifs: Vec<String> = if ensure_all {
@@ -35,36 +40,37 @@ fn prepare_kmer_category(
 /// For kmers of size 1..6, this includes all possible motifs.
 /// For larger kmer sizes, only the seen motifs is included as the number otherwise explodes.
 ///
-/// * `windows`        – slice of per-window raw counts
-/// * `canonical`      – canonical reverse complements when true
+/// * `windows`        – slice of per-window raw counts (with `canonical`,
+///   collapsed at the code level, see `canonical_kmer_counts`)
+/// * `canonical`      – list canonical motifs when true
 /// * `kmer_specs`     – validated specs for every k we want to keep
 ///
 pub fn prepare_decoded_counts(
     windows: &[DecodedCounts],
     canonical: bool,
-    kmer_specs: &HashMap<u8, KmerSpec>,
-) -> (Vec<DecodedCounts>, HashMap<u8, Vec<String>>) {
+    kmer_specs: &BTreeMap<u8, KmerSpec>,
+) -> (Vec<DecodedCounts>, BTreeMap<u8, Vec<String>>) {
     let n_windows = windows.len();
 
     // Initialise one empty DecodedCounts per window
     let mut out = vec![
         DecodedCounts {
-            counts: HashMap::new()
+            counts: BTreeMap::new()
         };
         n_windows
     ];
 
-    let mut motifs_by_k: HashMap<u8, Vec<String>> = HashMap::new();
+    let mut motifs_by_k: BTreeMap<u8, Vec<String>> = BTreeMap::new();
 
     // Loop over every k we validated
-    for (&k, _) in kmer_specs {
+    for &k in kmer_specs.keys() {
         // Reference (match) bins for this k
         let (count_bins, motifs) =
             prepare_kmer_category(windows, kmer_specs, k as usize, canonical, k <= 6);
 
-        // Insert into the corresponding window
-        for i in 0..n_windows {
-            out[i].counts.insert(k, count_bins[i].clone());
+        // Move into the corresponding window
+        for (window, bin) in out.iter_mut().zip(count_bins) {
+            window.counts.insert(k, bin);
         }
         motifs_by_k.insert(k, motifs);
     }
@@ -72,33 +78,103 @@ pub fn prepare_decoded_counts(
     (out, motifs_by_k)
 }
 
-/// Collect per-window bins for the requested motif type and (optionally)
-/// canonical them into strand-agnostic form.
+/// Motif of every decoded code of a kmer size
+type MotifOfCode = FxHashMap<u64, String>;
+
+/// Prepare per-window kmer code counts for all kmer sizes.
+///
+/// Gives the same result as `prepare_decoded_counts` on the windows decoded
+/// by `split_and_decode_counts`, but motifs are collected on the codes and
+/// every distinct code is decoded once rather than once per window.
+///
+/// * `windows`        – per-window code counts (with `canonical`, collapsed
+///   at the code level, see `canonical_kmer_counts`)
+/// * `canonical`      – list canonical motifs when true
+/// * `kmer_specs`     – validated specs for every k we want to keep
+pub fn prepare_code_counts(
+    windows: &[FxHashMap<Kmer, BigCount>],
+    canonical: bool,
+    kmer_specs: &BTreeMap<u8, KmerSpec>,
+) -> (Vec<DecodedCounts>, BTreeMap<u8, Vec<String>>) {
+    // Distinct codes by k
+    let mut codes_by_k: BTreeMap<u8, FxHashSet<u64>> = kmer_specs
+        .keys()
+        .map(|&k| (k, FxHashSet::default()))
+        .collect();
+    for counts in windows {
+        for kmer in counts.keys() {
+            codes_by_k.entry(kmer.k).or_default().insert(kmer.code);
+        }
+    }
+
+    // Decode every distinct code once, dropping motifs with N
+    let (motif_of, motifs_by_k): (BTreeMap<u8, MotifOfCode>, BTreeMap<u8, Vec<String>>) =
+        codes_by_k
+            .into_iter()
+            .map(|(k, codes)| {
+                let (decoded, motifs) = code_motifs(k, codes, canonical, kmer_specs);
+                ((k, decoded), (k, motifs))
+            })
+            .unzip();
+
+    let mut out = vec![
+        DecodedCounts {
+            counts: kmer_specs
+                .keys()
+                .map(|&k| (k, FxHashMap::default()))
+                .collect()
+        };
+        windows.len()
+    ];
+    for (window, counts) in out.iter_mut().zip(windows) {
+        for (kmer, &cnt) in counts {
+            if let Some(motif) = motif_of[&kmer.k].get(&kmer.code) {
+                let bin = window.counts.get_mut(&kmer.k).unwrap();
+                bin.insert(motif.clone(), cnt);
+            }
+        }
+    }
+
+    (out, motifs_by_k)
+}
+
+/// Decode the distinct `codes` of kmer size `k` once, dropping motifs with N.
+///
+/// Returns the motif of every kept code and the sorted motif columns. For
+/// kmers of size 1..6, the columns include all possible motifs.
+pub fn code_motifs(
+    k: u8,
+    codes: FxHashSet<u64>,
+    canonical: bool,
+    kmer_specs: &BTreeMap<u8, KmerSpec>,
+) -> (MotifOfCode, Vec<String>) {
+    let spec = &kmer_specs[&k];
+    let decoded: MotifOfCode = codes
+        .into_iter()
+        .map(|code| (code, spec.decode_kmer(code)))
+        .filter(|(_, motif)| !motif.contains('N'))
+        .collect();
+    let set: HashSet<String> = if k <= 6 {
+        all_motifs(k as usize, kmer_specs).into_iter().collect()
+    } else {
+        decoded.values().cloned().collect()
+    };
+    (decoded, sorted_motifs(set, canonical))
+}
+
+/// Collect per-window bins for the requested motif type.
 ///
 /// * `windows` – slice of `DecodedCounts` (“one window” each).
 /// * `k` – kmer-size to pull out of every `DecodedCounts`.
-/// * `canonical` – if `true`, run the appropriate collapse_*_map helper.
 ///
 /// Returns a fresh `Vec<FxHashMap<String, BigCount>>` – one map per window.
 fn extract_bins(
     windows: &[DecodedCounts],
     k: usize, // pattern only; field values are ignored
-    canonical: bool,
 ) -> Vec<FxHashMap<String, BigCount>> {
     windows
         .iter()
-        .map(|dc| {
-            // 1. Pick the raw map for this window
-            let raw: FxHashMap<String, BigCount> =
-                dc.counts.get(&(k as u8)).cloned().unwrap_or_default();
-
-            // 2. Collapse if requested, otherwise return the raw map
-            if canonical {
-                collapse_map(&raw)
-            } else {
-                raw
-            }
-        })
+        .map(|dc| dc.counts.get(&(k as u8)).cloned().unwrap_or_default())
         .collect()
 }
 
@@ -116,15 +192,202 @@ fn collect_motifs(
         windows.iter().flat_map(|m| m.keys().cloned()).collect()
     };
 
-    // Strand-collapse if requested
-    let collapsed_set = if canonical { collapse_set(&set) } else { set };
+    sorted_motifs(set, canonical)
+}
 
-    // Convert to sorted Vec
+/// Sorted motifs of `set`, strand-collapsed when `canonical`
+fn sorted_motifs(set: HashSet<String>, canonical: bool) -> Vec<String> {
+    let collapsed_set = if canonical { collapse_set(&set) } else { set };
     let mut v: Vec<String> = collapsed_set.into_iter().collect();
     v.sort_unstable();
     v
 }
 
+/// Rarefy (subsample without replacement) every window to `depth` counts per k.
+///
+/// Motif counts are drawn from a multivariate hypergeometric distribution
+/// by sequential hypergeometric draws over the sorted motifs.
+/// Each (window, k) pair gets its own RNG derived from `seed`, so the result
+/// does not depend on the number of threads.
+///
+/// Windows with fewer than `depth` counts are left unchanged.
+///
+/// Returns the number of (window, k) pairs below `depth`.
+pub fn rarefy_counts(windows: &mut [DecodedCounts], depth: BigCount, seed: u64) -> usize {
+    windows
+        .par_iter_mut()
+        .enumerate()
+        .map(|(row, dc)| {
+            let mut n_shallow = 0;
+            for (&k, map) in dc.counts.iter_mut() {
+                let total: BigCount = map.values().sum();
+                if total < depth {
+                    n_shallow += 1;
+                    continue;
+                }
+                let mut rng = StdRng::seed_from_u64(
+                    seed ^ (row as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15) ^ ((k as u64) << 56),
+                );
+                let mut motifs: Vec<&String> = map.keys().collect();
+                motifs.sort_unstable();
+                let mut sampled: FxHashMap<String, BigCount> = FxHashMap::default();
+                let mut pop_left = total;
+                let mut draws_left = depth;
+                for motif in motifs {
+                    if draws_left == 0 {
+                        break;
+                    }
+                    let cnt = map[motif];
+                    let x = if cnt == pop_left {
+                        draws_left
+                    } else {
+                        sample_hypergeometric(pop_left, cnt, draws_left, &mut rng)
+                    };
+                    if x > 0 {
+                        sampled.insert(motif.clone(), x);
+                    }
+                    pop_left -= cnt;
+                    draws_left -= x;
+                }
+                *map = sampled;
+            }
+            n_shallow
+        })
+        .sum()
+}
+
+/// Scaling of window counts before writing
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Normalization {
+    /// Raw counts
+    None,
+    /// Count / number of counted kmers (valid positions) in the window
+    Frequency,
+    /// Count per 1000 bases of effective (non-N, non-blacklisted) window length
+    PerKb,
+}
+
+/// Per-window scale factors for every k implementing `normalization`.
+///
+/// * `effective_lengths` – non-N, non-blacklisted bases per window
+///   (only used for `PerKb`).
+///
+/// Windows without valid positions or effective length get a factor of 0.
+/// Returns `None` for `Normalization::None`.
+pub fn row_scales(
+    windows: &[DecodedCounts],
+    effective_lengths: &[u64],
+    normalization: Normalization,
+) -> Option<BTreeMap<u8, Vec<f64>>> {
+    let inverse = |denominator: f64, numerator: f64| {
+        if denominator > 0.0 {
+            numerator / denominator
+        } else {
+            0.0
+        }
+    };
+    let ks: HashSet<u8> = windows
+        .iter()
+        .flat_map(|w| w.counts.keys().copied())
+        .collect();
+    let scales_for_k = |k: u8| -> Vec<f64> {
+        match normalization {
+            Normalization::Frequency => windows
+                .iter()
+                .map(|w| {
+                    let total: BigCount = w.counts.get(&k).map_or(0, |m| m.values().sum());
+                    inverse(total as f64, 1.0)
+                })
+                .collect(),
+            _ => effective_lengths
+                .iter()
+                .map(|&len| inverse(len as f64, 1000.0))
+                .collect(),
+        }
+    };
+    match normalization {
+        Normalization::None => None,
+        _ => Some(ks.into_iter().map(|k| (k, scales_for_k(k))).collect()),
+    }
+}
+
+/// Number of possible motifs of length `k`: 4ᵏ, or the number of canonical
+/// motifs when `canonical` (palindromes only exist for even `k`)
+pub fn n_possible_motifs(k: u8, canonical: bool) -> f64 {
+    let all = 4f64.powi(k as i32);
+    match canonical {
+        true if k.is_multiple_of(2) => (all + 4f64.powi(k as i32 / 2)) / 2.0,
+        true => all / 2.0,
+        false => all,
+    }
+}
+
+/// K‑mer diversity of a window
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Diversity {
+    /// Shannon entropy (in bits) of the motif frequencies
+    pub shannon_entropy: f64,
+    /// Fraction of the possible motifs that are observed
+    pub distinct_fraction: f64,
+    /// Observed motifs relative to the most that could be observed with the
+    /// window's number of k‑mers, i.e. `min(n_possible, total)`
+    pub linguistic_complexity: f64,
+}
+
+/// Diversity of the motif `counts` of a window with `n_possible` possible
+/// motifs. Windows without counts get zeros.
+pub fn kmer_diversity(counts: &FxHashMap<String, BigCount>, n_possible: f64) -> Diversity {
+    let total: BigCount = counts.values().sum();
+    if total == 0 {
+        return Diversity {
+            shannon_entropy: 0.0,
+            distinct_fraction: 0.0,
+            linguistic_complexity: 0.0,
+        };
+    }
+    let observed = counts.values().filter(|&&c| c > 0).count() as f64;
+    let shannon_entropy = -counts
+        .values()
+        .filter(|&&c| c > 0)
+        .map(|&c| {
+            let p = c as f64 / total as f64;
+            p * p.log2()
+        })
+        .sum::<f64>();
+    Diversity {
+        // Avoid -0.0 for single-motif windows
+        shannon_entropy: shannon_entropy.max(0.0),
+        distinct_fraction: observed / n_possible,
+        linguistic_complexity: observed / n_possible.min(total as f64),
+    }
+}
+
+/// Number of successes in `draws` draws without replacement from a population
+/// of `population` items of which `successes` are successes.
+///
+/// `Hypergeometric` rejects some parameters where its initial probability
+/// underflows; those are sampled by drawing one item at a time.
+fn sample_hypergeometric<R: Rng>(
+    population: BigCount,
+    successes: BigCount,
+    draws: BigCount,
+    rng: &mut R,
+) -> BigCount {
+    if let Ok(dist) = Hypergeometric::new(population, successes, draws) {
+        return dist.sample(rng);
+    }
+    let (mut pop_left, mut succ_left) = (population, successes);
+    let mut x = 0;
+    for _ in 0..draws {
+        if rng.gen_range(0..pop_left) < succ_left {
+            succ_left -= 1;
+            x += 1;
+        }
+        pop_left -= 1;
+    }
+    x
+}
+
 /// Use the first window’s keys, sort them, and return the order.
 /// Panics only if `bins` is empty.
 pub fn motif_order(bins: &[FxHashMap<String, impl Copy>]) -> Vec<String> {
@@ -137,13 +400,27 @@ pub fn motif_order(bins: &[FxHashMap<String, impl Copy>]) -> Vec<String> {
     motifs
 }
 
+/// Sorted (canonical) motifs of every k, i.e. the full column set of the
+/// count matrices regardless of which motifs are observed.
+pub fn motif_universe(
+    kmer_specs: &BTreeMap<u8, KmerSpec>,
+    canonical: bool,
+) -> BTreeMap<u8, Vec<String>> {
+    kmer_specs
+        .keys()
+        .map(|&k| {
+            let all = all_motifs(k as usize, kmer_specs);
+            (k, collect_motifs(&[], all, canonical, true))
+        })
+        .collect()
+}
+
 /// Return all possible reference motifs (4ᵏ) for a given k.
 ///
 /// No motifs with 'N' are returned.
-pub fn all_motifs(k: usize, specs: &HashMap<u8, KmerSpec>) -> Vec<String> {
+pub fn all_motifs(k: usize, specs: &BTreeMap<u8, KmerSpec>) -> Vec<String> {
     let spec = &specs[&(k as u8)];
-    let max_code = 5u64.pow(k as u32) - 1; // no-N space
-    (0..=max_code)
+    (0..spec.n_codes())
         .map(|c| spec.decode_kmer(c))
         .filter(|m| !m.contains('N'))
         .collect()
@@ -169,16 +446,6 @@ pub fn revcomp(seq: &str) -> String {
     seq.chars().rev().map(comp).collect()
 }
 
-/// Collapse a map of reference k-mer counts into canonical keys, summing counts
-pub fn collapse_map(map: &FxHashMap<String, u64>) -> FxHashMap<String, u64> {
-    let mut out: FxHashMap<String, u64> = FxHashMap::default();
-    for (kmer, &count) in map {
-        let canon = canonical(kmer.to_owned());
-        *out.entry(canon).or_default() += count;
-    }
-    out
-}
-
 /// Collapse a set of motifs into canonical form
 pub fn collapse_set(set: &HashSet<String>) -> HashSet<String> {
     set.iter().map(|kmer| canonical(kmer.to_owned())).collect()
@@ -0,0 +1,164 @@
+use anyhow::{Context, Result};
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// Stages of counting a reference, timed with `--profile`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Stage {
+    /// Reading a chromosome sequence
+    Load,
+    /// Building positional kmer codes
+    Encode,
+    /// Counting kmers per window (including encoding when counts are
+    /// streamed from the rolling codes)
+    Count,
+    /// Decoding and post-processing counts for the outputs
+    Prepare,
+    /// Writing output files
+    Write,
+}
+
+impl Stage {
+    pub const ALL: [Stage; 5] = [
+        Stage::Load,
+        Stage::Encode,
+        Stage::Count,
+        Stage::Prepare,
+        Stage::Write,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Stage::Load => "load",
+            Stage::Encode => "encode",
+            Stage::Count => "count",
+            Stage::Prepare => "prepare",
+            Stage::Write => "write",
+        }
+    }
+}
+
+/// Summed wall time of a stage and the peak resident memory of the process
+/// at the end of any of its runs
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct StageTime {
+    pub secs: f64,
+    /// `None` where the peak RSS cannot be read (non-Linux systems)
+    pub peak_rss: Option<u64>,
+}
+
+impl StageTime {
+    fn add(&mut self, other: StageTime) {
+        self.secs += other.secs;
+        self.peak_rss = self.peak_rss.max(other.peak_rss);
+    }
+}
+
+/// Wall times per stage and chromosome. Does nothing unless enabled.
+///
+/// Chromosomes are counted concurrently, so the peak RSS includes the
+/// memory of other chromosomes in progress.
+#[derive(Debug, Default)]
+pub struct Profiler {
+    enabled: bool,
+    /// By chromosome (`None` for stages of the whole run) and stage
+    times: Mutex<HashMap<(Option<String>, Stage), StageTime>>,
+}
+
+impl Profiler {
+    pub fn new(enabled: bool) -> Self {
+        Profiler {
+            enabled,
+            times: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Run `f` and add its wall time to `stage` of `chrom`
+    pub fn time<T>(&self, chrom: Option<&str>, stage: Stage, f: impl FnOnce() -> T) -> T {
+        if !self.enabled {
+            return f();
+        }
+        let start = Instant::now();
+        let out = f();
+        self.add(chrom, stage, start.elapsed());
+        out
+    }
+
+    /// Add `elapsed` to `stage` of `chrom`
+    pub fn add(&self, chrom: Option<&str>, stage: Stage, elapsed: Duration) {
+        if !self.enabled {
+            return;
+        }
+        let time = StageTime {
+            secs: elapsed.as_secs_f64(),
+            peak_rss: peak_rss_bytes(),
+        };
+        self.times
+            .lock()
+            .unwrap()
+            .entry((chrom.map(str::to_string), stage))
+            .or_default()
+            .add(time);
+    }
+
+    /// Times of every stage, summed over chromosomes
+    pub fn totals(&self) -> Vec<(Stage, StageTime)> {
+        let times = self.times.lock().unwrap();
+        Stage::ALL
+            .into_iter()
+            .filter_map(|stage| {
+                let mut total: Option<StageTime> = None;
+                for ((_, s), time) in times.iter() {
+                    if *s == stage {
+                        total.get_or_insert_with(StageTime::default).add(*time);
+                    }
+                }
+                Some((stage, total?))
+            })
+            .collect()
+    }
+
+    /// Write `chrom, stage, wall_secs, peak_rss_bytes` rows of the
+    /// `chromosomes` (in order), followed by the stages of the whole run
+    /// (chrom `.`) and the totals of every stage (chrom `total`)
+    pub fn write_tsv(&self, path: &Path, chromosomes: &[String]) -> Result<()> {
+        let mut w = BufWriter::new(File::create(path).context("Create profile fail")?);
+        writeln!(w, "chrom\tstage\twall_secs\tpeak_rss_bytes")?;
+        let mut write_row = |chrom: &str, stage: Stage, time: StageTime| -> Result<()> {
+            let rss = time.peak_rss.map_or(String::new(), |b| b.to_string());
+            writeln!(w, "{}\t{}\t{:.3}\t{}", chrom, stage.name(), time.secs, rss)?;
+            Ok(())
+        };
+        {
+            let times = self.times.lock().unwrap();
+            let chroms = chromosomes.iter().map(Some).chain([None]);
+            for chrom in chroms {
+                for stage in Stage::ALL {
+                    if let Some(time) = times.get(&(chrom.cloned(), stage)) {
+                        write_row(chrom.map_or(".", String::as_str), stage, *time)?;
+                    }
+                }
+            }
+        }
+        for (stage, time) in self.totals() {
+            write_row("total", stage, time)?;
+        }
+        w.flush().context("Write profile fail")?;
+        Ok(())
+    }
+}
+
+/// Peak resident set size of this process in bytes (`VmHWM` of
+/// `/proc/self/status`), if available
+pub fn peak_rss_bytes() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|line| line.starts_with("VmHWM:"))?;
+    let kb: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kb * 1024)
+}
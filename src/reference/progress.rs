@@ -0,0 +1,151 @@
+use anyhow::{Context, Result};
+use serde_json::{json, Value};
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+    sync::Mutex,
+    time::Instant,
+};
+
+/// Progress events as JSON lines for `--progress-json`, for workflow
+/// tools that cannot parse the progress bar. Does nothing unless enabled.
+///
+/// Every event is one JSON object with the `event` (`stage`,
+/// `chromosome` or `done`), the current `stage`, the chromosomes done
+/// and in total, the `fraction` of reference bases counted, the
+/// `elapsed_secs` of the run and the `eta_secs` of counting (`null` until
+/// a chromosome is done). `chromosome` events also name the chromosome.
+///
+/// Failing writes (e.g. a closed pipe) are ignored so they cannot fail
+/// the run.
+pub struct ProgressLog {
+    sink: Option<Mutex<Box<dyn Write + Send>>>,
+    start: Instant,
+    state: Mutex<ProgressState>,
+}
+
+#[derive(Default)]
+struct ProgressState {
+    stage: String,
+    chroms_done: u64,
+    chroms_total: u64,
+    bases_done: u64,
+    bases_total: u64,
+    counting_start: Option<Instant>,
+}
+
+impl ProgressLog {
+    /// A log that writes nothing
+    pub fn disabled() -> Self {
+        Self::with_sink(None)
+    }
+
+    /// Write events to stderr for `-`, to the file at `path` for other
+    /// paths and nowhere for `None`
+    pub fn open(path: Option<&Path>) -> Result<Self> {
+        let sink: Option<Box<dyn Write + Send>> = match path {
+            None => None,
+            Some(path) if path == Path::new("-") => Some(Box::new(std::io::stderr())),
+            Some(path) => {
+                let f =
+                    File::create(path).context(format!("Create progress file {:?} fail", path))?;
+                Some(Box::new(BufWriter::new(f)))
+            }
+        };
+        Ok(Self::with_sink(sink))
+    }
+
+    /// Write events to `sink`
+    pub fn with_sink(sink: Option<Box<dyn Write + Send>>) -> Self {
+        ProgressLog {
+            sink: sink.map(Mutex::new),
+            start: Instant::now(),
+            state: Mutex::new(ProgressState::default()),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.sink.is_some()
+    }
+
+    /// Start a new stage, e.g. `load` or `write`
+    pub fn stage(&self, stage: &str) {
+        if !self.is_enabled() {
+            return;
+        }
+        let mut state = self.state.lock().unwrap();
+        state.stage = stage.to_string();
+        self.emit("stage", &state, None);
+    }
+
+    /// Start the `count` stage of `n_chroms` chromosomes with
+    /// `total_bases` bases
+    pub fn start_counting(&self, n_chroms: u64, total_bases: u64) {
+        if !self.is_enabled() {
+            return;
+        }
+        let mut state = self.state.lock().unwrap();
+        *state = ProgressState {
+            stage: "count".to_string(),
+            chroms_total: n_chroms,
+            bases_total: total_bases,
+            counting_start: Some(Instant::now()),
+            ..ProgressState::default()
+        };
+        self.emit("stage", &state, None);
+    }
+
+    /// A chromosome of `length` bases is counted
+    pub fn chromosome_done(&self, chrom: &str, length: u64) {
+        if !self.is_enabled() {
+            return;
+        }
+        let mut state = self.state.lock().unwrap();
+        state.chroms_done += 1;
+        state.bases_done += length;
+        self.emit("chromosome", &state, Some(chrom));
+    }
+
+    /// The run is done and its outputs are in place
+    pub fn finish(&self) {
+        if !self.is_enabled() {
+            return;
+        }
+        let mut state = self.state.lock().unwrap();
+        state.stage = "done".to_string();
+        self.emit("done", &state, None);
+    }
+
+    fn emit(&self, event: &str, state: &ProgressState, chrom: Option<&str>) {
+        let Some(sink) = &self.sink else {
+            return;
+        };
+        let fraction = match state.bases_total {
+            0 => 0.0,
+            total => state.bases_done as f64 / total as f64,
+        };
+        let eta_secs = match state.counting_start {
+            Some(start) if fraction > 0.0 => {
+                let secs = start.elapsed().as_secs_f64();
+                Value::from(secs * (1.0 - fraction) / fraction)
+            }
+            _ => Value::Null,
+        };
+        let mut line = json!({
+            "event": event,
+            "stage": state.stage,
+            "chromosomes_done": state.chroms_done,
+            "chromosomes_total": state.chroms_total,
+            "fraction": fraction,
+            "elapsed_secs": self.start.elapsed().as_secs_f64(),
+            "eta_secs": eta_secs,
+        });
+        if let Some(chrom) = chrom {
+            line["chromosome"] = Value::from(chrom);
+        }
+        let mut sink = sink.lock().unwrap();
+        let _ = writeln!(sink, "{}", line);
+        let _ = sink.flush();
+    }
+}
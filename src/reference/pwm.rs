@@ -0,0 +1,218 @@
+use anyhow::{bail, Context, Result};
+use std::path::Path;
+
+/// Probability added to every base before computing log-odds scores, so
+/// bases never seen in a motif do not score `-inf`
+const PSEUDO_PROBABILITY: f64 = 0.01;
+
+/// A position weight matrix with log2-odds scores against a uniform
+/// background
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pwm {
+    pub name: String,
+    /// Scores of A, C, G and T at each motif position
+    pub log_odds: Vec<[f64; 4]>,
+}
+
+impl Pwm {
+    /// PWM from the base frequencies (or counts) of A, C, G and T at each
+    /// motif position
+    pub fn from_frequencies(name: String, rows: &[[f64; 4]]) -> Result<Self> {
+        if rows.is_empty() {
+            bail!("Motif {} has no positions", name);
+        }
+        let mut log_odds = Vec::with_capacity(rows.len());
+        for row in rows {
+            let total: f64 = row.iter().sum();
+            if total <= 0.0 || row.iter().any(|&v| v < 0.0) {
+                bail!("Motif {} has a position without base frequencies", name);
+            }
+            log_odds.push(row.map(|v| {
+                let p = (v / total + PSEUDO_PROBABILITY) / (1.0 + 4.0 * PSEUDO_PROBABILITY);
+                (p / 0.25).log2()
+            }));
+        }
+        Ok(Pwm { name, log_odds })
+    }
+
+    /// Motif length
+    pub fn len(&self) -> usize {
+        self.log_odds.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.log_odds.is_empty()
+    }
+
+    /// Score at the relative `threshold` between the lowest (0) and highest
+    /// (1) possible score
+    pub fn score_threshold(&self, threshold: f64) -> f64 {
+        let (min, max) = self.log_odds.iter().fold((0.0, 0.0), |(min, max), row| {
+            let (lo, hi) = row
+                .iter()
+                .fold((f64::MAX, f64::MIN), |(lo, hi), &s| (lo.min(s), hi.max(s)));
+            (min + lo, max + hi)
+        });
+        min + threshold * (max - min)
+    }
+
+    /// Best score of `site` (of the motif length) on either strand, or
+    /// `None` when it has other bases than A/C/G/T
+    pub fn site_score(&self, site: &[u8]) -> Option<f64> {
+        let mut forward = 0.0;
+        let mut reverse = 0.0;
+        for (i, &b) in site.iter().enumerate() {
+            let base = base_index(b)?;
+            forward += self.log_odds[i][base];
+            // The complement of base `b` is `3 - b`
+            reverse += self.log_odds[site.len() - 1 - i][3 - base];
+        }
+        Some(f64::max(forward, reverse))
+    }
+}
+
+#[inline]
+fn base_index(b: u8) -> Option<usize> {
+    match b.to_ascii_uppercase() {
+        b'A' => Some(0),
+        b'C' => Some(1),
+        b'G' => Some(2),
+        b'T' => Some(3),
+        _ => None,
+    }
+}
+
+/// Number of sites in `seq` scoring at least `min_score` on either strand
+/// and the sum of their scores. Sites must lie fully within `seq`.
+pub fn scan_pwm(pwm: &Pwm, seq: &[u8], min_score: f64) -> (u64, f64) {
+    if seq.len() < pwm.len() {
+        return (0, 0.0);
+    }
+    seq.windows(pwm.len())
+        .filter_map(|site| pwm.site_score(site))
+        .filter(|&score| score >= min_score)
+        .fold((0, 0.0), |(n, sum), score| (n + 1, sum + score))
+}
+
+/// PWM hits of a window: the number of sites and the sum of their scores
+/// for each PWM
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PwmHits {
+    pub hits: Vec<u64>,
+    pub scores: Vec<f64>,
+}
+
+impl PwmHits {
+    /// Hits of `pwms` in the sequence `ranges` (e.g. the blocks of a BED12
+    /// window) with per-PWM score thresholds `min_scores`
+    pub fn of(pwms: &[Pwm], min_scores: &[f64], ranges: &[&[u8]]) -> Self {
+        let mut out = PwmHits {
+            hits: vec![0; pwms.len()],
+            scores: vec![0.0; pwms.len()],
+        };
+        for (i, (pwm, &min_score)) in pwms.iter().zip(min_scores).enumerate() {
+            for seq in ranges {
+                let (n, sum) = scan_pwm(pwm, seq, min_score);
+                out.hits[i] += n;
+                out.scores[i] += sum;
+            }
+        }
+        out
+    }
+}
+
+/// Load PWMs from a MEME motif file or a JASPAR (`.jaspar`/`.pfm`) file
+pub fn load_pwms(path: &Path) -> Result<Vec<Pwm>> {
+    let text = std::fs::read_to_string(path).context(format!("Reading PWMs from {:?}", path))?;
+    let pwms = parse_pwms(&text).context(format!("Parsing PWMs from {:?}", path))?;
+    if pwms.is_empty() {
+        bail!("No motifs found in {:?}", path);
+    }
+    Ok(pwms)
+}
+
+/// Parse PWMs in MEME or JASPAR format (detected from the content)
+pub fn parse_pwms(text: &str) -> Result<Vec<Pwm>> {
+    if text.contains("letter-probability matrix") {
+        parse_meme(text)
+    } else {
+        parse_jaspar(text)
+    }
+}
+
+/// Motif name from its ID and optional alternative name
+fn motif_name(fields: &[&str]) -> String {
+    fields.join("_")
+}
+
+/// Parse whitespace-separated numbers, ignoring JASPAR brackets and a
+/// leading base letter
+fn parse_numbers(line: &str) -> Result<Vec<f64>> {
+    line.split(|c: char| c.is_whitespace() || c == '[' || c == ']')
+        .filter(|v| !v.is_empty())
+        .filter(|v| !matches!(*v, "A" | "C" | "G" | "T"))
+        .map(|v| v.parse().context(format!("Parsing motif value {:?}", v)))
+        .collect()
+}
+
+/// MEME format: `MOTIF id [alt]` lines followed by a `letter-probability
+/// matrix` of one row of A/C/G/T probabilities per position
+fn parse_meme(text: &str) -> Result<Vec<Pwm>> {
+    let mut pwms = Vec::new();
+    let mut name: Option<String> = None;
+    let mut lines = text.lines();
+    while let Some(line) = lines.next() {
+        let line = line.trim();
+        if let Some(rest) = line.strip_prefix("MOTIF") {
+            let fields: Vec<&str> = rest.split_whitespace().collect();
+            name = Some(motif_name(&fields));
+        } else if line.starts_with("letter-probability matrix") {
+            let name = name
+                .take()
+                .context("letter-probability matrix without MOTIF")?;
+            let mut rows = Vec::new();
+            for row in lines.by_ref().map(str::trim) {
+                if row.is_empty() || row.starts_with("URL") {
+                    break;
+                }
+                let values = parse_numbers(row)?;
+                if values.len() != 4 {
+                    bail!("Motif {} has a row without 4 probabilities", name);
+                }
+                rows.push([values[0], values[1], values[2], values[3]]);
+            }
+            pwms.push(Pwm::from_frequencies(name, &rows)?);
+        }
+    }
+    Ok(pwms)
+}
+
+/// JASPAR format: `>id [name]` headers followed by one row of counts per
+/// base (A, C, G, T), optionally as `A [ 1 2 3 ]`
+fn parse_jaspar(text: &str) -> Result<Vec<Pwm>> {
+    let mut pwms = Vec::new();
+    let mut lines = text.lines().map(str::trim).filter(|l| !l.is_empty());
+    while let Some(line) = lines.next() {
+        let Some(header) = line.strip_prefix('>') else {
+            bail!("Expected a '>' motif header, found {:?}", line);
+        };
+        let fields: Vec<&str> = header.split_whitespace().collect();
+        let name = motif_name(&fields);
+        let mut by_base = Vec::with_capacity(4);
+        for _ in 0..4 {
+            let row = lines
+                .next()
+                .context(format!("Motif {} has fewer than 4 rows", name))?;
+            by_base.push(parse_numbers(row)?);
+        }
+        let width = by_base[0].len();
+        if by_base.iter().any(|row| row.len() != width) {
+            bail!("Motif {} has rows of different lengths", name);
+        }
+        let rows: Vec<[f64; 4]> = (0..width)
+            .map(|i| [by_base[0][i], by_base[1][i], by_base[2][i], by_base[3][i]])
+            .collect();
+        pwms.push(Pwm::from_frequencies(name, &rows)?);
+    }
+    Ok(pwms)
+}
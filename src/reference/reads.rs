@@ -0,0 +1,222 @@
+use crate::cli::bam::{BamRecord, FLAG_PAIRED, FLAG_READ1, FLAG_UNMAPPED};
+use crate::cli::BigCount;
+use crate::reference::counting::{count_kmers, KmerSource, WindowLayout};
+use crate::reference::kmer_codec::{Kmer, KmerSpec};
+use anyhow::{bail, Result};
+use fxhash::FxHashMap;
+use std::collections::{BTreeMap, HashMap};
+
+/// Bases of reads collected before their k-mers are counted
+const READ_BATCH_BASES: usize = 1 << 22;
+
+/// Which reads the read tools keep
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ReadFilter {
+    /// Lowest mapping quality (BAM only)
+    pub min_mapq: u8,
+    /// Flags that must all be set (BAM only)
+    pub require_flags: u16,
+    /// Flags that must all be unset (BAM only)
+    pub exclude_flags: u16,
+    /// Shortest read sequence
+    pub min_length: usize,
+}
+
+impl ReadFilter {
+    pub fn keeps(&self, record: &BamRecord) -> bool {
+        record.mapq >= self.min_mapq
+            && record.flag & self.require_flags == self.require_flags
+            && record.flag & self.exclude_flags == 0
+            && self.keeps_length(record.seq.len())
+    }
+
+    pub fn keeps_length(&self, len: usize) -> bool {
+        len >= self.min_length
+    }
+}
+
+/// Counts k-mers of many short reads by joining them, separated by `N`,
+/// into batches counted from their sequence with `count_kmers`. K-mers never span
+/// two reads. All specs must be radix-5 or 2-bit encoded.
+pub struct ReadKmerCounter<'a> {
+    kmer_specs: &'a BTreeMap<u8, KmerSpec>,
+    /// Only count the k-mers starting at multiples of this batch position,
+    /// i.e. at the first base of fixed-size reads
+    read_stride: Option<usize>,
+    batch: Vec<u8>,
+    counts: FxHashMap<Kmer, BigCount>,
+}
+
+impl<'a> ReadKmerCounter<'a> {
+    /// Count every k-mer of the reads
+    pub fn all_kmers(kmer_specs: &'a BTreeMap<u8, KmerSpec>) -> Self {
+        Self::new(kmer_specs, None)
+    }
+
+    /// Count only the first k-mer of every read (see `add_read_start`)
+    pub fn read_starts(kmer_specs: &'a BTreeMap<u8, KmerSpec>) -> Self {
+        let max_k = kmer_specs.keys().max().copied().unwrap_or(1) as usize;
+        Self::new(kmer_specs, Some(max_k + 1))
+    }
+
+    fn new(kmer_specs: &'a BTreeMap<u8, KmerSpec>, read_stride: Option<usize>) -> Self {
+        ReadKmerCounter {
+            kmer_specs,
+            read_stride,
+            batch: Vec::with_capacity(READ_BATCH_BASES + 1024),
+            counts: FxHashMap::default(),
+        }
+    }
+
+    /// Add the k-mers of `seq`
+    pub fn add_read(&mut self, seq: &[u8]) {
+        self.batch.extend_from_slice(seq);
+        self.batch.push(b'N');
+        self.flush_if_full();
+    }
+
+    /// Add the first k-mer of `seq` for every k (a `read_starts` counter).
+    /// Reads shorter than k add nothing for that k.
+    pub fn add_read_start(&mut self, seq: &[u8]) {
+        let width = self.read_stride.expect("a read_starts counter") - 1;
+        let n = seq.len().min(width);
+        self.batch.extend_from_slice(&seq[..n]);
+        self.batch.resize(self.batch.len() + width + 1 - n, b'N');
+        self.flush_if_full();
+    }
+
+    fn flush_if_full(&mut self) {
+        if self.batch.len() >= READ_BATCH_BASES {
+            self.flush();
+        }
+    }
+
+    fn flush(&mut self) {
+        if self.batch.is_empty() {
+            return;
+        }
+        let len = self.batch.len() as u64;
+        let stride = self.read_stride;
+        let specs: Vec<&KmerSpec> = self.kmer_specs.values().collect();
+        let keep = |_, pos: usize, _| stride.is_none_or(|stride| pos.is_multiple_of(stride));
+        let source = KmerSource::Sequence {
+            seq: &self.batch,
+            specs: &specs,
+            keep: &keep,
+        };
+        let mut batch_counts = vec![FxHashMap::default()];
+        count_kmers(
+            &mut batch_counts,
+            &WindowLayout::new(&[(0, len, 0)], len),
+            source,
+        );
+        for (kmer, count) in batch_counts.into_iter().flatten() {
+            *self.counts.entry(kmer).or_insert(0) += count;
+        }
+        self.batch.clear();
+    }
+
+    /// The summed counts of all added reads
+    pub fn finish(mut self) -> FxHashMap<Kmer, BigCount> {
+        self.flush();
+        self.counts
+    }
+}
+
+/// The 5' end of the fragment strand of a read: the read itself on the
+/// forward strand and the reverse complement of its last `width` bases on
+/// the reverse strand. The CIGAR is not decoded, so soft-clipped bases are
+/// included.
+pub fn fragment_end(record: &BamRecord, width: usize) -> Vec<u8> {
+    if record.is_reverse() {
+        let start = record.seq.len().saturating_sub(width);
+        record.seq[start..]
+            .iter()
+            .rev()
+            .map(|&b| complement(b))
+            .collect()
+    } else {
+        record.seq[..record.seq.len().min(width)].to_vec()
+    }
+}
+
+fn complement(base: u8) -> u8 {
+    match base.to_ascii_uppercase() {
+        b'A' => b'T',
+        b'C' => b'G',
+        b'G' => b'C',
+        b'T' => b'A',
+        _ => b'N',
+    }
+}
+
+/// Histogram of fragment lengths (`|tlen|`) of the kept mapped read pairs,
+/// counting each pair once (by its first read). Fragments longer than
+/// `max_length` are counted in the second value.
+pub fn fragment_lengths(
+    records: impl Iterator<Item = Result<BamRecord>>,
+    filter: &ReadFilter,
+    max_length: u64,
+) -> Result<(BTreeMap<u64, u64>, u64)> {
+    let mut lengths = BTreeMap::new();
+    let mut n_longer = 0;
+    for record in records {
+        let record = record?;
+        let pair_flags = FLAG_PAIRED | FLAG_READ1;
+        if record.flag & pair_flags != pair_flags
+            || record.flag & FLAG_UNMAPPED != 0
+            || record.tlen == 0
+            || !filter.keeps(&record)
+        {
+            continue;
+        }
+        let length = record.tlen.unsigned_abs();
+        if length > max_length {
+            n_longer += 1;
+        } else {
+            *lengths.entry(length).or_insert(0) += 1;
+        }
+    }
+    Ok((lengths, n_longer))
+}
+
+/// Histogram of the number of reads per duplicate family, i.e. the depth
+/// a consensus read of the family would have.
+///
+/// Reads are in the same family when they share the reference, start,
+/// strand and template length; pairs are counted by their first read.
+/// Records must be coordinate-sorted.
+pub fn family_sizes(
+    records: impl Iterator<Item = Result<BamRecord>>,
+    filter: &ReadFilter,
+) -> Result<BTreeMap<u64, u64>> {
+    let mut sizes = BTreeMap::new();
+    let mut families: HashMap<(i64, bool), u64> = HashMap::new();
+    let mut current = (-1, -1);
+    for record in records {
+        let record = record?;
+        if record.flag & FLAG_UNMAPPED != 0
+            || (record.flag & FLAG_PAIRED != 0 && record.flag & FLAG_READ1 == 0)
+            || !filter.keeps(&record)
+        {
+            continue;
+        }
+        let position = (record.ref_id, record.pos);
+        if position != current {
+            if position < current {
+                bail!("Family sizes require a coordinate-sorted BAM file");
+            }
+            for (_, n) in families.drain() {
+                *sizes.entry(n).or_insert(0) += 1;
+            }
+            current = position;
+        }
+        *families
+            .entry((record.tlen, record.is_reverse()))
+            .or_insert(0) += 1;
+    }
+    for (_, n) in families {
+        *sizes.entry(n).or_insert(0) += 1;
+    }
+    Ok(sizes)
+}
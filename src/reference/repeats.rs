@@ -0,0 +1,113 @@
+/// Homopolymer runs of at least this length share the last bin
+pub const MAX_HOMOPOLYMER_BIN: usize = 20;
+
+/// Longest repeat unit of short tandem repeats
+pub const MAX_STR_UNIT: usize = 6;
+
+/// Shortest short tandem repeat tract (and at least 3 units)
+pub const MIN_STR_LENGTH: usize = 12;
+
+/// Homopolymer and short tandem repeat (STR) content of a window
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RepeatContent {
+    /// Number of homopolymer runs (of A, C, G or T) of length 1, 2, …,
+    /// with runs of `MAX_HOMOPOLYMER_BIN` or more bases in the last bin
+    pub homopolymer_runs: [u64; MAX_HOMOPOLYMER_BIN],
+    /// Bases in perfect tandem repeats by unit length 1 to `MAX_STR_UNIT`
+    pub str_bases: [u64; MAX_STR_UNIT],
+}
+
+impl RepeatContent {
+    /// Column names of `homopolymer_runs`, e.g. `["1", …, "20+"]`
+    pub fn homopolymer_columns() -> Vec<String> {
+        (1..=MAX_HOMOPOLYMER_BIN)
+            .map(|len| match len {
+                MAX_HOMOPOLYMER_BIN => format!("{len}+"),
+                _ => len.to_string(),
+            })
+            .collect()
+    }
+
+    /// Column names of `str_bases`, e.g. `["unit1", …, "unit6"]`
+    pub fn str_columns() -> Vec<String> {
+        (1..=MAX_STR_UNIT).map(|u| format!("unit{u}")).collect()
+    }
+
+    /// Repeat content of `seq`. Repeats are clipped to the sequence, so
+    /// runs crossing a window boundary count in both windows.
+    pub fn of(seq: &[u8]) -> Self {
+        let mut content = RepeatContent::default();
+        content.add_homopolymer_runs(seq);
+        for unit in 1..=MAX_STR_UNIT {
+            content.str_bases[unit - 1] = str_bases(seq, unit);
+        }
+        content
+    }
+
+    fn add_homopolymer_runs(&mut self, seq: &[u8]) {
+        let mut i = 0;
+        while i < seq.len() {
+            let base = seq[i].to_ascii_uppercase();
+            let start = i;
+            i += 1;
+            if !is_acgt(base) {
+                continue;
+            }
+            while i < seq.len() && seq[i].to_ascii_uppercase() == base {
+                i += 1;
+            }
+            self.homopolymer_runs[(i - start).min(MAX_HOMOPOLYMER_BIN) - 1] += 1;
+        }
+    }
+}
+
+impl std::ops::AddAssign for RepeatContent {
+    fn add_assign(&mut self, other: Self) {
+        for (a, b) in self.homopolymer_runs.iter_mut().zip(other.homopolymer_runs) {
+            *a += b;
+        }
+        for (a, b) in self.str_bases.iter_mut().zip(other.str_bases) {
+            *a += b;
+        }
+    }
+}
+
+#[inline]
+fn is_acgt(b: u8) -> bool {
+    matches!(b, b'A' | b'C' | b'G' | b'T')
+}
+
+/// Bases in perfect tandem repeats of a `unit`-base unit with at least 3
+/// units and `MIN_STR_LENGTH` bases. Units that are themselves repeats of a
+/// shorter unit (e.g. `AA`) are left to the shorter unit.
+fn str_bases(seq: &[u8], unit: usize) -> u64 {
+    let same = |i: usize| {
+        let b = seq[i].to_ascii_uppercase();
+        is_acgt(b) && b == seq[i - unit].to_ascii_uppercase()
+    };
+    let min_length = MIN_STR_LENGTH.max(3 * unit);
+    let mut bases = 0;
+    let mut i = unit;
+    while i < seq.len() {
+        if !same(i) {
+            i += 1;
+            continue;
+        }
+        // The tract [start, i) repeats the unit at `start`
+        let start = i - unit;
+        while i < seq.len() && same(i) {
+            i += 1;
+        }
+        if i - start >= min_length && is_primitive(&seq[start..start + unit]) {
+            bases += (i - start) as u64;
+        }
+    }
+    bases
+}
+
+/// Whether `unit` is not a repeat of a shorter unit
+fn is_primitive(unit: &[u8]) -> bool {
+    (1..unit.len())
+        .filter(|d| unit.len().is_multiple_of(*d))
+        .all(|d| (d..unit.len()).any(|j| !unit[j].eq_ignore_ascii_case(&unit[j - d])))
+}
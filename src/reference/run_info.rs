@@ -0,0 +1,192 @@
+use crate::cli::cloud::streamed_files;
+use crate::cli::BigCount;
+use crate::reference::counting::RefKmerExtractionCounters;
+use anyhow::{Context, Result};
+use serde_json::{json, Map, Value};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+/// Counters collected while processing one chromosome
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ChromCounters {
+    /// Sequence length in reference coordinates
+    pub length: u64,
+    pub windows: u64,
+    /// Non-N, non-blacklisted bases in all windows
+    pub effective_bases: u64,
+    /// Number of counted kmers per k
+    pub kmers_by_k: BTreeMap<u8, BigCount>,
+    /// Positions of the whole chromosome by how they were used
+    pub positions: RefKmerExtractionCounters,
+}
+
+/// Provenance of a run, written as `run_info.json`
+#[derive(Debug, Clone, Default)]
+pub struct RunInfo {
+    /// Resolved command-line options as `(option, values)`
+    pub options: Vec<(String, Vec<String>)>,
+    pub reference: String,
+    /// SHA-256 of the reference file (`None` for remote references)
+    pub reference_sha256: Option<String>,
+    pub chromosomes: Vec<String>,
+    pub skipped_chromosomes: Vec<String>,
+    pub kmer_sizes: Vec<u8>,
+    /// Named random seeds used in the run, e.g. `rarefy`
+    pub seeds: BTreeMap<String, u64>,
+    pub wall_time_secs: f64,
+    pub chrom_counters: BTreeMap<String, ChromCounters>,
+}
+
+impl RunInfo {
+    pub fn to_json(&self) -> Value {
+        let options: Map<String, Value> = self
+            .options
+            .iter()
+            .map(|(name, values)| {
+                let value = match values.as_slice() {
+                    [single] => json!(single),
+                    _ => json!(values),
+                };
+                (name.clone(), value)
+            })
+            .collect();
+        let per_chromosome: Map<String, Value> = self
+            .chrom_counters
+            .iter()
+            .map(|(chr, c)| {
+                let kmers: Map<String, Value> = c
+                    .kmers_by_k
+                    .iter()
+                    .map(|(k, n)| (k.to_string(), json!(n)))
+                    .collect();
+                (
+                    chr.clone(),
+                    json!({
+                        "length": c.length,
+                        "windows": c.windows,
+                        "effective_bases": c.effective_bases,
+                        "kmers": kmers,
+                        "positions": {
+                            "total": c.positions.total,
+                            "blacklisted": c.positions.blacklisted,
+                            "ambiguous": c.positions.ambiguous,
+                            "counted": c.positions.counted,
+                            "low_complexity": c.positions.low_complexity,
+                            "skipped": c.positions.skipped,
+                        },
+                    }),
+                )
+            })
+            .collect();
+        json!({
+            "version": env!("CARGO_PKG_VERSION"),
+            "options": options,
+            "reference": {
+                "path": self.reference,
+                "sha256": self.reference_sha256,
+            },
+            "chromosomes": self.chromosomes,
+            "skipped_chromosomes": self.skipped_chromosomes,
+            "kmer_sizes": self.kmer_sizes,
+            "seeds": self.seeds,
+            "wall_time_secs": self.wall_time_secs,
+            "per_chromosome": per_chromosome,
+        })
+    }
+
+    /// Write as pretty-printed JSON
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let text = serde_json::to_string_pretty(&self.to_json())?;
+        std::fs::write(path, text + "\n").context(format!("Writing run info {:?}", path))
+    }
+}
+
+/// Hex-encoded SHA-256 of a file
+pub fn sha256_file(path: &Path) -> Result<String> {
+    let mut file = File::open(path).context(format!("Opening {:?} for checksum", path))?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0u8; 1 << 20];
+    loop {
+        let n = file
+            .read(&mut buf)
+            .context(format!("Reading {:?} for checksum", path))?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect())
+}
+
+/// Write `manifest.sha256` and `manifest.tsv` to the staged output
+/// directory `dir` of a run, listing every file the run wrote below it.
+///
+/// `manifest.sha256` has `<sha256>  <file>` lines as written by `sha256sum`,
+/// so `cd <output-dir> && sha256sum -c manifest.sha256` verifies the
+/// outputs. `manifest.tsv` has the byte size of every file as
+/// `file<TAB>bytes` lines. Paths are relative to `dir` and sorted. Existing
+/// manifests are replaced and not listed. Files uploaded directly to object
+/// storage are included.
+pub fn write_checksum_manifest(dir: &Path) -> Result<()> {
+    let manifests = [dir.join("manifest.sha256"), dir.join("manifest.tsv")];
+    let mut files = Vec::new();
+    collect_files(dir, &mut files)?;
+    files.retain(|f| !manifests.contains(f));
+
+    // Files uploaded directly to object storage were hashed while streamed
+    let mut entries = Vec::new();
+    for file in files {
+        let n_bytes = std::fs::metadata(&file)
+            .context(format!("Reading size of {:?}", file))?
+            .len();
+        entries.push((sha256_file(&file)?, n_bytes, file));
+    }
+    entries.extend(
+        streamed_files(dir)
+            .into_iter()
+            .map(|f| (f.sha256, f.bytes, f.path)),
+    );
+    entries.sort_by(|a, b| a.2.cmp(&b.2));
+
+    let mut checksums = String::new();
+    let mut sizes = String::from("file\tbytes\n");
+    for (sha256, n_bytes, file) in &entries {
+        let rel: Vec<String> = file
+            .strip_prefix(dir)?
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy().into_owned())
+            .collect();
+        let rel = rel.join("/");
+        // `sha256sum` escapes backslashes and newlines in names and marks
+        // such lines with a leading backslash
+        if rel.contains(['\\', '\n']) {
+            let escaped = rel.replace('\\', "\\\\").replace('\n', "\\n");
+            checksums.push_str(&format!("\\{}  {}\n", sha256, escaped));
+        } else {
+            checksums.push_str(&format!("{}  {}\n", sha256, rel));
+        }
+        sizes.push_str(&format!("{}\t{}\n", rel, n_bytes));
+    }
+    std::fs::write(&manifests[0], checksums).context(format!("Writing {:?}", manifests[0]))?;
+    std::fs::write(&manifests[1], sizes).context(format!("Writing {:?}", manifests[1]))
+}
+
+fn collect_files(dir: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
+    for entry in std::fs::read_dir(dir).context(format!("Reading {:?}", dir))? {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            collect_files(&entry.path(), files)?;
+        } else if file_type.is_file() {
+            files.push(entry.path());
+        }
+    }
+    Ok(())
+}
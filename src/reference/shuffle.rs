@@ -0,0 +1,117 @@
+use rand::seq::SliceRandom;
+use rand::Rng;
+
+/// Nucleotides that take part in shuffling. Everything else
+/// (N, blacklist-masked bases, ...) stays at its position.
+#[inline]
+fn is_acgt(b: u8) -> bool {
+    matches!(b.to_ascii_uppercase(), b'A' | b'C' | b'G' | b'T')
+}
+
+#[inline]
+fn base_idx(b: u8) -> usize {
+    match b.to_ascii_uppercase() {
+        b'A' => 0,
+        b'C' => 1,
+        b'G' => 2,
+        _ => 3,
+    }
+}
+
+const IDX_BASE: [u8; 4] = [b'A', b'C', b'G', b'T'];
+
+/// Shuffle an A/C/G/T sequence while preserving its exact dinucleotide
+/// counts, first base and last base.
+///
+/// Uses the Eulerian-walk method of Altschul & Erickson (1985):
+/// a random spanning arborescence of "last exit" edges is sampled and the
+/// remaining edges of every vertex are permuted before walking the graph.
+///
+/// Bases are upper-cased. `seq` must only contain A/C/G/T (any case).
+pub fn dinucleotide_shuffle<R: Rng>(seq: &mut [u8], rng: &mut R) {
+    let len = seq.len();
+    if len < 3 {
+        return;
+    }
+
+    // Outgoing edges (next base) per vertex
+    let mut edges: [Vec<u8>; 4] = Default::default();
+    for w in seq.windows(2) {
+        edges[base_idx(w[0])].push(base_idx(w[1]) as u8);
+    }
+    let first = base_idx(seq[0]);
+    let last = base_idx(seq[len - 1]);
+
+    // Sample last-exit edges until they form a tree rooted at `last`
+    let mut last_exit: [Option<usize>; 4] = [None; 4];
+    loop {
+        for v in 0..4 {
+            last_exit[v] = if v == last || edges[v].is_empty() {
+                None
+            } else {
+                Some(rng.gen_range(0..edges[v].len()))
+            };
+        }
+        let connected = (0..4).all(|v| {
+            if v == last || edges[v].is_empty() {
+                return true;
+            }
+            // Follow last-exit edges; must reach `last` without cycling
+            let mut cur = v;
+            for _ in 0..4 {
+                match last_exit[cur] {
+                    Some(e) => cur = edges[cur][e] as usize,
+                    None => break,
+                }
+                if cur == last {
+                    return true;
+                }
+            }
+            cur == last
+        });
+        if connected {
+            break;
+        }
+    }
+
+    // Permute all other edges, keep the last-exit edge at the end
+    for v in 0..4 {
+        if let Some(e) = last_exit[v] {
+            let n = edges[v].len();
+            edges[v].swap(e, n - 1);
+            edges[v][..n - 1].shuffle(rng);
+        } else {
+            edges[v].shuffle(rng);
+        }
+    }
+
+    // Walk the graph
+    let mut next = [0usize; 4];
+    let mut cur = first;
+    seq[0] = IDX_BASE[cur];
+    for out in seq.iter_mut().skip(1) {
+        let to = edges[cur][next[cur]] as usize;
+        next[cur] += 1;
+        *out = IDX_BASE[to];
+        cur = to;
+    }
+}
+
+/// Dinucleotide-shuffle every maximal A/C/G/T run in `seq` independently.
+///
+/// Other bases (N, blacklist-masked bases, ...) keep their positions, so
+/// the number of valid k-mer positions is unchanged.
+pub fn shuffle_segments<R: Rng>(seq: &mut [u8], rng: &mut R) {
+    let mut i = 0;
+    while i < seq.len() {
+        if !is_acgt(seq[i]) {
+            i += 1;
+            continue;
+        }
+        let start = i;
+        while i < seq.len() && is_acgt(seq[i]) {
+            i += 1;
+        }
+        dinucleotide_shuffle(&mut seq[start..i], rng);
+    }
+}
@@ -0,0 +1,142 @@
+use crate::cli::BigCount;
+use crate::reference::kmer_codec::Kmer;
+use anyhow::{Context, Result};
+use fxhash::{FxHashMap, FxHashSet};
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter, ErrorKind, Read, Write},
+    path::{Path, PathBuf},
+};
+use tempfile::TempDir;
+
+/// Per-chromosome window counts spilled to temporary files until the sparse
+/// matrices are written.
+///
+/// Every (window set, chromosome, kmer size) gets a file of little-endian
+/// `(window, code, count)` triplets, sorted by window and code. Windows are
+/// numbered within their chromosome. The files are removed on drop.
+#[derive(Debug)]
+pub struct CountSpill {
+    dir: TempDir,
+}
+
+impl CountSpill {
+    /// Create a temporary spill directory inside `parent`
+    pub fn new(parent: &Path) -> Result<Self> {
+        let dir = tempfile::Builder::new()
+            .prefix(".spill-")
+            .tempdir_in(parent)
+            .context(format!("Creating spill directory in {:?}", parent))?;
+        Ok(CountSpill { dir })
+    }
+
+    fn path(&self, set: usize, chrom: usize, k: u8) -> PathBuf {
+        self.dir
+            .path()
+            .join(format!("set{set}_chrom{chrom}_k{k}.spill"))
+    }
+
+    /// Spill the window counts of one chromosome of a window set, with a
+    /// file for every size in `kmer_sizes`
+    pub fn store(
+        &self,
+        set: usize,
+        chrom: usize,
+        windows: &[FxHashMap<Kmer, BigCount>],
+        kmer_sizes: &[u8],
+    ) -> Result<()> {
+        let mut triplets_by_k: FxHashMap<u8, Vec<[u64; 3]>> =
+            kmer_sizes.iter().map(|&k| (k, Vec::new())).collect();
+        for (window, counts) in windows.iter().enumerate() {
+            for (kmer, &count) in counts {
+                triplets_by_k
+                    .entry(kmer.k)
+                    .or_default()
+                    .push([window as u64, kmer.code, count]);
+            }
+        }
+        for (k, mut triplets) in triplets_by_k {
+            triplets.sort_unstable();
+            let path = self.path(set, chrom, k);
+            let f = File::create(&path).context(format!("Creating spill file {:?}", path))?;
+            let mut w = BufWriter::new(f);
+            for value in triplets.iter().flatten() {
+                w.write_all(&value.to_le_bytes())?;
+            }
+            w.flush()
+                .context(format!("Writing spill file {:?}", path))?;
+        }
+        Ok(())
+    }
+
+    /// Read the `(window, code, count)` triplets of one chromosome
+    pub fn read(&self, set: usize, chrom: usize, k: u8) -> Result<SpillReader> {
+        let path = self.path(set, chrom, k);
+        let f = File::open(&path).context(format!("Opening spill file {:?}", path))?;
+        Ok(SpillReader {
+            reader: BufReader::new(f),
+        })
+    }
+
+    /// Distinct codes of kmer size `k` in the first `n_chroms` chromosomes of
+    /// a window set
+    pub fn distinct_codes(&self, set: usize, n_chroms: usize, k: u8) -> Result<FxHashSet<u64>> {
+        let mut codes = FxHashSet::default();
+        for chrom in 0..n_chroms {
+            for triplet in self.read(set, chrom, k)? {
+                codes.insert(triplet?[1]);
+            }
+        }
+        Ok(codes)
+    }
+
+    /// Merge the triplets of all chromosomes of a window set (in chromosome
+    /// order) into `(row, code, count)` triplets of one matrix. `rows_by_chrom`
+    /// is the number of windows of each chromosome.
+    pub fn merged<'a>(
+        &'a self,
+        set: usize,
+        rows_by_chrom: &'a [usize],
+        k: u8,
+    ) -> impl Iterator<Item = Result<[u64; 3]>> + 'a {
+        let offsets = rows_by_chrom.iter().scan(0u64, |offset, &rows| {
+            let start = *offset;
+            *offset += rows as u64;
+            Some(start)
+        });
+        offsets.enumerate().flat_map(
+            move |(chrom, offset)| -> Box<dyn Iterator<Item = Result<[u64; 3]>>> {
+                match self.read(set, chrom, k) {
+                    Ok(reader) => Box::new(reader.map(move |triplet| {
+                        let [window, code, count] = triplet?;
+                        Ok([offset + window, code, count])
+                    })),
+                    Err(err) => Box::new(std::iter::once(Err(err))),
+                }
+            },
+        )
+    }
+}
+
+/// Iterator over the triplets of a spill file
+pub struct SpillReader {
+    reader: BufReader<File>,
+}
+
+impl Iterator for SpillReader {
+    type Item = Result<[u64; 3]>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut buf = [0u8; 24];
+        match self.reader.read_exact(&mut buf) {
+            Ok(()) => {}
+            Err(err) if err.kind() == ErrorKind::UnexpectedEof => return None,
+            Err(err) => return Some(Err(err.into())),
+        }
+        let mut triplet = [0u64; 3];
+        for (value, bytes) in triplet.iter_mut().zip(buf.chunks_exact(8)) {
+            *value = u64::from_le_bytes(bytes.try_into().unwrap());
+        }
+        Some(Ok(triplet))
+    }
+}
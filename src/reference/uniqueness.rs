@@ -0,0 +1,79 @@
+use crate::reference::kmer_codec::KmerSpec;
+use fxhash::FxHashMap;
+
+/// Genome-wide counts of canonical k-mer codes
+pub type KmerFrequencies = FxHashMap<u64, u32>;
+
+/// Canonical codes of the k-mers (without N) starting at each position of
+/// `seq`, or `None` where no such k-mer starts
+fn canonical_codes<'a>(seq: &[u8], spec: &'a KmerSpec) -> impl Iterator<Item = Option<u64>> + 'a {
+    spec.build_codes(seq).into_iter().map(move |code| {
+        if code == spec.sentinel_none() || code == spec.sentinel_n() {
+            None
+        } else {
+            Some(spec.canonical_code(code))
+        }
+    })
+}
+
+/// Add the k-mers of `seq` to the genome-wide `frequencies`.
+///
+/// A k-mer and its reverse complement share a count, as reads from either
+/// strand align to both.
+pub fn add_kmer_frequencies(frequencies: &mut KmerFrequencies, seq: &[u8], spec: &KmerSpec) {
+    for code in canonical_codes(seq, spec).flatten() {
+        let count = frequencies.entry(code).or_insert(0);
+        *count = count.saturating_add(1);
+    }
+}
+
+/// Uniqueness (1 / genome-wide frequency) of the k-mer starting at every
+/// position of `seq`, or `None` where no k-mer without N starts.
+///
+/// K-mers missing from `frequencies` (e.g. on sequences that were not
+/// counted) are treated as unique.
+pub fn uniqueness_scores(
+    seq: &[u8],
+    spec: &KmerSpec,
+    frequencies: &KmerFrequencies,
+) -> Vec<Option<f64>> {
+    canonical_codes(seq, spec)
+        .map(|code| {
+            let count = frequencies.get(&code?).copied().unwrap_or(1).max(1);
+            Some(1.0 / count as f64)
+        })
+        .collect()
+}
+
+/// bedGraph intervals `(start, end, score)` of per-position scores with
+/// runs of equal scores merged. Positions without a score are left out.
+pub fn score_runs(scores: &[Option<f64>]) -> Vec<(u64, u64, f64)> {
+    let mut runs: Vec<(u64, u64, f64)> = Vec::new();
+    for (pos, score) in scores.iter().enumerate() {
+        let Some(score) = *score else { continue };
+        let pos = pos as u64;
+        match runs.last_mut() {
+            Some(last) if last.1 == pos && last.2 == score => last.1 = pos + 1,
+            _ => runs.push((pos, pos + 1, score)),
+        }
+    }
+    runs
+}
+
+/// Mean score of the positions with a score in consecutive windows of
+/// `size` bases, as `(start, end, mean)`. Windows without scores are left
+/// out and the last window is shortened to the sequence end.
+pub fn window_means(scores: &[Option<f64>], size: u64) -> Vec<(u64, u64, f64)> {
+    scores
+        .chunks(size as usize)
+        .enumerate()
+        .filter_map(|(i, chunk)| {
+            let (sum, n) = chunk
+                .iter()
+                .flatten()
+                .fold((0.0, 0usize), |(sum, n), s| (sum + s, n + 1));
+            let start = i as u64 * size;
+            (n > 0).then(|| (start, start + chunk.len() as u64, sum / n as f64))
+        })
+        .collect()
+}
@@ -0,0 +1,205 @@
+use crate::cli::io::open_maybe_gz;
+use crate::reference::chrom_alias::ChromAliases;
+use anyhow::{bail, Context, Result};
+use std::{
+    collections::{HashMap, HashSet},
+    io::BufRead,
+    path::Path,
+};
+
+/// A variant to apply to the reference, with a 0-based position.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Variant {
+    pub pos: u64,
+    pub ref_allele: Vec<u8>,
+    pub alt_allele: Vec<u8>,
+}
+
+impl Variant {
+    /// End of the replaced reference bases (exclusive)
+    pub fn ref_end(&self) -> u64 {
+        self.pos + self.ref_allele.len() as u64
+    }
+}
+
+/// Load variants from a (gzipped) VCF, keyed by chromosome and sorted by position.
+///
+/// * Only records on `chromosomes` that pass filters (`PASS` or `.`) are kept.
+/// * With `sample`, the first non-reference allele of its `GT` is applied
+///   and records where the sample is homozygous reference are skipped.
+///   Otherwise the first `ALT` allele is applied.
+/// * SNVs (and MNVs of equal length) are always kept; other length-changing
+///   variants only with `include_indels`. Symbolic alleles (`<DEL>`, `*`)
+///   are skipped.
+/// * Chromosome names are translated to reference names with `aliases`.
+pub fn load_vcf(
+    path: &Path,
+    chromosomes: &[String],
+    sample: Option<&str>,
+    include_indels: bool,
+    aliases: Option<&ChromAliases>,
+) -> Result<HashMap<String, Vec<Variant>>> {
+    if path
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("bcf"))
+    {
+        bail!(
+            "BCF files are not supported; convert with `bcftools view -Oz -o <out.vcf.gz> {:?}`",
+            path
+        );
+    }
+    let reader = open_maybe_gz(path).context(format!("opening VCF {:?}", path))?;
+    let wanted: HashSet<&str> = chromosomes.iter().map(String::as_str).collect();
+    let mut variants: HashMap<String, Vec<Variant>> = HashMap::new();
+    // Column of the selected sample
+    let mut sample_col: Option<usize> = None;
+    for (line_idx, line) in reader.lines().enumerate() {
+        let line = line.context("reading VCF line")?;
+        if line.starts_with("##") || line.trim().is_empty() {
+            continue;
+        }
+        let cols: Vec<&str> = line.split('\t').collect();
+        if line.starts_with('#') {
+            if let Some(name) = sample {
+                sample_col = Some(
+                    cols.iter()
+                        .skip(9)
+                        .position(|c| *c == name)
+                        .map(|i| i + 9)
+                        .context(format!("Sample {} not found in VCF {:?}", name, path))?,
+                );
+            }
+            continue;
+        }
+        if cols.len() < 8 {
+            bail!("Malformed VCF line {}: too few columns", line_idx + 1);
+        }
+        let chr = aliases.map_or(cols[0], |a| a.resolve(cols[0]));
+        if !wanted.contains(chr) || !matches!(cols[6], "PASS" | ".") {
+            continue;
+        }
+        let pos: u64 = cols[1]
+            .parse()
+            .context(format!("Parsing VCF position on line {}", line_idx + 1))?;
+        let alts: Vec<&str> = cols[4].split(',').collect();
+        let allele_idx = match sample {
+            Some(name) => {
+                let col =
+                    sample_col.context(format!("VCF {:?} has no #CHROM header line", path))?;
+                if cols.len() <= col {
+                    bail!(
+                        "VCF line {} has no column for sample {}",
+                        line_idx + 1,
+                        name
+                    );
+                }
+                match non_ref_allele(&cols, col) {
+                    Some(idx) => idx,
+                    None => continue,
+                }
+            }
+            None => 1,
+        };
+        let Some(alt) = alts.get(allele_idx - 1) else {
+            bail!("Genotype allele out of range on VCF line {}", line_idx + 1);
+        };
+        if alt.starts_with('<') || alt.contains(['*', '[', ']', '.']) {
+            continue;
+        }
+        let variant = Variant {
+            pos: pos.saturating_sub(1),
+            ref_allele: cols[3].to_ascii_uppercase().into_bytes(),
+            alt_allele: alt.to_ascii_uppercase().into_bytes(),
+        };
+        if variant.ref_allele.len() != variant.alt_allele.len() && !include_indels {
+            continue;
+        }
+        variants.entry(chr.to_string()).or_default().push(variant);
+    }
+    for v in variants.values_mut() {
+        v.sort_by_key(|var| var.pos);
+    }
+    Ok(variants)
+}
+
+/// Index of the first non-reference allele in the `GT` field of `col`
+fn non_ref_allele(cols: &[&str], col: usize) -> Option<usize> {
+    let gt_idx = cols.get(8)?.split(':').position(|f| f == "GT")?;
+    let gt = cols.get(col)?.split(':').nth(gt_idx)?;
+    gt.split(['/', '|'])
+        .filter_map(|a| a.parse::<usize>().ok())
+        .find(|&a| a > 0)
+}
+
+/// Maps reference coordinates to coordinates in a sequence with indels applied.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CoordinateMap {
+    /// Applied length-changing variants as `(ref_pos, ref_end, personal_pos, alt_len)`
+    changes: Vec<(u64, u64, u64, u64)>,
+}
+
+impl CoordinateMap {
+    /// Position in the personalized sequence of reference position `pos`.
+    ///
+    /// Positions inside a deletion map to the end of the remaining allele.
+    pub fn to_personal(&self, pos: u64) -> u64 {
+        let i = self.changes.partition_point(|&(p, _, _, _)| p < pos);
+        if i == 0 {
+            return pos;
+        }
+        let (ref_pos, ref_end, personal_pos, alt_len) = self.changes[i - 1];
+        if pos < ref_end {
+            personal_pos + (pos - ref_pos).min(alt_len)
+        } else {
+            personal_pos + alt_len + (pos - ref_end)
+        }
+    }
+
+    pub fn is_identity(&self) -> bool {
+        self.changes.is_empty()
+    }
+}
+
+/// Summary of applying variants to a sequence
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ApplySummary {
+    pub applied: usize,
+    /// REF allele does not match the reference sequence
+    pub ref_mismatch: usize,
+    /// Overlaps an already applied variant or extends past the sequence end
+    pub overlapping: usize,
+}
+
+/// Apply sorted `variants` to `seq`.
+///
+/// Variants whose `REF` does not match the sequence (case-insensitive) or
+/// that overlap a previously applied variant are skipped. Substituted bases
+/// are upper-case.
+pub fn apply_variants(seq: &[u8], variants: &[Variant]) -> (Vec<u8>, CoordinateMap, ApplySummary) {
+    let mut out = Vec::with_capacity(seq.len());
+    let mut map = CoordinateMap::default();
+    let mut summary = ApplySummary::default();
+    // Reference position up to which `seq` has been copied
+    let mut copied = 0u64;
+    for var in variants {
+        let end = var.ref_end();
+        if var.pos < copied || end > seq.len() as u64 {
+            summary.overlapping += 1;
+            continue;
+        }
+        if !seq[var.pos as usize..end as usize].eq_ignore_ascii_case(&var.ref_allele) {
+            summary.ref_mismatch += 1;
+            continue;
+        }
+        out.extend_from_slice(&seq[copied as usize..var.pos as usize]);
+        if var.ref_allele.len() != var.alt_allele.len() {
+            map.changes
+                .push((var.pos, end, out.len() as u64, var.alt_allele.len() as u64));
+        }
+        out.extend_from_slice(&var.alt_allele);
+        copied = end;
+        summary.applied += 1;
+    }
+    out.extend_from_slice(&seq[copied as usize..]);
+    (out, map, summary)
+}
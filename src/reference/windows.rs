@@ -0,0 +1,237 @@
+use crate::reference::bed::{Anchor, BedColumns, WindowMap};
+use clap::ValueEnum;
+use rand::Rng;
+use std::collections::HashMap;
+
+/// How the shorter last (and, with an offset, first) window of a
+/// chromosome is handled
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum LastWindow {
+    /// Keep the short window
+    #[default]
+    Keep,
+    /// Drop the short window
+    Drop,
+    /// Move the start of the short window back so it has the full size
+    /// (overlapping the previous window)
+    Extend,
+    /// Add the remaining bases to the previous window
+    Merge,
+}
+
+/// Output order of windows read from a file
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum WindowOrder {
+    /// Order of the window file
+    #[default]
+    Input,
+    /// By chromosome and position
+    Sorted,
+}
+
+/// Fixed-size windows `(start, end, idx)` of a chromosome.
+///
+/// Windows start at `offset` and then every `step` bases until one reaches
+/// the chromosome end; that last window is shortened to the chromosome end
+/// and handled according to `last`. A chromosome shorter than `size` gets a
+/// single window (none with `LastWindow::Drop`).
+///
+/// With an `offset`, the bases before it form a leading short window that
+/// is handled like the last one ('extend' moves its end forward, 'merge'
+/// adds it to the next window).
+pub fn size_windows(
+    chrom_len: u64,
+    size: u64,
+    step: u64,
+    offset: u64,
+    last: LastWindow,
+) -> Vec<(u64, u64, u64)> {
+    let mut windows = Vec::new();
+    let lead = (0, offset.min(chrom_len));
+    let has_lead = offset > 0 && chrom_len > 0;
+    if has_lead {
+        windows.push(lead);
+    }
+    let mut start = offset;
+    let mut n_tiles = 0;
+    while start < chrom_len {
+        windows.push((start, (start + size).min(chrom_len)));
+        n_tiles += 1;
+        if start + size >= chrom_len {
+            break;
+        }
+        start += step;
+    }
+    if let Some(&(start, end)) = windows.last().filter(|_| n_tiles > 0) {
+        if end - start < size {
+            match last {
+                LastWindow::Keep => {}
+                LastWindow::Drop => {
+                    windows.pop();
+                }
+                LastWindow::Extend => {
+                    let n = windows.len();
+                    windows[n - 1].0 = chrom_len.saturating_sub(size);
+                }
+                LastWindow::Merge if windows.len() > 1 => {
+                    windows.pop();
+                    windows.last_mut().unwrap().1 = chrom_len;
+                }
+                LastWindow::Merge => {}
+            }
+        }
+    }
+    if has_lead && windows.first() == Some(&lead) && lead.1 < size {
+        match last {
+            LastWindow::Keep => {}
+            LastWindow::Drop => {
+                windows.remove(0);
+            }
+            LastWindow::Extend => windows[0].1 = size.min(chrom_len),
+            LastWindow::Merge if windows.len() > 1 => {
+                windows.remove(0);
+                windows[0].0 = 0;
+            }
+            LastWindow::Merge => {}
+        }
+    }
+    windows
+        .into_iter()
+        .enumerate()
+        .map(|(idx, (start, end))| (start, end, idx as u64))
+        .collect()
+}
+
+/// `n` windows `(start, end, idx)` of (nearly) equal size covering a
+/// chromosome. Boundaries are rounded down, and empty windows (when the
+/// chromosome is shorter than `n`) are left out.
+pub fn equal_windows(chrom_len: u64, n: u64) -> Vec<(u64, u64, u64)> {
+    let bound = |i: u64| (i as u128 * chrom_len as u128 / n as u128) as u64;
+    (0..n)
+        .map(|i| (bound(i), bound(i + 1)))
+        .filter(|(start, end)| end > start)
+        .enumerate()
+        .map(|(idx, (start, end))| (start, end, idx as u64))
+        .collect()
+}
+
+/// Symmetric windows around `anchors`, labelled with the anchor names.
+///
+/// Each window spans the anchor base and `flank` bases on each side
+/// (shortened at the chromosome start). The window index is the anchor's
+/// position in `anchors` and the returned columns hold the BED `name`.
+pub fn anchor_windows(
+    anchors: &[Anchor],
+    chromosomes: &[String],
+    flank: u64,
+) -> (WindowMap, BedColumns) {
+    let mut mapping: WindowMap = chromosomes
+        .iter()
+        .map(|chr| (chr.to_string(), Vec::new()))
+        .collect();
+    let mut values = Vec::with_capacity(anchors.len());
+    for (idx, anchor) in anchors.iter().enumerate() {
+        mapping.entry(anchor.chrom.clone()).or_default().push((
+            anchor.pos.saturating_sub(flank),
+            anchor.pos + flank + 1,
+            idx as u64,
+        ));
+        values.push(vec![anchor.name.clone()]);
+    }
+    for v in mapping.values_mut() {
+        v.sort_unstable_by_key(|&(s, e, _)| (s, e));
+    }
+    let names = vec!["name".to_string()];
+    (mapping, BedColumns { names, values })
+}
+
+/// Positions of `anchors` by chromosome, in file order
+pub fn anchors_by_chrom(anchors: &[Anchor]) -> HashMap<String, Vec<u64>> {
+    let mut positions: HashMap<String, Vec<u64>> = HashMap::new();
+    for anchor in anchors {
+        positions
+            .entry(anchor.chrom.clone())
+            .or_default()
+            .push(anchor.pos);
+    }
+    positions
+}
+
+/// Split windows at `intervals` (e.g. blacklisted regions) into the
+/// sub-windows of bases outside them.
+///
+/// Sub-windows keep the index of their window, and windows fully covered
+/// by intervals are removed. `intervals` must be sorted by start and
+/// non-overlapping.
+pub fn split_windows_at_intervals(
+    windows: &[(u64, u64, u64)],
+    intervals: &[(u64, u64)],
+) -> Vec<(u64, u64, u64)> {
+    let mut out = Vec::with_capacity(windows.len());
+    for &(start, end, idx) in windows {
+        let first = intervals.partition_point(|&(_, e)| e <= start);
+        let mut pos = start;
+        for &(s, e) in intervals[first..].iter().take_while(|&&(s, _)| s < end) {
+            if s > pos {
+                out.push((pos, s, idx));
+            }
+            pos = pos.max(e);
+        }
+        if pos < end {
+            out.push((pos, end, idx));
+        }
+    }
+    out
+}
+
+/// Random windows with the lengths and indices of `windows`, placed
+/// uniformly on a chromosome of length `chrom_len` outside `avoid`
+/// (e.g. blacklisted regions).
+///
+/// `avoid` must be sorted by start and non-overlapping. A window that fits
+/// in no gap between the intervals is placed anywhere on the chromosome,
+/// and one longer than the chromosome spans all of it.
+pub fn random_windows(
+    windows: &[(u64, u64, u64)],
+    chrom_len: u64,
+    avoid: &[(u64, u64)],
+    rng: &mut impl Rng,
+) -> Vec<(u64, u64, u64)> {
+    let mut gaps = Vec::with_capacity(avoid.len() + 1);
+    let mut pos = 0;
+    for &(s, e) in avoid {
+        if s.min(chrom_len) > pos {
+            gaps.push((pos, s.min(chrom_len)));
+        }
+        pos = pos.max(e);
+    }
+    if pos < chrom_len {
+        gaps.push((pos, chrom_len));
+    }
+    // Cumulative number of valid starts per gap, by window length
+    let mut valid_starts: HashMap<u64, Vec<u64>> = HashMap::new();
+    windows
+        .iter()
+        .map(|&(start, end, idx)| {
+            let len = end.saturating_sub(start).min(chrom_len);
+            let cumulative = valid_starts.entry(len).or_insert_with(|| {
+                gaps.iter()
+                    .scan(0, |total, &(s, e)| {
+                        *total += (e - s + 1).saturating_sub(len);
+                        Some(*total)
+                    })
+                    .collect()
+            });
+            let start = match cumulative.last() {
+                Some(&total) if total > 0 => {
+                    let r = rng.gen_range(0..total);
+                    let gap = cumulative.partition_point(|&c| c <= r);
+                    let before = if gap == 0 { 0 } else { cumulative[gap - 1] };
+                    gaps[gap].0 + r - before
+                }
+                _ => rng.gen_range(0..=chrom_len - len),
+            };
+            (start, start + len, idx)
+        })
+        .collect()
+}
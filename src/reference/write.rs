@@ -1,25 +1,275 @@
+use crate::cli::bgzf::BgzfWriter;
+use crate::cli::cloud::create_output;
+use crate::cli::tabix::TabixIndexBuilder;
 use crate::cli::BigCount;
-use crate::reference::kmer_codec::{DecodedCounts, KmerSpec};
-use anyhow::{Context, Result};
+use crate::reference::code_cache::safe_file_name;
+use crate::reference::kmer_codec::DecodedCounts;
+use crate::reference::motif_groups::MotifGroups;
+use crate::reference::process_counts::{canonical as canonical_motif, kmer_diversity, revcomp};
+use crate::reference::pwm::PwmHits;
+use crate::reference::repeats::RepeatContent;
+use anyhow::{bail, Context, Result};
+use arrow_array::{ArrayRef, Float64Array, RecordBatch, StringArray, UInt64Array};
+use arrow_ipc::writer::FileWriter;
+use arrow_schema::{DataType, Field, Schema};
+use clap::ValueEnum;
 use fxhash::FxHashMap;
 use ndarray::{arr1, Array2, ArrayView1};
+use ndarray_npy::WritableElement;
 use ndarray_npy::WriteNpyExt; // trait brings .write_npy into scope
-use ndarray_npy::{write_npy, WritableElement};
-use num_traits::NumCast;
-use std::collections::HashMap;
+use num_traits::{NumCast, ToPrimitive};
+use serde_json::{json, Map, Value};
+use std::borrow::Borrow;
+use std::collections::{BTreeMap, HashMap};
 use std::fs::File;
 use std::io::Cursor;
-use std::io::Write;
-use std::path::Path;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use zip::{write::SimpleFileOptions, ZipWriter};
 
-/// Write one `.npy` matrix and a companion `*_motifs.txt` file for every
-/// k present in `prepared_windows`.
+use h5::H5Writer;
+
+/// Format of the count matrices
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    /// NumPy `.npy` (or `.npz` when sparse) with `*_motifs.txt` and `*_motifs.json` files
+    Npy,
+    /// Tab-separated table with a motif header row and window identifier columns
+    Tsv,
+    /// Arrow IPC file (Feather v2) with window identifier and motif columns
+    Arrow,
+    /// Long (tidy) table with one `chrom, start, end, k, motif, count` row
+    /// per non-zero count
+    Long,
+    /// HDF5 file `counts.h5` with a `k<k>/counts` matrix and `k<k>/motifs`
+    /// per k and the `windows` coordinates (requires the `hdf5` feature)
+    Hdf5,
+}
+
+/// Layout of sparse count matrices
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum SparseFormat {
+    /// Coordinate format (`row`, `col`, `data`)
+    Coo,
+    /// Compressed sparse rows (`indptr`, `indices`, `data`); fast row slicing
+    Csr,
+}
+
+/// Element type of written count arrays
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum CountDtype {
+    U16,
+    U32,
+    U64,
+    F32,
+    F64,
+}
+
+impl CountDtype {
+    /// Bytes of one element
+    pub fn bytes(&self) -> u64 {
+        match self {
+            CountDtype::U16 => 2,
+            CountDtype::U32 | CountDtype::F32 => 4,
+            CountDtype::U64 | CountDtype::F64 => 8,
+        }
+    }
+}
+
+/// Convert counts to `T`, failing on values that cannot be represented exactly
+fn cast_counts<T: NumCast + ToPrimitive>(
+    counts: impl Iterator<Item = BigCount>,
+    dtype: CountDtype,
+) -> Result<Vec<T>> {
+    counts
+        .map(|c| {
+            NumCast::from(c)
+                .filter(|t: &T| t.to_u64() == Some(c))
+                .with_context(|| {
+                    format!(
+                        "Count {} cannot be stored exactly as {:?}; use a wider --count-dtype",
+                        c, dtype
+                    )
+                })
+        })
+        .collect()
+}
+
+/// Write `arr` as `.npy` to `path`, created with `create_output` so it is
+/// uploaded directly when the output directory is in object storage
+pub fn write_npy<A: WriteNpyExt + ?Sized>(path: &Path, arr: &A) -> Result<()> {
+    let mut out = create_output(path)?;
+    arr.write_npy(&mut out)
+        .context(format!("Write {:?} fail", path))?;
+    out.finish().context(format!("Write {:?} fail", path))
+}
+
+/// Write a windows × columns count matrix as `.npy` with element type `dtype`
+pub fn write_counts_npy(path: &Path, mat: &Array2<BigCount>, dtype: CountDtype) -> Result<()> {
+    fn write_as<T: NumCast + ToPrimitive + WritableElement>(
+        path: &Path,
+        mat: &Array2<BigCount>,
+        dtype: CountDtype,
+    ) -> Result<()> {
+        let cast =
+            Array2::from_shape_vec(mat.dim(), cast_counts::<T>(mat.iter().copied(), dtype)?)?;
+        write_npy(path, &cast)
+    }
+    match dtype {
+        CountDtype::U16 => write_as::<u16>(path, mat, dtype),
+        CountDtype::U32 => write_as::<u32>(path, mat, dtype),
+        CountDtype::U64 => write_npy(path, mat),
+        CountDtype::F32 => write_as::<f32>(path, mat, dtype),
+        CountDtype::F64 => write_as::<f64>(path, mat, dtype),
+    }
+}
+
+/// Per-window scale factors for every k, e.g. from `--normalize`
+pub type RowScales = BTreeMap<u8, Vec<f64>>;
+
+/// Options shared by the matrix and table writers
+#[derive(Debug, Clone, Copy)]
+pub struct CountWriteOptions<'a> {
+    /// Element type of `.npy`/`.npz` arrays. Scaled values are written as
+    /// `f32` when `F32` is selected and as `f64` otherwise.
+    pub dtype: CountDtype,
+    /// Multiply the counts of window `i` by `row_scales[k][i]`
+    pub row_scales: Option<&'a RowScales>,
+    /// Also write summed counts per motif group
+    pub motif_groups: Option<&'a MotifGroups>,
+    /// Only write the group matrices
+    pub groups_only: bool,
+    /// Motifs are canonical; `k<k>_motifs.json` lists their reverse complements
+    pub canonical: bool,
+    /// BGZF-compress long-format tables and write a tabix index
+    pub bgzip: bool,
+}
+
+impl CountWriteOptions<'_> {
+    /// Raw `u64` counts without groups
+    pub fn counts() -> Self {
+        CountWriteOptions {
+            dtype: CountDtype::U64,
+            row_scales: None,
+            motif_groups: None,
+            groups_only: false,
+            canonical: false,
+            bgzip: false,
+        }
+    }
+
+    fn scales_for(&self, k: u8) -> Option<&[f64]> {
+        self.row_scales.and_then(|s| s.get(&k)).map(Vec::as_slice)
+    }
+}
+
+/// A windows × columns matrix of raw or scaled counts
+enum Values {
+    Counts(Array2<BigCount>),
+    Scaled(Array2<f64>),
+}
+
+impl Values {
+    /// Scale row `i` of `mat` by `scales[i]` (if given)
+    fn new(mat: Array2<BigCount>, scales: Option<&[f64]>) -> Self {
+        match scales {
+            None => Values::Counts(mat),
+            Some(scales) => {
+                let mut scaled = mat.mapv(|c| c as f64);
+                for (mut row, &f) in scaled.rows_mut().into_iter().zip(scales) {
+                    row *= f;
+                }
+                Values::Scaled(scaled)
+            }
+        }
+    }
+
+    /// Value at `(i, j)` as text, `None` when it is zero
+    fn nonzero_cell(&self, i: usize, j: usize) -> Option<String> {
+        match self {
+            Values::Counts(mat) => Some(mat[[i, j]]).filter(|&c| c != 0).map(|c| c.to_string()),
+            Values::Scaled(mat) => Some(mat[[i, j]])
+                .filter(|&v| v != 0.0)
+                .map(|v| v.to_string()),
+        }
+    }
+}
+
+/// Write raw or scaled values as `.npy` (see `CountWriteOptions::dtype`)
+fn write_values_npy(path: &Path, values: &Values, dtype: CountDtype) -> Result<()> {
+    match values {
+        Values::Counts(mat) => write_counts_npy(path, mat, dtype),
+        Values::Scaled(mat) if dtype == CountDtype::F32 => write_npy(path, &mat.mapv(|v| v as f32)),
+        Values::Scaled(mat) => write_npy(path, mat),
+    }
+}
+
+/// Serialise counts as a 1-D `.npy` buffer with element type `dtype`
+fn counts_to_npy(counts: &[BigCount], dtype: CountDtype) -> Result<Vec<u8>> {
+    let it = counts.iter().copied();
+    match dtype {
+        CountDtype::U16 => vec_to_npy(&cast_counts::<u16>(it, dtype)?),
+        CountDtype::U32 => vec_to_npy(&cast_counts::<u32>(it, dtype)?),
+        CountDtype::U64 => vec_to_npy(counts),
+        CountDtype::F32 => vec_to_npy(&cast_counts::<f32>(it, dtype)?),
+        CountDtype::F64 => vec_to_npy(&cast_counts::<f64>(it, dtype)?),
+    }
+}
+
+/// Identifiers of the windows (rows) in tabular output
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WindowIds {
+    /// A single genome-wide window, identified as `window = global`
+    Global,
+    /// `(chrom, start, end)` per window
+    Coordinates(Vec<(String, u64, u64)>),
+}
+
+impl WindowIds {
+    pub fn len(&self) -> usize {
+        match self {
+            WindowIds::Global => 1,
+            WindowIds::Coordinates(c) => c.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Names of the identifier columns
+    fn columns(&self) -> Vec<&'static str> {
+        match self {
+            WindowIds::Global => vec!["window"],
+            WindowIds::Coordinates(_) => vec!["chrom", "start", "end"],
+        }
+    }
+
+    /// Identifier values of window `i`
+    fn row(&self, i: usize) -> Vec<String> {
+        match self {
+            WindowIds::Global => vec!["global".to_string()],
+            WindowIds::Coordinates(c) => {
+                let (chr, start, end) = &c[i];
+                vec![chr.clone(), start.to_string(), end.to_string()]
+            }
+        }
+    }
+}
+
+/// Write one `.npy` matrix and companion `*_motifs.txt`/`*_motifs.json`
+/// files for every k present in `prepared_windows`.
 ///
 /// * `prepared_windows` – windows of decoded counts.
-/// * `kmer_specs`       – validated specs: the keys determine which k values
-///                        will be written, and in which order.
+/// * `motifs_by_k`      – ordered motifs (columns) for every k to write.
 /// * `output_dir`       – target directory.
+/// * `sparse`           – write a sparse `.npz` in this layout instead of a
+///   dense `.npy`.
+/// * `options`          – element type, row scaling and motif groups. Counts
+///   that cannot be stored exactly in `dtype` are an error. With motif
+///   groups, a `k<k>_group_counts.npy` matrix of summed group counts is
+///   written too.
 ///
 /// * For reference windows the files are named  `k<k>_counts.npy`, e.g.
 ///   `k3_counts.npy`.  
@@ -28,87 +278,1255 @@ use zip::{write::SimpleFileOptions, ZipWriter};
 /// used across all windows of that k-mer size.
 pub fn write_decoded_counts_matrix(
     prepared_windows: &[DecodedCounts],
-    kmer_specs: &HashMap<u8, KmerSpec>,
-    motifs_by_k: &HashMap<u8, Vec<String>>,
+    motifs_by_k: &BTreeMap<u8, Vec<String>>,
     output_dir: &Path,
-    save_sparse: bool,
+    sparse: Option<SparseFormat>,
+    options: &CountWriteOptions,
 ) -> anyhow::Result<()> {
     let n_win = prepared_windows.len();
+    let empty = FxHashMap::default();
 
-    for &k in kmer_specs.keys() {
-        // Collect reference bins for this k
-        let mut ref_bins: Vec<FxHashMap<String, BigCount>> = vec![FxHashMap::default(); n_win];
-        for (idx, win) in prepared_windows.iter().enumerate() {
-            if let Some(bin) = win.counts.get(&k) {
-                ref_bins[idx] = bin.clone();
+    for &k in motifs_by_k.keys() {
+        // Borrow the reference bins for this k
+        let ref_bins = bins_of_k(prepared_windows, k, &empty);
+        let tag = format!("k{}", k);
+        let scales = options.scales_for(k);
+        if let Some(groups) = options.motif_groups {
+            let (labels, mat) = group_matrix(&ref_bins, groups, k as usize);
+            if !labels.is_empty() && n_win > 0 {
+                write_values_npy(
+                    &output_dir.join(format!("{tag}_group_counts.npy")),
+                    &Values::new(mat, scales),
+                    options.dtype,
+                )?;
+                write_lines(&output_dir.join(format!("{tag}_groups.txt")), &labels)?;
+            }
+            if options.groups_only {
+                continue;
             }
         }
-        let tag = format!("k{}", k);
-        if save_sparse {
-            write_category_sparse(&mut ref_bins, &motifs_by_k[&k], &tag, output_dir)?;
+        if let Some(format) = sparse {
+            write_category_sparse(
+                &ref_bins,
+                &motifs_by_k[&k],
+                &tag,
+                output_dir,
+                format,
+                options.dtype,
+                scales,
+            )?;
         } else {
-            write_category(&mut ref_bins, &motifs_by_k[&k], &tag, output_dir)?;
+            write_category(
+                &ref_bins,
+                &motifs_by_k[&k],
+                &tag,
+                output_dir,
+                options.dtype,
+                scales,
+            )?;
+        }
+        if n_win > 0 {
+            write_motif_lists(output_dir, &tag, &motifs_by_k[&k], options.canonical)?;
         }
     }
 
     Ok(())
 }
 
-/// Write <prefix>_counts.npy and <prefix>_motifs.txt
+/// Write <prefix>_counts.npy
 ///
 /// * `motifs`  - The motifs to include for all bins in the order you want it saved in.
 fn write_category(
-    bins: &[FxHashMap<String, BigCount>],
+    bins: &[&FxHashMap<String, BigCount>],
     motifs: &[String],
     prefix: &str,
     out_dir: &Path,
+    dtype: CountDtype,
+    scales: Option<&[f64]>,
 ) -> anyhow::Result<()> {
     if bins.is_empty() {
         return Ok(()); // nothing to write
     }
 
-    // Output matrix
-    let n_rows = bins.len();
-    let n_cols = motifs.len();
-    let mut mat = Array2::<BigCount>::zeros((n_rows, n_cols));
+    let values = Values::new(dense_matrix(bins, motifs), scales);
+
+    // Persist outputs
+    write_values_npy(
+        &out_dir.join(format!("{prefix}_counts.npy")),
+        &values,
+        dtype,
+    )
+}
+
+/// Write the column motifs as `<prefix>_motifs.txt` (one per line) and
+/// `<prefix>_motifs.json`.
+///
+/// The JSON maps every motif to its `column`, whether it is `palindromic`
+/// (its own reverse complement) and, when `canonical`, the
+/// `reverse_complement` whose counts were merged into it.
+pub fn write_motif_lists(
+    out_dir: &Path,
+    prefix: &str,
+    motifs: &[String],
+    canonical: bool,
+) -> Result<()> {
+    write_lines(&out_dir.join(format!("{prefix}_motifs.txt")), motifs)?;
+    let path = out_dir.join(format!("{prefix}_motifs.json"));
+    let index = File::create(&path).context(format!("Create {:?} fail", path))?;
+    serde_json::to_writer_pretty(BufWriter::new(index), &motif_index(motifs, canonical))
+        .context(format!("Writing {:?}", path))
+}
+
+/// Motif → column index (see `write_motif_lists`)
+pub fn motif_index(motifs: &[String], canonical: bool) -> Value {
+    let index: Map<String, Value> = motifs
+        .iter()
+        .enumerate()
+        .map(|(col, motif)| {
+            let rc = revcomp(motif);
+            let mut entry = json!({
+                "column": col,
+                "palindromic": rc == *motif,
+            });
+            if canonical {
+                entry["reverse_complement"] = json!(rc);
+            }
+            (motif.clone(), entry)
+        })
+        .collect();
+    json!({ "canonical": canonical, "motifs": index })
+}
+
+/// Write `k<k>_diversity.tsv` with the Shannon entropy, distinct-motif
+/// fraction and linguistic complexity of every window (rows in the order of
+/// the count matrices). `n_possible` holds the number of possible motifs of
+/// every k.
+pub fn write_kmer_diversity(
+    windows: &[DecodedCounts],
+    n_possible: &BTreeMap<u8, f64>,
+    out_dir: &Path,
+) -> Result<()> {
+    let empty = FxHashMap::default();
+    for &k in n_possible.keys() {
+        let path = out_dir.join(format!("k{k}_diversity.tsv"));
+        let mut writer =
+            BufWriter::new(File::create(&path).context(format!("Create {:?} fail", path))?);
+        writeln!(
+            writer,
+            "shannon_entropy\tdistinct_fraction\tlinguistic_complexity"
+        )?;
+        for window in windows {
+            let d = kmer_diversity(window.counts.get(&k).unwrap_or(&empty), n_possible[&k]);
+            writeln!(
+                writer,
+                "{}\t{}\t{}",
+                d.shannon_entropy, d.distinct_fraction, d.linguistic_complexity
+            )?;
+        }
+        writer.flush().context(format!("Writing {:?}", path))?;
+    }
+    Ok(())
+}
+
+/// Write the repeat content of every window as `homopolymer_runs.npy` and
+/// `str_bases.npy` (rows in the order of the count matrices), with their
+/// column names in `homopolymer_runs_columns.txt` and
+/// `str_bases_columns.txt`.
+pub fn write_repeat_content(windows: &[RepeatContent], out_dir: &Path) -> Result<()> {
+    let homopolymers = RepeatContent::homopolymer_columns();
+    let strs = RepeatContent::str_columns();
+    let mut homopolymer_mat = Array2::<BigCount>::zeros((windows.len(), homopolymers.len()));
+    let mut str_mat = Array2::<BigCount>::zeros((windows.len(), strs.len()));
+    for (row, window) in windows.iter().enumerate() {
+        for (col, &n) in window.homopolymer_runs.iter().enumerate() {
+            homopolymer_mat[[row, col]] = n;
+        }
+        for (col, &n) in window.str_bases.iter().enumerate() {
+            str_mat[[row, col]] = n;
+        }
+    }
+    for (name, mat, columns) in [
+        ("homopolymer_runs", homopolymer_mat, homopolymers),
+        ("str_bases", str_mat, strs),
+    ] {
+        let path = out_dir.join(format!("{name}.npy"));
+        write_npy(&path, &mat)?;
+        write_lines(&out_dir.join(format!("{name}_columns.txt")), &columns)?;
+    }
+    Ok(())
+}
+
+/// Write the PWM hits of every window as `pwm_hits.npy` (number of sites)
+/// and `pwm_scores.npy` (sum of site scores), with rows in the order of
+/// the count matrices and the PWM `names` in `pwm_names.txt`.
+pub fn write_pwm_hits(windows: &[PwmHits], names: &[String], out_dir: &Path) -> Result<()> {
+    let mut hits_mat = Array2::<BigCount>::zeros((windows.len(), names.len()));
+    let mut scores_mat = Array2::<f64>::zeros((windows.len(), names.len()));
+    for (row, window) in windows.iter().enumerate() {
+        for (col, (&n, &score)) in window.hits.iter().zip(&window.scores).enumerate() {
+            hits_mat[[row, col]] = n;
+            scores_mat[[row, col]] = score;
+        }
+    }
+    let path = out_dir.join("pwm_hits.npy");
+    write_npy(&path, &hits_mat)?;
+    let path = out_dir.join("pwm_scores.npy");
+    write_npy(&path, &scores_mat)?;
+    write_lines(&out_dir.join("pwm_names.txt"), names)
+}
+
+/// Subdirectory of every chromosome of `chroms` for `--split-by-chromosome`
+/// (see `safe_file_name`). Fails when two chromosomes would share one.
+pub fn chromosome_dir_names(chroms: &[&str]) -> Result<Vec<String>> {
+    let mut seen: HashMap<String, &str> = HashMap::new();
+    let mut names = Vec::with_capacity(chroms.len());
+    for &chr in chroms {
+        let name = safe_file_name(chr);
+        if let Some(other) = seen.insert(name.clone(), chr) {
+            bail!(
+                "Chromosomes '{}' and '{}' would both be written to the subdirectory '{}'",
+                other,
+                chr,
+                name
+            );
+        }
+        names.push(name);
+    }
+    Ok(names)
+}
+
+/// Write one entry per line (motif, group and input lists)
+pub fn write_lines(path: &Path, lines: &[String]) -> Result<()> {
+    let mut txt = File::create(path).context(format!("Create {:?} fail", path))?;
+    for l in lines {
+        writeln!(txt, "{l}")?;
+    }
+    Ok(())
+}
+
+/// Borrow the counts of `k` in every window, with `empty` for windows
+/// without them
+fn bins_of_k<'a>(
+    windows: &'a [DecodedCounts],
+    k: u8,
+    empty: &'a FxHashMap<String, BigCount>,
+) -> Vec<&'a FxHashMap<String, BigCount>> {
+    windows
+        .iter()
+        .map(|win| win.counts.get(&k).unwrap_or(empty))
+        .collect()
+}
+
+/// Dense windows × motifs matrix with columns in the order of `motifs`
+fn dense_matrix(bins: &[&FxHashMap<String, BigCount>], motifs: &[String]) -> Array2<BigCount> {
+    let mut mat = Array2::<BigCount>::zeros((bins.len(), motifs.len()));
 
     // Pre-compute motif → column index once
     let col_of: FxHashMap<_, _> = motifs.iter().enumerate().map(|(c, m)| (m, c)).collect();
 
-    for (row, hm) in bins.iter().enumerate() {
+    for (row, &hm) in bins.iter().enumerate() {
         for (motif, &cnt) in hm {
             if let Some(&col) = col_of.get(motif) {
                 mat[(row, col)] = cnt; // Counts overwrite the zero
             }
         }
     }
+    mat
+}
 
-    // Persist outputs
-    write_npy(out_dir.join(format!("{prefix}_counts.npy")), &mat)?;
+/// Group labels used for `k` and the windows × groups matrix of summed counts.
+///
+/// Counts of motifs in the same group are summed. Motifs without a group
+/// are ignored. Columns follow the order of first appearance in the mapping.
+fn group_matrix(
+    bins: &[&FxHashMap<String, BigCount>],
+    groups: &MotifGroups,
+    k: usize,
+) -> (Vec<String>, Array2<BigCount>) {
+    let labels = groups.labels_for_k(k);
+    // Global group index → column in this k's matrix
+    let label_col: FxHashMap<&str, usize> = labels
+        .iter()
+        .enumerate()
+        .map(|(c, l)| (l.as_str(), c))
+        .collect();
+
+    let mut mat = Array2::<BigCount>::zeros((bins.len(), labels.len()));
+    for (row, &hm) in bins.iter().enumerate() {
+        for (motif, &cnt) in hm {
+            if let Some(&g) = groups.index.get(motif) {
+                let col = label_col[groups.labels[g].as_str()];
+                mat[(row, col)] += cnt;
+            }
+        }
+    }
+    (labels, mat)
+}
+
+// Tabular version
+
+/// Writes one table: `(path, window_ids, column names, windows × columns values)`
+type TableWriter = fn(&Path, &WindowIds, &[String], &Values) -> Result<()>;
+
+/// Write one `k<k>_counts.<tsv|arrow>` table for every k in `motifs_by_k`.
+///
+/// Rows are windows, prefixed by the identifier columns in `window_ids`,
+/// followed by one column per motif.
+/// With motif groups in `options`, a `k<k>_group_counts.<tsv|arrow>` table
+/// of summed group counts is written too (and only that table with
+/// `groups_only`). Scaled values are written as floats.
+///
+/// `format` must be a tabular format (`Tsv`, `Arrow`, `Long` or `Hdf5`).
+/// `Long` writes all k into a single `counts_long.tsv` (see `write_long`)
+/// and `Hdf5` into a single `counts.h5`.
+pub fn write_decoded_counts_table(
+    prepared_windows: &[DecodedCounts],
+    motifs_by_k: &BTreeMap<u8, Vec<String>>,
+    window_ids: &WindowIds,
+    output_dir: &Path,
+    format: OutputFormat,
+    options: &CountWriteOptions,
+) -> Result<()> {
+    if window_ids.len() != prepared_windows.len() {
+        bail!(
+            "Got {} window identifiers for {} windows",
+            window_ids.len(),
+            prepared_windows.len()
+        );
+    }
+    let (ext, write_table): (_, TableWriter) = match format {
+        OutputFormat::Tsv => ("tsv", write_tsv),
+        OutputFormat::Arrow => ("arrow", write_arrow),
+        OutputFormat::Long => {
+            return write_decoded_counts_long(
+                prepared_windows,
+                motifs_by_k,
+                window_ids,
+                output_dir,
+                options,
+            )
+        }
+        OutputFormat::Hdf5 => {
+            return write_decoded_counts_hdf5(
+                prepared_windows,
+                motifs_by_k,
+                window_ids,
+                output_dir,
+                options,
+            )
+        }
+        OutputFormat::Npy => bail!("npy is not a tabular output format"),
+    };
+    let empty = FxHashMap::default();
+    for (&k, motifs) in motifs_by_k {
+        let bins = bins_of_k(prepared_windows, k, &empty);
+        let scales = options.scales_for(k);
+        if let Some(groups) = options.motif_groups {
+            let (labels, mat) = group_matrix(&bins, groups, k as usize);
+            if !labels.is_empty() {
+                write_table(
+                    &output_dir.join(format!("k{k}_group_counts.{ext}")),
+                    window_ids,
+                    &labels,
+                    &Values::new(mat, scales),
+                )?;
+            }
+            if options.groups_only {
+                continue;
+            }
+        }
+        write_table(
+            &output_dir.join(format!("k{k}_counts.{ext}")),
+            window_ids,
+            motifs,
+            &Values::new(dense_matrix(&bins, motifs), scales),
+        )?;
+    }
+    Ok(())
+}
+
+/// Write all k into a single `counts.h5`: per k a `k<k>/counts` matrix
+/// (chunked by rows and deflate-compressed, with elements of `dtype` like
+/// the `.npy` matrices) with its `k<k>/motifs` columns, the
+/// `k<k>/group_counts` matrix and `k<k>/groups` with motif groups, and the
+/// window identifiers as `windows/chrom`, `windows/start` and
+/// `windows/end` (`windows/window` for a global window).
+fn write_decoded_counts_hdf5(
+    prepared_windows: &[DecodedCounts],
+    motifs_by_k: &BTreeMap<u8, Vec<String>>,
+    window_ids: &WindowIds,
+    output_dir: &Path,
+    options: &CountWriteOptions,
+) -> Result<()> {
+    let h5 = H5Writer::create(&output_dir.join("counts.h5"))?;
+    let empty = FxHashMap::default();
+    for (&k, motifs) in motifs_by_k {
+        let bins = bins_of_k(prepared_windows, k, &empty);
+        let scales = options.scales_for(k);
+        let group = format!("k{k}");
+        if let Some(groups) = options.motif_groups {
+            let (labels, mat) = group_matrix(&bins, groups, k as usize);
+            if !labels.is_empty() {
+                let values = Values::new(mat, scales);
+                h5.write_values(&group, "group_counts", &values, options.dtype)?;
+                h5.write_strings(&group, "groups", &labels)?;
+            }
+            if options.groups_only {
+                continue;
+            }
+        }
+        let values = Values::new(dense_matrix(&bins, motifs), scales);
+        h5.write_values(&group, "counts", &values, options.dtype)?;
+        h5.write_strings(&group, "motifs", motifs)?;
+    }
+    match window_ids {
+        WindowIds::Global => h5.write_strings("windows", "window", &["global".to_string()])?,
+        WindowIds::Coordinates(coordinates) => {
+            let chroms: Vec<String> = coordinates.iter().map(|(c, _, _)| c.clone()).collect();
+            let starts: Vec<u64> = coordinates.iter().map(|&(_, s, _)| s).collect();
+            let ends: Vec<u64> = coordinates.iter().map(|&(_, _, e)| e).collect();
+            h5.write_strings("windows", "chrom", &chroms)?;
+            h5.write_u64s("windows", "start", &starts)?;
+            h5.write_u64s("windows", "end", &ends)?;
+        }
+    }
+    h5.finish()
+}
+
+#[cfg(feature = "hdf5")]
+mod h5 {
+    use super::{cast_counts, CountDtype, Values};
+    use crate::cli::BigCount;
+    use anyhow::{anyhow, Context, Result};
+    use hdf5::types::VarLenUnicode;
+    use hdf5::H5Type;
+    use ndarray::Array2;
+    use num_traits::{NumCast, ToPrimitive};
+    use std::path::{Path, PathBuf};
+
+    /// Rows per chunk of the matrices
+    const CHUNK_ROWS: usize = 1024;
+
+    /// Deflate (gzip) level of the matrices
+    const DEFLATE_LEVEL: u8 = 4;
+
+    /// Writes datasets into the groups of one `.h5` file
+    pub struct H5Writer {
+        file: hdf5::File,
+        path: PathBuf,
+    }
+
+    impl H5Writer {
+        pub fn create(path: &Path) -> Result<Self> {
+            let file = hdf5::File::create(path).context(format!("Create {:?} fail", path))?;
+            Ok(H5Writer {
+                file,
+                path: path.to_path_buf(),
+            })
+        }
+
+        /// Write a windows × columns matrix as `group/name`
+        pub fn write_values(
+            &self,
+            group: &str,
+            name: &str,
+            values: &Values,
+            dtype: CountDtype,
+        ) -> Result<()> {
+            match values {
+                Values::Counts(mat) => match dtype {
+                    CountDtype::U16 => self.write_cast::<u16>(group, name, mat, dtype),
+                    CountDtype::U32 => self.write_cast::<u32>(group, name, mat, dtype),
+                    CountDtype::U64 => self.write_matrix(group, name, mat),
+                    CountDtype::F32 => self.write_cast::<f32>(group, name, mat, dtype),
+                    CountDtype::F64 => self.write_cast::<f64>(group, name, mat, dtype),
+                },
+                Values::Scaled(mat) if dtype == CountDtype::F32 => {
+                    self.write_matrix(group, name, &mat.mapv(|v| v as f32))
+                }
+                Values::Scaled(mat) => self.write_matrix(group, name, mat),
+            }
+        }
+
+        /// Write variable-length UTF-8 strings as `group/name`
+        pub fn write_strings(&self, group: &str, name: &str, strings: &[String]) -> Result<()> {
+            let strings = strings
+                .iter()
+                .map(|s| {
+                    s.parse::<VarLenUnicode>()
+                        .map_err(|e| anyhow!("Cannot store {:?} in HDF5: {}", s, e))
+                })
+                .collect::<Result<Vec<_>>>()?;
+            self.group(group)?
+                .new_dataset_builder()
+                .with_data(&strings[..])
+                .create(name)
+                .context(self.write_fail(group, name))?;
+            Ok(())
+        }
+
+        /// Write integers as `group/name`
+        pub fn write_u64s(&self, group: &str, name: &str, values: &[u64]) -> Result<()> {
+            self.group(group)?
+                .new_dataset_builder()
+                .with_data(values)
+                .create(name)
+                .context(self.write_fail(group, name))?;
+            Ok(())
+        }
+
+        pub fn finish(self) -> Result<()> {
+            let path = self.path;
+            self.file.close().context(format!("Write {:?} fail", path))
+        }
+
+        fn write_cast<T: NumCast + ToPrimitive + H5Type>(
+            &self,
+            group: &str,
+            name: &str,
+            mat: &Array2<BigCount>,
+            dtype: CountDtype,
+        ) -> Result<()> {
+            let cast =
+                Array2::from_shape_vec(mat.dim(), cast_counts::<T>(mat.iter().copied(), dtype)?)?;
+            self.write_matrix(group, name, &cast)
+        }
+
+        fn write_matrix<T: H5Type>(&self, group: &str, name: &str, mat: &Array2<T>) -> Result<()> {
+            let (rows, cols) = mat.dim();
+            let builder = self.group(group)?.new_dataset_builder().with_data(mat);
+            // Empty datasets cannot be chunked
+            let builder = if rows > 0 && cols > 0 {
+                builder
+                    .chunk((rows.min(CHUNK_ROWS), cols))
+                    .deflate(DEFLATE_LEVEL)
+            } else {
+                builder
+            };
+            builder.create(name).context(self.write_fail(group, name))?;
+            Ok(())
+        }
+
+        /// The group `name`, created on first use
+        fn group(&self, name: &str) -> Result<hdf5::Group> {
+            if self.file.link_exists(name) {
+                self.file.group(name)
+            } else {
+                self.file.create_group(name)
+            }
+            .context(self.write_fail(name, ""))
+        }
+
+        fn write_fail(&self, group: &str, name: &str) -> String {
+            format!("Write {}/{} to {:?} fail", group, name, self.path)
+        }
+    }
+}
+
+/// Stand-in used when built without the `hdf5` feature
+#[cfg(not(feature = "hdf5"))]
+mod h5 {
+    use super::{CountDtype, Values};
+    use anyhow::{bail, Result};
+    use std::path::Path;
+
+    pub struct H5Writer;
+
+    impl H5Writer {
+        pub fn create(path: &Path) -> Result<Self> {
+            bail!(
+                "Writing {:?} requires HDF5 support; rebuild with `cargo install --features hdf5`",
+                path
+            )
+        }
+
+        pub fn write_values(&self, _: &str, _: &str, _: &Values, _: CountDtype) -> Result<()> {
+            unreachable!()
+        }
+
+        pub fn write_strings(&self, _: &str, _: &str, _: &[String]) -> Result<()> {
+            unreachable!()
+        }
+
+        pub fn write_u64s(&self, _: &str, _: &str, _: &[u64]) -> Result<()> {
+            unreachable!()
+        }
+
+        pub fn finish(self) -> Result<()> {
+            unreachable!()
+        }
+    }
+}
+
+/// Write the counts of all k as `counts_long.tsv` (see `write_long`), and
+/// group counts as `group_counts_long.tsv` with motif groups in `options`
+fn write_decoded_counts_long(
+    prepared_windows: &[DecodedCounts],
+    motifs_by_k: &BTreeMap<u8, Vec<String>>,
+    window_ids: &WindowIds,
+    output_dir: &Path,
+    options: &CountWriteOptions,
+) -> Result<()> {
+    let ext = if options.bgzip { "tsv.gz" } else { "tsv" };
+    let mut counts = Vec::new();
+    let mut group_counts = Vec::new();
+    let empty = FxHashMap::default();
+    for &k in motifs_by_k.keys() {
+        let bins = bins_of_k(prepared_windows, k, &empty);
+        let scales = options.scales_for(k);
+        if let Some(groups) = options.motif_groups {
+            let (labels, mat) = group_matrix(&bins, groups, k as usize);
+            if !labels.is_empty() {
+                group_counts.push((k, labels, Values::new(mat, scales)));
+            }
+            if options.groups_only {
+                continue;
+            }
+        }
+        let motifs = &motifs_by_k[&k];
+        let values = Values::new(dense_matrix(&bins, motifs), scales);
+        counts.push((k, motifs.clone(), values));
+    }
+    if !counts.is_empty() {
+        let path = output_dir.join(format!("counts_long.{ext}"));
+        write_long(&path, window_ids, "motif", &counts, options.bgzip)?;
+    }
+    if !group_counts.is_empty() {
+        let path = output_dir.join(format!("group_counts_long.{ext}"));
+        write_long(&path, window_ids, "group", &group_counts, options.bgzip)?;
+    }
+    Ok(())
+}
 
-    let mut txt = File::create(out_dir.join(format!("{prefix}_motifs.txt")))?;
-    for m in motifs {
-        writeln!(txt, "{m}")?;
+/// Write one `<window ids>, k, <label_column>, count` row per window, k and
+/// non-zero value, in window order.
+///
+/// The header line starts with `#`. With `bgzip` the file is BGZF-compressed
+/// and, for sorted window coordinates, a tabix index (`<path>.tbi`) is written.
+fn write_long(
+    path: &Path,
+    window_ids: &WindowIds,
+    label_column: &str,
+    tables: &[(u8, Vec<String>, Values)],
+    bgzip: bool,
+) -> Result<()> {
+    let file = BufWriter::new(File::create(path).context(format!("Create {:?} fail", path))?);
+    let mut header: Vec<&str> = window_ids.columns();
+    header.extend(["k", label_column, "count"]);
+    let header = format!("#{}\n", header.join("\t"));
+    let write_err = || format!("Write {:?} fail", path);
+
+    if !bgzip {
+        let mut out = file;
+        out.write_all(header.as_bytes()).context(write_err())?;
+        for i in 0..window_ids.len() {
+            let ids = window_ids.row(i).join("\t");
+            for (k, labels, values) in tables {
+                for (j, label) in labels.iter().enumerate() {
+                    if let Some(value) = values.nonzero_cell(i, j) {
+                        writeln!(out, "{ids}\t{k}\t{label}\t{value}").context(write_err())?;
+                    }
+                }
+            }
+        }
+        return out.flush().context(write_err());
     }
 
+    let mut out = BgzfWriter::new(file);
+    out.write_all(header.as_bytes()).context(write_err())?;
+    let coords = match window_ids {
+        WindowIds::Coordinates(coords) => Some(coords),
+        WindowIds::Global => None,
+    };
+    let mut index = coords.map(|_| TabixIndexBuilder::default());
+    for i in 0..window_ids.len() {
+        let ids = window_ids.row(i).join("\t");
+        for (k, labels, values) in tables {
+            for (j, label) in labels.iter().enumerate() {
+                let Some(value) = values.nonzero_cell(i, j) else {
+                    continue;
+                };
+                let vstart = out.virtual_offset();
+                writeln!(out, "{ids}\t{k}\t{label}\t{value}").context(write_err())?;
+                if let (Some(builder), Some(coords)) = (index.as_mut(), coords) {
+                    let (chr, start, end) = &coords[i];
+                    if let Err(e) = builder.add(chr, *start, *end, vstart, out.virtual_offset()) {
+                        eprintln!("Warning: {:?} is not indexed: {}", path, e);
+                        index = None;
+                    }
+                }
+            }
+        }
+    }
+    out.finish().context(write_err())?;
+    if let Some(builder) = index {
+        builder.write(&PathBuf::from(format!("{}.tbi", path.display())))?;
+    }
     Ok(())
 }
 
+/// Write `mat` with a header row and leading identifier columns
+fn write_tsv(
+    path: &Path,
+    window_ids: &WindowIds,
+    columns: &[String],
+    values: &Values,
+) -> Result<()> {
+    let mut out = BufWriter::new(File::create(path).context(format!("Create {:?} fail", path))?);
+    let header: Vec<&str> = window_ids
+        .columns()
+        .into_iter()
+        .chain(columns.iter().map(String::as_str))
+        .collect();
+    writeln!(out, "{}", header.join("\t"))?;
+    for i in 0..window_ids.len() {
+        let mut fields = window_ids.row(i);
+        match values {
+            Values::Counts(mat) => fields.extend(mat.row(i).iter().map(|c| c.to_string())),
+            Values::Scaled(mat) => fields.extend(mat.row(i).iter().map(|v| v.to_string())),
+        }
+        writeln!(out, "{}", fields.join("\t"))?;
+    }
+    out.flush().context(format!("Write {:?} fail", path))?;
+    Ok(())
+}
+
+/// Write `mat` as a single-batch Arrow IPC file with leading identifier
+/// columns (`chrom` as utf8, `start`/`end` as uint64) and one uint64
+/// (float64 when scaled) column per entry of `columns`
+fn write_arrow(
+    path: &Path,
+    window_ids: &WindowIds,
+    columns: &[String],
+    values: &Values,
+) -> Result<()> {
+    let mut fields: Vec<Field> = Vec::with_capacity(columns.len() + 3);
+    let mut arrays: Vec<ArrayRef> = Vec::with_capacity(columns.len() + 3);
+    match window_ids {
+        WindowIds::Global => {
+            fields.push(Field::new("window", DataType::Utf8, false));
+            arrays.push(Arc::new(StringArray::from(vec!["global"])));
+        }
+        WindowIds::Coordinates(coords) => {
+            fields.push(Field::new("chrom", DataType::Utf8, false));
+            fields.push(Field::new("start", DataType::UInt64, false));
+            fields.push(Field::new("end", DataType::UInt64, false));
+            arrays.push(Arc::new(StringArray::from_iter_values(
+                coords.iter().map(|(chr, _, _)| chr),
+            )));
+            arrays.push(Arc::new(UInt64Array::from_iter_values(
+                coords.iter().map(|(_, start, _)| *start),
+            )));
+            arrays.push(Arc::new(UInt64Array::from_iter_values(
+                coords.iter().map(|(_, _, end)| *end),
+            )));
+        }
+    }
+    match values {
+        Values::Counts(mat) => {
+            for (name, col) in columns.iter().zip(mat.columns()) {
+                fields.push(Field::new(name, DataType::UInt64, false));
+                arrays.push(Arc::new(UInt64Array::from_iter_values(col.iter().copied())));
+            }
+        }
+        Values::Scaled(mat) => {
+            for (name, col) in columns.iter().zip(mat.columns()) {
+                fields.push(Field::new(name, DataType::Float64, false));
+                arrays.push(Arc::new(Float64Array::from_iter_values(
+                    col.iter().copied(),
+                )));
+            }
+        }
+    }
+
+    let schema = Arc::new(Schema::new(fields));
+    let batch = RecordBatch::try_new(schema.clone(), arrays)
+        .context(format!("Building Arrow table for {:?}", path))?;
+    let file = File::create(path).context(format!("Create {:?} fail", path))?;
+    let mut writer = FileWriter::try_new(BufWriter::new(file), &schema)?;
+    writer.write(&batch)?;
+    writer.finish().context(format!("Write {:?} fail", path))?;
+    Ok(())
+}
+
+// Streaming version
+
+/// Appends window rows to one `.npy` or `.tsv` file per k (and motif group
+/// table) as they are produced, so the full matrix is never held in memory.
+///
+/// The columns are fixed up front by `motifs_by_k`. `.npy` files are
+/// created with a placeholder header that is completed by `finish()`.
+pub struct StreamingCountWriter {
+    format: OutputFormat,
+    dtype: CountDtype,
+    scaled: bool,
+    motif_groups: Option<MotifGroups>,
+    groups_only: bool,
+    /// Output files per k, in increasing k
+    sinks: Vec<KSinks>,
+}
+
+/// Output files of one k
+struct KSinks {
+    k: u8,
+    /// Columns of the counts file
+    motifs: Vec<String>,
+    counts: Option<RowSink>,
+    groups: Option<RowSink>,
+}
+
+/// An open file that rows are appended to
+struct RowSink {
+    path: std::path::PathBuf,
+    out: BufWriter<File>,
+    n_rows: u64,
+    n_cols: usize,
+}
+
+/// Length reserved for `.npy` headers so the final shape can be filled in
+const NPY_STREAM_HEADER_LEN: usize = 128;
+
+impl StreamingCountWriter {
+    /// Create the output files for every k in `motifs_by_k` in `output_dir`.
+    ///
+    /// * `format` – `Npy` or `Tsv`.
+    /// * `scaled` – rows will be written with row scales (float output).
+    ///
+    /// Motif (and group) lists are written right away.
+    pub fn create(
+        output_dir: &Path,
+        format: OutputFormat,
+        motifs_by_k: &BTreeMap<u8, Vec<String>>,
+        options: &CountWriteOptions,
+        scaled: bool,
+    ) -> Result<Self> {
+        let ext = match format {
+            OutputFormat::Npy => "npy",
+            OutputFormat::Tsv => "tsv",
+            OutputFormat::Arrow | OutputFormat::Long | OutputFormat::Hdf5 => {
+                bail!("{:?} output cannot be streamed", format)
+            }
+        };
+        let mut writer = StreamingCountWriter {
+            format,
+            dtype: options.dtype,
+            scaled,
+            motif_groups: options.motif_groups.cloned(),
+            groups_only: options.groups_only,
+            sinks: Vec::new(),
+        };
+        for &k in motifs_by_k.keys() {
+            let motifs = motifs_by_k[&k].clone();
+            let groups = match &writer.motif_groups {
+                Some(groups) => {
+                    let labels = groups.labels_for_k(k as usize);
+                    if labels.is_empty() {
+                        None
+                    } else {
+                        let path = output_dir.join(format!("k{k}_group_counts.{ext}"));
+                        let sink = writer.open_sink(&path, &labels)?;
+                        if format == OutputFormat::Npy {
+                            write_lines(&output_dir.join(format!("k{k}_groups.txt")), &labels)?;
+                        }
+                        Some(sink)
+                    }
+                }
+                None => None,
+            };
+            let counts = if writer.groups_only && writer.motif_groups.is_some() {
+                None
+            } else {
+                let path = output_dir.join(format!("k{k}_counts.{ext}"));
+                let sink = writer.open_sink(&path, &motifs)?;
+                if format == OutputFormat::Npy {
+                    write_motif_lists(output_dir, &format!("k{k}"), &motifs, options.canonical)?;
+                }
+                Some(sink)
+            };
+            writer.sinks.push(KSinks {
+                k,
+                motifs,
+                counts,
+                groups,
+            });
+        }
+        Ok(writer)
+    }
+
+    fn open_sink(&self, path: &Path, columns: &[String]) -> Result<RowSink> {
+        let file = File::create(path).context(format!("Create {:?} fail", path))?;
+        let mut sink = RowSink {
+            path: path.to_path_buf(),
+            out: BufWriter::new(file),
+            n_rows: 0,
+            n_cols: columns.len(),
+        };
+        match self.format {
+            OutputFormat::Npy => {
+                let header = npy_stream_header(self.descr(), 0, sink.n_cols)?;
+                sink.out.write_all(&header)?;
+            }
+            _ => {
+                let header: Vec<&str> = ["chrom", "start", "end"]
+                    .into_iter()
+                    .chain(columns.iter().map(String::as_str))
+                    .collect();
+                writeln!(sink.out, "{}", header.join("\t"))?;
+            }
+        }
+        Ok(sink)
+    }
+
+    /// NumPy type descriptor of the written values
+    fn descr(&self) -> &'static str {
+        match (self.scaled, self.dtype) {
+            (_, CountDtype::F32) => "<f4",
+            (true, _) | (false, CountDtype::F64) => "<f8",
+            (false, CountDtype::U16) => "<u2",
+            (false, CountDtype::U32) => "<u4",
+            (false, CountDtype::U64) => "<u8",
+        }
+    }
+
+    /// Append the rows of `windows`, identified by `window_ids`.
+    ///
+    /// `row_scales` must be given exactly when the writer was created as
+    /// `scaled`.
+    pub fn write_rows(
+        &mut self,
+        windows: &[DecodedCounts],
+        window_ids: &WindowIds,
+        row_scales: Option<&RowScales>,
+    ) -> Result<()> {
+        if window_ids.len() != windows.len() {
+            bail!(
+                "Got {} window identifiers for {} windows",
+                window_ids.len(),
+                windows.len()
+            );
+        }
+        if row_scales.is_some() != self.scaled {
+            bail!("Row scales must be given exactly for scaled streaming output");
+        }
+        let (format, dtype) = (self.format, self.dtype);
+        let empty = FxHashMap::default();
+        for KSinks {
+            k,
+            motifs,
+            counts,
+            groups,
+        } in self.sinks.iter_mut()
+        {
+            let bins = bins_of_k(windows, *k, &empty);
+            let scales = row_scales.and_then(|s| s.get(k)).map(Vec::as_slice);
+            if let (Some(sink), Some(mg)) = (groups.as_mut(), self.motif_groups.as_ref()) {
+                let (_, mat) = group_matrix(&bins, mg, *k as usize);
+                sink.append(&Values::new(mat, scales), window_ids, format, dtype)?;
+            }
+            if let Some(sink) = counts.as_mut() {
+                let values = Values::new(dense_matrix(&bins, motifs), scales);
+                sink.append(&values, window_ids, format, dtype)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Flush all files and complete the `.npy` headers
+    pub fn finish(self) -> Result<()> {
+        let descr = self.descr();
+        for KSinks { counts, groups, .. } in self.sinks {
+            for sink in counts.into_iter().chain(groups) {
+                let mut file = sink
+                    .out
+                    .into_inner()
+                    .map_err(|e| e.into_error())
+                    .context("Flushing streamed counts")?;
+                if self.format == OutputFormat::Npy {
+                    use std::io::{Seek, SeekFrom};
+                    file.seek(SeekFrom::Start(0))?;
+                    file.write_all(&npy_stream_header(descr, sink.n_rows, sink.n_cols)?)
+                        .context(format!("Write {:?} fail", sink.path))?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl RowSink {
+    fn append(
+        &mut self,
+        values: &Values,
+        window_ids: &WindowIds,
+        format: OutputFormat,
+        dtype: CountDtype,
+    ) -> Result<()> {
+        match format {
+            OutputFormat::Npy => {
+                let bytes: Vec<u8> = match values {
+                    Values::Counts(mat) => {
+                        let it = mat.iter().copied();
+                        match dtype {
+                            CountDtype::U16 => {
+                                le_bytes(cast_counts::<u16>(it, dtype)?, u16::to_le_bytes)
+                            }
+                            CountDtype::U32 => {
+                                le_bytes(cast_counts::<u32>(it, dtype)?, u32::to_le_bytes)
+                            }
+                            CountDtype::U64 => le_bytes(it, u64::to_le_bytes),
+                            CountDtype::F32 => {
+                                le_bytes(cast_counts::<f32>(it, dtype)?, f32::to_le_bytes)
+                            }
+                            CountDtype::F64 => {
+                                le_bytes(cast_counts::<f64>(it, dtype)?, f64::to_le_bytes)
+                            }
+                        }
+                    }
+                    Values::Scaled(mat) if dtype == CountDtype::F32 => {
+                        le_bytes(mat.iter().map(|&v| v as f32), f32::to_le_bytes)
+                    }
+                    Values::Scaled(mat) => le_bytes(mat.iter().copied(), f64::to_le_bytes),
+                };
+                self.out
+                    .write_all(&bytes)
+                    .context(format!("Write {:?} fail", self.path))?;
+            }
+            _ => {
+                for i in 0..window_ids.len() {
+                    let mut fields = window_ids.row(i);
+                    match values {
+                        Values::Counts(mat) => {
+                            fields.extend(mat.row(i).iter().map(|c| c.to_string()))
+                        }
+                        Values::Scaled(mat) => {
+                            fields.extend(mat.row(i).iter().map(|v| v.to_string()))
+                        }
+                    }
+                    writeln!(self.out, "{}", fields.join("\t"))?;
+                }
+            }
+        }
+        self.n_rows += window_ids.len() as u64;
+        Ok(())
+    }
+}
+
+/// Little-endian bytes of all values
+fn le_bytes<T, const N: usize>(
+    values: impl IntoIterator<Item = T>,
+    to_bytes: fn(T) -> [u8; N],
+) -> Vec<u8> {
+    values.into_iter().flat_map(to_bytes).collect()
+}
+
+/// `.npy` v1.0 header of a C-order 2D array padded to `NPY_STREAM_HEADER_LEN`
+fn npy_stream_header(descr: &str, n_rows: u64, n_cols: usize) -> Result<Vec<u8>> {
+    let dict =
+        format!("{{'descr': '{descr}', 'fortran_order': False, 'shape': ({n_rows}, {n_cols}), }}");
+    let magic_len = 6 + 2 + 2; // \x93NUMPY + ver + hdr_len field
+    let header_len = NPY_STREAM_HEADER_LEN - magic_len;
+    if dict.len() + 1 > header_len {
+        bail!(
+            "Matrix shape ({}, {}) too large for .npy header",
+            n_rows,
+            n_cols
+        );
+    }
+    let mut buf = Vec::<u8>::with_capacity(NPY_STREAM_HEADER_LEN);
+    buf.extend_from_slice(b"\x93NUMPY\x01\x00");
+    buf.extend(&(header_len as u16).to_le_bytes());
+    buf.extend_from_slice(dict.as_bytes());
+    buf.resize(NPY_STREAM_HEADER_LEN - 1, b' ');
+    buf.push(b'\n');
+    Ok(buf)
+}
+
+/// Write the counts of a single (global) window as Jellyfish column dumps,
+/// `k<k>_jellyfish.txt`, with one `motif count` line per counted motif.
+///
+/// Motifs are written in the order of `motifs_by_k`. Canonical motifs
+/// (lexicographically smallest of a kmer and its reverse complement) match
+/// the output of `jellyfish count -C`.
+pub fn write_jellyfish_dump(
+    window: &DecodedCounts,
+    motifs_by_k: &BTreeMap<u8, Vec<String>>,
+    output_dir: &Path,
+) -> Result<()> {
+    for (k, motifs) in motifs_by_k {
+        let path = output_dir.join(format!("k{k}_jellyfish.txt"));
+        let mut out =
+            BufWriter::new(File::create(&path).context(format!("Create {:?} fail", path))?);
+        let counts = window.counts.get(k);
+        for motif in motifs {
+            let count = counts.and_then(|c| c.get(motif)).copied().unwrap_or(0);
+            if count > 0 {
+                writeln!(out, "{motif} {count}").context(format!("Write {:?} fail", path))?;
+            }
+        }
+        out.flush().context(format!("Write {:?} fail", path))?;
+    }
+    Ok(())
+}
+
+/// Number of distinct motifs occurring `n` times, as `(n, n_motifs)` sorted
+/// by `n`. Motifs with a zero count are not included.
+pub fn kmer_spectrum(counts: &FxHashMap<String, BigCount>) -> Vec<(BigCount, u64)> {
+    let mut spectrum: BTreeMap<BigCount, u64> = BTreeMap::new();
+    for &count in counts.values().filter(|&&c| c > 0) {
+        *spectrum.entry(count).or_insert(0) += 1;
+    }
+    spectrum.into_iter().collect()
+}
+
+/// Write the k‑mer spectrum (count of counts) of a single (global) window
+/// per k to `k<k>_spectrum.tsv`, with one `n<TAB>n_motifs` line per
+/// occurring count and no header, like `jellyfish histo` (e.g. for
+/// GenomeScope).
+pub fn write_kmer_spectrum(window: &DecodedCounts, output_dir: &Path) -> Result<()> {
+    for (k, counts) in &window.counts {
+        let path = output_dir.join(format!("k{k}_spectrum.tsv"));
+        let mut out =
+            BufWriter::new(File::create(&path).context(format!("Create {:?} fail", path))?);
+        for (n, n_motifs) in kmer_spectrum(counts) {
+            writeln!(out, "{n}\t{n_motifs}").context(format!("Write {:?} fail", path))?;
+        }
+        out.flush().context(format!("Write {:?} fail", path))?;
+    }
+    Ok(())
+}
+
+/// Write the histograms of several inputs to one long-format TSV with an
+/// `input<TAB><value_name><TAB>count` header and a line per occurring value
+/// of every input.
+pub fn write_histograms(
+    path: &Path,
+    value_name: &str,
+    histograms: &[(String, BTreeMap<u64, u64>)],
+) -> Result<()> {
+    let mut out = BufWriter::new(File::create(path).context(format!("Create {:?} fail", path))?);
+    writeln!(out, "input\t{value_name}\tcount").context(format!("Write {:?} fail", path))?;
+    for (input, histogram) in histograms {
+        for (value, count) in histogram {
+            writeln!(out, "{input}\t{value}\t{count}").context(format!("Write {:?} fail", path))?;
+        }
+    }
+    out.flush().context(format!("Write {:?} fail", path))?;
+    Ok(())
+}
+
+/// Writes the per-window counts of selected motifs as bedGraph tracks
+/// (`track_<motif>.bedGraph`) for genome browsers.
+///
+/// Rows are appended per batch of windows, so the writer works for full
+/// and streamed outputs alike.
+pub struct MotifTrackWriter {
+    tracks: Vec<MotifTrack>,
+}
+
+struct MotifTrack {
+    k: u8,
+    /// Column of the motif in the prepared counts
+    key: String,
+    path: std::path::PathBuf,
+    out: BufWriter<File>,
+}
+
+impl MotifTrackWriter {
+    /// Create a track file with a header line for every motif in `output_dir`.
+    ///
+    /// * `canonical` – counts are collapsed to canonical motifs, so a motif
+    ///   is looked up by its canonical form.
+    pub fn create(output_dir: &Path, motifs: &[String], canonical: bool) -> Result<Self> {
+        let mut tracks = Vec::with_capacity(motifs.len());
+        for motif in motifs {
+            let upper = motif.to_ascii_uppercase();
+            let key = if canonical {
+                canonical_motif(upper)
+            } else {
+                upper
+            };
+            let path = output_dir.join(format!("track_{motif}.bedGraph"));
+            let mut out =
+                BufWriter::new(File::create(&path).context(format!("Create {:?} fail", path))?);
+            writeln!(
+                out,
+                "track type=bedGraph name=\"{motif}\" description=\"{motif} per window\""
+            )
+            .context(format!("Write {:?} fail", path))?;
+            tracks.push(MotifTrack {
+                k: motif.len() as u8,
+                key,
+                path,
+                out,
+            });
+        }
+        Ok(MotifTrackWriter { tracks })
+    }
+
+    /// Append one line per window, scaled by `row_scales` (if given)
+    pub fn write_rows(
+        &mut self,
+        windows: &[DecodedCounts],
+        window_ids: &WindowIds,
+        row_scales: Option<&RowScales>,
+    ) -> Result<()> {
+        let WindowIds::Coordinates(coords) = window_ids else {
+            bail!("Motif tracks need window coordinates");
+        };
+        for track in &mut self.tracks {
+            let scales = row_scales.and_then(|s| s.get(&track.k));
+            for (i, ((chr, start, end), win)) in coords.iter().zip(windows).enumerate() {
+                let count = win
+                    .counts
+                    .get(&track.k)
+                    .and_then(|c| c.get(&track.key))
+                    .copied()
+                    .unwrap_or(0);
+                match scales {
+                    Some(scales) => writeln!(
+                        track.out,
+                        "{chr}\t{start}\t{end}\t{}",
+                        count as f64 * scales[i]
+                    ),
+                    None => writeln!(track.out, "{chr}\t{start}\t{end}\t{count}"),
+                }
+                .context(format!("Write {:?} fail", track.path))?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn finish(self) -> Result<()> {
+        for mut track in self.tracks {
+            track
+                .out
+                .flush()
+                .context(format!("Write {:?} fail", track.path))?;
+        }
+        Ok(())
+    }
+}
+
 // Sparse version
 
 type Idx = u64; // 64-bit row and column indices
 
-/// Write SciPy-compatible COO matrix as <prefix>_counts_sparse.npz + <prefix>_motifs.txt
+/// Write SciPy-compatible COO or CSR matrix as <prefix>_counts_sparse.npz
 ///
 /// * `bins`   – Per-bin motif→count hash maps
 /// * `motifs` – Full ordered motif list; defines column order
+/// * `format` – COO (`row`, `col`, `data`) or CSR (`indptr`, `indices`,
+///   `data`, column indices sorted within rows)
+/// * `dtype`  – element type of `data`
+/// * `scales` – optional per-row scale factors; scaled `data` is written as
+///   `f32` with `dtype` F32 and `f64` otherwise
 ///
 /// Examples
 /// --------
 ///
 /// Python: Loading with scipy.sparse and looping through each window:
 ///
-/// ```
+/// ```python
 /// coo = scipy.sparse.load_npz("k11_counts_sparse.npz")`
 /// csr = coo.tocsr()
 /// for i in range(csr.shape[0]):
@@ -119,11 +1537,14 @@ type Idx = u64; // 64-bit row and column indices
 /// with open("my_prefix_motifs.txt") as f:
 ///     motifs = [line.strip() for line in f]
 /// ```
-pub fn write_category_sparse(
-    bins: &[FxHashMap<String, BigCount>],
+pub fn write_category_sparse<B: Borrow<FxHashMap<String, BigCount>>>(
+    bins: &[B],
     motifs: &[String],
     prefix: &str,
     out_dir: &Path,
+    format: SparseFormat,
+    dtype: CountDtype,
+    scales: Option<&[f64]>,
 ) -> Result<()> {
     if bins.is_empty() {
         return Ok(());
@@ -140,59 +1561,212 @@ pub fn write_category_sparse(
         .collect();
 
     // Collect triplets with one allocation
-    let nnz: usize = bins.iter().map(|hm| hm.len()).sum();
-    let mut row = Vec::<Idx>::with_capacity(nnz);
-    let mut col = Vec::<Idx>::with_capacity(nnz);
-    let mut val = Vec::<BigCount>::with_capacity(nnz);
-
+    let nnz: usize = bins.iter().map(|hm| hm.borrow().len()).sum();
+    let mut out = SparseTriplets::with_capacity(nnz, n_rows);
     for (r, hm) in bins.iter().enumerate() {
         let ri: Idx = NumCast::from(r).context("row index overflow u64")?;
-        for (motif, &count) in hm {
-            if let Some(&ci) = motif_index.get(motif.as_str()) {
-                row.push(ri);
-                col.push(ci);
-                val.push(count);
-            }
+        let mut entries: Vec<(Idx, BigCount)> = hm
+            .borrow()
+            .iter()
+            .filter_map(|(motif, &count)| motif_index.get(motif.as_str()).map(|&ci| (ci, count)))
+            .collect();
+        out.push_row(ri, &mut entries);
+    }
+
+    write_sparse_npz(out, n_cols, prefix, out_dir, format, dtype, scales)
+}
+
+/// Write <prefix>_counts_sparse.npz from triplets collected one row at a
+/// time (e.g. merged from spilled counts).
+///
+/// `triplets` must be ordered by row. Columns within a row can be in any
+/// order; they are sorted.
+pub fn write_triplets_sparse(
+    triplets: impl IntoIterator<Item = Result<(Idx, Idx, BigCount)>>,
+    n_rows: usize,
+    n_cols: usize,
+    prefix: &str,
+    out_dir: &Path,
+    format: SparseFormat,
+    dtype: CountDtype,
+) -> Result<()> {
+    if n_rows == 0 {
+        return Ok(());
+    }
+    let mut out = SparseTriplets::with_capacity(0, n_rows);
+    let mut entries: Vec<(Idx, BigCount)> = Vec::new();
+    let mut current: Idx = 0;
+    for triplet in triplets {
+        let (ri, ci, count) = triplet?;
+        if ri < current {
+            bail!("Triplets are not ordered by row");
+        }
+        if ri as usize >= n_rows {
+            bail!("Row {} is outside the {} rows of the matrix", ri, n_rows);
+        }
+        if ri > current {
+            out.push_row(current, &mut entries);
+            current = ri;
         }
+        entries.push((ci, count));
     }
+    out.push_row(current, &mut entries);
+    out.finish(n_rows);
+    write_sparse_npz(out, n_cols, prefix, out_dir, format, dtype, None)
+}
+
+/// Coordinates, values and CSR row pointers of a sparse matrix
+struct SparseTriplets {
+    row: Vec<Idx>,
+    col: Vec<Idx>,
+    val: Vec<BigCount>,
+    // CSR row pointers: entries of row r are at indptr[r]..indptr[r + 1]
+    indptr: Vec<Idx>,
+}
+
+impl SparseTriplets {
+    fn with_capacity(nnz: usize, n_rows: usize) -> Self {
+        let mut indptr = Vec::with_capacity(n_rows + 1);
+        indptr.push(0);
+        SparseTriplets {
+            row: Vec::with_capacity(nnz),
+            col: Vec::with_capacity(nnz),
+            val: Vec::with_capacity(nnz),
+            indptr,
+        }
+    }
+
+    /// Append the `(column, count)` entries of row `ri` (and empty rows
+    /// before it), draining `entries`. Rows must be pushed in order.
+    fn push_row(&mut self, ri: Idx, entries: &mut Vec<(Idx, BigCount)>) {
+        // Sorted for CSR and so COO files don't depend on hash map order
+        entries.sort_unstable_by_key(|&(ci, _)| ci);
+        while self.indptr.len() <= ri as usize {
+            self.indptr.push(self.col.len() as Idx);
+        }
+        for (ci, count) in entries.drain(..) {
+            self.row.push(ri);
+            self.col.push(ci);
+            self.val.push(count);
+        }
+        self.indptr.push(self.col.len() as Idx);
+    }
+
+    /// Add empty rows up to `n_rows`
+    fn finish(&mut self, n_rows: usize) {
+        while self.indptr.len() <= n_rows {
+            self.indptr.push(self.col.len() as Idx);
+        }
+    }
+}
+
+/// Pack sparse triplets into <prefix>_counts_sparse.npz
+fn write_sparse_npz(
+    triplets: SparseTriplets,
+    n_cols: usize,
+    prefix: &str,
+    out_dir: &Path,
+    format: SparseFormat,
+    dtype: CountDtype,
+    scales: Option<&[f64]>,
+) -> Result<()> {
+    let SparseTriplets {
+        row,
+        col,
+        val,
+        indptr,
+    } = triplets;
+    let n_rows = indptr.len() - 1;
 
     // Serialise numeric vectors
-    let row_npy = vec_to_npy(&row)?;
-    let col_npy = vec_to_npy(&col)?;
-    let val_npy = vec_to_npy(&val)?;
+    let val_npy = match scales {
+        None => counts_to_npy(&val, dtype)?,
+        Some(scales) => {
+            let scaled = val
+                .iter()
+                .zip(&row)
+                .map(|(&c, &r)| c as f64 * scales[r as usize]);
+            if dtype == CountDtype::F32 {
+                vec_to_npy(&scaled.map(|v| v as f32).collect::<Vec<_>>())?
+            } else {
+                vec_to_npy(&scaled.collect::<Vec<_>>())?
+            }
+        }
+    };
 
     // shape = np.array([n_rows, n_cols], dtype=int64)
     let shape_arr = arr1(&[n_rows as i64, n_cols as i64]);
     let mut shape_buf = Vec::<u8>::new();
     shape_arr.write_npy(Cursor::new(&mut shape_buf))?;
 
-    // format = np.array('coo', dtype='|S3')
-    let format_buf = numpy_string_scalar("coo")?;
+    // Index arrays and format = np.array('coo', dtype='|S3')
+    let (index_arrays, format_buf) = match format {
+        SparseFormat::Coo => (
+            [
+                ("row.npy", vec_to_npy(&row)?),
+                ("col.npy", vec_to_npy(&col)?),
+            ],
+            numpy_string_scalar("coo")?,
+        ),
+        SparseFormat::Csr => (
+            [
+                ("indices.npy", vec_to_npy(&col)?),
+                ("indptr.npy", vec_to_npy(&indptr)?),
+            ],
+            numpy_string_scalar("csr")?,
+        ),
+    };
 
     // Pack everything into <prefix>_counts_sparse.npz
     let npz_path = out_dir.join(format!("{prefix}_counts_sparse.npz"));
-    let file = File::create(&npz_path)?;
-    let mut npz = ZipWriter::new(file);
+    let mut npz = ZipWriter::new_stream(create_output(&npz_path)?);
     let opts = SimpleFileOptions::default().compression_method(zip::CompressionMethod::Deflated);
 
-    npz.start_file("row.npy", opts)?;
-    npz.write_all(&row_npy)?;
-    npz.start_file("col.npy", opts)?;
-    npz.write_all(&col_npy)?;
+    for (name, buf) in &index_arrays {
+        npz.start_file(*name, opts)?;
+        npz.write_all(buf)?;
+    }
     npz.start_file("data.npy", opts)?;
     npz.write_all(&val_npy)?;
     npz.start_file("shape.npy", opts)?;
     npz.write_all(&shape_buf)?;
     npz.start_file("format.npy", opts)?;
     npz.write_all(&format_buf)?;
-    npz.finish()?;
+    npz.finish()?.into_inner().finish()?;
 
-    // Plain-text motif list
-    let mut txt = File::create(out_dir.join(format!("{prefix}_motifs.txt")))?;
-    for m in motifs {
-        writeln!(txt, "{m}")?;
+    Ok(())
+}
+
+/// Pack all files in `dir` into a single `.npz` archive at `bundle_path`.
+///
+/// Files are stored (deflated) under their file names, sorted by name, and a
+/// `manifest.tsv` entry lists every file with its size in bytes.
+/// `numpy.load()` returns arrays for the `.npy` entries (keyed without the
+/// extension) and raw bytes for all other entries.
+pub fn bundle_directory(dir: &Path, bundle_path: &Path) -> Result<()> {
+    let mut names: Vec<String> = Vec::new();
+    for entry in std::fs::read_dir(dir).context(format!("Reading {:?}", dir))? {
+        let entry = entry?;
+        if entry.file_type()?.is_file() {
+            names.push(entry.file_name().to_string_lossy().into_owned());
+        }
     }
+    names.sort();
 
+    let mut npz = ZipWriter::new_stream(create_output(bundle_path)?);
+    let opts = SimpleFileOptions::default()
+        .compression_method(zip::CompressionMethod::Deflated)
+        .large_file(true);
+    let mut manifest = String::from("file\tbytes\n");
+    for name in &names {
+        let mut src = File::open(dir.join(name)).context(format!("Opening {}", name))?;
+        npz.start_file(name.as_str(), opts)?;
+        let n_bytes = std::io::copy(&mut src, &mut npz).context(format!("Bundling {}", name))?;
+        manifest.push_str(&format!("{name}\t{n_bytes}\n"));
+    }
+    npz.start_file("manifest.tsv", opts)?;
+    npz.write_all(manifest.as_bytes())?;
+    npz.finish()?.into_inner().finish()?;
     Ok(())
 }
 
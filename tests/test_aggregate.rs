@@ -0,0 +1,100 @@
+#[cfg(test)]
+mod tests {
+    use ndarray::{arr2, Array1, Array2};
+    use ndarray_npy::ReadNpyExt;
+    use reference::reference::aggregate::*;
+    use reference::reference::write::*;
+    use tempfile::tempdir;
+
+    fn regions(regions: &[(&str, u64, u64)]) -> Vec<Region> {
+        regions
+            .iter()
+            .map(|&(chrom, start, end)| (chrom.to_string(), start, end))
+            .collect()
+    }
+
+    #[test]
+    fn windows_are_assigned_to_containing_bins() {
+        let windows = regions(&[
+            ("chr1", 0, 10),
+            ("chr1", 10, 20),
+            ("chr1", 15, 25),
+            ("chr1", 30, 35),
+            ("chr2", 0, 10),
+        ]);
+
+        let tiles = assign_windows(&windows, &TargetBins::Size(20));
+        assert_eq!(
+            tiles.bins,
+            regions(&[("chr1", 0, 20), ("chr1", 20, 35), ("chr2", 0, 10)])
+        );
+        // The third window crosses a tile border
+        assert_eq!(
+            tiles.bins_of_window,
+            vec![vec![0], vec![0], vec![], vec![1], vec![2]]
+        );
+        assert_eq!(tiles.windows_per_bin(), vec![2, 1, 1]);
+
+        let targets = regions(&[("chr1", 5, 40), ("chr1", 0, 20), ("chr3", 0, 10)]);
+        let assigned = assign_windows(&windows, &TargetBins::Regions(targets.clone()));
+        assert_eq!(assigned.bins, targets);
+        assert_eq!(
+            assigned.bins_of_window,
+            vec![vec![1], vec![0, 1], vec![0], vec![0], vec![]]
+        );
+    }
+
+    #[test]
+    fn runs_are_aggregated_into_coarser_bins() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let input = dir.path().join("run");
+        std::fs::create_dir(&input)?;
+        std::fs::write(
+            input.join("bins.bed"),
+            "chr1\t0\t10\t0\nchr1\t10\t20\t0\nchr1\t20\t30\t0\n",
+        )?;
+        std::fs::write(input.join("k1_motifs.txt"), "A\nC\n")?;
+        write_counts_npy(
+            &input.join("k1_counts.npy"),
+            &arr2(&[[1, 2], [3, 4], [5, 6]]),
+            CountDtype::U32,
+        )?;
+        write_triplets_sparse(
+            [(0, 0, 1), (1, 0, 2), (1, 1, 3), (2, 1, 4)].map(Ok),
+            3,
+            2,
+            "k2",
+            &input,
+            SparseFormat::Coo,
+            CountDtype::U64,
+        )?;
+
+        let out = dir.path().join("out");
+        let summary = aggregate_run(&input, &out, &TargetBins::Size(20), CountDtype::U64)?;
+        assert_eq!(
+            summary.matrices,
+            vec!["k1_counts.npy", "k2_counts_sparse.npz"]
+        );
+        assert_eq!((summary.n_windows, summary.n_unassigned), (3, 0));
+        assert_eq!(summary.n_bins, 2);
+
+        let dense = Array2::<u64>::read_npy(std::fs::File::open(out.join("k1_counts.npy"))?)?;
+        assert_eq!(dense, arr2(&[[4, 6], [5, 6]]));
+
+        let file = std::fs::File::open(out.join("k2_counts_sparse.npz"))?;
+        let mut npz = zip::ZipArchive::new(file)?;
+        let mut read = |name: &str| -> anyhow::Result<Array1<u64>> {
+            Ok(Array1::<u64>::read_npy(npz.by_name(name)?)?)
+        };
+        assert_eq!(read("row.npy")?.to_vec(), vec![0, 0, 1]);
+        assert_eq!(read("col.npy")?.to_vec(), vec![0, 1, 1]);
+        assert_eq!(read("data.npy")?.to_vec(), vec![3, 3, 4]);
+
+        assert_eq!(
+            std::fs::read_to_string(out.join("bins.bed"))?,
+            "#chrom\tstart\tend\tn_windows\nchr1\t0\t20\t2\nchr1\t20\t30\t1\n"
+        );
+        assert!(out.join("k1_motifs.txt").exists());
+        Ok(())
+    }
+}
@@ -1,6 +1,7 @@
 #[cfg(test)]
 mod tests {
     use reference::reference::bed::*;
+    use std::collections::HashMap;
     use std::io::Write;
     use tempfile::NamedTempFile;
 
@@ -24,7 +25,7 @@ chr2\t5\t15
         let tmp = write_bed(bed);
         let chromosomes = vec!["chr1".into(), "chr2".into()];
 
-        let map = load_windows(tmp.path(), &chromosomes)?;
+        let map = load_windows(tmp.path(), &chromosomes, None)?;
 
         // chr1 should hold two windows sorted by (start,end)
         let w1 = &map["chr1"];
@@ -46,7 +47,7 @@ chr2\t5\t15
         let tmp = write_bed(bed);
         let chromosomes = vec!["chr1".into(), "chrX".into()];
 
-        let map = load_windows(tmp.path(), &chromosomes)?;
+        let map = load_windows(tmp.path(), &chromosomes, None)?;
 
         assert_eq!(map["chr1"].len(), 1);
         // chrX was requested but absent in BED → empty Vec
@@ -61,10 +62,112 @@ chr2\t5\t15
         let tmp = write_bed(bed);
         let chromosomes = vec!["chr1".into()];
 
-        let err = load_windows(tmp.path(), &chromosomes).unwrap_err();
+        let err = load_windows(tmp.path(), &chromosomes, None).unwrap_err();
         assert!(
             err.to_string().contains("Parsing window start"),
             "unexpected error: {err}"
         );
     }
+
+    #[test]
+    fn extra_columns_are_kept_by_original_index() -> anyhow::Result<()> {
+        let bed = "\
+chr1\t10\t20\tgeneA\t5\t+
+chrX\t0\t5\tskipped
+chr1\t0\t5\tgeneB
+";
+        let tmp = write_bed(bed);
+        let chromosomes = vec!["chr1".into()];
+
+        let (map, columns) = load_windows_with_columns(tmp.path(), &chromosomes, None)?;
+
+        assert_eq!(map["chr1"], vec![(0, 5, 1), (10, 20, 0)]);
+        assert_eq!(columns.names, vec!["name", "score", "strand"]);
+        assert_eq!(columns.values[0], vec!["geneA", "5", "+"]);
+        assert_eq!(columns.values[1], vec!["geneB", ".", "."]);
+        Ok(())
+    }
+
+    #[test]
+    fn anchors_are_read_from_bed_and_tsv_rows() -> anyhow::Result<()> {
+        let anchors = "\
+# anchors
+chr1\t100\t101\tTSS1\t0\t+
+chr1\t50\tCTCF
+chr2\t10
+chrX\t5\t6\tskipped
+";
+        let tmp = write_bed(anchors);
+        let chromosomes = vec!["chr1".into(), "chr2".into()];
+
+        let loaded = load_anchors(tmp.path(), &chromosomes, None)?;
+        let simple: Vec<(&str, u64, &str)> = loaded
+            .iter()
+            .map(|a| (a.chrom.as_str(), a.pos, a.name.as_str()))
+            .collect();
+        assert_eq!(
+            simple,
+            vec![
+                ("chr1", 100, "TSS1"),
+                ("chr1", 50, "CTCF"),
+                ("chr2", 10, ".")
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn windows_are_validated_clamped_and_merged() -> anyhow::Result<()> {
+        let bed = "\
+chr1\t0\t100
+chr1\t50\t150
+chr1\t200\t200
+chr1\t300\t400
+chr1\t300\t400
+chr1\t900\t1100
+";
+        let tmp = write_bed(bed);
+        let chromosomes = vec!["chr1".into()];
+        let mut windows = load_windows(tmp.path(), &chromosomes, None)?;
+        let lengths = HashMap::from([("chr1".to_string(), 1000)]);
+
+        let issues: Vec<(u64, WindowIssue)> = validate_windows(&windows, &lengths)
+            .into_iter()
+            .map(|p| (p.original_idx, p.issue))
+            .collect();
+        assert_eq!(
+            issues,
+            vec![
+                (1, WindowIssue::Overlap),
+                (2, WindowIssue::Empty),
+                (4, WindowIssue::Duplicate),
+                (5, WindowIssue::BeyondChromEnd),
+            ]
+        );
+
+        assert_eq!(clamp_windows(&mut windows, &lengths), 1);
+        assert_eq!(merge_overlapping_windows(&mut windows), 2);
+        assert_eq!(
+            windows["chr1"],
+            vec![(0, 150, 0), (300, 400, 3), (900, 1000, 5)]
+        );
+        assert!(validate_windows(&windows, &lengths).is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn bed12_blocks_are_absolute() -> anyhow::Result<()> {
+        let bed = "\
+chr1\t100\t200\ttx1\t0\t+\t100\t200\t0\t2\t10,20,\t0,80,
+chr1\t300\t400\ttx2\t0\t-\t300\t400\t0\t1\t100\t0
+";
+        let tmp = write_bed(bed);
+        let chromosomes = vec!["chr1".into()];
+        let (windows, columns) = load_windows_with_columns(tmp.path(), &chromosomes, None)?;
+
+        let blocks = bed12_blocks(&windows, &columns)?;
+        assert_eq!(blocks[0], vec![(100, 110), (180, 200)]);
+        assert_eq!(blocks[1], vec![(300, 400)]);
+        Ok(())
+    }
 }
@@ -0,0 +1,15 @@
+#[cfg(test)]
+mod tests {
+    use reference::reference::bisulfite::*;
+
+    #[test]
+    fn conversion_keeps_soft_masking() {
+        let mut seq = b"ACGTcgNn".to_vec();
+        bisulfite_convert(&mut seq, Bisulfite::Ct);
+        assert_eq!(seq, b"ATGTtgNn");
+
+        let mut seq = b"ACGTcgNn".to_vec();
+        bisulfite_convert(&mut seq, Bisulfite::Ga);
+        assert_eq!(seq, b"ACATcaNn");
+    }
+}
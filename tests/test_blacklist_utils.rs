@@ -106,3 +106,57 @@ mod tests_seq_blacklisting {
         assert!(seq.iter().all(|&b| b == BLACKLIST_BYTE));
     }
 }
+
+#[cfg(test)]
+mod tests_soft_masking {
+    use reference::reference::blacklist::{mask_intervals_as_n, soft_masked_intervals};
+
+    #[test]
+    fn soft_masked_runs_are_found() {
+        assert_eq!(soft_masked_intervals(b"acGTNnA"), vec![(0, 2), (5, 6)]);
+        assert!(soft_masked_intervals(b"ACGT").is_empty());
+    }
+
+    #[test]
+    fn masked_runs_become_n() {
+        let mut seq = b"acGTtt".to_vec();
+        let ivs = soft_masked_intervals(&seq);
+        mask_intervals_as_n(&mut seq, &ivs);
+        assert_eq!(seq, b"NNGTNN");
+    }
+}
+
+#[cfg(test)]
+mod tests_dust {
+    use reference::reference::blacklist::dust_intervals;
+
+    /// Pseudo-random (high-complexity) sequence
+    fn random_seq(len: usize, mut state: u64) -> Vec<u8> {
+        (0..len)
+            .map(|_| {
+                state = state
+                    .wrapping_mul(6364136223846793005)
+                    .wrapping_add(1442695040888963407);
+                b"ACGT"[(state >> 62) as usize]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn homopolymers_are_masked() {
+        let complex = random_seq(300, 7);
+        assert!(dust_intervals(&complex, 64, 20.0).is_empty());
+
+        let mut seq = random_seq(100, 1);
+        seq.extend(b"a".repeat(80));
+        seq.extend(random_seq(100, 2));
+        let intervals = dust_intervals(&seq, 64, 20.0);
+        assert_eq!(intervals.len(), 1);
+        let (start, end) = intervals[0];
+        assert!(start <= 100 && end >= 180, "{:?}", intervals);
+        assert!(end - start < 120, "{:?}", intervals);
+
+        // Too short for a window
+        assert!(dust_intervals(b"AAAAAAAA", 64, 20.0).is_empty());
+    }
+}
@@ -0,0 +1,76 @@
+#[cfg(test)]
+mod tests {
+    use fxhash::FxHashMap;
+    use reference::reference::checkpoint::*;
+    use reference::reference::counting::RefKmerExtractionCounters;
+    use reference::reference::kmer_codec::Kmer;
+    use reference::reference::pwm::PwmHits;
+    use tempfile::tempdir;
+
+    type Checkpointed = (
+        Vec<FxHashMap<Kmer, u64>>,
+        Option<Vec<FxHashMap<Kmer, u64>>>,
+        Vec<(String, u64, f64, Option<PwmHits>)>,
+        RefKmerExtractionCounters,
+    );
+
+    #[test]
+    fn checkpoints_roundtrip_and_check_fingerprints() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let path = checkpoint_path(dir.path(), "chrUn:1/2");
+        assert_eq!(path.file_name().unwrap(), "chrUn_1_2.checkpoint");
+
+        let counts =
+            FxHashMap::from_iter([(Kmer { k: 3, code: 7 }, 2), (Kmer { k: 5, code: 1 }, 9)]);
+        let value: Checkpointed = (
+            vec![counts, FxHashMap::default()],
+            None,
+            vec![(
+                "chr1".to_string(),
+                100,
+                0.25,
+                Some(PwmHits {
+                    hits: vec![1, 0],
+                    scores: vec![3.5, 0.0],
+                }),
+            )],
+            RefKmerExtractionCounters::new(10, 8, 6),
+        );
+        assert!(load_checkpoint::<Checkpointed>(&path, 1)?.is_none());
+        store_checkpoint(&path, 1, &value)?;
+        assert_eq!(load_checkpoint::<Checkpointed>(&path, 1)?, Some(value));
+        // Made with other options
+        assert!(load_checkpoint::<Checkpointed>(&path, 2)?.is_none());
+
+        // Truncated checkpoints are ignored
+        let bytes = std::fs::read(&path)?;
+        std::fs::write(&path, &bytes[..bytes.len() - 3])?;
+        assert!(load_checkpoint::<Checkpointed>(&path, 1)?.is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn fingerprints_skip_ignored_options() {
+        let options = |threads: &str, k: &str| {
+            vec![
+                ("n_threads".to_string(), vec![threads.to_string()]),
+                ("kmer_sizes".to_string(), vec![k.to_string()]),
+            ]
+        };
+        let fingerprint = |options: &[(String, Vec<String>)], seed| {
+            options_fingerprint(options, &["n_threads"], &[seed])
+        };
+        assert_eq!(
+            fingerprint(&options("1", "3"), None),
+            fingerprint(&options("8", "3"), None)
+        );
+        assert_ne!(
+            fingerprint(&options("1", "3"), None),
+            fingerprint(&options("1", "4"), None)
+        );
+        assert_ne!(
+            fingerprint(&options("1", "3"), None),
+            fingerprint(&options("1", "3"), Some(1))
+        );
+    }
+}
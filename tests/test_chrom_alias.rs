@@ -0,0 +1,49 @@
+#[cfg(test)]
+mod tests {
+    use reference::reference::chrom_alias::*;
+    use std::io::Write;
+    use tempfile::NamedTempFile;
+
+    fn names(list: &[&str]) -> Vec<String> {
+        list.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn alias_table_maps_to_reference_names() -> anyhow::Result<()> {
+        let mut tmp = NamedTempFile::new()?;
+        write!(
+            tmp,
+            "# ucsc\tensembl\tgenbank\nchr1\t1\tCM000663.2\nchr2\t2\tCM000664.2\nchrX\tX\tCM000685.2\n"
+        )?;
+        // Reference uses Ensembl names
+        let aliases = load_chrom_aliases(tmp.path(), &names(&["1", "2"]))?;
+        assert_eq!(aliases.resolve("chr1"), "1");
+        assert_eq!(aliases.resolve("CM000664.2"), "2");
+        assert_eq!(aliases.resolve("1"), "1");
+        // No reference name on the line: untouched
+        assert_eq!(aliases.resolve("chrX"), "chrX");
+        assert_eq!(aliases.len(), 4);
+        Ok(())
+    }
+
+    #[test]
+    fn ucsc_ensembl_translation_is_symmetric() {
+        let mut ucsc = ChromAliases::default();
+        ucsc.add_ucsc_ensembl(&names(&["chr1", "chrM", "chrX"]));
+        assert_eq!(ucsc.resolve("1"), "chr1");
+        assert_eq!(ucsc.resolve("MT"), "chrM");
+        assert_eq!(ucsc.resolve("X"), "chrX");
+
+        let mut ensembl = ChromAliases::default();
+        ensembl.add_ucsc_ensembl(&names(&["1", "MT"]));
+        assert_eq!(ensembl.resolve("chr1"), "1");
+        assert_eq!(ensembl.resolve("chrM"), "MT");
+        assert_eq!(ensembl.resolve("2"), "2");
+
+        // Names resolving to the same reference name are kept once
+        assert_eq!(
+            ucsc.resolve_all(&names(&["1", "chr1", "X", "chrX", "chr2", "1"])),
+            names(&["chr1", "chrX", "chr2"])
+        );
+    }
+}
@@ -0,0 +1,61 @@
+#[cfg(test)]
+mod tests {
+    use reference::reference::code_cache::*;
+    use reference::reference::kmer_codec::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn cached_codes_roundtrip() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let seq = b"ACGTNACGTTTGCA";
+        let specs = build_kmer_specs(&[3, 8])?;
+
+        let built = load_or_build_codes_per_k(dir.path(), "chr1", seq, &specs, false)?;
+        assert!(cache_path(dir.path(), "chr1", 8).exists());
+
+        // Second call reads from disk and gives identical codes
+        let loaded = load_or_build_codes_per_k(dir.path(), "chr1", seq, &specs, false)?;
+        for k in [3u8, 8] {
+            assert_eq!(built[&k].len(), seq.len());
+            assert_eq!(built[&k].width(), loaded[&k].width());
+            for i in 0..seq.len() {
+                assert_eq!(built[&k].get(i), loaded[&k].get(i));
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn stale_cache_is_not_loaded() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let specs = build_kmer_specs(&[2])?;
+        let path = cache_path(dir.path(), "chr1", 2);
+
+        let codes = build_codes_per_k(b"ACGT", &specs).remove(&2).unwrap();
+        store_codes(&path, &specs[&2], &codes, &sequence_hash(b"ACGT", false))?;
+
+        // Different sequence (e.g. another blacklist) or circularity
+        assert!(load_codes(&path, &specs[&2], &sequence_hash(b"ACXT", false))?.is_none());
+        assert!(load_codes(&path, &specs[&2], &sequence_hash(b"ACGT", true))?.is_none());
+        assert!(load_codes(&path, &specs[&2], &sequence_hash(b"ACGT", false))?.is_some());
+
+        // Codes of another encoding, or of the previous format version
+        let mut bytes = std::fs::read(&path)?;
+        bytes[10] ^= 0xff;
+        std::fs::write(&path, &bytes)?;
+        assert!(load_codes(&path, &specs[&2], &sequence_hash(b"ACGT", false))?.is_none());
+        bytes[10] ^= 0xff;
+        bytes[..8].copy_from_slice(b"RKCODES1");
+        std::fs::write(&path, &bytes)?;
+        assert!(load_codes(&path, &specs[&2], &sequence_hash(b"ACGT", false))?.is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn odd_contig_names_give_safe_paths() {
+        let p = cache_path(std::path::Path::new("/cache"), "HLA-A*01:01/x", 5);
+        assert_eq!(p.file_name().unwrap(), "HLA-A_01_01_x.k5.codes");
+        assert_eq!(safe_file_name(".."), "__");
+        assert_eq!(safe_file_name(""), "_");
+    }
+}
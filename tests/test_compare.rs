@@ -0,0 +1,86 @@
+#[cfg(test)]
+mod tests {
+    use ndarray::{arr2, Array2};
+    use ndarray_npy::ReadNpyExt;
+    use reference::reference::compare::*;
+    use reference::reference::write::*;
+    use std::path::Path;
+    use tempfile::tempdir;
+
+    fn write_run(dir: &Path, bins: &str, motifs: &str, sparse: &[(u64, u64, u64)]) {
+        std::fs::create_dir_all(dir).unwrap();
+        std::fs::write(dir.join("bins.bed"), bins).unwrap();
+        std::fs::write(dir.join("k1_motifs.txt"), "A\nC\n").unwrap();
+        write_counts_npy(
+            &dir.join("k1_counts.npy"),
+            &arr2(&[[1, 2], [3, 4]]),
+            CountDtype::U64,
+        )
+        .unwrap();
+        std::fs::write(dir.join("k2_motifs.txt"), motifs).unwrap();
+        write_triplets_sparse(
+            sparse.iter().map(|&t| Ok(t)),
+            2,
+            motifs.lines().count(),
+            "k2",
+            dir,
+            SparseFormat::Csr,
+            CountDtype::U64,
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn runs_are_compared_by_window_and_motif() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let (a, b) = (dir.path().join("a"), dir.path().join("b"));
+        let bins = "chr1\t0\t10\t0\nchr1\t10\t20\t0\n";
+        write_run(&a, bins, "AA\nAC\n", &[(0, 0, 4), (1, 1, 1)]);
+        // Other blacklist overlaps do not matter
+        write_run(
+            &b,
+            "chr1\t0\t10\t0.5\nchr1\t10\t20\t0\n",
+            "AC\nGG\n",
+            &[(0, 1, 3), (1, 0, 7)],
+        );
+        std::fs::write(b.join("k3_counts.npy"), "")?;
+
+        let out = dir.path().join("out");
+        let (comparisons, skipped) = compare_runs(&a, &b, &out, 1.0)?;
+        assert_eq!(skipped, vec!["k3_counts.npy"]);
+
+        // Same dense counts
+        assert_eq!(comparisons[0].matrix, "k1_counts.npy");
+        assert_eq!(comparisons[0].n_changed, 0);
+        assert!((comparisons[0].pearson - 1.0).abs() < 1e-12);
+
+        // Columns AA, AC, GG
+        let k2 = &comparisons[1];
+        assert_eq!((k2.n_motifs, k2.total_a, k2.total_b), (3, 5, 10));
+        assert_eq!((k2.n_changed, k2.max_abs_diff), (3, 6));
+        assert_eq!(
+            std::fs::read_to_string(out.join("k2_motifs.txt"))?,
+            "AA\nAC\nGG\n"
+        );
+        let diff = Array2::<i64>::read_npy(std::fs::File::open(out.join("k2_diff.npy"))?)?;
+        assert_eq!(diff, arr2(&[[-4, 0, 3], [0, 6, 0]]));
+        let ratio = Array2::<f64>::read_npy(std::fs::File::open(out.join("k2_log2_ratio.npy"))?)?;
+        assert!((ratio[[0, 0]] - (1.0f64 / 5.0).log2()).abs() < 1e-12);
+        assert_eq!(ratio[[0, 1]], 0.0);
+
+        let summary = std::fs::read_to_string(out.join("k2_motif_summary.tsv"))?;
+        assert_eq!(summary.lines().nth(2), Some("AC\t1\t7\t6\t2"));
+        assert!(out.join("compare_summary.tsv").exists());
+        Ok(())
+    }
+
+    #[test]
+    fn other_windows_are_rejected() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let (a, b) = (dir.path().join("a"), dir.path().join("b"));
+        write_run(&a, "chr1\t0\t10\nchr1\t10\t20\n", "AA\n", &[]);
+        write_run(&b, "chr1\t0\t10\nchr1\t10\t30\n", "AA\n", &[]);
+        assert!(compare_runs(&a, &b, &dir.path().join("out"), 1.0).is_err());
+        Ok(())
+    }
+}
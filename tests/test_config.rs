@@ -0,0 +1,194 @@
+#[cfg(test)]
+mod tests {
+    use clap::{Arg, ArgAction, Command};
+    use reference::cli::config::*;
+    use std::collections::HashSet;
+    use std::ffi::OsString;
+    use tempfile::tempdir;
+
+    fn command() -> Command {
+        Command::new("reference")
+            .arg(Arg::new("config").long("config"))
+            .arg(Arg::new("reference").short('r').long("ref"))
+            .arg(
+                Arg::new("kmer_sizes")
+                    .short('k')
+                    .long("kmer-sizes")
+                    .action(ArgAction::Append)
+                    .value_delimiter(','),
+            )
+            .arg(
+                Arg::new("blacklist")
+                    .short('b')
+                    .long("blacklist")
+                    .action(ArgAction::Append),
+            )
+            .arg(
+                Arg::new("canonical")
+                    .short('c')
+                    .long("canonical")
+                    .action(ArgAction::SetTrue),
+            )
+            .arg(
+                Arg::new("n_threads")
+                    .short('t')
+                    .long("n-threads")
+                    .default_value("1"),
+            )
+            .arg(
+                Arg::new("circular")
+                    .long("circular")
+                    .num_args(0..)
+                    .action(ArgAction::Append),
+            )
+            .arg(Arg::new("chromosomes_regex").long("chromosomes-regex"))
+            .subcommand(Command::new("query").arg(Arg::new("region").long("region")))
+    }
+
+    fn strings(args: &[OsString]) -> Vec<String> {
+        args.iter()
+            .map(|arg| arg.to_string_lossy().into_owned())
+            .collect()
+    }
+
+    #[test]
+    fn config_files_parse_scalars_and_arrays() -> anyhow::Result<()> {
+        let text = r#"
+# Run options
+ref = "hg38.2bit"   # trailing comment
+kmer_sizes = [3, 5]
+blacklist = [
+    'a#1.bed',
+    "b \"x\".bed",
+]
+canonical = true
+"#;
+        let entries = parse_config(text, ConfigFormat::Toml, &command())?;
+        let expected = vec![
+            ("ref".to_string(), vec!["hg38.2bit".to_string()]),
+            (
+                "kmer_sizes".to_string(),
+                vec!["3".to_string(), "5".to_string()],
+            ),
+            (
+                "blacklist".to_string(),
+                vec!["a#1.bed".to_string(), "b \"x\".bed".to_string()],
+            ),
+            ("canonical".to_string(), vec!["true".to_string()]),
+        ];
+        assert_eq!(entries, expected);
+
+        let text = r#"
+# Run options
+ref: hg38.2bit
+kmer_sizes: [3, 5]
+blacklist:
+  - 'a#1.bed'
+  - "b \"x\".bed"
+canonical: true
+"#;
+        assert_eq!(
+            parse_config(text, ConfigFormat::Yaml, &command())?,
+            expected
+        );
+        assert!(parse_config("", ConfigFormat::Yaml, &command())?.is_empty());
+
+        let toml = |text| parse_config(text, ConfigFormat::Toml, &command());
+        assert!(toml("[section]").is_err());
+        assert!(toml("ref = [1, 2").is_err());
+        assert!(toml("ref = 1\nref = 2").is_err());
+        assert!(toml("ref = \"open").is_err());
+        assert!(toml("ref = [[1]]").is_err());
+        let yaml = |text| parse_config(text, ConfigFormat::Yaml, &command());
+        assert!(yaml("ref: 1\nref: 2").is_err());
+        assert!(yaml("ref: {a: 1}").is_err());
+
+        // Unknown keys are reported with their position
+        let err = toml("ref = 'a'\nkmer_size = 3").unwrap_err().to_string();
+        assert!(err.contains("unknown option 'kmer_size'"), "{}", err);
+        assert!(err.contains("line 2"), "{}", err);
+        let err = yaml("ref: a\nkmer_size: 3").unwrap_err().to_string();
+        assert!(err.contains("unknown option 'kmer_size'"), "{}", err);
+        assert!(err.contains("line 2"), "{}", err);
+        Ok(())
+    }
+
+    #[test]
+    fn command_line_options_override_the_config() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("run.toml");
+        std::fs::write(
+            &path,
+            "ref = 'hg38.2bit'\nkmer-sizes = [3, 5]\nblacklist = ['a.bed', 'b.bed']\n\
+             canonical = true\nn_threads = 1\n",
+        )?;
+        let args: Vec<OsString> = ["reference", "-k", "4", "--config"]
+            .into_iter()
+            .map(OsString::from)
+            .chain([path.clone().into_os_string()])
+            .collect();
+        let args = with_config_args(&command(), args)?;
+        assert_eq!(
+            strings(&args[..5]),
+            vec![
+                "reference",
+                "--ref=hg38.2bit",
+                "--blacklist=a.bed",
+                "--blacklist=b.bed",
+                "--canonical"
+            ]
+        );
+        let matches = command().try_get_matches_from(&args)?;
+        let ks: Vec<&String> = matches.get_many("kmer_sizes").unwrap().collect();
+        assert_eq!(ks, vec!["4"]);
+        assert!(matches.get_flag("canonical"));
+
+        // Subcommands without a config option are left alone
+        let args: Vec<OsString> = ["reference", "query", "--region", "chr1"]
+            .into_iter()
+            .map(OsString::from)
+            .collect();
+        assert_eq!(with_config_args(&command(), args.clone())?, args);
+        Ok(())
+    }
+
+    #[test]
+    fn config_args_check_keys_and_flags() {
+        let given = HashSet::from(["reference".to_string()]);
+        let entry = |key: &str, values: &[&str]| {
+            vec![(
+                key.to_string(),
+                values.iter().map(|v| v.to_string()).collect(),
+            )]
+        };
+        let args = |key, values| config_args(&command(), &entry(key, values), &given);
+        assert!(args("unknown", &["1"]).is_err());
+        assert!(args("canonical", &["yes"]).is_err());
+        assert!(args("config", &["other.toml"]).is_err());
+        assert!(args("canonical", &["false"]).unwrap().is_empty());
+        // Given on the command line
+        assert!(args("ref", &["other.2bit"]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn written_configs_parse_back() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("config.toml");
+        let options = vec![
+            ("reference".to_string(), vec!["dir/hg 38.2bit".to_string()]),
+            (
+                "kmer_sizes".to_string(),
+                vec!["3".to_string(), "5".to_string()],
+            ),
+            ("canonical".to_string(), vec!["false".to_string()]),
+            ("circular".to_string(), vec![]),
+            (
+                "chromosomes_regex".to_string(),
+                vec!["^chr\\d+$".to_string()],
+            ),
+        ];
+        write_config(&path, &options)?;
+        assert_eq!(load_config(&path, &command())?, options);
+        Ok(())
+    }
+}
@@ -27,7 +27,11 @@ mod counting_tests {
         let windows = vec![(0, seq.len() as u64, 0)];
         let mut buckets = vec![FxHashMap::<Kmer, BigCount>::default(); windows.len()];
 
-        count_kmers_by_window(&mut buckets, &encs, &windows, seq.len() as u64);
+        count_all(
+            &mut buckets,
+            &WindowLayout::new(&windows, seq.len() as u64),
+            &encs,
+        );
 
         // Decode -> human-readable
         let mut human: FxHashMap<String, u64> = FxHashMap::default();
@@ -61,7 +65,11 @@ mod counting_tests {
         let windows = vec![(0, seq.len() as u64, 0)];
         let mut buckets = vec![FxHashMap::<Kmer, BigCount>::default(); 1];
 
-        count_kmers_by_window(&mut buckets, &encs, &windows, seq.len() as u64);
+        count_all(
+            &mut buckets,
+            &WindowLayout::new(&windows, seq.len() as u64),
+            &encs,
+        );
 
         assert_eq!(buckets[0].len(), 1);
         assert_eq!(buckets[0].values().copied().sum::<u64>(), 2);
@@ -86,7 +94,11 @@ mod counting_tests {
         let windows = vec![(0, 2, 0), (2, 4, 1)]; // two half-windows
         let mut buckets = vec![FxHashMap::<Kmer, BigCount>::default(); windows.len()];
 
-        count_kmers_by_window(&mut buckets, &encs, &windows, seq.len() as u64);
+        count_all(
+            &mut buckets,
+            &WindowLayout::new(&windows, seq.len() as u64),
+            &encs,
+        );
 
         for bucket in buckets {
             assert_eq!(bucket.values().copied().sum::<u64>(), 1);
@@ -114,7 +126,11 @@ mod counting_tests {
         let windows = vec![(0, 4, 0)]; // 4-bp window
         let mut buckets = vec![FxHashMap::<Kmer, BigCount>::default(); 1];
 
-        count_kmers_by_window(&mut buckets, &encs, &windows, seq.len() as u64);
+        count_all(
+            &mut buckets,
+            &WindowLayout::new(&windows, seq.len() as u64),
+            &encs,
+        );
 
         assert!(buckets[0].is_empty());
     }
@@ -140,7 +156,11 @@ mod counting_tests {
         let windows = vec![(0, 2, 0)];
         let mut buckets = vec![FxHashMap::<Kmer, BigCount>::default(); 1];
 
-        count_kmers_by_window(&mut buckets, &encs, &windows, seq.len() as u64);
+        count_all(
+            &mut buckets,
+            &WindowLayout::new(&windows, seq.len() as u64),
+            &encs,
+        );
 
         assert!(buckets[0].is_empty());
     }
@@ -166,7 +186,11 @@ mod counting_tests {
         let windows = vec![(0, 4, 0)];
         let mut buckets = vec![FxHashMap::<Kmer, BigCount>::default(); 1];
 
-        count_kmers_by_window(&mut buckets, &encs, &windows, seq.len() as u64);
+        count_all(
+            &mut buckets,
+            &WindowLayout::new(&windows, seq.len() as u64),
+            &encs,
+        );
 
         // Exactly one k-mer counted
         assert_eq!(buckets[0].values().copied().sum::<u64>(), 1);
@@ -200,8 +224,455 @@ mod counting_tests {
         let windows = vec![(start, seq.len() as u64, 0)];
         let mut buckets = vec![FxHashMap::<Kmer, BigCount>::default(); 1];
 
-        count_kmers_by_window(&mut buckets, &encs, &windows, seq.len() as u64);
+        count_all(
+            &mut buckets,
+            &WindowLayout::new(&windows, seq.len() as u64),
+            &encs,
+        );
 
         assert!(buckets[0].is_empty());
     }
+
+    // Circular sequences count wrap-around k-mers in the last window
+    #[test]
+    fn circular_counts_wrap_around_kmers() {
+        let seq = b"ACGT"; // circular 2-mers: AC CG GT TA
+
+        let specs = build_kmer_specs(&[2]).unwrap();
+        let codes_by_k = build_codes_per_k_circular(seq, &specs);
+        let spec2 = &specs[&2];
+
+        let mut encs: SmallVec<[Enc<'_>; 8]> = SmallVec::new();
+        encs.push(Enc {
+            k: 2,
+            codes: &codes_by_k[&2],
+            none: spec2.sentinel_none(),
+            n: spec2.sentinel_n(),
+        });
+
+        let windows = vec![(0, 2, 0), (2, 4, 1)];
+        let mut buckets = vec![FxHashMap::<Kmer, BigCount>::default(); windows.len()];
+
+        let layout = WindowLayout {
+            circular: true,
+            ..WindowLayout::new(&windows, seq.len() as u64)
+        };
+        count_all(&mut buckets, &layout, &encs);
+
+        // First window keeps the within-window rule: only AC
+        assert_eq!(buckets[0].values().copied().sum::<u64>(), 1);
+        // Last window also gets the wrapping TA
+        let motifs: Vec<String> = buckets[1]
+            .keys()
+            .map(|kmer| spec2.decode_kmer(kmer.code))
+            .collect();
+        assert_eq!(buckets[1].len(), 2);
+        assert!(motifs.contains(&"GT".to_string()));
+        assert!(motifs.contains(&"TA".to_string()));
+    }
+
+    /// Count the windows of `layout` from the codes of the whole sequence
+    fn count_all(counts: &mut [FxHashMap<Kmer, BigCount>], layout: &WindowLayout, encs: &[Enc]) {
+        let source = KmerSource::Codes {
+            encs,
+            chunk: (0, layout.chrom_len),
+        };
+        count_kmers(counts, layout, source);
+    }
+
+    /// Count every window of `layout` on its own, so none is derived from
+    /// the previous window
+    fn count_each(layout: &WindowLayout, source: KmerSource) -> Vec<FxHashMap<Kmer, BigCount>> {
+        layout
+            .windows
+            .iter()
+            .map(|window| {
+                let mut counts = vec![FxHashMap::default()];
+                let layout = WindowLayout {
+                    windows: std::slice::from_ref(window),
+                    ..*layout
+                };
+                count_kmers(&mut counts, &layout, source);
+                counts.pop().unwrap()
+            })
+            .collect()
+    }
+
+    fn encs_for<'a>(
+        specs: &std::collections::BTreeMap<u8, KmerSpec>,
+        codes: &'a std::collections::BTreeMap<u8, KmerCodes>,
+    ) -> SmallVec<[Enc<'a>; 8]> {
+        let mut encs: SmallVec<[Enc<'a>; 8]> = SmallVec::new();
+        for (&k, spec) in specs {
+            encs.push(Enc {
+                k,
+                codes: &codes[&k],
+                none: spec.sentinel_none(),
+                n: spec.sentinel_n(),
+            });
+        }
+        encs
+    }
+
+    // Counting in chunks gives the same counts as a single pass
+    #[test]
+    fn chunked_counts_match_single_pass() {
+        let seq = b"ACGTNACGGTTACAGGTCAANNTGCATGCA";
+        let specs = build_kmer_specs(&[1, 3]).unwrap();
+        let windows = vec![(0, 7, 0), (7, 19, 1), (5, 30, 2)];
+        let len = seq.len() as u64;
+
+        for circular in [false, true] {
+            let full_codes = if circular {
+                build_codes_per_k_circular(seq, &specs)
+            } else {
+                build_codes_per_k(seq, &specs)
+            };
+            let layout = WindowLayout {
+                circular,
+                ..WindowLayout::new(&windows, len)
+            };
+            let mut expected = vec![FxHashMap::<Kmer, BigCount>::default(); windows.len()];
+            count_all(&mut expected, &layout, &encs_for(&specs, &full_codes));
+
+            let mut chunked = vec![FxHashMap::<Kmer, BigCount>::default(); windows.len()];
+            for start in (0..seq.len()).step_by(4) {
+                let end = (start + 4).min(seq.len());
+                let codes = build_codes_per_k_chunk(seq, &specs, start, end, circular);
+                let source = KmerSource::Codes {
+                    encs: &encs_for(&specs, &codes),
+                    chunk: (start as u64, end as u64),
+                };
+                count_kmers(&mut chunked, &layout, source);
+            }
+            assert_eq!(chunked, expected);
+        }
+    }
+
+    // Flat-array and hashed counts are drained into the window maps
+    #[test]
+    fn tally_drains_dense_and_sparse_counts() {
+        let mut tally = KmerTally::new([2, DENSE_MAX_K + 1]);
+        let small = Kmer { k: 2, code: 7 };
+        let large = Kmer {
+            k: DENSE_MAX_K + 1,
+            code: 123_456,
+        };
+        for kmer in [small, small, large] {
+            tally.add(kmer);
+        }
+        let mut counts = FxHashMap::<Kmer, BigCount>::default();
+        counts.insert(small, 1);
+        tally.drain_into(&mut counts);
+        assert_eq!(counts[&small], 3);
+        assert_eq!(counts[&large], 1);
+
+        // The tally is empty after draining
+        let mut next = FxHashMap::<Kmer, BigCount>::default();
+        tally.add(large);
+        tally.drain_into(&mut next);
+        assert_eq!(next.len(), 1);
+        assert_eq!(next[&large], 1);
+    }
+
+    // Counting from the rolling codes matches counting positional codes
+    #[test]
+    fn streaming_counts_match_positional_counts() {
+        let seq = b"ACGTNACGGTTACAGGTCAANNTGCATGCAacgtRTTAGC";
+        let specs = build_kmer_specs(&[1, 3, 7]).unwrap();
+        let windows = vec![(0, 10, 0), (10, 20, 1), (20, 38, 2), (38, 50, 3)];
+        let len = seq.len() as u64;
+
+        let codes = build_codes_per_k(seq, &specs);
+        let layout = WindowLayout::new(&windows, len);
+        let mut expected = vec![FxHashMap::<Kmer, BigCount>::default(); windows.len()];
+        count_all(&mut expected, &layout, &encs_for(&specs, &codes));
+        let spec_list: Vec<&KmerSpec> = specs.values().collect();
+        let streamed = count_each(
+            &layout,
+            KmerSource::Sequence {
+                seq,
+                specs: &spec_list,
+                keep: &|_, _, _| true,
+            },
+        );
+        assert_eq!(streamed, expected);
+
+        // Only k-mers kept by the filter are counted
+        let even = count_each(
+            &layout,
+            KmerSource::Sequence {
+                seq,
+                specs: &spec_list,
+                keep: &|k, pos, _| k == 1 && pos % 2 == 0,
+            },
+        );
+        let n: BigCount = even.iter().flat_map(|c| c.values()).sum();
+        let expected_n = seq
+            .iter()
+            .step_by(2)
+            .filter(|b| b"ACGTacgt".contains(b))
+            .count();
+        assert_eq!(n, expected_n as BigCount);
+    }
+
+    #[test]
+    fn expected_distinct_kmers_are_bounded() {
+        // One per position
+        assert_eq!(expected_distinct_kmers(100, 10), 91);
+        // At most 4^k
+        assert_eq!(expected_distinct_kmers(1000, 3), 64);
+        // Shorter windows than k have none
+        assert_eq!(expected_distinct_kmers(5, 10), 0);
+        // Long windows are capped
+        assert!(expected_distinct_kmers(1 << 40, 31) < 1 << 40);
+    }
+
+    #[test]
+    fn reused_tally_counts_each_window_alone() {
+        let mut tally = KmerTally::new([2, 12]);
+        tally.reserve(1000);
+        let mut first = FxHashMap::default();
+        tally.add(Kmer { k: 2, code: 1 });
+        tally.add(Kmer { k: 12, code: 7 });
+        tally.add(Kmer { k: 12, code: 7 });
+        tally.drain_into(&mut first);
+        let mut second = FxHashMap::default();
+        tally.add(Kmer { k: 12, code: 3 });
+        tally.drain_into(&mut second);
+        assert_eq!(
+            first,
+            FxHashMap::from_iter([(Kmer { k: 2, code: 1 }, 1), (Kmer { k: 12, code: 7 }, 2)])
+        );
+        assert_eq!(second, FxHashMap::from_iter([(Kmer { k: 12, code: 3 }, 1)]));
+    }
+
+    // Long windows split into parallel pieces and groups of sliding windows
+    // give the same counts as a single pass
+    #[test]
+    fn parallel_pieces_match_single_pass_counts() {
+        let mut state: u64 = 17;
+        let mut seq: Vec<u8> = (0..2_500_000)
+            .map(|_| {
+                state = state.wrapping_mul(6364136223846793005).wrapping_add(1);
+                b"ACGT"[(state >> 62) as usize]
+            })
+            .collect();
+        seq[1_048_580] = b'N';
+        let specs = build_kmer_specs(&[2, 5, 9]).unwrap();
+        let len = seq.len() as u64;
+
+        let mut expected = FxHashMap::<Kmer, BigCount>::default();
+        for (&k, spec) in &specs {
+            for code in spec.build_codes(&seq) {
+                if code != spec.sentinel_none() && code != spec.sentinel_n() {
+                    *expected.entry(Kmer { k, code }).or_insert(0) += 1;
+                }
+            }
+        }
+        let global = [(0, len, 0)];
+        let spec_list: Vec<&KmerSpec> = specs.values().collect();
+        let source = KmerSource::Sequence {
+            seq: &seq,
+            specs: &spec_list,
+            keep: &|_, _, _| true,
+        };
+        let streamed = count_each(&WindowLayout::new(&global, len), source);
+        assert_eq!(streamed, vec![expected.clone()]);
+        let codes = build_codes_per_k(&seq, &specs);
+        let encs = encs_for(&specs, &codes);
+        let mut positional = vec![FxHashMap::<Kmer, BigCount>::default()];
+        count_all(&mut positional, &WindowLayout::new(&global, len), &encs);
+        assert_eq!(positional, vec![expected]);
+
+        // More windows than one group of sliding windows
+        let windows: Vec<(u64, u64, u64)> =
+            (0..2100).map(|i| (i * 1000, i * 1000 + 5000, i)).collect();
+        let layout = WindowLayout::new(&windows, len);
+        let direct = count_each(
+            &layout,
+            KmerSource::Codes {
+                encs: &encs,
+                chunk: (0, len),
+            },
+        );
+        let mut sliding = vec![FxHashMap::<Kmer, BigCount>::default(); windows.len()];
+        count_all(&mut sliding, &layout, &encs);
+        assert_eq!(sliding, direct);
+    }
+
+    // Sliding windows derived from the previous window match direct counts
+    #[test]
+    fn sliding_window_counts_match_direct_counts() {
+        let seq = b"ACGTNACGGTTACAGGTCAANNTGCATGCAGGATCCATTAGC";
+        let specs = build_kmer_specs(&[1, 3, 5]).unwrap();
+        let len = seq.len() as u64;
+        let mut windows: Vec<(u64, u64, u64)> = (0..len)
+            .step_by(3)
+            .enumerate()
+            .map(|(i, s)| (s, s + 12, i as u64))
+            .collect();
+        // Non-monotone and non-overlapping windows are counted directly
+        windows.extend([(2, 6, 100), (30, 31, 101)]);
+
+        for circular in [false, true] {
+            let codes = if circular {
+                build_codes_per_k_circular(seq, &specs)
+            } else {
+                build_codes_per_k(seq, &specs)
+            };
+            let encs = encs_for(&specs, &codes);
+            let layout = WindowLayout {
+                circular,
+                ..WindowLayout::new(&windows, len)
+            };
+            let source = KmerSource::Codes {
+                encs: &encs,
+                chunk: (0, len),
+            };
+            let expected = count_each(&layout, source);
+            let mut sliding = vec![FxHashMap::<Kmer, BigCount>::default(); windows.len()];
+            count_all(&mut sliding, &layout, &encs);
+            assert_eq!(sliding, expected);
+        }
+    }
+
+    // K-mers starting in tiling windows are counted once, and direct,
+    // streamed and sliding counts agree
+    #[test]
+    fn kmers_starting_in_tiling_windows_are_counted_once() {
+        let seq = b"ACGTNACGGTTACAGGTCAANNTGCATGCAGGATCCATTAGC";
+        let specs = build_kmer_specs(&[1, 3, 5]).unwrap();
+        let len = seq.len() as u64;
+        let tiles = |width: u64, step: usize| -> Vec<(u64, u64, u64)> {
+            (0..len)
+                .step_by(step)
+                .enumerate()
+                .map(|(i, s)| (s, s + width, i as u64))
+                .collect()
+        };
+        let codes = build_codes_per_k(seq, &specs);
+        let encs = encs_for(&specs, &codes);
+        let spec_list: Vec<&KmerSpec> = specs.values().collect();
+        let count = |windows: &[(u64, u64, u64)], boundary| {
+            let layout = WindowLayout {
+                boundary,
+                ..WindowLayout::new(windows, len)
+            };
+            let codes = KmerSource::Codes {
+                encs: &encs,
+                chunk: (0, len),
+            };
+            let direct = count_each(&layout, codes);
+            let rolling = KmerSource::Sequence {
+                seq,
+                specs: &spec_list,
+                keep: &|_, _, _| true,
+            };
+            let streamed = count_each(&layout, rolling);
+            let mut sliding = vec![FxHashMap::<Kmer, BigCount>::default(); windows.len()];
+            count_all(&mut sliding, &layout, &encs);
+            assert_eq!(streamed, direct);
+            assert_eq!(sliding, direct);
+            direct
+        };
+        let summed = |counts: Vec<FxHashMap<Kmer, BigCount>>| {
+            let mut summed = FxHashMap::<Kmer, BigCount>::default();
+            for (kmer, n) in counts.iter().flatten() {
+                *summed.entry(*kmer).or_insert(0) += n;
+            }
+            summed
+        };
+
+        let global = count(&[(0, len, 0)], KmerBoundary::WithinWindow).remove(0);
+        let starting = summed(count(&tiles(4, 4), KmerBoundary::StartsInWindow));
+        assert_eq!(starting, global);
+        // K-mers crossing window ends are lost
+        let within = summed(count(&tiles(4, 4), KmerBoundary::WithinWindow));
+        assert!(within.values().sum::<BigCount>() < global.values().sum());
+
+        // Overlapping windows are counted from the previous window
+        for boundary in [KmerBoundary::WithinWindow, KmerBoundary::StartsInWindow] {
+            count(&tiles(12, 3), boundary);
+        }
+    }
+
+    #[test]
+    fn extraction_counters_split_positions() {
+        // 2 N, 3 A/C/G/T of which 1 is blacklisted
+        let before = b"ACNnG";
+        let after = b"ACNnN";
+        let mut counters = RefKmerExtractionCounters::new(5, count_acgt(before), count_acgt(after));
+        assert_eq!(
+            counters,
+            RefKmerExtractionCounters {
+                total: 5,
+                blacklisted: 1,
+                ambiguous: 2,
+                counted: 2,
+                low_complexity: 0,
+                skipped: 0,
+            }
+        );
+        counters += counters;
+        assert_eq!(counters.tsv_fields(), "10\t2\t4\t4\t0\t0");
+
+        // 1 of the 2 bases left by the blacklist is low-complexity
+        let counters = RefKmerExtractionCounters::new(5, 3, 1).with_low_complexity(2);
+        assert_eq!((counters.blacklisted, counters.low_complexity), (1, 1));
+
+        // Every other position of "ACNnG" (0, 2 and 4) has 2 A/C/G/T bases
+        assert_eq!(count_acgt_strided(before, 0..5, 2), 2);
+        assert_eq!(count_acgt_strided(before, 1..5, 2), 1);
+        let counters = RefKmerExtractionCounters::new(5, 3, 3).with_position_stride(2);
+        assert_eq!((counters.counted, counters.skipped), (2, 1));
+    }
+
+    #[test]
+    fn offset_counts_sum_over_anchors() {
+        let seq = b"ACGTACGTAC";
+        let specs = build_kmer_specs(&[1]).unwrap();
+        let codes = build_codes_per_k(seq, &specs);
+        let mut profile = vec![FxHashMap::<Kmer, BigCount>::default(); 3];
+        // Offsets before the start (anchor 0) and past the end (anchor 9)
+        // are skipped
+        count_kmers_by_offset(&mut profile, &encs_for(&specs, &codes), &[0, 5, 9], 1);
+
+        let spec1 = &specs[&1];
+        let human: Vec<Vec<(String, BigCount)>> = profile
+            .iter()
+            .map(|counts| {
+                let mut row: Vec<_> = counts
+                    .iter()
+                    .map(|(kmer, &cnt)| (spec1.decode_kmer(kmer.code), cnt))
+                    .collect();
+                row.sort();
+                row
+            })
+            .collect();
+        let row = |pairs: &[(&str, BigCount)]| -> Vec<(String, BigCount)> {
+            pairs.iter().map(|&(m, c)| (m.to_string(), c)).collect()
+        };
+        assert_eq!(human[0], row(&[("A", 2)]));
+        assert_eq!(human[1], row(&[("A", 1), ("C", 2)]));
+        assert_eq!(human[2], row(&[("C", 1), ("G", 1)]));
+    }
+
+    #[test]
+    fn block_counts_include_junction_kmers() {
+        // Blocks "AC" + "GT" (skipping the "TTT" intron) -> AC CG GT
+        let seq = b"ACTTTGT";
+        let specs = build_kmer_specs(&[2]).unwrap();
+        let counts = count_kmers_in_blocks(seq, &[0..2, 5..7], &specs);
+
+        let spec2 = &specs[&2];
+        let mut human: FxHashMap<String, u64> = FxHashMap::default();
+        for (kmer, &cnt) in &counts {
+            human.insert(spec2.decode_kmer(kmer.code), cnt);
+        }
+        assert_eq!(human.len(), 3);
+        assert_eq!(human["AC"], 1);
+        assert_eq!(human["CG"], 1);
+        assert_eq!(human["GT"], 1);
+    }
 }
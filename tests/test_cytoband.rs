@@ -0,0 +1,43 @@
+#[cfg(test)]
+mod tests {
+    use reference::reference::cytoband::*;
+    use std::io::Write;
+    use tempfile::NamedTempFile;
+
+    fn write_cytobands(contents: &str) -> NamedTempFile {
+        let mut file = NamedTempFile::new().expect("create temp file");
+        file.write_all(contents.as_bytes())
+            .expect("write temp file");
+        file
+    }
+
+    #[test]
+    fn cytoband_windows_by_band_and_arm() -> anyhow::Result<()> {
+        let txt = "\
+chr1\t0\t100\tp36.33\tgneg
+chr1\t100\t200\tp36.32\tgpos25
+chr1\t200\t250\tq11\tacen
+chr1\t250\t400\tq12\tgvar
+chr2\t0\t50\tp11\tgneg
+chrX\t0\t50\tp22.33\tgneg
+";
+        let tmp = write_cytobands(txt);
+        let chromosomes: Vec<String> = vec!["chr1".into(), "chr2".into()];
+        let bands = load_cytobands(tmp.path(), &chromosomes, None)?;
+        assert_eq!(bands.len(), 5);
+        assert_eq!(bands[2].stain, "acen");
+
+        let (windows, columns) = cytoband_windows(&bands, &chromosomes, CytobandLevel::Band);
+        assert_eq!(windows["chr1"].len(), 4);
+        assert_eq!(windows["chr2"], vec![(0, 50, 4)]);
+        assert_eq!(columns.names, vec!["name", "gieStain"]);
+        assert_eq!(columns.values[1], vec!["p36.32", "gpos25"]);
+
+        let (windows, columns) = cytoband_windows(&bands, &chromosomes, CytobandLevel::Arm);
+        assert_eq!(windows["chr1"], vec![(0, 200, 0), (200, 400, 1)]);
+        assert_eq!(windows["chr2"], vec![(0, 50, 2)]);
+        assert_eq!(columns.names, vec!["name"]);
+        assert_eq!(columns.values, vec![vec!["p"], vec!["q"], vec!["p"]]);
+        Ok(())
+    }
+}
@@ -0,0 +1,83 @@
+#[cfg(test)]
+mod tests {
+    use reference::reference::gtf::*;
+    use std::io::Write;
+    use tempfile::NamedTempFile;
+
+    fn write_gtf(contents: &str) -> NamedTempFile {
+        let mut file = NamedTempFile::new().expect("create temp file");
+        file.write_all(contents.as_bytes())
+            .expect("write temp file");
+        file
+    }
+
+    #[test]
+    fn parses_gtf_and_gff3_attributes() {
+        let gtf = r#"gene_id "ENSG1"; gene_name "TP53"; gene_type "protein_coding";"#;
+        assert_eq!(parse_attribute(gtf, "gene_name"), Some("TP53"));
+        assert_eq!(parse_attribute(gtf, "gene_id"), Some("ENSG1"));
+        assert_eq!(parse_attribute(gtf, "missing"), None);
+
+        let gff = "ID=gene:ENSG1;Name=TP53;biotype=protein_coding";
+        assert_eq!(parse_attribute(gff, "Name"), Some("TP53"));
+        assert_eq!(parse_attribute(gff, "ID"), Some("gene:ENSG1"));
+    }
+
+    #[test]
+    fn loads_only_requested_features_as_zero_based() -> anyhow::Result<()> {
+        let gtf = "\
+#!genome-build GRCh38
+chr1\tsrc\tgene\t11\t20\t.\t+\t.\tgene_id \"G1\"; gene_name \"A\";
+chr1\tsrc\texon\t11\t15\t.\t+\t.\tgene_id \"G1\"; gene_name \"A\";
+chr2\tsrc\tgene\t1\t5\t.\t-\t.\tgene_id \"G2\"; gene_name \"B\";
+chrX\tsrc\tgene\t1\t5\t.\t-\t.\tgene_id \"G3\"; gene_name \"C\";
+";
+        let tmp = write_gtf(gtf);
+        let chromosomes = vec!["chr1".into(), "chr2".into()];
+        let recs = load_gtf(tmp.path(), "gene", &chromosomes, None)?;
+        assert_eq!(recs.len(), 2);
+        assert_eq!((recs[0].start, recs[0].end), (10, 20));
+        assert_eq!(recs[1].strand, '-');
+        assert_eq!(recs[1].attribute("gene_name"), Some("B"));
+        Ok(())
+    }
+
+    #[test]
+    fn overlapping_returns_unique_names_in_order() {
+        let ivs = FeatureIntervals::new(vec![
+            (0, 1000, "LONG".to_string()),
+            (100, 200, "A".to_string()),
+            (150, 160, "A".to_string()),
+            (300, 400, "B".to_string()),
+        ]);
+        assert_eq!(ivs.overlapping(120, 180), vec!["LONG", "A"]);
+        assert_eq!(ivs.overlapping(200, 300), vec!["LONG"]);
+        assert_eq!(ivs.overlapping(350, 2000), vec!["LONG", "B"]);
+        assert!(ivs.overlapping(1000, 2000).is_empty());
+    }
+
+    #[test]
+    fn feature_windows_are_labelled_and_deduplicated() -> anyhow::Result<()> {
+        let gtf = "\
+chr1\tsrc\texon\t11\t20\t.\t+\t.\tgene_id \"G1\"; exon_id \"E1\";
+chr1\tsrc\texon\t11\t20\t.\t+\t.\tgene_id \"G1\"; exon_id \"E1\";
+chr1\tsrc\texon\t5\t8\t.\t-\t.\tID=exon:E2
+chr2\tsrc\texon\t101\t200\t.\t-\t.\tgene_id \"G2\"; exon_id \"E3\";
+";
+        let tmp = write_gtf(gtf);
+        let chromosomes: Vec<String> = vec!["chr1".into(), "chr2".into(), "chr3".into()];
+        let recs = load_gtf(tmp.path(), "exon", &chromosomes, None)?;
+
+        let (windows, columns) = feature_windows(&recs, &chromosomes, "exon_id", None);
+        assert_eq!(windows["chr1"], vec![(4, 8, 1), (10, 20, 0)]);
+        assert!(windows["chr3"].is_empty());
+        assert_eq!(columns.names, vec!["name", "score", "strand"]);
+        assert_eq!(columns.values[1], vec!["exon:E2", ".", "-"]);
+
+        // Promoters flank the strand-aware start
+        let (windows, columns) = feature_windows(&recs, &chromosomes, "gene_id", Some(50));
+        assert_eq!(windows["chr2"], vec![(149, 249, 2)]);
+        assert_eq!(columns.values[2][0], "G2");
+        Ok(())
+    }
+}
@@ -0,0 +1,21 @@
+#[cfg(test)]
+mod tests {
+    use anyhow::Context;
+    use reference::cli::interrupt::*;
+    use signal_hook::consts::SIGTERM;
+
+    #[test]
+    fn signals_stop_the_run_with_their_exit_code() -> anyhow::Result<()> {
+        install_handlers()?;
+        check_interrupted()?;
+
+        // The first signal is recorded instead of ending the process
+        signal_hook::low_level::raise(SIGTERM)?;
+        let err = check_interrupted().context("Counting chr1").unwrap_err();
+        let interrupted = interrupted_by(&err).unwrap();
+        assert_eq!(interrupted, Interrupted { signal: SIGTERM });
+        assert_eq!(interrupted.exit_code(), 143);
+        assert!(interrupted_by(&anyhow::anyhow!("other")).is_none());
+        Ok(())
+    }
+}
@@ -0,0 +1,366 @@
+#[cfg(test)]
+mod tests {
+    use reference::cli::io::*;
+    use std::io::Write;
+    use tempfile::NamedTempFile;
+
+    fn write_fasta(contents: &str) -> NamedTempFile {
+        let mut file = NamedTempFile::new().expect("create temp file");
+        file.write_all(contents.as_bytes())
+            .expect("write temp file");
+        file
+    }
+
+    #[test]
+    fn multi_fasta_records_are_read_in_order() -> anyhow::Result<()> {
+        let fa = "\
+>tx1 some description
+ACGT
+AC
+
+>tx2
+nnAC
+>tx3
+";
+        let tmp = write_fasta(fa);
+        let recs = read_fasta_records(tmp.path())?;
+        assert_eq!(recs.len(), 3);
+        assert_eq!(recs[0], ("tx1".to_string(), b"ACGTAC".to_vec()));
+        assert_eq!(recs[1], ("tx2".to_string(), b"nnAC".to_vec()));
+        // Empty records are kept
+        assert!(recs[2].1.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn duplicate_record_names_return_error() {
+        let tmp = write_fasta(">a\nAC\n>a\nGT\n");
+        let err = read_fasta_records(tmp.path()).unwrap_err();
+        assert!(err.to_string().contains("Duplicate"), "unexpected: {err}");
+    }
+
+    #[test]
+    fn fai_index_is_built_and_used_for_random_access() -> anyhow::Result<()> {
+        let fa = ">chr1 desc\nACGTA\nCGTAC\nGG\n>chr2\nTTTT\nA\n";
+        let index = build_fai(fa.as_bytes())?;
+        assert_eq!(
+            index[0],
+            FaiEntry {
+                name: "chr1".into(),
+                length: 12,
+                offset: 11,
+                line_bases: 5,
+                line_width: 6,
+            }
+        );
+        assert_eq!((index[1].length, index[1].offset), (5, 32));
+
+        let tmp = write_fasta(fa);
+        let source = IndexedFastaSource::open(tmp.path())?;
+        assert_eq!(source.names(), vec!["chr1", "chr2"]);
+        assert_eq!(source.read("chr1")?, b"ACGTACGTACGG".to_vec());
+        assert_eq!(source.read("chr2")?, b"TTTTA".to_vec());
+        assert!(source.read("chr3").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn ragged_fasta_lines_cannot_be_indexed() {
+        let err = build_fai(">chr1\nACG\nACGTA\n".as_bytes()).unwrap_err();
+        assert!(err.to_string().contains("different lengths"), "{err}");
+    }
+
+    #[test]
+    fn ref_format_is_detected_from_extension() {
+        use std::path::Path;
+        assert_eq!(
+            detect_ref_format(Path::new("hg38.2bit")).unwrap(),
+            RefFormat::TwoBit
+        );
+        assert_eq!(
+            detect_ref_format(Path::new("/x/GRCh38.FA")).unwrap(),
+            RefFormat::Fasta
+        );
+        assert!(detect_ref_format(Path::new("genome.txt")).is_err());
+    }
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut enc = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        enc.write_all(data).unwrap();
+        enc.finish().unwrap()
+    }
+
+    #[test]
+    fn compressed_fasta_is_read_with_and_without_indices() -> anyhow::Result<()> {
+        let fa = ">chr1\nACGTA\nCGTAC\nGG\n>chr2\nTTTT\nA\n>chr3\nNNNC\n";
+        let dir = tempfile::tempdir()?;
+
+        // Plain gzip: decompressed into memory
+        let gz_path = dir.path().join("plain.fa.gz");
+        std::fs::write(&gz_path, gzip(fa.as_bytes()))?;
+        let source = open_sequence_source(&gz_path, RefFormat::Auto)?;
+        assert_eq!(source.names(), vec!["chr1", "chr2", "chr3"]);
+        assert_eq!(source.read("chr2")?, b"TTTTA".to_vec());
+
+        // bgzip-like: independent gzip members of 7 bytes + .gzi/.fai
+        let bgz_path = dir.path().join("blocked.fa.gz");
+        let mut compressed = Vec::new();
+        let mut gzi = Vec::new();
+        let chunks: Vec<&[u8]> = fa.as_bytes().chunks(7).collect();
+        for (i, chunk) in chunks.iter().enumerate() {
+            if i > 0 {
+                gzi.push((compressed.len() as u64, (i * 7) as u64));
+            }
+            compressed.extend(gzip(chunk));
+        }
+        std::fs::write(&bgz_path, compressed)?;
+        let mut gzi_bytes = (gzi.len() as u64).to_le_bytes().to_vec();
+        for (c, u) in gzi {
+            gzi_bytes.extend(c.to_le_bytes());
+            gzi_bytes.extend(u.to_le_bytes());
+        }
+        std::fs::write(dir.path().join("blocked.fa.gz.gzi"), gzi_bytes)?;
+        let fai: String = build_fai(fa.as_bytes())?
+            .iter()
+            .map(|e| {
+                format!(
+                    "{}\t{}\t{}\t{}\t{}\n",
+                    e.name, e.length, e.offset, e.line_bases, e.line_width
+                )
+            })
+            .collect();
+        std::fs::write(dir.path().join("blocked.fa.gz.fai"), fai)?;
+
+        let source = BgzfFastaSource::open(&bgz_path)?;
+        assert_eq!(source.read("chr1")?, b"ACGTACGTACGG".to_vec());
+        assert_eq!(source.read("chr2")?, b"TTTTA".to_vec());
+        assert_eq!(source.read("chr3")?, b"NNNC".to_vec());
+        Ok(())
+    }
+
+    /// Encode `(name, seq)` records as a 2bit file (N and lower-case blocks included)
+    fn two_bit_bytes(records: &[(&str, &str)]) -> Vec<u8> {
+        fn blocks(seq: &[u8], pred: impl Fn(u8) -> bool) -> Vec<(u32, u32)> {
+            let mut out = Vec::new();
+            let mut i = 0;
+            while i < seq.len() {
+                if !pred(seq[i]) {
+                    i += 1;
+                    continue;
+                }
+                let start = i;
+                while i < seq.len() && pred(seq[i]) {
+                    i += 1;
+                }
+                out.push((start as u32, (i - start) as u32));
+            }
+            out
+        }
+        let mut recs = Vec::new();
+        for (_, seq) in records {
+            let seq = seq.as_bytes();
+            let mut r: Vec<u8> = (seq.len() as u32).to_le_bytes().to_vec();
+            for bl in [
+                blocks(seq, |b| b.eq_ignore_ascii_case(&b'N')),
+                blocks(seq, |b| b.is_ascii_lowercase()),
+            ] {
+                r.extend((bl.len() as u32).to_le_bytes());
+                bl.iter().for_each(|(s, _)| r.extend(s.to_le_bytes()));
+                bl.iter().for_each(|(_, l)| r.extend(l.to_le_bytes()));
+            }
+            r.extend(0u32.to_le_bytes());
+            for chunk in seq.chunks(4) {
+                let mut byte = 0u8;
+                for (i, b) in chunk.iter().enumerate() {
+                    let code = match b.to_ascii_uppercase() {
+                        b'C' => 1,
+                        b'A' => 2,
+                        b'G' => 3,
+                        _ => 0,
+                    };
+                    byte |= code << (6 - 2 * i);
+                }
+                r.push(byte);
+            }
+            recs.push(r);
+        }
+        let mut out = Vec::new();
+        for v in [0x1A412743u32, 0, records.len() as u32, 0] {
+            out.extend(v.to_le_bytes());
+        }
+        let mut offset = 16 + records.iter().map(|(n, _)| 5 + n.len()).sum::<usize>();
+        for ((name, _), r) in records.iter().zip(&recs) {
+            out.push(name.len() as u8);
+            out.extend(name.as_bytes());
+            out.extend((offset as u32).to_le_bytes());
+            offset += r.len();
+        }
+        recs.iter().for_each(|r| out.extend(r));
+        out
+    }
+
+    #[test]
+    fn two_bit_source_reads_in_parallel_with_soft_masks() -> anyhow::Result<()> {
+        use rayon::prelude::*;
+        let records = [("chr1", "ACGTNNacgtTTG"), ("chr2", "ggCCAT"), ("chrM", "T")];
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("ref.2bit");
+        std::fs::write(&path, two_bit_bytes(&records))?;
+
+        let source = open_sequence_source(&path, RefFormat::Auto)?;
+        assert_eq!(source.names(), vec!["chr1", "chr2", "chrM"]);
+        let reads: Vec<Vec<u8>> = (0..30)
+            .into_par_iter()
+            .map(|i| source.read(records[i % 3].0))
+            .collect::<anyhow::Result<_>>()?;
+        for (i, seq) in reads.iter().enumerate() {
+            assert_eq!(seq, records[i % 3].1.as_bytes());
+        }
+        assert!(source.read("chr3").is_err());
+        Ok(())
+    }
+
+    /// Serve `data` over HTTP with range request support; returns the URL
+    fn serve_ranges(data: Vec<u8>) -> String {
+        use std::io::{BufRead, BufReader};
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/ref.2bit", listener.local_addr().unwrap());
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut range = None;
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    if line.trim().is_empty() {
+                        break;
+                    }
+                    if let Some(r) = line.to_lowercase().strip_prefix("range: bytes=") {
+                        let (s, e) = r.trim().split_once('-').unwrap();
+                        range = Some((s.parse::<usize>().unwrap(), e.parse::<usize>().unwrap()));
+                    }
+                }
+                let (s, e) = range.unwrap();
+                let e = e.min(data.len() - 1);
+                write!(
+                    stream,
+                    "HTTP/1.1 206 Partial Content\r\nContent-Range: bytes {}-{}/{}\r\n\
+                     Content-Length: {}\r\nConnection: close\r\n\r\n",
+                    s,
+                    e,
+                    data.len(),
+                    e + 1 - s
+                )
+                .unwrap();
+                stream.write_all(&data[s..=e]).unwrap();
+            }
+        });
+        url
+    }
+
+    #[test]
+    fn remote_two_bit_is_read_with_range_requests() -> anyhow::Result<()> {
+        use std::io::{Read, Seek, SeekFrom};
+        let records = [("chr1", "ACGTNNacgtTTG"), ("chr2", "ggCCAT")];
+        let data = two_bit_bytes(&records);
+        let url = serve_ranges(data.clone());
+
+        let mut reader = reference::cli::remote::HttpRangeReader::open(&url)?;
+        assert_eq!(reader.len(), data.len() as u64);
+        reader.seek(SeekFrom::End(-4))?;
+        let mut tail = Vec::new();
+        reader.read_to_end(&mut tail)?;
+        assert_eq!(tail, data[data.len() - 4..]);
+
+        let source = open_sequence_source(std::path::Path::new(&url), RefFormat::Auto)?;
+        assert_eq!(source.names(), vec!["chr1", "chr2"]);
+        assert_eq!(source.read("chr2")?, b"ggCCAT".to_vec());
+        Ok(())
+    }
+
+    #[test]
+    fn staged_output_dir_replaces_only_on_commit() -> anyhow::Result<()> {
+        let parent = tempfile::tempdir()?;
+        let out = parent.path().join("out");
+        std::fs::create_dir(&out)?;
+        std::fs::write(out.join("stale.txt"), "old")?;
+
+        assert!(StagedOutputDir::new(&out, false, &[]).is_err());
+
+        // Dropped without commit: nothing changes
+        let staged = StagedOutputDir::new(&out, true, &[])?;
+        std::fs::write(staged.path().join("counts.txt"), "new")?;
+        drop(staged);
+        assert!(out.join("stale.txt").exists());
+        assert_eq!(std::fs::read_dir(parent.path())?.count(), 1);
+
+        let staged = StagedOutputDir::new(&out, true, &[])?;
+        std::fs::write(staged.path().join("counts.txt"), "new")?;
+        staged.commit()?;
+        assert!(!out.join("stale.txt").exists());
+        assert_eq!(std::fs::read_to_string(out.join("counts.txt"))?, "new");
+        assert_eq!(std::fs::read_dir(parent.path())?.count(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn staged_output_dir_refuses_dirs_with_inputs() -> anyhow::Result<()> {
+        use std::path::Path;
+
+        let parent = tempfile::tempdir()?;
+        let out = parent.path().join("out");
+        std::fs::create_dir_all(out.join("beds"))?;
+        let bed = out.join("beds").join("windows.bed");
+        std::fs::write(&bed, "chr1\t0\t10\n")?;
+
+        let err = StagedOutputDir::new(&out, true, &[&bed]).err().unwrap();
+        assert!(err.to_string().contains("contains the input"));
+        assert!(bed.exists());
+        // The working directory and its ancestors
+        for dir in [".", ".."] {
+            let err = StagedOutputDir::new(Path::new(dir), true, &[])
+                .err()
+                .unwrap();
+            assert!(err.to_string().contains("contains the working directory"));
+        }
+        // Inputs elsewhere are fine
+        let other = parent.path().join("windows.bed");
+        std::fs::write(&other, "chr1\t0\t10\n")?;
+        StagedOutputDir::new(&out, true, &[&other])?.commit()?;
+        assert!(!bed.exists());
+        Ok(())
+    }
+
+    #[test]
+    fn parses_object_store_urls() -> anyhow::Result<()> {
+        use reference::cli::cloud::*;
+        use std::path::Path;
+        assert!(is_object_store_url(Path::new("s3://bucket/runs/1")));
+        assert!(is_object_store_url(Path::new("gs://bucket")));
+        assert!(!is_object_store_url(Path::new("results/s3")));
+        assert_eq!(
+            parse_object_store_url("s3://bucket/runs/1/")?,
+            ("s3", "bucket", "runs/1")
+        );
+        assert_eq!(parse_object_store_url("gs://bucket")?, ("gs", "bucket", ""));
+        assert!(parse_object_store_url("s3:///runs").is_err());
+        assert!(parse_object_store_url("az://bucket/runs").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn creates_outputs_locally_outside_object_storage() -> anyhow::Result<()> {
+        use reference::cli::cloud::*;
+        use std::io::Write;
+        let dir = tempfile::tempdir()?;
+        let _route = OutputRoute::local(dir.path());
+        let path = dir.path().join("k1_counts.npy");
+        let mut out = create_output(&path)?;
+        out.write_all(b"counts")?;
+        out.finish()?;
+        assert_eq!(std::fs::read(&path)?, b"counts");
+        assert!(streamed_files(dir.path()).is_empty());
+        Ok(())
+    }
+}
@@ -1,13 +1,13 @@
 #[cfg(test)]
 mod tests {
-    use std::collections::HashMap;
+    use std::collections::BTreeMap;
 
     use fxhash::FxHashMap;
     use reference::reference::kmer_codec::*;
     use reference::reference::process_counts::*;
 
     /* --------------------------------------------------------------------- */
-    /*  collapse_map / canonical                                            */
+    /*  canonical / canonical_kmer_counts                                   */
     /* --------------------------------------------------------------------- */
 
     #[test]
@@ -21,7 +21,7 @@ mod tests {
         let fwd = "ACG"; // rc == "CGT"
         let rc = revcomp(fwd);
         assert_eq!(rc, "CGT");
-        assert!(fwd < &rc);
+        assert!(fwd < rc.as_str());
         assert_eq!(canonical(fwd.to_string()), fwd);
         assert_eq!(canonical(rc), fwd); // canonical of rc collapses back
 
@@ -30,13 +30,50 @@ mod tests {
     }
 
     #[test]
-    fn collapse_map_sums_reverse_complements() {
-        let mut m: FxHashMap<String, u64> = FxHashMap::default();
-        m.insert("ACG".into(), 2);
-        m.insert("CGT".into(), 3); // reverse complement of ACG
-        let collapsed = collapse_map(&m);
-        assert_eq!(collapsed.len(), 1);
-        assert_eq!(collapsed["ACG"], 5);
+    fn canonical_kmer_counts_sums_reverse_complements() {
+        let specs = build_kmer_specs(&[3]).unwrap();
+        let spec = &specs[&3];
+        let code = |kmer: &str| spec.build_codes(kmer.as_bytes())[0];
+        let kmer = |seq: &str| Kmer {
+            k: 3,
+            code: code(seq),
+        };
+        let mut m: FxHashMap<Kmer, u64> = FxHashMap::default();
+        m.insert(kmer("ACG"), 2);
+        m.insert(kmer("CGT"), 3); // reverse complement of ACG
+        m.insert(kmer("TTT"), 1);
+        let collapsed = canonical_kmer_counts(m, &specs);
+        assert_eq!(collapsed.len(), 2);
+        assert_eq!(collapsed[&kmer("ACG")], 5);
+        assert_eq!(collapsed[&kmer("AAA")], 1);
+
+        // Matches the string-based canonical form
+        for seq in ["CAT", "GGA", "TCC", "ACG"] {
+            let canon = spec.decode_kmer(spec.canonical_code(code(seq)));
+            assert_eq!(canon, canonical(seq.to_string()));
+        }
+    }
+
+    #[test]
+    fn both_strand_kmer_counts_adds_reverse_complements() {
+        let specs = build_kmer_specs(&[4]).unwrap();
+        let spec = &specs[&4];
+        let kmer = |seq: &str| Kmer {
+            k: 4,
+            code: spec.build_codes(seq.as_bytes())[0],
+        };
+        let mut m: FxHashMap<Kmer, u64> = FxHashMap::default();
+        m.insert(kmer("AACC"), 2);
+        m.insert(kmer("GGTT"), 3); // reverse complement of AACC
+        m.insert(kmer("AAAA"), 1);
+        m.insert(kmer("ACGT"), 4); // palindrome
+        let both = both_strand_kmer_counts(m, &specs);
+        assert_eq!(both.len(), 5);
+        assert_eq!(both[&kmer("AACC")], 5);
+        assert_eq!(both[&kmer("GGTT")], 5);
+        assert_eq!(both[&kmer("AAAA")], 1);
+        assert_eq!(both[&kmer("TTTT")], 1);
+        assert_eq!(both[&kmer("ACGT")], 8);
     }
 
     /* --------------------------------------------------------------------- */
@@ -53,6 +90,19 @@ mod tests {
         assert_eq!(encode_base(b'X'), 4); // unknown → 4
     }
 
+    #[test]
+    fn batch_encoding_matches_encode_base() {
+        // Every byte value, at every offset into the SIMD lanes
+        let seq: Vec<u8> = (0..=255u8).cycle().take(1000).collect();
+        for start in 0..33 {
+            let mut out = vec![9; seq.len() - start];
+            encode_bases(&seq[start..], &mut out);
+            for (&b, &val) in seq[start..].iter().zip(&out) {
+                assert_eq!(val as u64, encode_base(b), "byte {}", b);
+            }
+        }
+    }
+
     #[test]
     fn choose_width_returns_correct_sentinals() {
         // k = 3 → 5^3 = 125 < 254 so fits in u8
@@ -123,6 +173,222 @@ mod tests {
         }
     }
 
+    #[test]
+    fn single_motifs_encode_and_explain_sentinels() {
+        // Radix-5 (k = 4) and 2-bit (k = 7) codes
+        let specs = build_kmer_specs(&[4, 7]).unwrap();
+        for (motif, k) in [("ACGT", 4), ("TTAGGCA", 7)] {
+            let spec = &specs[&k];
+            let code = spec.encode_kmer(motif).unwrap();
+            assert_eq!(spec.decode_kmer(code), motif);
+            assert_eq!(spec.encode_kmer(&motif.to_lowercase()).unwrap(), code);
+            assert_eq!(spec.sentinel_meaning(code), None);
+        }
+        let spec = &specs[&4];
+        // A = 0, C = 1, G = 2, T = 3 with the first base most significant
+        assert_eq!(spec.encode_kmer("AACG").unwrap(), 5 + 2);
+        let n = spec.encode_kmer("ACNT").unwrap();
+        assert_eq!(n, spec.sentinel_n());
+        assert_eq!(spec.sentinel_meaning(n), Some("k-mer with N"));
+        assert!(spec.sentinel_meaning(spec.sentinel_none()).is_some());
+        assert!(spec.encode_kmer("ACG").is_err());
+    }
+
+    #[test]
+    fn two_bit_codes_roundtrip_and_mask_n_runs() {
+        let specs = build_kmer_specs(&[7, 31]).unwrap();
+        let spec = &specs[&7];
+        assert_eq!(spec.encoding(), Encoding::TwoBit);
+        assert_eq!(spec.width(), Width::U16);
+        assert_eq!(specs[&31].width(), Width::U64);
+
+        let seq = b"ACGTTGCANNACGTACGTA";
+        let codes = spec.build_codes(seq);
+        assert_eq!(codes.len(), seq.len());
+        for (i, &code) in codes.iter().enumerate() {
+            let expected = match seq.get(i..i + 7) {
+                Some(kmer) if !kmer.contains(&b'N') => std::str::from_utf8(kmer).unwrap(),
+                _ => "NNNNNNN",
+            };
+            assert_eq!(spec.decode_kmer(code), expected);
+        }
+        // Bitwise reverse complement
+        assert_eq!(spec.decode_kmer(spec.revcomp_code(codes[0])), "GCAACGT");
+        assert_eq!(spec.decode_kmer(spec.canonical_code(codes[0])), "ACGTTGC");
+
+        let spec = &specs[&31];
+        let seq = b"ACGTACGTACGTACGTACGTACGTACGTACC";
+        let code = spec.build_codes(seq)[0];
+        assert_eq!(spec.decode_kmer(code).as_bytes(), seq);
+        let rc = spec.decode_kmer(spec.revcomp_code(code));
+        assert_eq!(rc, revcomp(std::str::from_utf8(seq).unwrap()));
+    }
+
+    // The single pass over all k matches building each k on its own
+    #[test]
+    fn fused_codes_match_per_k_codes() {
+        let specs = build_kmer_specs(&[1, 3, 7, 12, 31, 40]).unwrap();
+        let seq = b"ACGTTGCANNACGTACgtaRACGTACGTTTGACCAGTACGATCGATCGTAGCTAGNNNACGTACGTACGATC";
+        // Longer than the encoding blocks, with N runs across their borders
+        let mut long: Vec<u8> = (0..10_000u64)
+            .map(|i| b"ACGT"[((i * 7919) ^ (i >> 3)) as usize % 4])
+            .collect();
+        long[4090..4100].fill(b'N');
+        long[8190] = b'N';
+        for seq in [&seq[..], &seq[..5], &b""[..], &long[..]] {
+            let codes_by_k = build_codes_per_k(seq, &specs);
+            for (k, spec) in &specs {
+                let codes = &codes_by_k[k];
+                assert_eq!(codes.width(), spec.width());
+                let fused: Vec<u64> = (0..codes.len()).map(|i| codes.get(i)).collect();
+                // Dictionary codes are numbered in order of first appearance
+                if spec.encoding() != Encoding::Dictionary {
+                    assert_eq!(fused, spec.build_codes(seq), "k={}", k);
+                }
+                let decoded: Vec<String> = fused.iter().map(|&c| spec.decode_kmer(c)).collect();
+                let expected: Vec<String> = spec
+                    .build_codes(seq)
+                    .iter()
+                    .map(|&c| spec.decode_kmer(c))
+                    .collect();
+                assert_eq!(decoded, expected, "k={}", k);
+            }
+        }
+    }
+
+    #[test]
+    fn kmer_specs_iterate_in_ascending_k() {
+        let specs = build_kmer_specs(&[12, 2, 5, 3]).unwrap();
+        assert_eq!(
+            specs.keys().copied().collect::<Vec<u8>>(),
+            vec![2, 3, 5, 12]
+        );
+    }
+
+    #[test]
+    fn only_listed_motif_codes_are_kept() {
+        let specs = build_kmer_specs(&[2, 3]).unwrap();
+        let motifs = BTreeMap::from([(3, vec!["ACG".to_string()]), (2, vec!["AA".to_string()])]);
+        let codes = MotifCodes::new(&motifs, &specs);
+        let code = |k: u8, kmer: &str| specs[&k].build_codes(kmer.as_bytes())[0];
+        assert!(codes.is_listed(&Kmer {
+            k: 3,
+            code: code(3, "ACG")
+        }));
+        assert!(!codes.is_listed(&Kmer {
+            k: 3,
+            code: code(3, "CGT")
+        }));
+        assert!(codes.either_strand[&3].contains(&code(3, "CGT")));
+        assert!(codes.either_strand[&2].contains(&code(2, "TT")));
+
+        let seq = b"ACGTACGAA";
+        let mut codes_by_k = build_codes_per_k(seq, &specs);
+        let kmer_codes = codes_by_k.get_mut(&3).unwrap();
+        keep_listed_codes(kmer_codes, &specs[&3], &codes.listed[&3]);
+        let kept: Vec<usize> = (0..seq.len())
+            .filter(|&pos| kmer_codes.get(pos) != specs[&3].sentinel_none())
+            .collect();
+        assert_eq!(kept, vec![0, 4]);
+    }
+
+    #[test]
+    fn strided_codes_are_kept() {
+        let specs = build_kmer_specs(&[2]).unwrap();
+        let spec = &specs[&2];
+        let seq = b"ACGTACGT";
+        let mut codes_by_k = build_codes_per_k(seq, &specs);
+        let kmer_codes = codes_by_k.get_mut(&2).unwrap();
+        // Codes of a chunk starting at position 1
+        keep_strided_codes(kmer_codes, spec, 3, 1);
+        let kept: Vec<usize> = (0..seq.len())
+            .filter(|&pos| kmer_codes.get(pos) != spec.sentinel_none())
+            .collect();
+        assert_eq!(kept, vec![2, 5]);
+    }
+
+    #[test]
+    fn excluded_motif_codes_are_dropped() {
+        let specs = build_kmer_specs(&[3]).unwrap();
+        let motifs = BTreeMap::from([(3, vec!["ACG".to_string(), "AAA".to_string()])]);
+        let codes = MotifCodes::new(&motifs, &specs);
+
+        let seq = b"ACGTACGAAA";
+        let mut codes_by_k = build_codes_per_k(seq, &specs);
+        let kmer_codes = codes_by_k.get_mut(&3).unwrap();
+        drop_excluded_codes(kmer_codes, &specs[&3], &codes.listed[&3]);
+        let kept: Vec<usize> = (0..seq.len())
+            .filter(|&pos| kmer_codes.get(pos) != specs[&3].sentinel_none())
+            .collect();
+        // ACG at 0 and 4 and AAA at 7 are dropped
+        assert_eq!(kept, vec![1, 2, 3, 5, 6]);
+    }
+
+    #[test]
+    fn dictionary_codes_decode_observed_kmers() {
+        let specs = build_kmer_specs(&[41]).unwrap();
+        let spec = &specs[&41];
+        assert_eq!(spec.encoding(), Encoding::Dictionary);
+        assert!(build_kmer_specs(&[64]).is_err());
+
+        let seq = format!("{}N{}", "ACGGT".repeat(10), "TTGCA".repeat(9));
+        let codes = spec.build_codes(seq.as_bytes());
+        for (i, &code) in codes.iter().enumerate() {
+            let expected = match seq.get(i..i + 41) {
+                Some(kmer) if !kmer.contains('N') => kmer.to_string(),
+                _ => "N".repeat(41),
+            };
+            assert_eq!(spec.decode_kmer(code), expected);
+        }
+        // Reverse complements share the number of their canonical form
+        let rc = spec.revcomp_code(codes[0]);
+        assert_eq!(spec.decode_kmer(rc), revcomp(&seq[..41]));
+        assert_eq!(spec.canonical_code(rc), spec.canonical_code(codes[0]));
+        assert_eq!(
+            spec.decode_kmer(spec.canonical_code(rc)),
+            canonical(seq[..41].to_string())
+        );
+        assert_eq!(spec.n_codes(), 4 * 10);
+        assert!(spec.is_kmer_code(rc) && !spec.is_kmer_code(spec.n_codes()));
+
+        // Palindromes are their own reverse complement, in any thread
+        let specs = build_kmer_specs(&[40]).unwrap();
+        let spec = &specs[&40];
+        let palindrome = "ACGT".repeat(10);
+        let codes: Vec<u64> = std::thread::scope(|scope| {
+            let handles: Vec<_> = [palindrome.as_bytes(), b"TTTT", &seq.as_bytes()[..45]]
+                .into_iter()
+                .map(|seq| scope.spawn(move || spec.build_codes(seq)[0]))
+                .collect();
+            handles.into_iter().map(|h| h.join().unwrap()).collect()
+        });
+        assert_eq!(spec.revcomp_code(codes[0]), codes[0]);
+        assert_eq!(spec.decode_kmer(codes[0]), palindrome);
+        assert_eq!(codes[1], spec.sentinel_none());
+        assert_eq!(spec.decode_kmer(codes[2]), seq[..40]);
+    }
+
+    #[test]
+    fn revcomp_codes_match_string_reverse_complements() {
+        let specs = build_kmer_specs(&[3]).unwrap();
+        let spec = &specs[&3];
+        let codes = spec.build_codes(b"AACGTN");
+        for &code in &codes[..3] {
+            let rc = spec.decode_kmer(spec.revcomp_code(code));
+            assert_eq!(rc, revcomp(&spec.decode_kmer(code)));
+        }
+        // Sentinels are kept
+        assert_eq!(spec.revcomp_code(codes[3]), spec.sentinel_n());
+        assert_eq!(spec.revcomp_code(codes[5]), spec.sentinel_none());
+
+        let kmer = |code| Kmer { k: 3, code };
+        // AAC once, ACG twice
+        let counts = FxHashMap::from_iter([(kmer(codes[0]), 1), (kmer(codes[1]), 2)]);
+        let flipped = split_and_decode_counts(&revcomp_kmer_counts(counts, &specs), &specs);
+        assert_eq!(flipped.counts[&3]["GTT"], 1);
+        assert_eq!(flipped.counts[&3]["CGT"], 2);
+    }
+
     /* --------------------------------------------------------------------- */
     /*  merge_decoded_counts                                                */
     /* --------------------------------------------------------------------- */
@@ -131,10 +397,10 @@ mod tests {
     fn merge_decoded_counts_sums_bins() {
         // Build two DecodedCounts with cross‑over motifs
         let mut dc1 = DecodedCounts {
-            counts: HashMap::new(),
+            counts: BTreeMap::new(),
         };
         let mut dc2 = DecodedCounts {
-            counts: HashMap::new(),
+            counts: BTreeMap::new(),
         };
 
         dc1.counts
@@ -175,12 +441,12 @@ mod tests {
         // Two windows with a single 2-mer each
         let specs = build_kmer_specs(&[7]).unwrap();
         let mut win1 = DecodedCounts {
-            counts: HashMap::new(),
+            counts: BTreeMap::new(),
         };
         win1.counts
             .insert(7, FxHashMap::from_iter([(String::from("AAAAAAA"), 1u64)]));
         let mut win2 = DecodedCounts {
-            counts: HashMap::new(),
+            counts: BTreeMap::new(),
         };
         // NOTE: 7-mer so it doesn't add all motifs!
         win2.counts
@@ -199,4 +465,184 @@ mod tests {
         assert_eq!(prepared[0].counts[&7]["AAAAAAA"], 1);
         assert_eq!(prepared[1].counts[&7]["CCCCCCC"], 1);
     }
+
+    #[test]
+    fn prepare_code_counts_matches_decoded_path() {
+        let specs = build_kmer_specs(&[2, 7]).unwrap();
+        let code_counts = |seq: &[u8]| {
+            let mut counts: FxHashMap<Kmer, u64> = FxHashMap::default();
+            for (&k, spec) in &specs {
+                for code in spec.build_codes(seq) {
+                    *counts.entry(Kmer { k, code }).or_insert(0) += 1;
+                }
+            }
+            counts
+        };
+        // The second window has N's and an empty third window
+        let windows = vec![
+            code_counts(b"ACGTTGCAAACCGT"),
+            code_counts(b"GGGANCCCTTTAGGGA"),
+            FxHashMap::default(),
+        ];
+
+        for canonical in [false, true] {
+            let decoded: Vec<DecodedCounts> = windows
+                .iter()
+                .map(|c| split_and_decode_counts(c, &specs))
+                .collect();
+            let expected = prepare_decoded_counts(&decoded, canonical, &specs);
+            assert_eq!(prepare_code_counts(&windows, canonical, &specs), expected);
+        }
+
+        // Merging codes matches merging decoded counts
+        let merged = merge_kmer_counts(windows.clone());
+        assert_eq!(
+            split_and_decode_counts(&merged, &specs),
+            merge_decoded_counts(
+                windows
+                    .iter()
+                    .map(|c| split_and_decode_counts(c, &specs))
+                    .collect()
+            )
+        );
+    }
+
+    /* --------------------------------------------------------------------- */
+    /*  rarefy_counts                                                        */
+    /* --------------------------------------------------------------------- */
+
+    #[test]
+    fn rarefy_counts_subsamples_to_depth_deterministically() {
+        let mut win = DecodedCounts {
+            counts: BTreeMap::new(),
+        };
+        win.counts.insert(
+            2,
+            FxHashMap::from_iter([
+                (String::from("AA"), 50u64),
+                (String::from("AC"), 30u64),
+                (String::from("GT"), 20u64),
+            ]),
+        );
+        let mut shallow = DecodedCounts {
+            counts: BTreeMap::new(),
+        };
+        shallow
+            .counts
+            .insert(2, FxHashMap::from_iter([(String::from("AA"), 5u64)]));
+
+        let mut a = vec![win.clone(), shallow.clone()];
+        let mut b = vec![win, shallow.clone()];
+        assert_eq!(rarefy_counts(&mut a, 10, 42), 1);
+        rarefy_counts(&mut b, 10, 42);
+
+        assert_eq!(a[0].counts[&2].values().sum::<u64>(), 10);
+        assert!(a[0].counts[&2]["AA"] <= 50);
+        assert_eq!(a, b); // same seed, same result
+        assert_eq!(a[1], shallow); // too shallow to rarefy
+    }
+
+    #[test]
+    fn rarefy_counts_handles_underflowing_hypergeometric() {
+        // Hypergeometric::new(1321, 105, 100) fails with PopulationTooLarge
+        let mut win = DecodedCounts {
+            counts: BTreeMap::new(),
+        };
+        win.counts.insert(
+            1,
+            FxHashMap::from_iter([(String::from("A"), 105u64), (String::from("C"), 1216u64)]),
+        );
+        let mut windows = vec![win];
+        assert_eq!(rarefy_counts(&mut windows, 100, 7), 0);
+        assert_eq!(windows[0].counts[&1].values().sum::<u64>(), 100);
+    }
+
+    #[test]
+    fn rarefy_counts_leaves_shallow_and_empty_windows() {
+        let mut shallow = DecodedCounts {
+            counts: BTreeMap::new(),
+        };
+        shallow.counts.insert(
+            2,
+            FxHashMap::from_iter([(String::from("AA"), 3u64), (String::from("AC"), 0u64)]),
+        );
+        shallow.counts.insert(3, FxHashMap::default());
+        let empty = DecodedCounts {
+            counts: BTreeMap::new(),
+        };
+        let mut exact = DecodedCounts {
+            counts: BTreeMap::new(),
+        };
+        exact.counts.insert(
+            2,
+            FxHashMap::from_iter([(String::from("AA"), 4u64), (String::from("AC"), 6u64)]),
+        );
+
+        let mut windows = vec![shallow.clone(), empty.clone(), exact.clone()];
+        // Depth above the totals of both k of the first window
+        assert_eq!(rarefy_counts(&mut windows, 10, 1), 2);
+        assert_eq!(windows[0], shallow);
+        assert_eq!(windows[1], empty);
+        assert_eq!(windows[2], exact); // all counts are drawn
+    }
+
+    #[test]
+    fn row_scales_give_frequencies_and_per_kb_densities() {
+        let win = |a: u64, c: u64| DecodedCounts {
+            counts: BTreeMap::from([(
+                1u8,
+                FxHashMap::from_iter([(String::from("A"), a), (String::from("C"), c)]),
+            )]),
+        };
+        let windows = vec![win(1, 3), win(0, 0)];
+        let freq = row_scales(&windows, &[8, 0], Normalization::Frequency).unwrap();
+        assert_eq!(freq[&1], vec![0.25, 0.0]);
+        let per_kb = row_scales(&windows, &[500, 0], Normalization::PerKb).unwrap();
+        assert_eq!(per_kb[&1], vec![2.0, 0.0]);
+        assert!(row_scales(&windows, &[8, 0], Normalization::None).is_none());
+    }
+
+    /* --------------------------------------------------------------------- */
+    /*  kmer_diversity                                                       */
+    /* --------------------------------------------------------------------- */
+
+    #[test]
+    fn kmer_diversity_of_windows() {
+        assert_eq!(n_possible_motifs(2, false), 16.0);
+        assert_eq!(n_possible_motifs(2, true), 10.0);
+        assert_eq!(n_possible_motifs(3, true), 32.0);
+
+        let counts: FxHashMap<String, u64> = [("AA", 2), ("AC", 2), ("AG", 0)]
+            .into_iter()
+            .map(|(m, c)| (m.to_string(), c))
+            .collect();
+        let d = kmer_diversity(&counts, 16.0);
+        assert_eq!(d.shannon_entropy, 1.0);
+        assert_eq!(d.distinct_fraction, 2.0 / 16.0);
+        assert_eq!(d.linguistic_complexity, 2.0 / 4.0);
+
+        let single: FxHashMap<String, u64> = [("AA".to_string(), 5)].into_iter().collect();
+        assert_eq!(kmer_diversity(&single, 16.0).shannon_entropy, 0.0);
+        let empty = FxHashMap::default();
+        assert_eq!(kmer_diversity(&empty, 16.0).linguistic_complexity, 0.0);
+    }
+
+    /* --------------------------------------------------------------------- */
+    /*  --kmer-sizes ranges                                                  */
+    /* --------------------------------------------------------------------- */
+
+    #[test]
+    fn kmer_size_ranges_expand() {
+        let ranges: Vec<_> = ["1-4", "6", "8-8"]
+            .iter()
+            .map(|s| parse_kmer_size_range(s, MAX_K).unwrap())
+            .collect();
+        assert_eq!(expand_kmer_sizes(&ranges), vec![1, 2, 3, 4, 6, 8]);
+
+        assert!(parse_kmer_size_range("0", MAX_K).is_err());
+        assert!(parse_kmer_size_range("5-64", MAX_K).is_err());
+        assert!(parse_kmer_size_range("6-4", MAX_K).is_err());
+        assert!(parse_kmer_size_range("3-", MAX_K).is_err());
+        assert!(parse_kmer_size_range("17", 16).is_err());
+    }
 }
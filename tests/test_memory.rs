@@ -0,0 +1,83 @@
+#[cfg(test)]
+mod tests {
+    use anyhow::bail;
+    use reference::reference::kmer_codec::build_kmer_specs;
+    use reference::reference::memory::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test]
+    fn estimates_grow_with_length_and_kmer_sizes() {
+        let small = build_kmer_specs(&[3]).unwrap();
+        let large = build_kmer_specs(&[3, 12]).unwrap();
+        // 2 bytes of sequence, 1 byte of codes and 64 possible 3-mers
+        assert_eq!(estimate_chrom_bytes(1000, &small), 3000 + 64 * 32);
+        assert!(estimate_chrom_bytes(2000, &small) > estimate_chrom_bytes(1000, &small));
+        assert!(estimate_chrom_bytes(1000, &large) > estimate_chrom_bytes(1000, &small));
+    }
+
+    #[test]
+    fn planned_memory_follows_threads_and_limit() {
+        let specs = build_kmer_specs(&[3]).unwrap();
+        // Up to 64 3-mers per window
+        assert_eq!(
+            estimate_window_counts_bytes([10, 1000], &specs),
+            (10 + 64) * 32
+        );
+        let lengths = [1000, 3000, 2000];
+        let one = |len| estimate_chrom_bytes(len, &specs);
+        assert_eq!(
+            estimate_peak_chrom_bytes(&lengths, &specs, 1, None),
+            one(3000)
+        );
+        assert_eq!(
+            estimate_peak_chrom_bytes(&lengths, &specs, 2, None),
+            one(3000) + one(2000)
+        );
+        // The largest chromosome runs even above the limit
+        assert_eq!(
+            estimate_peak_chrom_bytes(&lengths, &specs, 3, Some(1)),
+            one(3000)
+        );
+    }
+
+    #[test]
+    fn tasks_run_within_the_budget() {
+        let budget = MemoryBudget::new(10);
+        let running = AtomicUsize::new(0);
+        let max_running = AtomicUsize::new(0);
+        // Items of 6 bytes run one at a time, items of 5 bytes two at a time
+        let results = budget
+            .run(
+                vec![3, 0, 2, 1],
+                |item| if item < 2 { 6 } else { 5 },
+                4,
+                |item| {
+                    let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                    max_running.fetch_max(now, Ordering::SeqCst);
+                    std::thread::sleep(std::time::Duration::from_millis(20));
+                    running.fetch_sub(1, Ordering::SeqCst);
+                    Ok(item * 10)
+                },
+            )
+            .unwrap();
+        assert_eq!(results, vec![30, 0, 20, 10]);
+        assert!(max_running.load(Ordering::SeqCst) <= 2);
+
+        // An item larger than the budget runs alone
+        let results = budget.run(vec![0], |_| 100, 2, Ok).unwrap();
+        assert_eq!(results, vec![0]);
+
+        let err = budget.run(
+            vec![0, 1],
+            |_| 1,
+            2,
+            |item| {
+                if item == 1 {
+                    bail!("failed")
+                }
+                Ok(item)
+            },
+        );
+        assert!(err.is_err());
+    }
+}
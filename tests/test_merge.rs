@@ -0,0 +1,137 @@
+#[cfg(test)]
+mod tests {
+    use ndarray::{arr2, Array1, Array2};
+    use ndarray_npy::ReadNpyExt;
+    use reference::reference::merge::*;
+    use reference::reference::write::*;
+    use std::path::{Path, PathBuf};
+    use tempfile::tempdir;
+
+    /// An output directory with a dense `k1` and a sparse `k2` matrix
+    fn run_dir(
+        dir: &Path,
+        dense: Array2<u64>,
+        sparse: &[(u64, u64, u64)],
+        motifs: &str,
+    ) -> anyhow::Result<PathBuf> {
+        std::fs::create_dir_all(dir)?;
+        std::fs::write(dir.join("bins.bed"), "chr1\t0\t10\nchr1\t10\t20\n")?;
+        std::fs::write(dir.join("k1_motifs.txt"), motifs)?;
+        std::fs::write(dir.join("k2_motifs.txt"), "AA\nAC\nAG\n")?;
+        write_counts_npy(&dir.join("k1_counts.npy"), &dense, CountDtype::U32)?;
+        write_triplets_sparse(
+            sparse.iter().map(|&t| Ok(t)),
+            2,
+            3,
+            "k2",
+            dir,
+            SparseFormat::Csr,
+            CountDtype::U16,
+        )?;
+        Ok(dir.to_path_buf())
+    }
+
+    #[test]
+    fn runs_are_summed_by_window_and_motif() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let a = run_dir(
+            &dir.path().join("a"),
+            arr2(&[[1, 2], [3, 4]]),
+            &[(0, 1, 5), (1, 2, 1)],
+            "A\nC\n",
+        )?;
+        let b = run_dir(
+            &dir.path().join("b"),
+            arr2(&[[10, 0], [0, 7]]),
+            &[(0, 0, 2), (1, 2, 4)],
+            "A\nC\n",
+        )?;
+        let out = dir.path().join("merged");
+        let merged = merge_runs(&[a, b], &out, CountDtype::U64)?;
+        assert_eq!(merged, vec!["k1_counts.npy", "k2_counts_sparse.npz"]);
+
+        let dense = Array2::<u64>::read_npy(std::fs::File::open(out.join("k1_counts.npy"))?)?;
+        assert_eq!(dense, arr2(&[[11, 2], [3, 11]]));
+
+        let file = std::fs::File::open(out.join("k2_counts_sparse.npz"))?;
+        let mut npz = zip::ZipArchive::new(file)?;
+        let mut read = |name: &str| -> anyhow::Result<Array1<u64>> {
+            Ok(Array1::<u64>::read_npy(npz.by_name(name)?)?)
+        };
+        assert_eq!(read("indptr.npy")?.to_vec(), vec![0, 2, 3]);
+        assert_eq!(read("indices.npy")?.to_vec(), vec![0, 1, 2]);
+        assert_eq!(read("data.npy")?.to_vec(), vec![2, 5, 5]);
+
+        assert!(out.join("bins.bed").exists());
+        assert!(out.join("k2_motifs.txt").exists());
+        Ok(())
+    }
+
+    #[test]
+    fn runs_with_other_motifs_are_aligned_on_their_union() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let a = run_dir(
+            &dir.path().join("a"),
+            arr2(&[[1, 2], [3, 4]]),
+            &[(0, 1, 5)],
+            "A\nC\n",
+        )?;
+        let b = run_dir(
+            &dir.path().join("b"),
+            arr2(&[[10, 20], [30, 40]]),
+            &[(1, 0, 2)],
+            "G\nA\n",
+        )?;
+        std::fs::write(b.join("k2_motifs.txt"), "AT\nAA\nAC\n")?;
+        let out = dir.path().join("merged");
+        merge_runs(&[a, b], &out, CountDtype::U64)?;
+
+        let dense = Array2::<u64>::read_npy(std::fs::File::open(out.join("k1_counts.npy"))?)?;
+        assert_eq!(dense, arr2(&[[21, 2, 10], [43, 4, 30]]));
+        assert_eq!(
+            std::fs::read_to_string(out.join("k1_motifs.txt"))?,
+            "A\nC\nG\n"
+        );
+
+        let sparse = SparseCounts::read(&out.join("k2_counts_sparse.npz"))?;
+        assert_eq!(sparse.shape, [2, 4]);
+        assert_eq!(sparse.triplets, vec![(0, 1, 5), (1, 3, 2)]);
+        assert_eq!(
+            std::fs::read_to_string(out.join("k2_motifs.txt"))?,
+            "AA\nAC\nAG\nAT\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn mismatching_runs_are_rejected() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let counts = || arr2(&[[1, 2], [3, 4]]);
+        let a = run_dir(&dir.path().join("a"), counts(), &[], "A\nC\n")?;
+        let out = dir.path().join("merged");
+
+        // Canonical and non-canonical motifs
+        let b = run_dir(&dir.path().join("b"), counts(), &[], "A\nC\n")?;
+        write_motif_lists(&a, "k1", &["A".to_string(), "C".to_string()], true)?;
+        write_motif_lists(&b, "k1", &["A".to_string(), "C".to_string()], false)?;
+        assert!(merge_runs(&[a.clone(), b], &out, CountDtype::U64).is_err());
+        std::fs::remove_file(a.join("k1_motifs.json"))?;
+
+        // Other windows
+        let c = run_dir(&dir.path().join("c"), counts(), &[], "A\nC\n")?;
+        std::fs::write(c.join("bins.bed"), "chr2\t0\t10\nchr2\t10\t20\n")?;
+        assert!(merge_runs(&[a.clone(), c], &out, CountDtype::U64).is_err());
+
+        // Other matrices
+        let d = run_dir(&dir.path().join("d"), counts(), &[], "A\nC\n")?;
+        std::fs::remove_file(d.join("k2_counts_sparse.npz"))?;
+        assert!(merge_runs(&[a.clone(), d], &out, CountDtype::U64).is_err());
+
+        // A single run, or writing into an input
+        assert!(merge_runs(std::slice::from_ref(&a), &out, CountDtype::U64).is_err());
+        let e = run_dir(&dir.path().join("e"), counts(), &[], "A\nC\n")?;
+        assert!(merge_runs(&[a.clone(), e], &a, CountDtype::U64).is_err());
+        assert!(!out.exists());
+        Ok(())
+    }
+}
@@ -0,0 +1,33 @@
+#[cfg(test)]
+mod tests {
+    use reference::reference::kmer_codec::*;
+    use reference::reference::minimizer::*;
+
+    #[test]
+    fn every_run_of_w_kmers_contains_a_minimizer() {
+        let specs = build_kmer_specs(&[3]).unwrap();
+        let seq = b"ACGTTGCAAGGCTNACGGATTACA";
+        let codes = build_codes_per_k(seq, &specs).remove(&3).unwrap();
+        let selected = minimizer_positions(&codes, &specs[&3], 4);
+        for run_start in 0..=seq.len() - 4 {
+            let run = run_start..run_start + 4;
+            let has_kmer = run
+                .clone()
+                .any(|p| p + 3 <= seq.len() && !seq[p..p + 3].contains(&b'N'));
+            assert_eq!(run.clone().any(|p| selected[p]), has_kmer);
+        }
+        // K-mers with N and the tail without full k-mers are never selected
+        assert!(!selected[11..14].iter().any(|&s| s));
+        assert!(!selected[seq.len() - 2..].iter().any(|&s| s));
+
+        // w = 1 keeps every k-mer
+        let all = minimizer_positions(&codes, &specs[&3], 1);
+        assert_eq!(all.iter().filter(|&&s| s).count(), seq.len() - 2 - 3);
+
+        let mut masked = codes;
+        keep_minimizers(&mut masked, &specs[&3], 4);
+        for (pos, &keep) in selected.iter().enumerate() {
+            assert_eq!(masked.get(pos) != specs[&3].sentinel_none(), keep);
+        }
+    }
+}
@@ -0,0 +1,124 @@
+#[cfg(test)]
+mod tests {
+    use reference::reference::kmer_codec::DecodedCounts;
+    use reference::reference::motif_groups::*;
+    use std::io::Write;
+    use tempfile::NamedTempFile;
+
+    fn write_tsv(contents: &str) -> NamedTempFile {
+        let mut file = NamedTempFile::new().expect("create temp file");
+        file.write_all(contents.as_bytes())
+            .expect("write temp file");
+        file
+    }
+
+    #[test]
+    fn groups_keep_order_of_first_appearance() -> anyhow::Result<()> {
+        let tmp = write_tsv("# motif\tgroup\nACGT\tCpG+\nAAAA\tCpG-\nccgg\tCpG+\nAC\tdi\n");
+        let groups = load_motif_groups(tmp.path(), false)?;
+        assert_eq!(groups.labels, vec!["CpG+", "CpG-", "di"]);
+        assert_eq!(groups.index["CCGG"], 0);
+        assert_eq!(groups.labels_for_k(4), vec!["CpG+", "CpG-"]);
+        assert_eq!(groups.labels_for_k(2), vec!["di"]);
+        Ok(())
+    }
+
+    #[test]
+    fn canonical_motifs_are_collapsed() -> anyhow::Result<()> {
+        // TTT is the reverse complement of AAA
+        let tmp = write_tsv("TTT\tpolyA\nAAA\tpolyA\n");
+        let groups = load_motif_groups(tmp.path(), true)?;
+        assert_eq!(groups.index.len(), 1);
+        assert!(groups.index.contains_key("AAA"));
+        Ok(())
+    }
+
+    #[test]
+    fn conflicting_groups_return_error() {
+        let tmp = write_tsv("TTT\tx\nAAA\ty\n");
+        let err = load_motif_groups(tmp.path(), true).unwrap_err();
+        assert!(err.to_string().contains("both"), "unexpected error: {err}");
+    }
+
+    #[test]
+    fn motif_lists_are_grouped_by_length() -> anyhow::Result<()> {
+        let tmp = write_tsv(
+            "# end motifs
+ccca
+TTT	polyA
+AAA
+CCCA
+
+AC
+",
+        );
+        let list = load_motif_list(tmp.path(), false)?;
+        assert_eq!(list.columns[&4], vec!["CCCA"]);
+        assert_eq!(list.columns[&3], vec!["AAA", "TTT"]);
+        assert_eq!(list.columns[&2], vec!["AC"]);
+        assert!(!list.is_degenerate());
+
+        let list = load_motif_list(tmp.path(), true)?;
+        assert_eq!(list.columns[&3], vec!["AAA"]);
+        assert_eq!(list.columns[&4], vec!["CCCA"]); // TGGG collapses to CCCA
+
+        let tmp = write_tsv("ACXT\n");
+        assert!(load_motif_list(tmp.path(), false).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn degenerate_motifs_are_expanded_and_summed() -> anyhow::Result<()> {
+        assert_eq!(expand_iupac("AWG")?, vec!["AAG", "ATG"]);
+        assert_eq!(expand_iupac("NA")?.len(), 4);
+        assert!(expand_iupac("AZ").is_err());
+
+        let tmp = write_tsv("CCWGG\nWGCW\n");
+        let list = load_motif_list(tmp.path(), false)?;
+        assert_eq!(list.columns[&4], vec!["WGCW"]);
+        assert_eq!(list.concrete["CCWGG"], vec!["CCAGG", "CCTGG"]);
+        assert_eq!(list.concrete_by_k()[&4].len(), 4);
+
+        let window = DecodedCounts {
+            counts: [
+                (
+                    5,
+                    [("CCAGG".to_string(), 2), ("CCTGG".to_string(), 3)]
+                        .into_iter()
+                        .collect(),
+                ),
+                (4, [("AGCT".to_string(), 1)].into_iter().collect()),
+            ]
+            .into_iter()
+            .collect(),
+        };
+        let summed = list.sum_columns(vec![window]);
+        assert_eq!(summed[0].counts[&5]["CCWGG"], 5);
+        assert_eq!(summed[0].counts[&4]["WGCW"], 1);
+        assert_eq!(summed[0].counts[&5].len(), 1);
+
+        // WGCW is its own reverse complement; CCWGG too
+        let list = load_motif_list(tmp.path(), true)?;
+        assert_eq!(list.columns[&5], vec!["CCWGG"]);
+        assert_eq!(list.concrete["CCWGG"], vec!["CCAGG"]);
+        Ok(())
+    }
+
+    #[test]
+    fn sbs96_contexts_are_in_cosmic_order() {
+        let list = sbs96_motifs(false);
+        let columns = &list.columns[&3];
+        assert_eq!(columns.len(), 96);
+        assert_eq!(columns[0], "A[C>A]A");
+        assert_eq!(columns[1], "A[C>A]C");
+        assert_eq!(columns[4], "C[C>A]A");
+        assert_eq!(columns[16], "A[C>G]A");
+        assert_eq!(columns[95], "T[T>G]T");
+        assert_eq!(list.concrete["A[C>T]G"], vec!["ACG", "CGT"]);
+        assert_eq!(list.concrete_by_k()[&3].len(), 64);
+
+        let list = sbs96_motifs(true);
+        assert_eq!(list.concrete["T[T>C]A"], vec!["TAA"]);
+        assert_eq!(list.concrete_by_k()[&3].len(), 32);
+    }
+}
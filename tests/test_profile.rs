@@ -0,0 +1,48 @@
+#[cfg(test)]
+mod tests {
+    use reference::reference::profile::*;
+    use std::time::Duration;
+    use tempfile::tempdir;
+
+    #[test]
+    fn stage_times_are_summed_per_chromosome() -> anyhow::Result<()> {
+        let profiler = Profiler::new(true);
+        profiler.add(Some("chr2"), Stage::Count, Duration::from_millis(500));
+        profiler.add(Some("chr1"), Stage::Load, Duration::from_millis(250));
+        profiler.add(Some("chr1"), Stage::Count, Duration::from_millis(1000));
+        profiler.add(Some("chr1"), Stage::Count, Duration::from_millis(1000));
+        assert_eq!(profiler.time(None, Stage::Write, || 3), 3);
+
+        let totals: Vec<(Stage, f64)> = profiler
+            .totals()
+            .into_iter()
+            .map(|(stage, time)| (stage, time.secs))
+            .collect();
+        assert_eq!(totals[0], (Stage::Load, 0.25));
+        assert_eq!(totals[1], (Stage::Count, 2.5));
+        assert_eq!(totals[2].0, Stage::Write);
+
+        let dir = tempdir()?;
+        let path = dir.path().join("profile.tsv");
+        profiler.write_tsv(&path, &["chr1".to_string(), "chr2".to_string()])?;
+        let rows: Vec<Vec<String>> = std::fs::read_to_string(&path)?
+            .lines()
+            .map(|line| line.split('\t').take(3).map(String::from).collect())
+            .collect();
+        assert_eq!(rows[0], vec!["chrom", "stage", "wall_secs"]);
+        assert_eq!(rows[1], vec!["chr1", "load", "0.250"]);
+        assert_eq!(rows[2], vec!["chr1", "count", "2.000"]);
+        assert_eq!(rows[3], vec!["chr2", "count", "0.500"]);
+        assert_eq!(rows[4][..2], [".", "write"]);
+        assert_eq!(rows[6], vec!["total", "count", "2.500"]);
+        Ok(())
+    }
+
+    #[test]
+    fn disabled_profiler_records_nothing() {
+        let profiler = Profiler::new(false);
+        profiler.add(Some("chr1"), Stage::Load, Duration::from_secs(1));
+        assert_eq!(profiler.time(Some("chr1"), Stage::Count, || 1), 1);
+        assert!(profiler.totals().is_empty());
+    }
+}
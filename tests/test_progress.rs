@@ -0,0 +1,53 @@
+#[cfg(test)]
+mod tests {
+    use reference::reference::progress::ProgressLog;
+    use serde_json::Value;
+    use tempfile::tempdir;
+
+    #[test]
+    fn progress_events_are_json_lines() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("progress.jsonl");
+        let progress = ProgressLog::open(Some(&path))?;
+        progress.stage("load");
+        progress.start_counting(2, 400);
+        progress.chromosome_done("chr2", 100);
+        progress.chromosome_done("chr1", 300);
+        progress.stage("write");
+        progress.finish();
+        drop(progress);
+
+        let events: Vec<Value> = std::fs::read_to_string(&path)?
+            .lines()
+            .map(serde_json::from_str)
+            .collect::<Result<_, _>>()?;
+        let field = |i: usize, key: &str| events[i][key].clone();
+        assert_eq!(events.len(), 6);
+        assert_eq!(field(0, "event"), "stage");
+        assert_eq!(field(0, "stage"), "load");
+        assert!(field(1, "eta_secs").is_null());
+
+        assert_eq!(field(2, "event"), "chromosome");
+        assert_eq!(field(2, "stage"), "count");
+        assert_eq!(field(2, "chromosome"), "chr2");
+        assert_eq!(field(2, "chromosomes_done"), 1);
+        assert_eq!(field(2, "chromosomes_total"), 2);
+        assert_eq!(field(2, "fraction"), 0.25);
+        assert!(field(2, "eta_secs").as_f64().unwrap() >= 0.0);
+        assert_eq!(field(3, "fraction"), 1.0);
+
+        assert_eq!(field(4, "stage"), "write");
+        assert_eq!(field(5, "event"), "done");
+        Ok(())
+    }
+
+    #[test]
+    fn disabled_progress_writes_nothing() -> anyhow::Result<()> {
+        let progress = ProgressLog::open(None)?;
+        assert!(!progress.is_enabled());
+        progress.start_counting(1, 10);
+        progress.chromosome_done("chr1", 10);
+        progress.finish();
+        Ok(())
+    }
+}
@@ -0,0 +1,69 @@
+#[cfg(test)]
+mod tests {
+    use reference::reference::pwm::*;
+
+    const JASPAR: &str = "\
+>MA0001.1 ACG
+A [ 10  0  0 ]
+C [  0 10  0 ]
+G [  0  0 10 ]
+T [  0  0  0 ]
+";
+
+    const MEME: &str = "\
+MEME version 4
+
+ALPHABET= ACGT
+
+MOTIF MA0001.1 ACG
+letter-probability matrix: alength= 4 w= 3 nsites= 10 E= 0
+  1.0 0.0 0.0 0.0
+  0.0 1.0 0.0 0.0
+  0.0 0.0 1.0 0.0
+
+MOTIF second
+letter-probability matrix: alength= 4 w= 1
+  0.25 0.25 0.25 0.25
+";
+
+    #[test]
+    fn jaspar_and_meme_motifs_are_parsed() {
+        let jaspar = parse_pwms(JASPAR).unwrap();
+        let meme = parse_pwms(MEME).unwrap();
+        assert_eq!(jaspar.len(), 1);
+        assert_eq!(jaspar[0].name, "MA0001.1_ACG");
+        assert_eq!(jaspar[0].len(), 3);
+        // Counts and probabilities give the same scores
+        assert_eq!(meme[0], jaspar[0]);
+        assert_eq!(meme[1].name, "second");
+        assert!(meme[1].log_odds[0].iter().all(|&s| s.abs() < 1e-12));
+
+        assert!(parse_pwms(">bad\nA [ 1 2 ]\nC [ 1 ]\nG [ 1 ]\nT [ 1 ]\n").is_err());
+    }
+
+    #[test]
+    fn sites_are_scanned_on_both_strands() {
+        let pwm = &parse_pwms(JASPAR).unwrap()[0];
+        let max = pwm.score_threshold(1.0);
+        assert_eq!(pwm.site_score(b"ACG"), Some(max));
+        // Reverse complement of ACG
+        assert_eq!(pwm.site_score(b"cgt"), Some(max));
+        assert_eq!(pwm.site_score(b"ANG"), None);
+
+        // ACG at 2 and CGT at 3 and 7; sites with a mismatch score 2/3
+        let seq = b"TTACGTTCGTTT";
+        let (n, sum) = scan_pwm(pwm, seq, pwm.score_threshold(0.8));
+        assert_eq!(n, 3);
+        assert!((sum - 3.0 * max).abs() < 1e-9);
+        let (n, _) = scan_pwm(pwm, seq, pwm.score_threshold(0.6));
+        assert!(n > 3);
+
+        // Sites must lie within a range
+        let hits = PwmHits::of(
+            std::slice::from_ref(pwm),
+            &[pwm.score_threshold(0.8)],
+            &[&seq[..4], &seq[4..]],
+        );
+        assert_eq!(hits.hits, vec![1]);
+    }
+}
@@ -0,0 +1,236 @@
+#[cfg(test)]
+mod tests {
+    use flate2::{write::GzEncoder, Compression};
+    use fxhash::FxHashMap;
+    use reference::cli::bam::{BamReader, BamRecord};
+    use reference::cli::fastq::FastqReader;
+    use reference::cli::BigCount;
+    use reference::reference::kmer_codec::{build_kmer_specs, Kmer};
+    use reference::reference::process_counts::prepare_code_counts;
+    use reference::reference::reads::*;
+    use std::collections::BTreeMap;
+    use std::io::{Cursor, Write};
+    use tempfile::tempdir;
+
+    fn record(ref_id: i32, pos: i64, flag: u16, tlen: i64, seq: &[u8]) -> BamRecord {
+        BamRecord {
+            ref_id,
+            pos,
+            mapq: 30,
+            flag,
+            tlen,
+            seq: seq.to_vec(),
+        }
+    }
+
+    /// Encode `records` as an uncompressed BAM stream with one reference
+    fn bam_bytes(records: &[BamRecord]) -> Vec<u8> {
+        let mut bam = b"BAM\x01".to_vec();
+        let text = b"@HD\tVN:1.6\n";
+        bam.extend((text.len() as i32).to_le_bytes());
+        bam.extend(text);
+        bam.extend(1i32.to_le_bytes());
+        bam.extend(5i32.to_le_bytes());
+        bam.extend(b"chr1\0");
+        bam.extend(1000i32.to_le_bytes());
+        for r in records {
+            let name = b"read\0";
+            let mut block = Vec::new();
+            block.extend(r.ref_id.to_le_bytes());
+            block.extend((r.pos as i32).to_le_bytes());
+            block.push(name.len() as u8);
+            block.push(r.mapq);
+            block.extend(0u16.to_le_bytes()); // bin
+            block.extend(1u16.to_le_bytes()); // n_cigar_op
+            block.extend(r.flag.to_le_bytes());
+            block.extend((r.seq.len() as i32).to_le_bytes());
+            block.extend((-1i32).to_le_bytes()); // next_refID
+            block.extend((-1i32).to_le_bytes()); // next_pos
+            block.extend((r.tlen as i32).to_le_bytes());
+            block.extend(name);
+            block.extend(((r.seq.len() as u32) << 4).to_le_bytes()); // <len>M
+            let code = |b: u8| b"=ACMGRSVTWYHKDBN".iter().position(|&c| c == b).unwrap() as u8;
+            for pair in r.seq.chunks(2) {
+                let low = pair.get(1).map_or(0, |&b| code(b));
+                block.push(code(pair[0]) << 4 | low);
+            }
+            block.extend(vec![30u8; r.seq.len()]);
+            bam.extend((block.len() as i32).to_le_bytes());
+            bam.extend(block);
+        }
+        bam
+    }
+
+    /// The non-zero counts of `counts` by motif
+    fn motif_counts(
+        counts: &[FxHashMap<Kmer, BigCount>],
+        ks: &[u8],
+    ) -> Vec<BTreeMap<String, BigCount>> {
+        let specs = build_kmer_specs(ks).unwrap();
+        let (prepared, _) = prepare_code_counts(counts, false, &specs);
+        prepared
+            .into_iter()
+            .map(|window| {
+                window
+                    .counts
+                    .into_values()
+                    .flatten()
+                    .filter(|(_, count)| *count > 0)
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn bam_records_roundtrip_through_bgzf_members() -> anyhow::Result<()> {
+        let records = vec![
+            record(0, 10, 0x63, 150, b"ACGTN"),
+            record(0, 20, 0x93, -150, b"GGCA"),
+            record(-1, -1, 0x4, 0, b"T"),
+        ];
+        let bytes = bam_bytes(&records);
+        // Two gzip members, like BGZF blocks, split inside a record
+        let dir = tempdir()?;
+        let path = dir.path().join("reads.bam");
+        let mut file = std::fs::File::create(&path)?;
+        for part in [&bytes[..60], &bytes[60..]] {
+            let mut gz = GzEncoder::new(Vec::new(), Compression::default());
+            gz.write_all(part)?;
+            file.write_all(&gz.finish()?)?;
+        }
+        drop(file);
+
+        let reader = BamReader::open(&path)?;
+        assert_eq!(reader.references(), &[("chr1".to_string(), 1000)]);
+        let read: Vec<BamRecord> = reader.collect::<anyhow::Result<_>>()?;
+        assert_eq!(read, records);
+        Ok(())
+    }
+
+    #[test]
+    fn truncated_bam_record_is_an_error() {
+        let bytes = bam_bytes(&[record(0, 10, 0, 0, b"ACGT")]);
+        let mut reader = BamReader::new(Cursor::new(&bytes[..bytes.len() - 2])).unwrap();
+        assert!(reader.read_record().is_err());
+        assert!(BamReader::new(Cursor::new(b"BAI\x01".to_vec())).is_err());
+    }
+
+    #[test]
+    fn read_filter_checks_mapq_flags_and_length() {
+        let filter = ReadFilter {
+            min_mapq: 20,
+            require_flags: 0x1,
+            exclude_flags: 0x400,
+            min_length: 3,
+        };
+        assert!(filter.keeps(&record(0, 0, 0x1, 0, b"ACG")));
+        assert!(!filter.keeps(&record(0, 0, 0x0, 0, b"ACG")));
+        assert!(!filter.keeps(&record(0, 0, 0x401, 0, b"ACG")));
+        assert!(!filter.keeps(&record(0, 0, 0x1, 0, b"AC")));
+        let mut low_mapq = record(0, 0, 0x1, 0, b"ACG");
+        low_mapq.mapq = 10;
+        assert!(!filter.keeps(&low_mapq));
+    }
+
+    #[test]
+    fn fragment_ends_follow_the_read_strand() {
+        assert_eq!(fragment_end(&record(0, 0, 0, 0, b"ACGTT"), 3), b"ACG");
+        assert_eq!(fragment_end(&record(0, 0, 0x10, 0, b"ACGTT"), 3), b"AAC");
+        assert_eq!(fragment_end(&record(0, 0, 0x10, 0, b"AG"), 3), b"CT");
+    }
+
+    #[test]
+    fn fragment_lengths_count_each_pair_once() -> anyhow::Result<()> {
+        let records = vec![
+            record(0, 10, 0x43, 150, b"A"),
+            record(0, 100, 0x83, -150, b"A"), // second read of the pair
+            record(0, 200, 0x63, -80, b"A"),
+            record(0, 300, 0x43, 5000, b"A"), // longer than the maximum
+            record(0, 400, 0x43, 0, b"A"),    // mate on another chromosome
+            record(0, 500, 0x0, 120, b"A"),   // single-end
+        ];
+        let (lengths, n_longer) =
+            fragment_lengths(records.into_iter().map(Ok), &ReadFilter::default(), 1000)?;
+        assert_eq!(lengths, BTreeMap::from([(80, 1), (150, 1)]));
+        assert_eq!(n_longer, 1);
+        Ok(())
+    }
+
+    #[test]
+    fn family_sizes_group_by_start_strand_and_length() -> anyhow::Result<()> {
+        let records = [
+            record(0, 10, 0x0, 0, b"A"),
+            record(0, 10, 0x400, 0, b"A"),
+            record(0, 10, 0x10, 0, b"A"),
+            record(0, 20, 0x43, 100, b"A"),
+            record(0, 20, 0x443, 100, b"A"),
+            record(0, 20, 0x443, 100, b"A"),
+            record(0, 20, 0x83, 100, b"A"), // second read
+            record(0, 20, 0x43, 90, b"A"),
+        ];
+        let sizes = family_sizes(records.iter().cloned().map(Ok), &ReadFilter::default())?;
+        assert_eq!(sizes, BTreeMap::from([(1, 2), (2, 1), (3, 1)]));
+
+        // Excluding the duplicates leaves families of one
+        let no_duplicates = ReadFilter {
+            exclude_flags: 0x400,
+            ..ReadFilter::default()
+        };
+        let sizes = family_sizes(records.iter().cloned().map(Ok), &no_duplicates)?;
+        assert_eq!(sizes, BTreeMap::from([(1, 4)]));
+
+        let unsorted = vec![record(0, 20, 0, 0, b"A"), record(0, 10, 0, 0, b"A")];
+        assert!(family_sizes(unsorted.into_iter().map(Ok), &ReadFilter::default()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn fastq_reader_reads_sequences_and_rejects_broken_records() -> anyhow::Result<()> {
+        let fastq = "@r1\nACGT\n+\nIIII\n\n@r2\nGG\n+r2\nII\n";
+        let mut reader = FastqReader::new(Box::new(Cursor::new(fastq)));
+        let mut seq = Vec::new();
+        assert!(reader.read_seq(&mut seq)?);
+        assert_eq!(seq, b"ACGT");
+        assert!(reader.read_seq(&mut seq)?);
+        assert_eq!(seq, b"GG");
+        assert!(!reader.read_seq(&mut seq)?);
+
+        let mut no_header = FastqReader::new(Box::new(Cursor::new(">r1\nACGT\n")));
+        assert!(no_header.read_seq(&mut seq).is_err());
+        let mut truncated = FastqReader::new(Box::new(Cursor::new("@r1\nACGT\n")));
+        assert!(truncated.read_seq(&mut seq).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn read_kmers_never_span_two_reads() {
+        let specs = build_kmer_specs(&[2]).unwrap();
+        let mut counter = ReadKmerCounter::all_kmers(&specs);
+        counter.add_read(b"AC");
+        counter.add_read(b"GT");
+        counter.add_read(b"A");
+        let counts = motif_counts(&[counter.finish()], &[2]);
+        assert_eq!(
+            counts[0],
+            BTreeMap::from([("AC".to_string(), 1), ("GT".to_string(), 1)])
+        );
+    }
+
+    #[test]
+    fn read_start_counter_counts_the_first_kmer_per_k() {
+        let specs = build_kmer_specs(&[1, 3]).unwrap();
+        let mut counter = ReadKmerCounter::read_starts(&specs);
+        counter.add_read_start(b"CCA");
+        counter.add_read_start(b"CCA");
+        counter.add_read_start(b"GT"); // too short for k=3
+        let counts = motif_counts(&[counter.finish()], &[1, 3]);
+        assert_eq!(
+            counts[0],
+            BTreeMap::from([
+                ("C".to_string(), 2),
+                ("CCA".to_string(), 2),
+                ("G".to_string(), 1),
+            ])
+        );
+    }
+}
@@ -0,0 +1,38 @@
+#[cfg(test)]
+mod tests {
+    use reference::reference::repeats::*;
+
+    #[test]
+    fn homopolymer_runs_are_binned_by_length() {
+        let content = RepeatContent::of(b"AACCCNGgT");
+        assert_eq!(content.homopolymer_runs[0], 1); // T
+        assert_eq!(content.homopolymer_runs[1], 2); // AA and Gg
+        assert_eq!(content.homopolymer_runs[2], 1); // CCC
+        assert_eq!(content.homopolymer_runs[3..].iter().sum::<u64>(), 0);
+
+        let long = RepeatContent::of(&[b'A'; 30]);
+        assert_eq!(long.homopolymer_runs[MAX_HOMOPOLYMER_BIN - 1], 1);
+        assert_eq!(RepeatContent::homopolymer_columns()[19], "20+");
+    }
+
+    #[test]
+    fn tandem_repeats_are_counted_by_primitive_unit() {
+        // 14 bases of (CA)n, flanked by G and T
+        let content = RepeatContent::of(b"GCACACACACACACAT");
+        assert_eq!(content.str_bases, [0, 14, 0, 0, 0, 0]);
+
+        // A 12-base homopolymer counts for unit 1 only
+        let content = RepeatContent::of(b"CAAAAAAAAAAAAG");
+        assert_eq!(content.str_bases, [12, 0, 0, 0, 0, 0]);
+
+        // Too short: 11 bases of (CAG)n
+        let content = RepeatContent::of(b"CAGCAGCAGCA");
+        assert_eq!(content.str_bases[2], 0);
+
+        let mut total = RepeatContent::of(b"AACAGCAGCAGCAGTT");
+        assert_eq!(total.str_bases[2], 12);
+        total += total;
+        assert_eq!(total.str_bases[2], 24);
+        assert_eq!(total.homopolymer_runs[1], 4);
+    }
+}
@@ -0,0 +1,77 @@
+#[cfg(test)]
+mod tests {
+    use reference::reference::run_info::*;
+    use std::collections::BTreeMap;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_sha256_file() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("abc.txt");
+        std::fs::write(&path, "abc").unwrap();
+        assert_eq!(
+            sha256_file(&path).unwrap(),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+
+    #[test]
+    fn test_run_info_json() {
+        let info = RunInfo {
+            options: vec![
+                (
+                    "kmer_sizes".to_string(),
+                    vec!["1".to_string(), "2".to_string()],
+                ),
+                ("canonical".to_string(), vec!["false".to_string()]),
+            ],
+            reference: "ref.2bit".to_string(),
+            chromosomes: vec!["chr1".to_string()],
+            skipped_chromosomes: vec!["chrX".to_string()],
+            kmer_sizes: vec![1, 2],
+            seeds: BTreeMap::from([("rarefy".to_string(), 7)]),
+            chrom_counters: BTreeMap::from([(
+                "chr1".to_string(),
+                ChromCounters {
+                    length: 100,
+                    windows: 2,
+                    effective_bases: 90,
+                    kmers_by_k: BTreeMap::from([(1, 90), (2, 88)]),
+                    ..Default::default()
+                },
+            )]),
+            ..Default::default()
+        };
+        let json = info.to_json();
+        assert_eq!(json["options"]["kmer_sizes"], serde_json::json!(["1", "2"]));
+        assert_eq!(json["options"]["canonical"], "false");
+        assert!(json["reference"]["sha256"].is_null());
+        assert_eq!(json["skipped_chromosomes"][0], "chrX");
+        assert_eq!(json["seeds"]["rarefy"], 7);
+        assert_eq!(json["per_chromosome"]["chr1"]["kmers"]["2"], 88);
+        assert_eq!(json["per_chromosome"]["chr1"]["effective_bases"], 90);
+    }
+
+    #[test]
+    fn test_checksum_manifest() {
+        let dir = tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("masked")).unwrap();
+        std::fs::write(dir.path().join("masked/b.txt"), "abc").unwrap();
+        std::fs::write(dir.path().join("a.txt"), "").unwrap();
+        write_checksum_manifest(dir.path()).unwrap();
+        // Rewriting does not list the manifests themselves
+        write_checksum_manifest(dir.path()).unwrap();
+
+        let manifest = std::fs::read_to_string(dir.path().join("manifest.sha256")).unwrap();
+        let lines: Vec<&str> = manifest.lines().collect();
+        assert_eq!(
+            lines,
+            vec![
+                "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855  a.txt",
+                "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad  masked/b.txt",
+            ]
+        );
+        let sizes = std::fs::read_to_string(dir.path().join("manifest.tsv")).unwrap();
+        assert_eq!(sizes, "file\tbytes\na.txt\t0\nmasked/b.txt\t3\n");
+    }
+}
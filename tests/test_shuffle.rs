@@ -0,0 +1,50 @@
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, SeedableRng};
+    use reference::reference::shuffle::*;
+    use std::collections::HashMap;
+
+    fn dinucs(seq: &[u8]) -> HashMap<(u8, u8), usize> {
+        let mut m = HashMap::new();
+        for w in seq.windows(2) {
+            *m.entry((w[0], w[1])).or_insert(0) += 1;
+        }
+        m
+    }
+
+    #[test]
+    fn dinucleotide_counts_and_ends_are_preserved() {
+        let original = b"ACGTTGCAAACGCGTATATGCCGATTACAGGATCCA".to_vec();
+        let mut seq = original.clone();
+        let mut rng = StdRng::seed_from_u64(7);
+        dinucleotide_shuffle(&mut seq, &mut rng);
+
+        assert_eq!(dinucs(&seq), dinucs(&original));
+        assert_eq!(seq[0], original[0]);
+        assert_eq!(seq.last(), original.last());
+        assert_ne!(seq, original);
+    }
+
+    #[test]
+    fn shuffle_is_deterministic_given_seed() {
+        let original = b"ACGTTGCAAACGCGTATATGCCGATTACAGGATCCA".to_vec();
+        let mut a = original.clone();
+        let mut b = original.clone();
+        dinucleotide_shuffle(&mut a, &mut StdRng::seed_from_u64(3));
+        dinucleotide_shuffle(&mut b, &mut StdRng::seed_from_u64(3));
+        assert_eq!(a, b);
+    }
+
+    #[test]
+    fn non_acgt_bases_stay_in_place() {
+        let original = b"ACGTACGGTNNNTTGACCAXXXGGCATTAC".to_vec();
+        let mut seq = original.clone();
+        shuffle_segments(&mut seq, &mut StdRng::seed_from_u64(1));
+        for (i, &b) in original.iter().enumerate() {
+            if b == b'N' || b == b'X' {
+                assert_eq!(seq[i], b);
+            }
+        }
+        assert_eq!(dinucs(&seq[..9]), dinucs(&original[..9]));
+    }
+}
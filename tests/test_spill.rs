@@ -0,0 +1,75 @@
+#[cfg(test)]
+mod tests {
+    use fxhash::FxHashMap;
+    use ndarray::Array1;
+    use ndarray_npy::ReadNpyExt;
+    use reference::reference::kmer_codec::Kmer;
+    use reference::reference::spill::CountSpill;
+    use reference::reference::write::*;
+    use tempfile::tempdir;
+
+    fn window(counts: &[(u64, u64)]) -> FxHashMap<Kmer, u64> {
+        counts
+            .iter()
+            .map(|&(code, count)| (Kmer { k: 1, code }, count))
+            .collect()
+    }
+
+    #[test]
+    fn spilled_chromosomes_merge_into_one_matrix() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let spill = CountSpill::new(dir.path())?;
+        // Chromosomes may finish in any order
+        spill.store(0, 1, &[window(&[(2, 5)])], &[1])?;
+        spill.store(0, 0, &[window(&[(3, 1), (0, 2)]), window(&[])], &[1])?;
+
+        let rows_by_chrom = [2, 1];
+        let mut codes: Vec<u64> = spill.distinct_codes(0, 2, 1)?.into_iter().collect();
+        codes.sort_unstable();
+        assert_eq!(codes, vec![0, 2, 3]);
+        let merged = spill
+            .merged(0, &rows_by_chrom, 1)
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        assert_eq!(merged, vec![[0, 0, 2], [0, 3, 1], [2, 2, 5]]);
+
+        // Same matrix as writing the in-memory counts
+        let triplets = spill
+            .merged(0, &rows_by_chrom, 1)
+            .map(|t| t.map(|[row, code, count]| (row, code, count)));
+        write_triplets_sparse(
+            triplets,
+            3,
+            4,
+            "k1",
+            dir.path(),
+            SparseFormat::Csr,
+            CountDtype::U64,
+        )?;
+        let file = std::fs::File::open(dir.path().join("k1_counts_sparse.npz"))?;
+        let mut npz = zip::ZipArchive::new(file)?;
+        let mut read = |name: &str| -> anyhow::Result<Array1<u64>> {
+            Ok(Array1::<u64>::read_npy(npz.by_name(name)?)?)
+        };
+        assert_eq!(read("indptr.npy")?.to_vec(), vec![0, 2, 2, 3]);
+        assert_eq!(read("indices.npy")?.to_vec(), vec![0, 3, 2]);
+        assert_eq!(read("data.npy")?.to_vec(), vec![2, 1, 5]);
+        Ok(())
+    }
+
+    #[test]
+    fn unordered_rows_are_rejected() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let triplets = vec![Ok((1, 0, 1)), Ok((0, 0, 1))];
+        assert!(write_triplets_sparse(
+            triplets,
+            2,
+            1,
+            "k1",
+            dir.path(),
+            SparseFormat::Coo,
+            CountDtype::U64,
+        )
+        .is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,116 @@
+#[cfg(test)]
+mod tests {
+    use flate2::read::MultiGzDecoder;
+    use reference::cli::bgzf::BgzfWriter;
+    use reference::cli::tabix::*;
+    use std::io::{BufRead, BufReader, Read, Seek, SeekFrom, Write};
+
+    #[test]
+    fn bgzf_is_gzip_and_virtual_offsets_point_at_lines() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("rows.tsv.gz");
+        let mut out = BgzfWriter::new(std::fs::File::create(&path)?);
+        let mut text = String::new();
+        let mut offsets = Vec::new();
+        for i in 0..20_000 {
+            let line = format!("chr1\t{}\t{}\tline{}\n", i * 10, i * 10 + 10, i);
+            offsets.push(out.virtual_offset());
+            out.write_all(line.as_bytes())?;
+            text.push_str(&line);
+        }
+        out.finish()?;
+
+        let mut decoded = String::new();
+        MultiGzDecoder::new(std::fs::File::open(&path)?).read_to_string(&mut decoded)?;
+        assert_eq!(decoded, text);
+
+        // Line 15000 lies beyond the first block
+        let voffset = offsets[15_000];
+        assert!(voffset >> 16 > 0);
+        let mut file = std::fs::File::open(&path)?;
+        file.seek(SeekFrom::Start(voffset >> 16))?;
+        let mut reader = BufReader::new(MultiGzDecoder::new(file));
+        std::io::copy(
+            &mut (&mut reader).take(voffset & 0xffff),
+            &mut std::io::sink(),
+        )?;
+        let mut line = String::new();
+        reader.read_line(&mut line)?;
+        assert_eq!(line, "chr1\t150000\t150010\tline15000\n");
+        Ok(())
+    }
+
+    #[test]
+    fn reg2bin_uses_smallest_enclosing_bin() {
+        assert_eq!(reg2bin(0, 1), 4681);
+        assert_eq!(reg2bin(16384, 16385), 4682);
+        // Crosses a 16 kb boundary but not a 128 kb one
+        assert_eq!(reg2bin(16000, 17000), 585);
+        assert_eq!(reg2bin(0, 1 << 29), 0);
+    }
+
+    #[test]
+    fn unsorted_records_are_rejected() {
+        let mut index = TabixIndexBuilder::default();
+        index.add("chr1", 100, 200, 0, 10).unwrap();
+        assert!(index.add("chr1", 50, 60, 10, 20).is_err());
+
+        let mut index = TabixIndexBuilder::default();
+        index.add("chr1", 100, 200, 0, 10).unwrap();
+        index.add("chr2", 0, 10, 10, 20).unwrap();
+        assert!(index.add("chr1", 300, 400, 20, 30).is_err());
+    }
+
+    #[test]
+    fn regions_are_parsed_to_half_open_intervals() -> anyhow::Result<()> {
+        assert_eq!(
+            parse_region("chr1:1,000,000-2,000,000")?,
+            ("chr1".to_string(), 999_999, 2_000_000)
+        );
+        assert_eq!(parse_region("chrM")?, ("chrM".to_string(), 0, u64::MAX));
+        assert_eq!(parse_region("chr2:11")?, ("chr2".to_string(), 10, u64::MAX));
+        assert!(parse_region("chr1:0-10").is_err());
+        assert!(parse_region("chr1:20-10").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn indexed_lines_are_queried_by_region() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("rows.tsv.gz");
+        let mut out = BgzfWriter::new(std::fs::File::create(&path)?);
+        let mut index = TabixIndexBuilder::default();
+        out.write_all(b"#chrom\tstart\tend\tvalue\n")?;
+        for chrom in ["chr1", "chr2"] {
+            for i in 0..50_000u64 {
+                let vstart = out.virtual_offset();
+                writeln!(out, "{chrom}\t{}\t{}\t{i}", i * 100, i * 100 + 100)?;
+                index.add(chrom, i * 100, i * 100 + 100, vstart, out.virtual_offset())?;
+            }
+        }
+        out.finish()?;
+        index.write(&dir.path().join("rows.tsv.gz.tbi"))?;
+
+        let mut lines = Vec::new();
+        query_lines(&path, "chr2", 4_000_050, 4_000_250, |line| {
+            lines.push(line.to_string());
+            Ok(())
+        })?;
+        assert_eq!(
+            lines,
+            vec![
+                "chr2\t4000000\t4000100\t40000",
+                "chr2\t4000100\t4000200\t40001",
+                "chr2\t4000200\t4000300\t40002",
+            ]
+        );
+
+        let mut n = 0;
+        query_lines(&path, "chr3", 0, 100, |_| {
+            n += 1;
+            Ok(())
+        })?;
+        assert_eq!(n, 0);
+        Ok(())
+    }
+}
@@ -0,0 +1,38 @@
+#[cfg(test)]
+mod tests {
+    use reference::reference::kmer_codec::build_kmer_specs;
+    use reference::reference::uniqueness::*;
+
+    #[test]
+    fn scores_are_inverse_genome_wide_frequencies() {
+        let specs = build_kmer_specs(&[3]).unwrap();
+        let spec = &specs[&3];
+        let mut frequencies = KmerFrequencies::default();
+        add_kmer_frequencies(&mut frequencies, b"AAAGTTTNACG", spec);
+        add_kmer_frequencies(&mut frequencies, b"CGA", spec);
+
+        // AAA and TTT share a count
+        let scores = uniqueness_scores(b"AAAGTTTNACG", spec, &frequencies);
+        assert_eq!(scores.len(), 11);
+        assert_eq!(scores[0], Some(0.5)); // AAA
+        assert_eq!(scores[1], Some(1.0)); // AAG
+        assert_eq!(scores[4], Some(0.5)); // TTT
+        assert_eq!(scores[5], None); // TTN
+        assert_eq!(scores[8], Some(1.0)); // ACG
+        assert_eq!(scores[9], None);
+    }
+
+    #[test]
+    fn scores_are_merged_into_runs_or_window_means() {
+        let scores = vec![Some(1.0), Some(1.0), None, Some(0.5), Some(1.0), None];
+        assert_eq!(
+            score_runs(&scores),
+            vec![(0, 2, 1.0), (3, 4, 0.5), (4, 5, 1.0)]
+        );
+        assert_eq!(
+            window_means(&scores, 4),
+            vec![(0, 4, 2.5 / 3.0), (4, 6, 1.0)]
+        );
+        assert!(window_means(&[None, None], 2).is_empty());
+    }
+}
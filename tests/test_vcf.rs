@@ -0,0 +1,97 @@
+#[cfg(test)]
+mod tests {
+    use reference::reference::vcf::*;
+    use std::io::Write;
+    use tempfile::NamedTempFile;
+
+    fn write_vcf(contents: &str) -> NamedTempFile {
+        let mut file = NamedTempFile::new().expect("create temp file");
+        file.write_all(contents.as_bytes())
+            .expect("write temp file");
+        file
+    }
+
+    const VCF: &str = "\
+##fileformat=VCFv4.2
+#CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\tFORMAT\tS1\tS2
+chr1\t5\t.\tA\tG,T\t.\tPASS\t.\tGT\t0/2\t0/0
+chr1\t2\t.\tC\tT\t.\tLowQual\t.\tGT\t1/1\t1/1
+chr1\t8\t.\tGT\tG\t.\t.\t.\tGT\t1|1\t0|1
+chr2\t3\t.\tA\t<DEL>\t.\tPASS\t.\tGT\t1/1\t1/1
+chr3\t3\t.\tA\tC\t.\tPASS\t.\tGT\t1/1\t1/1
+";
+
+    #[test]
+    fn vcf_records_are_filtered_by_sample_and_type() -> anyhow::Result<()> {
+        let tmp = write_vcf(VCF);
+        let chromosomes = vec!["chr1".to_string(), "chr2".to_string()];
+
+        // First ALT, SNVs only
+        let all = load_vcf(tmp.path(), &chromosomes, None, false, None)?;
+        assert_eq!(all.len(), 1);
+        assert_eq!(all["chr1"].len(), 1);
+        assert_eq!(all["chr1"][0].pos, 4);
+        assert_eq!(all["chr1"][0].alt_allele, b"G".to_vec());
+
+        // Sample genotypes pick the allele; indels included
+        let s1 = load_vcf(tmp.path(), &chromosomes, Some("S1"), true, None)?;
+        let alts: Vec<&[u8]> = s1["chr1"].iter().map(|v| v.alt_allele.as_slice()).collect();
+        assert_eq!(alts, vec![&b"T"[..], &b"G"[..]]);
+        let s2 = load_vcf(tmp.path(), &chromosomes, Some("S2"), true, None)?;
+        assert_eq!(s2["chr1"].len(), 1);
+        assert_eq!(s2["chr1"][0].pos, 7);
+
+        assert!(load_vcf(tmp.path(), &chromosomes, Some("S3"), false, None).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn variants_are_applied_with_coordinate_map() {
+        let seq = b"ACGTacgtAC".to_vec();
+        let variants = vec![
+            // SNV
+            Variant {
+                pos: 1,
+                ref_allele: b"C".to_vec(),
+                alt_allele: b"T".to_vec(),
+            },
+            // Deletion of "TA" (case-insensitive REF match)
+            Variant {
+                pos: 2,
+                ref_allele: b"GTA".to_vec(),
+                alt_allele: b"G".to_vec(),
+            },
+            // Overlaps the deletion
+            Variant {
+                pos: 3,
+                ref_allele: b"T".to_vec(),
+                alt_allele: b"C".to_vec(),
+            },
+            // Insertion after position 6
+            Variant {
+                pos: 6,
+                ref_allele: b"G".to_vec(),
+                alt_allele: b"GAA".to_vec(),
+            },
+            // REF mismatch
+            Variant {
+                pos: 9,
+                ref_allele: b"G".to_vec(),
+                alt_allele: b"A".to_vec(),
+            },
+        ];
+        let (personal, map, summary) = apply_variants(&seq, &variants);
+        assert_eq!(personal, b"ATGcGAAtAC".to_vec());
+        assert_eq!(summary.applied, 3);
+        assert_eq!(summary.overlapping, 1);
+        assert_eq!(summary.ref_mismatch, 1);
+
+        assert_eq!(map.to_personal(2), 2);
+        // Inside the deletion
+        assert_eq!(map.to_personal(4), 3);
+        assert_eq!(map.to_personal(5), 3);
+        // After the insertion
+        assert_eq!(map.to_personal(7), 7);
+        assert_eq!(map.to_personal(10), 10);
+    }
+}
@@ -0,0 +1,160 @@
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, SeedableRng};
+    use reference::reference::bed::{clamp_windows, Anchor};
+    use reference::reference::windows::*;
+    use std::collections::HashMap;
+
+    fn coords(windows: Vec<(u64, u64, u64)>) -> Vec<(u64, u64)> {
+        windows.into_iter().map(|(s, e, _)| (s, e)).collect()
+    }
+
+    #[test]
+    fn last_window_policies() {
+        let windows = |last| coords(size_windows(2500, 1000, 1000, 0, last));
+        assert_eq!(
+            windows(LastWindow::Keep),
+            vec![(0, 1000), (1000, 2000), (2000, 2500)]
+        );
+        assert_eq!(windows(LastWindow::Drop), vec![(0, 1000), (1000, 2000)]);
+        assert_eq!(
+            windows(LastWindow::Extend),
+            vec![(0, 1000), (1000, 2000), (1500, 2500)]
+        );
+        assert_eq!(windows(LastWindow::Merge), vec![(0, 1000), (1000, 2500)]);
+
+        // Full-size last windows are never changed
+        for last in [LastWindow::Drop, LastWindow::Extend, LastWindow::Merge] {
+            assert_eq!(coords(size_windows(2000, 1000, 1000, 0, last)).len(), 2);
+        }
+    }
+
+    #[test]
+    fn short_chromosomes_and_sliding_windows() {
+        assert_eq!(
+            coords(size_windows(500, 1000, 1000, 0, LastWindow::Keep)),
+            vec![(0, 500)]
+        );
+        assert!(size_windows(500, 1000, 1000, 0, LastWindow::Drop).is_empty());
+        assert_eq!(
+            coords(size_windows(500, 1000, 1000, 0, LastWindow::Extend)),
+            vec![(0, 500)]
+        );
+        assert_eq!(
+            coords(size_windows(500, 1000, 1000, 0, LastWindow::Merge)),
+            vec![(0, 500)]
+        );
+
+        let sliding = size_windows(1300, 1000, 200, 0, LastWindow::Extend);
+        assert_eq!(sliding, vec![(0, 1000, 0), (200, 1200, 1), (300, 1300, 2)]);
+    }
+
+    #[test]
+    fn offset_windows_start_with_a_short_window() {
+        let windows = |last| coords(size_windows(2200, 1000, 1000, 500, last));
+        assert_eq!(
+            windows(LastWindow::Keep),
+            vec![(0, 500), (500, 1500), (1500, 2200)]
+        );
+        assert_eq!(windows(LastWindow::Drop), vec![(500, 1500)]);
+        assert_eq!(
+            windows(LastWindow::Extend),
+            vec![(0, 1000), (500, 1500), (1200, 2200)]
+        );
+        assert_eq!(windows(LastWindow::Merge), vec![(0, 2200)]);
+        assert_eq!(
+            coords(size_windows(300, 1000, 1000, 500, LastWindow::Keep)),
+            vec![(0, 300)]
+        );
+    }
+
+    #[test]
+    fn equal_windows_cover_the_chromosome() {
+        assert_eq!(coords(equal_windows(10, 3)), vec![(0, 3), (3, 6), (6, 10)]);
+        assert_eq!(equal_windows(1000, 4)[3], (750, 1000, 3));
+        // Fewer windows than requested for very short chromosomes
+        assert_eq!(coords(equal_windows(2, 4)), vec![(0, 1), (1, 2)]);
+        assert!(equal_windows(0, 4).is_empty());
+    }
+
+    #[test]
+    fn anchor_windows_flank_each_anchor() {
+        let anchor = |chrom: &str, pos, name: &str| Anchor {
+            chrom: chrom.to_string(),
+            pos,
+            name: name.to_string(),
+        };
+        let anchors = vec![
+            anchor("chr1", 500, "TSS1"),
+            anchor("chr1", 50, "TSS2"),
+            anchor("chr2", 1000, "."),
+        ];
+        let chromosomes: Vec<String> = vec!["chr1".into(), "chr2".into(), "chr3".into()];
+        let (windows, columns) = anchor_windows(&anchors, &chromosomes, 100);
+        // Sorted by start, shortened at the chromosome start
+        assert_eq!(windows["chr1"], vec![(0, 151, 1), (400, 601, 0)]);
+        assert_eq!(windows["chr2"], vec![(900, 1101, 2)]);
+        assert!(windows["chr3"].is_empty());
+        assert_eq!(columns.names, vec!["name"]);
+        assert_eq!(columns.values[1], vec!["TSS2"]);
+    }
+
+    #[test]
+    fn anchors_near_chromosome_ends_keep_their_positions() {
+        let anchor = |chrom: &str, pos| Anchor {
+            chrom: chrom.to_string(),
+            pos,
+            name: ".".to_string(),
+        };
+        // Within `flank` of the start and end of a 100 bp chr1
+        let anchors = vec![anchor("chr1", 97), anchor("chr1", 3), anchor("chr2", 0)];
+        let chromosomes: Vec<String> = vec!["chr1".into(), "chr2".into()];
+        let (mut windows, _) = anchor_windows(&anchors, &chromosomes, 10);
+        let lengths = HashMap::from([("chr1".to_string(), 100), ("chr2".to_string(), 5)]);
+        clamp_windows(&mut windows, &lengths);
+        assert_eq!(windows["chr1"], vec![(0, 14, 1), (87, 100, 0)]);
+        assert_eq!(windows["chr2"], vec![(0, 5, 2)]);
+
+        let positions = anchors_by_chrom(&anchors);
+        assert_eq!(positions["chr1"], vec![97, 3]);
+        assert_eq!(positions["chr2"], vec![0]);
+    }
+
+    #[test]
+    fn windows_are_split_at_intervals() {
+        let windows = vec![(0, 100, 0), (100, 200, 1), (200, 300, 2), (50, 250, 3)];
+        let blacklist = vec![(20, 30), (90, 110), (150, 250)];
+        assert_eq!(
+            split_windows_at_intervals(&windows, &blacklist),
+            vec![
+                (0, 20, 0),
+                (30, 90, 0),
+                (110, 150, 1),
+                (250, 300, 2),
+                (50, 90, 3),
+                (110, 150, 3),
+            ]
+        );
+        // Fully covered windows are removed
+        assert!(split_windows_at_intervals(&[(150, 250, 0)], &blacklist).is_empty());
+    }
+
+    #[test]
+    fn random_windows_are_length_matched_and_avoid_intervals() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let windows = vec![(0, 100, 0), (100, 150, 1), (500, 530, 2)];
+        let blacklist = vec![(100, 400), (450, 900)];
+        for _ in 0..50 {
+            let random = random_windows(&windows, 1000, &blacklist, &mut rng);
+            assert_eq!(random.len(), windows.len());
+            for (&(s, e, idx), &(rs, re, ridx)) in windows.iter().zip(&random) {
+                assert_eq!((re - rs, ridx), (e - s, idx));
+                assert!(blacklist.iter().all(|&(bs, be)| re <= bs || rs >= be));
+            }
+        }
+        // Windows fitting in no gap are placed anywhere
+        let random = random_windows(&[(0, 200, 0)], 300, &[(100, 110)], &mut rng);
+        assert_eq!(random[0].1 - random[0].0, 200);
+        assert!(random[0].1 <= 300);
+    }
+}
@@ -0,0 +1,377 @@
+#[cfg(test)]
+mod tests {
+    use fxhash::FxHashMap;
+    use reference::reference::kmer_codec::DecodedCounts;
+    use reference::reference::write::*;
+    use std::collections::BTreeMap;
+    use tempfile::tempdir;
+
+    fn window(counts: &[(&str, u64)]) -> DecodedCounts {
+        let bin: FxHashMap<String, u64> = counts.iter().map(|(m, c)| (m.to_string(), *c)).collect();
+        DecodedCounts {
+            counts: BTreeMap::from([(1u8, bin)]),
+        }
+    }
+
+    #[test]
+    fn tsv_has_motif_header_and_window_ids() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let motifs = BTreeMap::from([(1u8, ["A", "C", "G", "T"].map(String::from).to_vec())]);
+        let windows = vec![window(&[("A", 2), ("T", 1)]), window(&[("G", 5)])];
+        let ids = WindowIds::Coordinates(vec![
+            ("chr1".to_string(), 0, 10),
+            ("chr1".to_string(), 10, 20),
+        ]);
+        write_decoded_counts_table(
+            &windows,
+            &motifs,
+            &ids,
+            dir.path(),
+            OutputFormat::Tsv,
+            &CountWriteOptions::counts(),
+        )?;
+        let table = std::fs::read_to_string(dir.path().join("k1_counts.tsv"))?;
+        assert_eq!(
+            table,
+            "chrom\tstart\tend\tA\tC\tG\tT\nchr1\t0\t10\t2\t0\t0\t1\nchr1\t10\t20\t0\t0\t5\t0\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn arrow_table_has_typed_window_columns() -> anyhow::Result<()> {
+        use arrow_array::cast::AsArray;
+        use arrow_array::types::UInt64Type;
+        use arrow_ipc::reader::FileReader;
+
+        let dir = tempdir()?;
+        let motifs = BTreeMap::from([(1u8, ["A", "C"].map(String::from).to_vec())]);
+        let windows = vec![window(&[("A", 2)]), window(&[("C", 5)])];
+        let ids = WindowIds::Coordinates(vec![
+            ("chr1".to_string(), 0, 10),
+            ("chr2".to_string(), 0, 10),
+        ]);
+        write_decoded_counts_table(
+            &windows,
+            &motifs,
+            &ids,
+            dir.path(),
+            OutputFormat::Arrow,
+            &CountWriteOptions::counts(),
+        )?;
+        let file = std::fs::File::open(dir.path().join("k1_counts.arrow"))?;
+        let batch = FileReader::try_new(file, None)?.next().unwrap()?;
+        let names: Vec<_> = batch
+            .schema()
+            .fields()
+            .iter()
+            .map(|f| f.name().clone())
+            .collect();
+        assert_eq!(names, vec!["chrom", "start", "end", "A", "C"]);
+        assert_eq!(batch.column(0).as_string::<i32>().value(1), "chr2");
+        let c = batch.column(4).as_primitive::<UInt64Type>();
+        assert_eq!(c.values().to_vec(), vec![0, 5]);
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(feature = "hdf5")]
+    fn hdf5_has_counts_motifs_and_windows() -> anyhow::Result<()> {
+        use hdf5::types::VarLenUnicode;
+
+        let dir = tempdir()?;
+        let motifs = BTreeMap::from([(1u8, ["A", "C"].map(String::from).to_vec())]);
+        let windows = vec![window(&[("A", 2)]), window(&[("C", 5)])];
+        let ids = WindowIds::Coordinates(vec![
+            ("chr1".to_string(), 0, 10),
+            ("chr2".to_string(), 5, 10),
+        ]);
+        let options = CountWriteOptions {
+            dtype: CountDtype::U16,
+            ..CountWriteOptions::counts()
+        };
+        write_decoded_counts_table(
+            &windows,
+            &motifs,
+            &ids,
+            dir.path(),
+            OutputFormat::Hdf5,
+            &options,
+        )?;
+        let h5 = hdf5::File::open(dir.path().join("counts.h5"))?;
+        let counts = h5.dataset("k1/counts")?.read_2d::<u16>()?;
+        assert_eq!(counts, ndarray::arr2(&[[2, 0], [0, 5]]));
+        let strings = |name: &str| -> anyhow::Result<Vec<String>> {
+            let values = h5.dataset(name)?.read_raw::<VarLenUnicode>()?;
+            Ok(values.iter().map(|s| s.as_str().to_string()).collect())
+        };
+        assert_eq!(strings("k1/motifs")?, ["A", "C"]);
+        assert_eq!(strings("windows/chrom")?, ["chr1", "chr2"]);
+        assert_eq!(h5.dataset("windows/start")?.read_raw::<u64>()?, [0, 5]);
+        assert_eq!(h5.dataset("windows/end")?.read_raw::<u64>()?, [10, 10]);
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(not(feature = "hdf5"))]
+    fn hdf5_requires_the_feature() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let motifs = BTreeMap::from([(1u8, ["A", "C"].map(String::from).to_vec())]);
+        let err = write_decoded_counts_table(
+            &[window(&[("A", 2)])],
+            &motifs,
+            &WindowIds::Global,
+            dir.path(),
+            OutputFormat::Hdf5,
+            &CountWriteOptions::counts(),
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("--features hdf5"));
+        assert!(!dir.path().join("counts.h5").exists());
+        Ok(())
+    }
+
+    #[test]
+    fn csr_rows_have_sorted_indices() -> anyhow::Result<()> {
+        use ndarray::Array1;
+        use ndarray_npy::ReadNpyExt;
+
+        let dir = tempdir()?;
+        let motifs = ["A", "C", "G", "T"].map(String::from).to_vec();
+        let bins: Vec<FxHashMap<String, u64>> = vec![
+            [("T", 1), ("A", 2)]
+                .iter()
+                .map(|(m, c)| (m.to_string(), *c))
+                .collect(),
+            FxHashMap::default(),
+            [("G", 5)]
+                .iter()
+                .map(|(m, c)| (m.to_string(), *c))
+                .collect(),
+        ];
+        write_category_sparse(
+            &bins,
+            &motifs,
+            "k1",
+            dir.path(),
+            SparseFormat::Csr,
+            CountDtype::U64,
+            None,
+        )?;
+        let file = std::fs::File::open(dir.path().join("k1_counts_sparse.npz"))?;
+        let mut npz = zip::ZipArchive::new(file)?;
+        let mut read = |name: &str| -> anyhow::Result<Array1<u64>> {
+            Ok(Array1::<u64>::read_npy(npz.by_name(name)?)?)
+        };
+        assert_eq!(read("indptr.npy")?.to_vec(), vec![0, 2, 2, 3]);
+        assert_eq!(read("indices.npy")?.to_vec(), vec![0, 3, 2]);
+        assert_eq!(read("data.npy")?.to_vec(), vec![2, 1, 5]);
+        Ok(())
+    }
+
+    #[test]
+    fn coo_entries_are_sorted_by_row_and_column() -> anyhow::Result<()> {
+        use ndarray::Array1;
+        use ndarray_npy::ReadNpyExt;
+
+        let dir = tempdir()?;
+        let motifs: Vec<String> = (0..64).map(|i| format!("M{i:02}")).collect();
+        // Inserted in reverse, so hash map order differs from column order
+        let bins: Vec<FxHashMap<String, u64>> =
+            vec![motifs.iter().rev().map(|m| (m.clone(), 1)).collect()];
+        write_category_sparse(
+            &bins,
+            &motifs,
+            "k1",
+            dir.path(),
+            SparseFormat::Coo,
+            CountDtype::U64,
+            None,
+        )?;
+        let file = std::fs::File::open(dir.path().join("k1_counts_sparse.npz"))?;
+        let mut npz = zip::ZipArchive::new(file)?;
+        let col = Array1::<u64>::read_npy(npz.by_name("col.npy")?)?;
+        assert_eq!(col.to_vec(), (0..64).collect::<Vec<u64>>());
+        Ok(())
+    }
+
+    #[test]
+    fn bundle_packs_files_with_manifest() -> anyhow::Result<()> {
+        use std::io::Read;
+
+        let dir = tempdir()?;
+        std::fs::write(dir.path().join("k1_motifs.txt"), "A\nC\n")?;
+        std::fs::write(dir.path().join("bins.bed"), "chr1\t0\t10\t0\n")?;
+        std::fs::create_dir(dir.path().join("masked"))?;
+        let bundle = dir.path().join("bundle.npz");
+        bundle_directory(dir.path(), &bundle)?;
+
+        let mut npz = zip::ZipArchive::new(std::fs::File::open(&bundle)?)?;
+        let names: Vec<&str> = (0..npz.len())
+            .filter_map(|i| npz.name_for_index(i))
+            .collect();
+        assert_eq!(names, vec!["bins.bed", "k1_motifs.txt", "manifest.tsv"]);
+        let mut manifest = String::new();
+        npz.by_name("manifest.tsv")?.read_to_string(&mut manifest)?;
+        assert_eq!(manifest, "file\tbytes\nbins.bed\t12\nk1_motifs.txt\t4\n");
+        Ok(())
+    }
+
+    #[test]
+    fn counts_too_large_for_dtype_are_an_error() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let motifs = ["A"].map(String::from).to_vec();
+        let bins: Vec<FxHashMap<String, u64>> =
+            vec![FxHashMap::from_iter([("A".to_string(), 70_000u64)])];
+        let err = write_category_sparse(
+            &bins,
+            &motifs,
+            "k1",
+            dir.path(),
+            SparseFormat::Coo,
+            CountDtype::U16,
+            None,
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("70000"), "unexpected error: {err}");
+        write_category_sparse(
+            &bins,
+            &motifs,
+            "k1",
+            dir.path(),
+            SparseFormat::Coo,
+            CountDtype::U32,
+            None,
+        )?;
+        Ok(())
+    }
+
+    #[test]
+    fn streamed_rows_match_the_full_matrix() -> anyhow::Result<()> {
+        use ndarray::{array, Array2};
+        use ndarray_npy::read_npy;
+
+        let dir = tempdir()?;
+        let motifs = BTreeMap::from([(1u8, ["A", "C", "G", "T"].map(String::from).to_vec())]);
+        let mut writer = StreamingCountWriter::create(
+            dir.path(),
+            OutputFormat::Npy,
+            &motifs,
+            &CountWriteOptions::counts(),
+            false,
+        )?;
+        let ids = |chr: &str, n: u64| {
+            WindowIds::Coordinates(
+                (0..n)
+                    .map(|i| (chr.to_string(), i * 10, i * 10 + 10))
+                    .collect(),
+            )
+        };
+        writer.write_rows(&[window(&[("A", 2), ("T", 1)])], &ids("chr1", 1), None)?;
+        writer.write_rows(
+            &[window(&[("G", 5)]), window(&[("C", 3)])],
+            &ids("chr2", 2),
+            None,
+        )?;
+        writer.finish()?;
+
+        let mat: Array2<u64> = read_npy(dir.path().join("k1_counts.npy"))?;
+        assert_eq!(mat, array![[2, 0, 0, 1], [0, 0, 5, 0], [0, 3, 0, 0]]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_motif_index() {
+        let motifs: Vec<String> = ["AC", "AT", "CA"].iter().map(|m| m.to_string()).collect();
+        let index = motif_index(&motifs, true);
+        assert_eq!(index["canonical"], true);
+        assert_eq!(index["motifs"]["AT"]["column"], 1);
+        assert_eq!(index["motifs"]["AT"]["palindromic"], true);
+        assert_eq!(index["motifs"]["AC"]["palindromic"], false);
+        assert_eq!(index["motifs"]["CA"]["reverse_complement"], "TG");
+
+        let index = motif_index(&motifs, false);
+        assert!(index["motifs"]["CA"].get("reverse_complement").is_none());
+    }
+
+    #[test]
+    fn test_motif_tracks() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let ids = WindowIds::Coordinates(vec![
+            ("chr1".to_string(), 0, 10),
+            ("chr1".to_string(), 10, 20),
+        ]);
+        let windows = [window(&[("A", 2), ("T", 1)]), window(&[("G", 4)])];
+        // Canonical counts are stored under A and C
+        let mut tracks = MotifTrackWriter::create(dir.path(), &["t".to_string()], true)?;
+        let scales: RowScales = BTreeMap::from([(1, vec![0.5, 1.0])]);
+        tracks.write_rows(&windows, &ids, Some(&scales))?;
+        tracks.finish()?;
+
+        let text = std::fs::read_to_string(dir.path().join("track_t.bedGraph"))?;
+        let lines: Vec<&str> = text.lines().collect();
+        assert!(lines[0].starts_with("track type=bedGraph name=\"t\""));
+        assert_eq!(lines[1..], ["chr1\t0\t10\t1", "chr1\t10\t20\t0"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_jellyfish_dump() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let motifs = BTreeMap::from([(1, vec!["A".into(), "C".into(), "G".into(), "T".into()])]);
+        write_jellyfish_dump(&window(&[("G", 5), ("A", 2)]), &motifs, dir.path())?;
+        let text = std::fs::read_to_string(dir.path().join("k1_jellyfish.txt"))?;
+        assert_eq!(text, "A 2\nG 5\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_kmer_spectrum() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let w = window(&[("A", 2), ("C", 5), ("G", 2), ("T", 0)]);
+        assert_eq!(kmer_spectrum(&w.counts[&1]), vec![(2, 2), (5, 1)]);
+        write_kmer_spectrum(&w, dir.path())?;
+        let text = std::fs::read_to_string(dir.path().join("k1_spectrum.tsv"))?;
+        assert_eq!(text, "2\t2\n5\t1\n");
+        Ok(())
+    }
+
+    #[test]
+    fn chromosome_dirs_are_safe_and_distinct() {
+        let names = chromosome_dir_names(&["chr1", "HLA-A*01:01", "..", ""]).unwrap();
+        assert_eq!(names, vec!["chr1", "HLA-A_01_01", "__", "_"]);
+        assert!(chromosome_dir_names(&["chrUn/a", "chrUn_a"]).is_err());
+    }
+
+    #[test]
+    fn split_by_chromosome_stays_inside_the_output_dir() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let fasta = dir.path().join("ref.fa");
+        std::fs::write(
+            &fasta,
+            ">chr1\nACGTACGTAC\n>..\nACGTTTGCAA\n>x/../y\nACGTAAAA\n",
+        )?;
+        let out = dir.path().join("nested").join("out");
+        let status = std::process::Command::new(env!("CARGO_BIN_EXE_reference"))
+            .arg("--ref-fasta")
+            .arg(&fasta)
+            .arg("-o")
+            .arg(&out)
+            .args(["-k", "2", "--by-size", "5", "--all-chromosomes"])
+            .arg("--split-by-chromosome")
+            .stdout(std::process::Stdio::null())
+            .status()?;
+        assert!(status.success());
+        for name in ["chr1", "__", "x_.._y"] {
+            let bins = std::fs::read_to_string(out.join(name).join("bins.bed"))?;
+            assert!(bins.lines().count() >= 2, "{}", name);
+            assert!(out.join(name).join("k2_counts.npy").exists());
+        }
+        // Nothing is written next to the output directory
+        let siblings: Vec<_> = std::fs::read_dir(dir.path().join("nested"))?
+            .map(|entry| entry.map(|e| e.file_name()))
+            .collect::<Result<_, _>>()?;
+        assert_eq!(siblings, vec!["out"]);
+        Ok(())
+    }
+}